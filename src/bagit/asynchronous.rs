@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::io::Write;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::task::{self, JoinSet};
+
+use crate::bagit::bag::{Bag, BagCreator};
+use crate::bagit::digest::{DigestAlgorithm, HexDigest, MultiDigestWriter};
+use crate::bagit::error::*;
+use crate::bagit::exclude::ExcludePatterns;
+use crate::bagit::progress::ProgressListener;
+use crate::bagit::tag::BagInfo;
+
+/// The number of bytes that are read from a file at a time when it's hashed
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Async variant of `create_bag()`. See `BagCreator::create_async()`.
+pub async fn create_bag_async<S: Into<PathBuf>, D: Into<PathBuf>>(
+    src_dir: S,
+    dst_dir: D,
+    bag_info: BagInfo,
+    algorithms: &[DigestAlgorithm],
+    include_hidden_files: bool,
+    exclude: ExcludePatterns,
+) -> Result<Bag> {
+    BagCreator::new(src_dir.into(), dst_dir.into())
+        .with_bag_info(bag_info)
+        .with_algorithms(algorithms)
+        .include_hidden_files(include_hidden_files)
+        .with_exclude_patterns(exclude)
+        .create_async()
+        .await
+}
+
+impl BagCreator {
+    /// Async variant of `create()`. The bag is created on one of the calling runtime's blocking
+    /// threads, so that copying and hashing the payload does not stall its other tasks. Copied
+    /// files are hashed as they're written. This must be called within a tokio runtime.
+    pub async fn create_async(self) -> Result<Bag> {
+        run_blocking(move || self.create()).await
+    }
+}
+
+/// Hashes the file at `path` with each of the `algorithms`, reading it with `tokio::fs`
+pub(crate) async fn hash_file_async(
+    path: &Path,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+) -> io::Result<HashMap<DigestAlgorithm, HexDigest>> {
+    let mut file = fs::File::open(path).await?;
+    progress.file_started(path);
+
+    let mut writer = MultiDigestWriter::new(algorithms, io::sink());
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buf[..read])?;
+        progress.bytes_hashed(path, read as u64);
+    }
+
+    progress.digests_timed(path, writer.digest_times());
+    progress.file_finished(path);
+    Ok(writer.finalize_hex())
+}
+
+/// Runs `f` on one of the current runtime's blocking threads, and returns its output. Panics are
+/// propagated to the caller.
+pub(crate) async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(output) => output,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("Task was cancelled: {e}"),
+    }
+}
+
+/// Runs the `tasks` on the current runtime with at most `limit` running at a time, and returns
+/// their outputs in the same order as the tasks. Panics are propagated to the caller.
+pub(crate) async fn run_concurrently<T, F>(limit: usize, tasks: Vec<F>) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let mut outputs = Vec::with_capacity(tasks.len());
+    outputs.resize_with(tasks.len(), || None);
+
+    let mut running = JoinSet::new();
+    for (i, task) in tasks.into_iter().enumerate() {
+        if running.len() >= limit.max(1) {
+            collect_next(&mut running, &mut outputs).await;
+        }
+        running.spawn(async move { (i, task.await) });
+    }
+    while !running.is_empty() {
+        collect_next(&mut running, &mut outputs).await;
+    }
+
+    outputs
+        .into_iter()
+        .map(|output| output.expect("Missing task output"))
+        .collect()
+}
+
+/// Waits for the next task in `running` to finish, and stores its output
+async fn collect_next<T: 'static>(running: &mut JoinSet<(usize, T)>, outputs: &mut [Option<T>]) {
+    match running.join_next().await {
+        Some(Ok((i, output))) => outputs[i] = Some(output),
+        Some(Err(e)) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Some(Err(e)) => panic!("Task was cancelled: {e}"),
+        None => {}
+    }
+}
+
+/// The number of files to process at a time for the number of `threads`, where 0 is the number of
+/// available CPUs
+pub(crate) fn concurrency(threads: usize) -> usize {
+    if threads == 0 {
+        thread::available_parallelism().map_or(1, |threads| threads.get())
+    } else {
+        threads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tokio::runtime::Builder;
+
+    use crate::bagit::asynchronous::{create_bag_async, run_concurrently};
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::exclude::ExcludePatterns;
+    use crate::bagit::tag::BagInfo;
+    use crate::bagit::validate::{validate_bag_async, ValidationOptions};
+
+    #[test]
+    fn create_and_validate_bag_async() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("file.txt"), "testing\n").unwrap();
+        fs::write(dir.join("nested/other.txt"), "more testing\n").unwrap();
+        fs::write(dir.join(".hidden"), "hidden\n").unwrap();
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let result = runtime.block_on(async {
+            let bag = create_bag_async(
+                &dir,
+                &dir,
+                BagInfo::new(),
+                &[DigestAlgorithm::Sha256],
+                false,
+                ExcludePatterns::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(&[DigestAlgorithm::Sha256], bag.algorithms());
+
+            let digest = DigestAlgorithm::Sha256
+                .hash_hex_async(&mut "testing\n".as_bytes())
+                .await
+                .unwrap();
+            assert_eq!(
+                "12a61f4e173fb3a11c05d6471f74728f76231b4a5fcd9667cef3af87a3ae4dc2",
+                digest.to_string()
+            );
+
+            let valid = validate_bag_async(&dir, &ValidationOptions::new())
+                .await
+                .unwrap();
+
+            fs::write(dir.join("data/file.txt"), "changed\n").unwrap();
+            let invalid = validate_bag_async(&dir, &ValidationOptions::new())
+                .await
+                .unwrap();
+
+            (valid, invalid)
+        });
+
+        let manifest = fs::read_to_string(dir.join("manifest-sha256.txt")).unwrap();
+
+        let (valid, invalid) = result;
+        assert!(valid.is_valid());
+        assert!(!invalid.is_valid());
+        assert!(manifest.contains(
+            "12a61f4e173fb3a11c05d6471f74728f76231b4a5fcd9667cef3af87a3ae4dc2  data/file.txt"
+        ));
+        assert!(!manifest.contains(".hidden"));
+    }
+
+    #[test]
+    fn create_bag_async_hashes_copies_as_they_are_written() {
+        let temp = tempfile::tempdir().unwrap();
+        let src_dir = temp.path().join("src");
+        let dst_dir = temp.path().join("dst");
+        fs::create_dir_all(src_dir.join("nested")).unwrap();
+        fs::write(src_dir.join("file.txt"), "testing\n").unwrap();
+        fs::write(src_dir.join("nested/other.txt"), "more testing\n").unwrap();
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let valid = runtime.block_on(async {
+            create_bag_async(
+                &src_dir,
+                &dst_dir,
+                BagInfo::new(),
+                &[DigestAlgorithm::Sha256],
+                false,
+                ExcludePatterns::default(),
+            )
+            .await
+            .unwrap();
+
+            validate_bag_async(&dst_dir, &ValidationOptions::new())
+                .await
+                .unwrap()
+        });
+
+        let manifest = fs::read_to_string(dst_dir.join("manifest-sha256.txt")).unwrap();
+
+        assert!(valid.is_valid());
+        assert!(manifest.contains(
+            "12a61f4e173fb3a11c05d6471f74728f76231b4a5fcd9667cef3af87a3ae4dc2  data/file.txt"
+        ));
+        assert_eq!(
+            "more testing\n",
+            fs::read_to_string(dst_dir.join("data/nested/other.txt")).unwrap()
+        );
+        assert!(src_dir.join("file.txt").exists());
+    }
+
+    #[test]
+    fn run_concurrently_keeps_task_order() {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let outputs = runtime.block_on(run_concurrently(
+            2,
+            (0..10u64)
+                .map(|i| async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(10 - i)).await;
+                    i
+                })
+                .collect(),
+        ));
+
+        assert_eq!((0..10).collect::<Vec<u64>>(), outputs);
+    }
+}
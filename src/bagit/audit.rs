@@ -0,0 +1,305 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use log::info;
+use snafu::ResultExt;
+
+use crate::bagit::bag::open_bag;
+use crate::bagit::consts::*;
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+use crate::bagit::manifest::read_payload_manifest;
+use crate::bagit::validate::{validate_bag, ValidationOptions, ValidationResult};
+
+/// Verifies the digests of the payload files of a bag that were verified least recently, so that
+/// a large bag can be audited a piece at a time. When each file was last verified is recorded in
+/// a state file, which is `.bagr.audit` in the bag's base directory by default.
+///
+/// Files that have never been verified are audited first, followed by the files that were
+/// verified the longest time ago. An audit is bounded by a time budget, a byte budget, or both.
+/// Without either, every file is verified.
+#[derive(Debug)]
+pub struct Auditor {
+    state_file: Option<PathBuf>,
+    max_duration: Option<Duration>,
+    max_bytes: Option<u64>,
+    options: ValidationOptions,
+}
+
+/// The outcome of an audit
+#[derive(Debug)]
+pub struct AuditReport {
+    validation: ValidationResult,
+    verified: Vec<String>,
+    verified_bytes: u64,
+    total: usize,
+    never_verified: usize,
+    oldest: Option<DateTime<Utc>>,
+}
+
+/// When the digests of each payload file were last verified. Each line of the state file
+/// describes one file: the number of seconds since the epoch when it was last verified, and its
+/// strictly percent encoded path, as it appears in the payload manifests, separated by a space.
+#[derive(Debug, Default)]
+struct AuditState {
+    verified: HashMap<String, i64>,
+}
+
+impl Auditor {
+    pub fn new() -> Self {
+        Self {
+            state_file: None,
+            max_duration: None,
+            max_bytes: None,
+            options: ValidationOptions::new(),
+        }
+    }
+
+    /// Sets the file that audit state is read from and written to. By default, it's `.bagr.audit`
+    /// in the bag's base directory.
+    pub fn with_state_file<P: Into<PathBuf>>(mut self, state_file: P) -> Self {
+        self.state_file = Some(state_file.into());
+        self
+    }
+
+    /// Sets the maximum amount of time to spend hashing files. Files that are not started before
+    /// it elapses are left for the next audit.
+    pub fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Sets the maximum number of bytes to hash. At least one file is always audited, even if
+    /// it's larger than the budget.
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Sets the options that the bag is validated with. Sampling, time limits, and fail fast are
+    /// controlled by the auditor and are ignored.
+    pub fn with_validation_options(mut self, options: ValidationOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Audits the bag in `base_dir`, and records the files that were verified in the state file.
+    /// The whole bag is checked for completeness, and tag files are always verified.
+    ///
+    /// An `Err` is only returned when the audit itself could not be completed. Problems with the
+    /// bag are reported on the `AuditReport`.
+    pub fn audit<P: AsRef<Path>>(&self, base_dir: P) -> Result<AuditReport> {
+        let base_dir = base_dir.as_ref();
+        let state_file = self
+            .state_file
+            .clone()
+            .unwrap_or_else(|| base_dir.join(AUDIT_STATE));
+
+        let bag = open_bag(base_dir)?;
+        let mut listed = BTreeSet::new();
+        for algorithm in bag.algorithms() {
+            let manifest =
+                read_payload_manifest(base_dir, *algorithm, self.options.path_encoding())?;
+            listed.extend(manifest.into_iter().map(|(path, _)| path));
+        }
+
+        let mut state = AuditState::load(&state_file)?;
+        state.verified.retain(|path, _| listed.contains(path));
+
+        let selected = self.select_files(base_dir, &listed, &state);
+        info!(
+            "Auditing {} of {} payload files",
+            selected.len(),
+            listed.len()
+        );
+
+        let options = self
+            .options
+            .clone()
+            .with_sample_rate(1.0)
+            .fail_fast(false)
+            .with_max_duration(self.max_duration)
+            .with_payload_files(selected.iter().map(|(path, _)| path.clone()));
+        let validation = validate_bag(base_dir, &options)?;
+
+        let unverified = validation
+            .unverified_files()
+            .iter()
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let failed = validation
+            .errors()
+            .filter_map(|issue| issue.path())
+            .collect::<Vec<_>>();
+
+        let now = Utc::now().timestamp();
+        let mut verified = Vec::new();
+        let mut verified_bytes = 0;
+
+        for (path, size) in selected {
+            if !unverified.contains(path.as_str()) && !has_failed(&failed, &path) {
+                state.verified.insert(path.clone(), now);
+                verified.push(path);
+                verified_bytes += size;
+            }
+        }
+
+        state.write(&state_file)?;
+
+        Ok(AuditReport {
+            validation,
+            verified,
+            verified_bytes,
+            total: listed.len(),
+            never_verified: listed.len() - state.verified.len(),
+            oldest: state
+                .verified
+                .values()
+                .min()
+                .and_then(|oldest| Utc.timestamp_opt(*oldest, 0).single()),
+        })
+    }
+
+    /// Returns the paths and sizes of the files to audit, ordered from the least recently verified
+    /// to the most recently verified, within the byte budget
+    fn select_files(
+        &self,
+        base_dir: &Path,
+        listed: &BTreeSet<String>,
+        state: &AuditState,
+    ) -> Vec<(String, u64)> {
+        let mut candidates = listed
+            .iter()
+            .map(|path| (state.verified.get(path).copied(), path))
+            .collect::<Vec<_>>();
+        // Files that were never verified sort first, and ties are broken by path
+        candidates.sort();
+
+        let mut selected = Vec::new();
+        let mut bytes = 0;
+
+        for (_, path) in candidates {
+            // Missing files count as empty; validation reports them as missing
+            let size = fs::metadata(base_dir.join(path)).map_or(0, |meta| meta.len());
+
+            if let Some(max_bytes) = self.max_bytes {
+                if !selected.is_empty() && bytes + size > max_bytes {
+                    break;
+                }
+            }
+
+            bytes += size;
+            selected.push((path.clone(), size));
+        }
+
+        selected
+    }
+}
+
+impl Default for Auditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuditReport {
+    /// True if no errors were identified while auditing the bag
+    pub fn is_valid(&self) -> bool {
+        self.validation.is_valid()
+    }
+
+    /// The result of validating the bag. Only the audited payload files were verified.
+    pub fn validation(&self) -> &ValidationResult {
+        &self.validation
+    }
+
+    /// The paths of the payload files whose digests were verified by this audit
+    pub fn verified_files(&self) -> &[String] {
+        &self.verified
+    }
+
+    /// The total size of the payload files that were verified by this audit
+    pub fn verified_bytes(&self) -> u64 {
+        self.verified_bytes
+    }
+
+    /// The number of payload files listed in the bag's manifests
+    pub fn total_files(&self) -> usize {
+        self.total
+    }
+
+    /// The number of payload files that have never been verified by an audit
+    pub fn never_verified(&self) -> usize {
+        self.never_verified
+    }
+
+    /// When the least recently verified file was verified, if any files have been verified
+    pub fn oldest_verification(&self) -> Option<DateTime<Utc>> {
+        self.oldest
+    }
+}
+
+impl AuditState {
+    /// Reads the state file at `path`. The state is empty if it does not exist, and lines that
+    /// cannot be parsed are skipped.
+    fn load(path: &Path) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("There is no audit state at {}", path.display());
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e).context(IoReadSnafu { path }),
+        };
+
+        let mut verified = HashMap::new();
+        for line in LineReader::new(file) {
+            let line = line?;
+            let entry = line.split_once(' ').and_then(|(timestamp, file)| {
+                Some((
+                    PathEncoding::Strict.decode(file).into_owned(),
+                    timestamp.parse::<i64>().ok()?,
+                ))
+            });
+            match entry {
+                Some((file, timestamp)) => {
+                    verified.insert(file, timestamp);
+                }
+                None if line.is_empty() => {}
+                None => info!("Skipping invalid audit state line: {line}"),
+            }
+        }
+
+        Ok(Self { verified })
+    }
+
+    /// Writes the state to `path`, replacing the existing state
+    fn write(&self, path: &Path) -> Result<()> {
+        info!("Writing {}", path.display());
+
+        let mut entries = self.verified.iter().collect::<Vec<_>>();
+        entries.sort();
+
+        let mut writer = BufWriter::new(File::create(path).context(IoCreateSnafu { path })?);
+        for (file, timestamp) in entries {
+            writeln!(writer, "{timestamp} {}", PathEncoding::Strict.encode(file))
+                .context(IoWriteSnafu { path })?;
+        }
+        writer.flush().context(IoWriteSnafu { path })
+    }
+}
+
+/// Returns true if an error was reported for the file or one of its directories
+fn has_failed(failed: &[&str], path: &str) -> bool {
+    failed.iter().any(|failed| {
+        path == *failed
+            || path
+                .strip_prefix(failed)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
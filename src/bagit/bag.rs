@@ -1,28 +1,56 @@
-use chrono::Local;
+use chrono::{DateTime, Local};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::Write;
-use std::io::{BufWriter, ErrorKind};
-use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{fs, io};
-
-use crate::bagit::digest::{DigestAlgorithm, HexDigest, MultiDigestWriter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use std::{fs, io, thread};
+
+use crate::bagit::digest::{
+    check_weak_algorithms, DigestAlgorithm, HexDigest, MultiDigestReader, MultiDigestWriter,
+    WeakAlgorithmPolicy,
+};
 use log::{error, info, warn};
+use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use regex::{Captures, Regex};
 use snafu::ResultExt;
+use strum_macros::Display as EnumDisplay;
 use walkdir::{DirEntry, WalkDir};
 
+use crate::bagit::cache::{DigestCache, DigestCacheWriter};
+use crate::bagit::checksums::ImportedDigests;
 use crate::bagit::consts::*;
-use crate::bagit::encoding::percent_encode;
+use crate::bagit::encoding::PathEncoding;
 use crate::bagit::error::Error::*;
 use crate::bagit::error::*;
+use crate::bagit::exclude::{ExcludePatterns, HiddenFilePolicy};
+use crate::bagit::inspect::inspect_file;
+use crate::bagit::io::LineReader;
+use crate::bagit::journal::Journal;
+use crate::bagit::lock::BagLock;
+use crate::bagit::manifest::{
+    read_manifest_entries, read_manifest_file, read_payload_manifest, split_manifest_line, Manifest,
+};
+use crate::bagit::progress::{NoProgress, ProgressListener, ProgressReader};
+use crate::bagit::scan::{FileScanner, ScanReader};
+use crate::bagit::snapshot::SourceSnapshot;
+use crate::bagit::spool::{DigestSpool, FileMeta};
+use crate::bagit::stats::{BagStats, StatsListener};
+use crate::bagit::store::{store_key, ObjectStore};
 use crate::bagit::tag::{
-    read_bag_declaration, read_bag_info, write_bag_declaration, write_bag_info, BagDeclaration,
-    BagInfo,
+    decode_reader, format_bag_size, read_bag_declaration, read_bag_info, write_bag_declaration,
+    write_bag_info, write_tag_file, BagDeclaration, BagInfo, PayloadOxum, TagList,
+};
+use crate::bagit::transform::NameTransforms;
+use crate::bagit::validate::{
+    check_payload_oxum, validate_bag, ValidationOptions, ValidationResult,
 };
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -37,22 +65,154 @@ pub struct Bag {
     declaration: BagDeclaration,
     bag_info: BagInfo,
     algorithms: Vec<DigestAlgorithm>,
+    /// Payload manifests, which are only read the first time they are needed
+    manifests: HashMap<DigestAlgorithm, OnceCell<Manifest>>,
+    /// Statistics about how the bag was created or updated, if it was by this process
+    stats: Option<BagStats>,
+}
+
+#[derive(Debug)]
+pub struct BagCreator {
+    src_dir: PathBuf,
+    dst_dir: PathBuf,
+    bag_info: BagInfo,
+    algorithms: Vec<DigestAlgorithm>,
+    include_hidden_files: bool,
+    hidden_file_policy: HiddenFilePolicy,
+    exclude_patterns: ExcludePatterns,
+    use_bagignore: bool,
+    warn_suspicious_files: bool,
+    exclusion_report: bool,
+    verify_source: bool,
+    force: bool,
+    reproducible: bool,
+    digest_cache: bool,
+    name_transforms: NameTransforms,
+    imported_digests: Option<ImportedDigests>,
+    /// Source files and the payload paths to copy them to, when the payload is listed instead of
+    /// read from the source directory
+    payload_files: Option<Vec<(PathBuf, PathBuf)>>,
+    /// Source files and the paths to copy them to, relative to the bag's base directory
+    tag_files: Vec<(PathBuf, PathBuf)>,
+    /// Source directories and the paths to copy their contents to, relative to the bag's base
+    /// directory
+    tag_dirs: Vec<(PathBuf, PathBuf)>,
+    weak_algorithm_policy: WeakAlgorithmPolicy,
+    threads: usize,
+    io_retries: IoRetries,
+    path_encoding: PathEncoding,
+    tag_line_width: Option<usize>,
+    calculate_bag_size: bool,
+    progress: Arc<dyn ProgressListener>,
+    scanner: Option<Arc<dyn FileScanner>>,
 }
 
 #[derive(Debug)]
 pub struct BagUpdater {
     bag: Bag,
-    recalculate_payload_manifests: bool,
+    recalculate_payload_manifests: Option<bool>,
     algorithms: Vec<DigestAlgorithm>,
     bagging_date: Option<String>,
     software_agent: Option<String>,
+    threads: usize,
+    io_retries: IoRetries,
+    incremental: bool,
+    detect_renames: bool,
+    prune_missing: bool,
+    force: bool,
+    digest_cache: bool,
+    path_encoding: PathEncoding,
+    tag_line_width: Option<usize>,
+    calculate_bag_size: bool,
+    weak_algorithm_policy: WeakAlgorithmPolicy,
+    progress: Arc<dyn ProgressListener>,
+    scanner: Option<Arc<dyn FileScanner>>,
+    /// Source files and the payload paths to add them at
+    additions: Vec<(PathBuf, PathBuf)>,
+    /// Payload paths of files to remove
+    removals: Vec<PathBuf>,
+    /// Algorithms to add payload manifests for
+    added_algorithms: Vec<DigestAlgorithm>,
+}
+
+/// Writes a new bag one payload file at a time from arbitrary readers. Files are hashed as they
+/// are written, so the payload does not need to be staged on disk first.
+#[derive(Debug)]
+pub struct BagWriter {
+    base_dir: PathBuf,
+    bag_info: BagInfo,
+    algorithms: Vec<DigestAlgorithm>,
+    path_encoding: PathEncoding,
+    tag_line_width: Option<usize>,
+    calculate_bag_size: bool,
+    payload_paths: HashSet<PathBuf>,
+    payload: DigestSpool,
+}
+
+/// A payload file as it's described by a bag's payload manifests
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PayloadFile {
+    path: String,
+    size: Option<u64>,
+    digests: HashMap<DigestAlgorithm, String>,
+}
+
+/// How reading or copying a file that fails with a transient IO error is retried
+#[derive(Debug, Copy, Clone)]
+struct IoRetries {
+    max_retries: u32,
+    /// The delay before the first retry, which doubles after every retry
+    delay: Duration,
 }
 
+/// A source file or directory that was left out of a bag's payload because it could not be read
 #[derive(Debug)]
-struct FileMeta {
+pub struct SkippedFile {
+    path: PathBuf,
+    error: Error,
+}
+
+/// A source file or directory that was not included in a bag's payload
+#[derive(Debug)]
+struct Exclusion {
+    /// The path the file would have had in the bag, relative the bag's base directory
+    path: PathBuf,
+    is_dir: bool,
+    reason: ExclusionReason,
+}
+
+#[derive(Debug, Copy, Clone, EnumDisplay)]
+enum ExclusionReason {
+    /// The file was hidden and hidden files were not included
+    #[strum(serialize = "hidden")]
+    Hidden,
+    /// The file matched an exclude pattern
+    #[strum(serialize = "excluded")]
+    Pattern,
+    /// The file could not be read and unreadable files were skipped
+    #[strum(serialize = "unreadable")]
+    Unreadable,
+}
+
+/// Digests from existing payload manifests that can be reused for payload files whose size and
+/// modification time are unchanged since they were last hashed, according to the digest cache
+#[derive(Debug)]
+struct KnownDigests {
+    cache: DigestCache,
+    digests: HashMap<PathBuf, HashMap<DigestAlgorithm, HexDigest>>,
+    /// Digests that were calculated during the current update, which are used regardless of the
+    /// modification times of the files
+    verified: HashMap<PathBuf, HashMap<DigestAlgorithm, HexDigest>>,
+}
+
+/// A payload file that is not listed in the payload manifests. Paths are relative to the payload
+/// directory.
+#[derive(Debug)]
+struct UnlistedFile {
     path: PathBuf,
-    size_bytes: u64,
     digests: HashMap<DigestAlgorithm, HexDigest>,
+    /// The listed path of the file, if it was moved since the manifests were written
+    renamed_from: Option<PathBuf>,
 }
 
 // TODO investigate BagIt Profiles
@@ -68,71 +228,26 @@ struct FileMeta {
 /// The `algorithms` are the algorithms that are used when calculating file digests. If none are
 /// provided, then `sha512` is used.
 ///
-/// When `include_hidden_files` is false, hidden files, files beginning with a `.`, will **not**
-/// be included in the bag. If the bag is being created in place, this further means that hidden
-/// files and directories will be **deleted**.
+/// When `include_hidden_files` is false, hidden files, files beginning with a `.` or, on Windows,
+/// with the hidden attribute, will **not** be included in the bag. Neither will files and directories that match the `exclude` patterns,
+/// or the patterns in a `.bagignore` file at the root of `src_dir`. If the bag is being created in
+/// place, this further means that these files and directories will be **deleted**.
+///
+/// See `BagCreator` for additional options.
 pub fn create_bag<S: AsRef<Path>, D: AsRef<Path>>(
     src_dir: S,
     dst_dir: D,
-    mut bag_info: BagInfo,
+    bag_info: BagInfo,
     algorithms: &[DigestAlgorithm],
     include_hidden_files: bool,
+    exclude: ExcludePatterns,
 ) -> Result<Bag> {
-    let src_dir = src_dir.as_ref();
-    let dst_dir = dst_dir.as_ref();
-
-    info!("Creating bag in {}", dst_dir.display());
-
-    let in_place = src_dir == dst_dir;
-    let algorithms = defaulted_algorithms(algorithms);
-
-    if !in_place {
-        fs::create_dir_all(dst_dir).context(IoCreateSnafu { path: dst_dir })?;
-    }
-
-    let temp_name = format!("temp-{}", epoch_seconds());
-    let temp_dir = dst_dir.join(&temp_name);
-
-    fs::create_dir(&temp_dir).context(IoCreateSnafu { path: &temp_dir })?;
-
-    let mut payload_meta = move_into_dir(
-        !in_place,
-        &src_dir,
-        &temp_dir,
-        &algorithms,
-        include_hidden_files,
-        |f| {
-            // Excludes the temp directory we're moving files into as well as hidden files
-            // when hidden files are not to be included in the bag and the bag is not being
-            // created in place.
-            f.file_name() != temp_name.as_str()
-                && !(!include_hidden_files && !in_place && is_hidden_file(f.file_name()))
-        },
-    )?;
-
-    let data_dir = dst_dir.join(DATA);
-    rename(temp_dir, &data_dir)?;
-
-    add_data_prefix(&mut payload_meta);
-    write_payload_manifests(&algorithms, &mut payload_meta, dst_dir)?;
-
-    let declaration = BagDeclaration::new();
-    write_bag_declaration(&declaration, dst_dir)?;
-
-    if bag_info.bagging_date().is_none() {
-        bag_info.add_bagging_date(current_date_str())?;
-    }
-    if bag_info.software_agent().is_none() {
-        bag_info.add_software_agent(bagr_software_agent())?;
-    }
-
-    bag_info.add_payload_oxum(build_payload_oxum(&payload_meta))?;
-
-    write_bag_info(&bag_info, dst_dir)?;
-
-    update_tag_manifests(dst_dir, &algorithms)?;
-
-    Ok(Bag::new(dst_dir, declaration, bag_info, algorithms))
+    BagCreator::new(src_dir, dst_dir)
+        .with_bag_info(bag_info)
+        .with_algorithms(algorithms)
+        .include_hidden_files(include_hidden_files)
+        .with_exclude_patterns(exclude)
+        .create()
 }
 
 /// Opens a BagIt bag in that already exists in the specified directory
@@ -143,7 +258,7 @@ pub fn open_bag<P: AsRef<Path>>(base_dir: P) -> Result<Bag> {
     let declaration = read_bag_declaration(base_dir)?;
     let algorithms = detect_digest_algorithms(base_dir)?;
 
-    let bag_info = read_bag_info(base_dir)?;
+    let bag_info = read_bag_info(base_dir, &declaration)?;
 
     Ok(Bag::new(base_dir, declaration, bag_info, algorithms))
 }
@@ -195,14 +310,24 @@ impl Bag {
         bag_info: BagInfo,
         algorithms: Vec<DigestAlgorithm>,
     ) -> Self {
+        let manifests = algorithms
+            .iter()
+            .map(|algorithm| (*algorithm, OnceCell::new()))
+            .collect();
         Self {
             base_dir: base_dir.as_ref().into(),
             declaration,
             bag_info,
             algorithms,
+            manifests,
+            stats: None,
         }
     }
 
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
     pub fn declaration(&self) -> &BagDeclaration {
         &self.declaration
     }
@@ -211,8 +336,192 @@ impl Bag {
         &self.bag_info
     }
 
-    // TODO get fetch entries
-    // TODO download fetch entries
+    /// The digest algorithms of the bag's payload manifests
+    pub fn algorithms(&self) -> &[DigestAlgorithm] {
+        &self.algorithms
+    }
+
+    /// Statistics about how the bag was created or updated, which are only available on the
+    /// `Bag` that `BagCreator` or `BagUpdater` returns
+    pub fn stats(&self) -> Option<&BagStats> {
+        self.stats.as_ref()
+    }
+
+    /// Writes a custom tag file at `path`, which is relative to the bag's base directory, eg.
+    /// `metadata/dc.txt`, and updates the tag manifests. Parent directories are created if they
+    /// do not exist. The path must not be in the payload directory or be one of the tag files
+    /// that bagr writes. Tag manifest paths are encoded using the standard BagIt encoding; use
+    /// `write_tag_file_with_encoding()` for bags that were created with a different encoding.
+    pub fn write_tag_file<P: AsRef<Path>>(&self, path: P, tags: &TagList) -> Result<()> {
+        self.write_tag_file_with_encoding(path, tags, PathEncoding::Standard)
+    }
+
+    /// Writes a custom tag file at `path` and updates the tag manifests, which encode paths
+    /// using `encoding`
+    pub fn write_tag_file_with_encoding<P: AsRef<Path>>(
+        &self,
+        path: P,
+        tags: &TagList,
+        encoding: PathEncoding,
+    ) -> Result<()> {
+        let path = validate_tag_file_path(path.as_ref())?;
+        let destination = self.base_dir.join(path);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+        }
+
+        write_tag_file(tags, &destination, Some(DEFAULT_TAG_LINE_WIDTH))?;
+
+        // Tag files are small, so there's no benefit to hashing them concurrently
+        update_tag_manifests(&self.base_dir, &self.algorithms, encoding, &thread_pool(1)?)
+    }
+
+    /// Replaces the bag's bag-info.txt with `bag_info` and updates the tag manifests. Unlike
+    /// updating the bag, the payload is not rehashed, and Bagging-Date and Bag-Software-Agent are
+    /// written exactly as they are in `bag_info`. Bags that are not encoded as UTF-8 are converted
+    /// to UTF-8. Tag manifest paths are encoded using the standard BagIt encoding; use
+    /// `write_bag_info_with_encoding()` for bags that were created with a different encoding.
+    pub fn write_bag_info(&mut self, bag_info: BagInfo) -> Result<()> {
+        self.write_bag_info_with_encoding(bag_info, PathEncoding::Standard)
+    }
+
+    /// Replaces the bag's bag-info.txt and updates the tag manifests, which encode paths using
+    /// `encoding`
+    pub fn write_bag_info_with_encoding(
+        &mut self,
+        bag_info: BagInfo,
+        encoding: PathEncoding,
+    ) -> Result<()> {
+        let _lock = BagLock::acquire(&self.base_dir, false)?;
+
+        if !self.declaration.is_utf8() {
+            self.declaration = convert_to_utf8(&self.base_dir, &self.declaration)?;
+        }
+
+        write_bag_info(&bag_info, &self.base_dir, Some(DEFAULT_TAG_LINE_WIDTH))?;
+        self.bag_info = bag_info;
+
+        update_tag_manifests(&self.base_dir, &self.algorithms, encoding, &thread_pool(1)?)
+    }
+
+    /// Returns all of the files that are listed in the bag's payload manifests, ordered by path.
+    /// The digests are read from the manifests and are not verified. Manifest paths are decoded
+    /// using the standard BagIt encoding; use `payload_files_with_encoding()` for bags that were
+    /// created with a different one.
+    pub fn payload_files(&self) -> Result<impl Iterator<Item = PayloadFile>> {
+        self.payload_files_with_encoding(PathEncoding::Standard)
+    }
+
+    /// The same as `payload_files()`, except manifest paths are decoded using `encoding`
+    pub fn payload_files_with_encoding(
+        &self,
+        encoding: PathEncoding,
+    ) -> Result<impl Iterator<Item = PayloadFile>> {
+        let mut files: BTreeMap<String, PayloadFile> = BTreeMap::new();
+
+        for algorithm in &self.algorithms {
+            for (path, digest) in read_payload_manifest(&self.base_dir, *algorithm, encoding)? {
+                files
+                    .entry(path)
+                    .or_insert_with_key(|path| PayloadFile {
+                        path: path.clone(),
+                        size: fs::metadata(self.base_dir.join(path))
+                            .ok()
+                            .map(|meta| meta.len()),
+                        digests: HashMap::new(),
+                    })
+                    .digests
+                    .insert(*algorithm, digest.into());
+            }
+        }
+
+        Ok(files.into_values())
+    }
+
+    /// Quickly checks that the bag's payload is complete by comparing the number and total size
+    /// of the files in its payload directory to its Payload-Oxum. Manifests are not read and no
+    /// digests are calculated, so this is a cheap sanity check to run before a full validation.
+    /// It is an error if the bag does not have a valid Payload-Oxum.
+    pub fn quick_check(&self) -> Result<ValidationResult> {
+        check_payload_oxum(&self.base_dir, &self.bag_info)
+    }
+
+    /// Returns the digest that the bag's payload manifest for `algorithm` lists for the file at
+    /// `path`, eg. `data/file.txt`. `None` is returned if the bag does not have a manifest for
+    /// `algorithm` or the file is not listed in it. The digest is not verified.
+    ///
+    /// Each manifest is read the first time it's needed, and its paths are decoded using the
+    /// standard BagIt encoding. Because the manifest is only read then, an error is returned if
+    /// it cannot be read or parsed, rather than `None`, which would be indistinguishable from a
+    /// file that's not listed.
+    pub fn digest_for<S: AsRef<str>>(
+        &self,
+        path: S,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Option<HexDigest>> {
+        let manifest = match self.manifests.get(&algorithm) {
+            Some(manifest) => manifest.get_or_try_init(|| {
+                read_payload_manifest(&self.base_dir, algorithm, PathEncoding::Standard)
+            })?,
+            None => return Ok(None),
+        };
+        Ok(manifest.get(path).cloned())
+    }
+
+    /// Generates tag manifests for a bag that does not have any, using the same algorithms as
+    /// its payload manifests, and returns their names. No other files are changed. Nothing is
+    /// written if the bag already has tag manifests, and an empty vec is returned.
+    pub fn add_tag_manifests(&self, encoding: PathEncoding) -> Result<Vec<String>> {
+        if has_tag_manifests(&self.base_dir)? {
+            info!(
+                "Bag at {} already has tag manifests",
+                self.base_dir.display()
+            );
+            return Ok(Vec::new());
+        }
+
+        update_tag_manifests(&self.base_dir, &self.algorithms, encoding, &thread_pool(1)?)?;
+
+        Ok(self
+            .algorithms
+            .iter()
+            .map(|algorithm| format!("{TAG_MANIFEST_PREFIX}-{algorithm}.txt"))
+            .collect())
+    }
+
+    /// Reverses the creation of a bag in place. The bag is validated, and then the contents of its
+    /// payload directory are moved up to its base directory, and all of its tag files, including
+    /// the manifests, are deleted. Nothing is changed if the bag is not valid, and the changes
+    /// are undone if the payload cannot be moved. Returns the base directory.
+    pub fn unbag(self) -> Result<PathBuf> {
+        let base_dir = self.base_dir;
+
+        let result = validate_bag(&base_dir, &ValidationOptions::default())?;
+        if !result.is_valid() {
+            return Err(InvalidBag {
+                path: base_dir,
+                errors: result
+                    .errors()
+                    .map(|issue| issue.message().into())
+                    .collect(),
+            });
+        }
+
+        let _lock = BagLock::acquire(&base_dir, false)?;
+        let work_dir = base_dir.join(format!("temp-{}", std::process::id()));
+        let journal = Journal::new();
+
+        move_payload_up(&base_dir, &work_dir, &journal).inspect_err(|_| {
+            info!("Undoing the changes made to {}", base_dir.display());
+            journal.rollback();
+        })?;
+
+        info!("Deleting tag files in {}", work_dir.display());
+        fs::remove_dir_all(&work_dir).context(IoDeleteSnafu { path: &work_dir })?;
+
+        Ok(base_dir)
+    }
 
     /// Creates a `BagUpdater` that's used to update an existing bag
     pub fn update(self) -> BagUpdater {
@@ -220,323 +529,2792 @@ impl Bag {
     }
 }
 
-impl BagUpdater {
-    pub fn new(bag: Bag) -> Self {
+impl PayloadFile {
+    /// The path of the file relative the bag's base directory, eg. `data/file.txt`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The size of the file in bytes, or `None` if it does not exist
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The digests of the file from each of the payload manifests that list it
+    pub fn digests(&self) -> &HashMap<DigestAlgorithm, String> {
+        &self.digests
+    }
+
+    /// The digest of the file from the payload manifest for `algorithm`, if it's listed in it
+    pub fn digest(&self, algorithm: DigestAlgorithm) -> Option<&str> {
+        self.digests.get(&algorithm).map(String::as_str)
+    }
+}
+
+impl IoRetries {
+    /// Failed files are not retried
+    const NONE: Self = Self {
+        max_retries: 0,
+        delay: Duration::from_secs(1),
+    };
+
+    /// Runs `op` on the file at `path`, retrying it with an exponential backoff when it fails
+    /// with a transient IO error
+    fn run<T, F>(&self, path: &Path, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+
+        loop {
+            match op() {
+                Err(e) if attempt < self.max_retries && is_transient(&e) => {
+                    attempt += 1;
+                    warn!(
+                        "Retrying {} in {}s, attempt {attempt} of {}: {e}",
+                        path.display(),
+                        delay.as_secs_f64(),
+                        self.max_retries
+                    );
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_secs(60));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl SkippedFile {
+    /// The path the file would have had in the bag, relative to the bag's base directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The error that occurred when the file was read
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl KnownDigests {
+    /// Returns known digests that are only used for verified files
+    fn empty() -> Self {
         Self {
-            bag,
-            recalculate_payload_manifests: true,
+            cache: DigestCache::default(),
+            digests: HashMap::new(),
+            verified: HashMap::new(),
+        }
+    }
+
+    /// Returns the known digests for the file at the `relative` payload path if its `size` and
+    /// `modified` time are the same as when it was last hashed
+    fn get(
+        &self,
+        relative: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> Option<&HashMap<DigestAlgorithm, HexDigest>> {
+        if let Some(digests) = self.verified.get(relative) {
+            return Some(digests);
+        }
+        match modified {
+            Some(modified)
+                if self
+                    .cache
+                    .is_unchanged(&Path::new(DATA).join(relative), size, modified) =>
+            {
+                self.digests.get(relative)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl BagCreator {
+    /// Creates a `BagCreator` that creates a bag in `dst_dir` from the files in `src_dir`. If
+    /// the directories are the same, then the bag is created in place.
+    pub fn new<S: AsRef<Path>, D: AsRef<Path>>(src_dir: S, dst_dir: D) -> Self {
+        Self {
+            src_dir: src_dir.as_ref().into(),
+            dst_dir: dst_dir.as_ref().into(),
+            bag_info: BagInfo::new(),
             algorithms: Vec::new(),
-            bagging_date: None,
-            software_agent: None,
+            include_hidden_files: true,
+            hidden_file_policy: HiddenFilePolicy::default(),
+            exclude_patterns: ExcludePatterns::default(),
+            use_bagignore: true,
+            warn_suspicious_files: false,
+            exclusion_report: false,
+            verify_source: false,
+            force: false,
+            reproducible: false,
+            digest_cache: false,
+            name_transforms: NameTransforms::new(),
+            imported_digests: None,
+            payload_files: None,
+            tag_files: Vec::new(),
+            tag_dirs: Vec::new(),
+            weak_algorithm_policy: WeakAlgorithmPolicy::default(),
+            threads: 0,
+            io_retries: IoRetries::NONE,
+            path_encoding: PathEncoding::Standard,
+            tag_line_width: Some(DEFAULT_TAG_LINE_WIDTH),
+            calculate_bag_size: false,
+            progress: Arc::new(NoProgress),
+            scanner: None,
         }
     }
 
+    /// Sets the tags to write to bag-info.txt. Bagging-Date and Bag-Software-Agent are added if
+    /// they are not set, and Payload-Oxum is always calculated.
+    pub fn with_bag_info(mut self, bag_info: BagInfo) -> Self {
+        self.bag_info = bag_info;
+        self
+    }
+
     /// Adds a digest algorithm to use for calculating manifests
     pub fn with_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
         self.algorithms.push(algorithm);
         self
     }
 
-    /// Sets the algorithms to use when calculating manifests. An empty slice will result in
-    /// the algorithms that were used to calculate the existing manifests to be used.
+    /// Sets the algorithms to use when calculating manifests. If none are provided, then `sha512`
+    /// is used.
     pub fn with_algorithms(mut self, algorithms: &[DigestAlgorithm]) -> Self {
         self.algorithms.clear();
         self.algorithms.extend_from_slice(algorithms);
         self
     }
 
-    /// Sets the Bagging-Date to add to bag-info.txt. None for the default value.
-    pub fn with_bagging_date(mut self, bagging_date: Option<String>) -> Self {
-        self.bagging_date = bagging_date;
+    /// Sets whether hidden files, files beginning with a `.` or, on Windows, with the hidden
+    /// attribute, are included in the bag. They are included by default. If they are not included
+    /// and the bag is being created in place, then hidden files and directories are **deleted**.
+    pub fn include_hidden_files(mut self, include_hidden_files: bool) -> Self {
+        self.include_hidden_files = include_hidden_files;
         self
     }
 
-    /// Sets the Bag-Software-Agent to add to bag-info.txt. None for the default value.
-    pub fn with_software_agent(mut self, software_agent: Option<String>) -> Self {
-        self.software_agent = software_agent;
+    /// Sets how hidden files are recognized when they're not included in the bag. By default,
+    /// files with the hidden attribute on Windows are hidden, in addition to dot files.
+    pub fn with_hidden_file_policy(mut self, policy: HiddenFilePolicy) -> Self {
+        self.hidden_file_policy = policy;
         self
     }
 
-    /// Enables/disables payload manifest recalculation on `finalize()`. This is enabled by default,
-    /// but can be disabled if the digest algorithms in use have not changed and there were no
-    /// changes to the payload.
-    pub fn recalculate_payload_manifests(mut self, recalculate: bool) -> Self {
-        self.recalculate_payload_manifests = recalculate;
+    /// Sets the patterns that select source files and directories to leave out of the bag. If
+    /// the bag is being created in place, then the matching files and directories are
+    /// **deleted**. By default, nothing is excluded.
+    pub fn with_exclude_patterns(mut self, exclude_patterns: ExcludePatterns) -> Self {
+        self.exclude_patterns = exclude_patterns;
         self
     }
 
-    /// Writes the changes to disk and recalculates manifests.
-    pub fn finalize(mut self) -> Result<Bag> {
-        let base_dir = &self.bag.base_dir;
+    /// Sets whether the gitignore-style patterns in the `.bagignore` file at the root of the
+    /// source directory are used to exclude files, in addition to the exclude patterns. The
+    /// `.bagignore` file is never included in the payload, and it's deleted when the bag is
+    /// created in place. This is enabled by default.
+    pub fn use_bagignore(mut self, use_bagignore: bool) -> Self {
+        self.use_bagignore = use_bagignore;
+        self
+    }
 
-        let algorithms = if !self.recalculate_payload_manifests || self.algorithms.is_empty() {
-            // must reuse same algorithms if payload manifests are not recalculated
-            &self.bag.algorithms
-        } else {
-            self.algorithms.sort();
-            self.algorithms.dedup();
-            &self.algorithms
-        };
+    /// Sets whether a tag file named exclusions.txt is written that lists the source files that
+    /// were not included in the payload and why. Each line contains the reason, followed by the
+    /// path the file would have had in the bag, strictly percent encoded. Directories end in
+    /// `/`. The report is written, even if it's empty, when this is enabled.
+    pub fn write_exclusion_report(mut self, exclusion_report: bool) -> Self {
+        self.exclusion_report = exclusion_report;
+        self
+    }
 
-        self.bag
-            .bag_info
-            .add_bagging_date(self.bagging_date.unwrap_or_else(current_date_str))?;
-        self.bag
-            .bag_info
-            .add_software_agent(self.software_agent.unwrap_or_else(bagr_software_agent))?;
+    /// Sets whether a snapshot of the path, size, and modification time of every source file is
+    /// taken before the payload is copied, and compared to the source afterwards. It is an error
+    /// if the source changed while it was copied, and the copied payload is deleted. Otherwise,
+    /// a tag file named transfer-consistency.txt is written that records the snapshot and when
+    /// it was verified. This is disabled by default, and is not supported when the bag is
+    /// created in place.
+    pub fn verify_source(mut self, verify_source: bool) -> Self {
+        self.verify_source = verify_source;
+        self
+    }
 
-        if self.recalculate_payload_manifests {
-            delete_payload_manifests(base_dir)?;
-            let payload_meta = update_payload_manifests(base_dir, algorithms)?;
-            self.bag
-                .bag_info
-                .add_payload_oxum(build_payload_oxum(&payload_meta))?;
-        }
+    /// Sets whether warnings are logged for payload files that are empty or have an extension
+    /// that does not match their detected format. These often indicate truncated transfers.
+    /// This is disabled by default.
+    pub fn warn_suspicious_files(mut self, warn_suspicious_files: bool) -> Self {
+        self.warn_suspicious_files = warn_suspicious_files;
+        self
+    }
 
-        write_bag_info(&self.bag.bag_info, base_dir)?;
+    /// Sets whether a bag is created in a directory that already contains payload or tag
+    /// manifests, for example from a previous run that failed. By default, this is an error.
+    /// When it's enabled, the manifests and any other tag files that bagr writes are deleted
+    /// before the bag is created. No other files are touched. It also replaces the lock that
+    /// another bagr process holds on the destination, which is only safe if the lock is stale.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
 
-        delete_tag_manifests(base_dir)?;
-        update_tag_manifests(base_dir, algorithms)?;
+    /// Sets whether the bag is created reproducibly, so that creating a bag from the same source
+    /// files with the same settings always produces byte-identical tag files. The version of bagr
+    /// is left out of the default Bag-Software-Agent, and, when a Bagging-Date is not set, the
+    /// date in the `SOURCE_DATE_EPOCH` environment variable is used. It is an error if neither
+    /// is set. Source verification is not supported because it records when it ran. This is
+    /// disabled by default.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
 
-        Ok(self.bag)
+    /// Sets whether the digests of the payload files are cached in their extended attributes, or
+    /// in `.bagr.cache` in the bag's base directory when the attributes cannot be set, so that
+    /// later updates and validations can reuse them for files whose size and modification time
+    /// have not changed. The cache is not listed in the tag manifests. This is disabled by
+    /// default.
+    pub fn digest_cache(mut self, digest_cache: bool) -> Self {
+        self.digest_cache = digest_cache;
+        self
     }
-}
 
-/// Copies/moves the contents of the `src_dir` into the `dst_dir` and returns meta about all of the
-/// moved files. If `copy_op` is true the files are copied, otherwise they're moved
-fn move_into_dir<S, D, P>(
-    copy_op: bool,
-    src_dir: S,
-    dst_dir: D,
-    algorithms: &[DigestAlgorithm],
-    include_hidden_files: bool,
-    predicate: P,
-) -> Result<Vec<FileMeta>>
-where
-    S: AsRef<Path>,
-    D: AsRef<Path>,
-    P: FnMut(&DirEntry) -> bool,
-{
-    let src_dir = src_dir.as_ref();
-    let dst_dir = dst_dir.as_ref();
+    /// Sets the rules that are used to rename the source files and directories as they're added
+    /// to the payload. It is an error if two files are renamed to the same path. By default,
+    /// names are not changed.
+    pub fn with_name_transforms(mut self, name_transforms: NameTransforms) -> Self {
+        self.name_transforms = name_transforms;
+        self
+    }
 
-    let mut file_meta = Vec::new();
-    let mut dirs = Vec::new();
+    /// Copies the file at `src` into the bag as a custom tag file at `path`, which is relative to
+    /// the bag's base directory, eg. `metadata/dc.txt`. The file is included in the tag
+    /// manifests. The path must not be in the payload directory or be one of the tag files that
+    /// bagr writes.
+    pub fn with_tag_file<S: AsRef<Path>, P: AsRef<Path>>(mut self, src: S, path: P) -> Self {
+        self.tag_files
+            .push((src.as_ref().into(), path.as_ref().into()));
+        self
+    }
+
+    /// Copies all of the files in the directory `src` into the bag as custom tag files under the
+    /// directory `path`, which is relative to the bag's base directory, eg. `metadata`. The same
+    /// restrictions apply to each file as to files added with `with_tag_file()`.
+    pub fn with_tag_dir<S: AsRef<Path>, P: AsRef<Path>>(mut self, src: S, path: P) -> Self {
+        self.tag_dirs
+            .push((src.as_ref().into(), path.as_ref().into()));
+        self
+    }
+
+    /// Uses digests that were already calculated for the source files, eg. by `sha256sum`,
+    /// instead of hashing the files again. Files are still hashed when they are sampled to verify
+    /// their imported digests, when digests were not imported for all of the bag's algorithms, or
+    /// when a file scanner is used, and it's an error if any of the digests that were imported
+    /// for a hashed file are wrong. Only applies when the payload is read from the source
+    /// directory.
+    pub fn with_imported_digests(mut self, imported_digests: Option<ImportedDigests>) -> Self {
+        self.imported_digests = imported_digests;
+        self
+    }
+
+    /// Creates the payload from a list of files instead of from the contents of the source
+    /// directory. Each file is copied from its source path to its destination, which is relative
+    /// to the payload directory, and is hashed as it's copied, so the files do not need to be
+    /// staged in one directory first. The source files are never moved, even when the source and
+    /// destination directories are the same.
+    ///
+    /// Exclusions and renames are not applied to listed files, and the source cannot be verified.
+    pub fn with_payload_files<I, S, P>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = (S, P)>,
+        S: AsRef<Path>,
+        P: AsRef<Path>,
+    {
+        self.payload_files = Some(
+            files
+                .into_iter()
+                .map(|(src, path)| (src.as_ref().into(), path.as_ref().into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Sets the maximum number of threads to use when calculating digests. The default, 0, uses
+    /// one thread per CPU.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the number of times that reading or copying a source file is retried when it fails
+    /// with a transient IO error, such as a timeout or a stale file handle, which are common when
+    /// the files are on a network mount like NFS or SMB. The default is 0, so that files are not
+    /// retried.
+    pub fn with_io_retries(mut self, io_retries: u32) -> Self {
+        self.io_retries.max_retries = io_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry of a file that failed with a transient IO error. The
+    /// delay doubles after every retry, up to a minute. The default is 1 second.
+    pub fn with_io_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.io_retries.delay = retry_delay;
+        self
+    }
+
+    /// Sets how weak digest algorithms, md5 and sha1, are treated when they're used to calculate
+    /// manifests. By default, a warning is logged.
+    pub fn with_weak_algorithm_policy(mut self, policy: WeakAlgorithmPolicy) -> Self {
+        self.weak_algorithm_policy = policy;
+        self
+    }
+
+    /// Sets the length that lines in bag-info.txt are folded at. Long values are wrapped onto
+    /// indented continuation lines. The default is 79 characters, and `None` disables folding.
+    pub fn with_tag_line_width(mut self, tag_line_width: Option<usize>) -> Self {
+        self.tag_line_width = tag_line_width;
+        self
+    }
+
+    /// When true, the Bag-Size in bag-info.txt is set to the human readable size of the payload,
+    /// eg. `42.6 MB`, replacing any existing value. The default is false.
+    pub fn with_calculate_bag_size(mut self, calculate_bag_size: bool) -> Self {
+        self.calculate_bag_size = calculate_bag_size;
+        self
+    }
+
+    /// Sets the profile that's used to percent encode file paths in manifests. The default,
+    /// `PathEncoding::Standard`, only encodes the characters the BagIt spec requires.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Sets the listener that receives progress updates while payload files are hashed
+    pub fn with_progress_listener(mut self, progress: Arc<dyn ProgressListener>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Sets the scanner that screens payload files, eg. for viruses, as they're read to
+    /// calculate their digests. Rejected files fail the creation of the bag. By default, files
+    /// are not scanned.
+    pub fn with_file_scanner(mut self, scanner: Option<Arc<dyn FileScanner>>) -> Self {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Creates the bag by moving or copying the source files into the bag's payload and then
+    /// writing all of the necessary tag files and manifests. If the bag cannot be created, the
+    /// changes that were made are undone: the source files are moved back when the bag is
+    /// created in place, and the copied files are deleted otherwise.
+    pub fn create(self) -> Result<Bag> {
+        self.create_bag(None, None)
+    }
+
+    /// Creates the bag like `create()`, except that source files and directories that cannot be
+    /// read, for example because of their permissions, are left out of the payload instead of
+    /// failing the bag. The bag is completed with everything else, and the files that were
+    /// skipped are returned along with it. They are also listed in the exclusion report, if it's
+    /// written, with the reason `unreadable`. Any other error still fails the bag.
+    ///
+    /// This is not supported when the bag is created in place or from listed payload files.
+    pub fn create_skipping_unreadable(self) -> Result<(Bag, Vec<SkippedFile>)> {
+        if self.payload_files.is_some() || self.src_dir == self.dst_dir {
+            return Err(General {
+                message: "Unreadable files can only be skipped when the bag is created in a \
+                    different directory from a source directory"
+                    .to_string(),
+            });
+        }
+
+        let mut skipped = Vec::new();
+        let bag = self.create_bag(None, Some(&mut skipped))?;
+        for file in &skipped {
+            warn!("Skipped {}: {}", file.path.display(), file.error);
+        }
+        Ok((bag, skipped))
+    }
+
+    /// Creates the bag from the files in the tar archive that's read from `reader`, instead of
+    /// from the source directory, which is ignored. Each file is written to the payload at its
+    /// path within the archive, and is hashed as it's written, so the archive does not need to be
+    /// extracted first. Directory entries are skipped, and any other entries that are not regular
+    /// files, such as links, are an error. If the bag cannot be created, the files that were
+    /// written are deleted.
+    ///
+    /// Exclusions, renames, listed payload files, and source verification are not supported.
+    pub fn create_from_tar<R: Read>(self, mut reader: R) -> Result<Bag> {
+        let unsupported = [
+            (
+                !self.exclude_patterns.is_empty() || !self.include_hidden_files,
+                "exclusions",
+            ),
+            (self.exclusion_report, "exclusion reports"),
+            (!self.name_transforms.is_empty(), "renames"),
+            (self.payload_files.is_some(), "listed payload files"),
+            (self.verify_source, "source verification"),
+        ];
+        if let Some((_, feature)) = unsupported.iter().find(|(enabled, _)| *enabled) {
+            return Err(General {
+                message: format!(
+                    "Bags that are created from a tar archive do not support {feature}"
+                ),
+            });
+        }
+
+        self.create_bag(Some(&mut reader), None)
+    }
+
+    /// Creates the bag from the payload in `tar`, if specified, and otherwise from the listed
+    /// payload files or the source directory. Unreadable source files are recorded in `skipped`
+    /// instead of failing the bag, if it's specified.
+    fn create_bag(
+        mut self,
+        tar: Option<&mut dyn Read>,
+        skipped: Option<&mut Vec<SkippedFile>>,
+    ) -> Result<Bag> {
+        let src_dir = self.src_dir.clone();
+        let dst_dir = self.dst_dir.clone();
+        let mut bag_info = std::mem::take(&mut self.bag_info);
+        let stats = Arc::new(StatsListener::new(self.progress.clone()));
+        self.progress = stats.clone();
+
+        info!("Creating bag in {}", dst_dir.display());
+
+        let in_place = tar.is_none() && self.payload_files.is_none() && src_dir == dst_dir;
+        if self.payload_files.is_some() && self.verify_source {
+            return Err(General {
+                message: "The source can not be verified when the payload files are listed"
+                    .to_string(),
+            });
+        }
+        if in_place && self.verify_source {
+            return Err(General {
+                message: "The source can only be verified when the bag is created in a different \
+                    directory"
+                    .to_string(),
+            });
+        }
+        if self.reproducible && self.verify_source {
+            return Err(General {
+                message: "The source can not be verified when the bag is created reproducibly"
+                    .to_string(),
+            });
+        }
+
+        add_default_tags(&mut bag_info, self.reproducible)?;
+
+        let algorithms = defaulted_algorithms(&self.algorithms);
+        check_weak_algorithms(&algorithms, self.weak_algorithm_policy)?;
+        let pool = thread_pool(self.threads)?;
+
+        // Tag files are read before the payload is moved because they may be in the source
+        let tag_files = read_tag_files(&list_tag_files(&self.tag_files, &self.tag_dirs)?)?;
+
+        // The payload is moved into the work directory before it's renamed to data, and
+        // excluded files are moved aside until the bag is complete
+        let work_dir = dst_dir.join(format!("temp-{}", std::process::id()));
+
+        let journal = Journal::new();
+        if !in_place {
+            journal.creating_tree(&dst_dir);
+            fs::create_dir_all(&dst_dir).context(IoCreateSnafu { path: &dst_dir })?;
+        }
+
+        let _lock = BagLock::acquire(&dst_dir, self.force).inspect_err(|_| journal.rollback())?;
+        let mut bag = self
+            .write_bag(
+                &journal,
+                &work_dir,
+                bag_info,
+                &algorithms,
+                &pool,
+                tag_files,
+                tar,
+                skipped,
+            )
+            .inspect_err(|_| {
+                info!("Undoing the changes made to {}", dst_dir.display());
+                journal.rollback();
+            })?;
+
+        // The bag is complete, so the excluded files and the ignore file can be deleted
+        info!("Deleting {}", work_dir.display());
+        fs::remove_dir_all(&work_dir).context(IoDeleteSnafu { path: &work_dir })?;
+
+        bag.stats = Some(stats.stats(bag.bag_info.payload_oxum()));
+        Ok(bag)
+    }
+
+    /// Moves, copies, or extracts the payload into `work_dir` and then writes the bag, recording
+    /// every change in `journal`
+    #[allow(clippy::too_many_arguments)]
+    fn write_bag(
+        self,
+        journal: &Journal,
+        work_dir: &Path,
+        mut bag_info: BagInfo,
+        algorithms: &[DigestAlgorithm],
+        pool: &ThreadPool,
+        tag_files: Vec<(PathBuf, Vec<u8>)>,
+        tar: Option<&mut dyn Read>,
+        skipped: Option<&mut Vec<SkippedFile>>,
+    ) -> Result<Bag> {
+        let src_dir = self.src_dir.as_path();
+        let dst_dir = self.dst_dir.as_path();
+        let in_place = tar.is_none() && self.payload_files.is_none() && src_dir == dst_dir;
+
+        let exclude_patterns =
+            with_bagignore_patterns(src_dir, self.use_bagignore, self.exclude_patterns)?;
+
+        remove_existing_artifacts(dst_dir, self.force)?;
+
+        let work_name = work_dir.file_name().unwrap_or_default();
+        let temp_dir = work_dir.join(DATA);
+        let discard_dir = work_dir.join(EXCLUDED_DIR);
+
+        if in_place {
+            journal.creating(work_dir);
+        } else {
+            journal.creating_tree(work_dir);
+        }
+        fs::create_dir(work_dir).context(IoCreateSnafu { path: work_dir })?;
+        fs::create_dir(&temp_dir).context(IoCreateSnafu { path: &temp_dir })?;
+
+        let mut excluded = Vec::new();
+
+        // The work directory and the lock are in the source when the destination is within it
+        let dst_in_src = dst_dir
+            .canonicalize()
+            .ok()
+            .zip(src_dir.canonicalize().ok())
+            .and_then(|(dst, src)| dst.strip_prefix(src).ok().map(Path::to_path_buf));
+        let is_bagr_file = |relative: &Path| {
+            dst_in_src.as_ref().is_some_and(|dst| {
+                relative.parent() == Some(dst.as_path())
+                    && (relative.file_name() == Some(work_name)
+                        || relative.file_name() == Some(OsStr::new(BAG_LOCK)))
+            })
+        };
+
+        // The destination is skipped in case it's within the source
+        let snapshot = self
+            .verify_source
+            .then(|| SourceSnapshot::capture(src_dir, |path| path == dst_dir))
+            .transpose()?;
+
+        let payload = match (tar, &self.payload_files) {
+            // Archived and listed files are written into the work directory's data directory
+            (Some(reader), _) => extract_payload_tar(
+                reader,
+                work_dir,
+                algorithms,
+                self.progress.as_ref(),
+                self.scanner.as_deref(),
+            )?,
+            (None, Some(files)) => {
+                let payload = DigestSpool::new();
+                for meta in add_payload_files(
+                    work_dir,
+                    files,
+                    algorithms,
+                    self.progress.as_ref(),
+                    self.scanner.as_deref(),
+                )? {
+                    payload.push(meta)?;
+                }
+                payload
+            }
+            (None, None) => move_into_dir(
+                !in_place,
+                src_dir,
+                &temp_dir,
+                &discard_dir,
+                algorithms,
+                (!self.include_hidden_files).then_some(self.hidden_file_policy),
+                &exclude_patterns,
+                &self.name_transforms,
+                pool,
+                self.progress.as_ref(),
+                self.scanner.as_deref(),
+                self.imported_digests.as_ref(),
+                self.io_retries,
+                journal,
+                &mut excluded,
+                skipped,
+                // Excludes the directory we're moving files into, the lock, and the ignore file
+                |f| {
+                    let is_bagignore =
+                        self.use_bagignore && f.depth() == 1 && f.file_name() == BAGIGNORE;
+                    !(is_bagignore || is_bagr_file(f.path().strip_prefix(src_dir).unwrap()))
+                },
+            )?,
+        };
+
+        let statement = match &snapshot {
+            Some(snapshot) => Some(verify_snapshot(snapshot, src_dir, dst_dir)?),
+            None => None,
+        };
+
+        let data_dir = dst_dir.join(DATA);
+        journal.rename(&temp_dir, &data_dir)?;
+
+        let bagignore = src_dir.join(BAGIGNORE);
+        if in_place && self.use_bagignore && bagignore.is_file() {
+            info!("Deleting {}", bagignore.display());
+            journal.rename(&bagignore, &work_dir.join(BAGIGNORE))?;
+        }
+
+        for algorithm in algorithms {
+            journal.creating(&dst_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt")));
+        }
+        let mut cache = if self.digest_cache {
+            journal.creating(&dst_dir.join(DIGEST_CACHE));
+            Some(DigestCacheWriter::create(dst_dir)?)
+        } else {
+            None
+        };
+
+        let payload_oxum =
+            write_payload_manifests(algorithms, payload, dst_dir, self.path_encoding, |meta| {
+                if self.warn_suspicious_files {
+                    warn_suspicious_file(dst_dir, meta)?;
+                }
+                match &mut cache {
+                    Some(cache) => cache.write(&meta.path, &meta.digests),
+                    None => Ok(()),
+                }
+            })?;
+
+        if let Some(cache) = cache {
+            cache.finish()?;
+        }
+
+        if self.exclusion_report {
+            journal.creating(&dst_dir.join(EXCLUSIONS_TXT));
+            write_exclusion_report(dst_dir, &mut excluded)?;
+        }
+
+        if let Some(statement) = statement {
+            let path = dst_dir.join(TRANSFER_CONSISTENCY_TXT);
+            journal.creating(&path);
+            write_tag_file(&statement, path, self.tag_line_width)?;
+        }
+
+        let declaration = BagDeclaration::new();
+        journal.creating(&dst_dir.join(BAGIT_TXT));
+        write_bag_declaration(&declaration, dst_dir)?;
+
+        bag_info.add_payload_oxum(payload_oxum)?;
+        if self.calculate_bag_size {
+            add_calculated_bag_size(&mut bag_info)?;
+        }
+
+        journal.creating(&dst_dir.join(BAG_INFO_TXT));
+        write_bag_info(&bag_info, dst_dir, self.tag_line_width)?;
+
+        for (path, contents) in tag_files {
+            let path = dst_dir.join(path);
+            journal.creating(&path);
+            write_file_contents(&path, &contents)?;
+        }
+
+        for algorithm in algorithms {
+            journal.creating(&dst_dir.join(format!("{TAG_MANIFEST_PREFIX}-{algorithm}.txt")));
+        }
+        update_tag_manifests_skipping(dst_dir, algorithms, self.path_encoding, pool, work_name)?;
+
+        Ok(Bag::new(
+            dst_dir,
+            declaration,
+            bag_info,
+            algorithms.to_vec(),
+        ))
+    }
+
+    /// Creates the bag in an object store, such as S3, instead of in the destination directory,
+    /// which is ignored. Each source file is uploaded to an object under `prefix`, eg.
+    /// `prefix/data/file.txt`, and is hashed as it's read to be uploaded, so the source is only
+    /// read once. The tag files are staged in a temporary directory and uploaded after the
+    /// payload, with the tag manifests last, so the bag is not complete until every object has
+    /// been written.
+    ///
+    /// Custom tag files, the exclusion report, source verification, suspicious file warnings, and
+    /// file scanners are not supported. It is an error if there's already a bag under the prefix.
+    pub fn create_in_store(self, store: &dyn ObjectStore, prefix: &str) -> Result<BagInfo> {
+        let src_dir = self.src_dir.as_path();
+        let mut bag_info = self.bag_info;
+
+        info!("Creating bag in {}", store.url(prefix.trim_matches('/')));
+
+        let unsupported = [
+            (
+                !self.tag_files.is_empty() || !self.tag_dirs.is_empty(),
+                "custom tag files",
+            ),
+            (self.payload_files.is_some(), "listed payload files"),
+            (self.exclusion_report, "exclusion reports"),
+            (self.verify_source, "source verification"),
+            (self.warn_suspicious_files, "suspicious file warnings"),
+            (self.scanner.is_some(), "file scanners"),
+            (self.digest_cache, "digest caches"),
+        ];
+        if let Some((_, feature)) = unsupported.iter().find(|(enabled, _)| *enabled) {
+            return Err(General {
+                message: format!(
+                    "Bags that are created in an object store do not support {feature}"
+                ),
+            });
+        }
+
+        add_default_tags(&mut bag_info, self.reproducible)?;
+
+        let algorithms = defaulted_algorithms(&self.algorithms);
+        check_weak_algorithms(&algorithms, self.weak_algorithm_policy)?;
+        let pool = thread_pool(self.threads)?;
+
+        let bagit_key = store_key(prefix, BAGIT_TXT);
+        if store.list(&bagit_key)?.iter().any(|o| o.key() == bagit_key) {
+            return Err(General {
+                message: format!("There is already a bag at {}", store.url(&bagit_key)),
+            });
+        }
+
+        let exclude_patterns =
+            with_bagignore_patterns(src_dir, self.use_bagignore, self.exclude_patterns)?;
+        let (files, _, _) = walk_source(
+            true,
+            src_dir,
+            (!self.include_hidden_files).then_some(self.hidden_file_policy),
+            &exclude_patterns,
+            &self.name_transforms,
+            &mut Vec::new(),
+            None,
+            |f| !(self.use_bagignore && f.depth() == 1 && f.file_name() == BAGIGNORE),
+        )?;
+
+        let progress = self.progress.as_ref();
+        progress.hashing_started(
+            files.len() as u64,
+            files.iter().map(|(_, _, size_bytes)| size_bytes).sum(),
+        );
+
+        let payload = DigestSpool::new();
+        pool.install(|| {
+            files
+                .par_iter()
+                .try_for_each(|(path, relative, size_bytes)| {
+                    let key = object_key(prefix, &Path::new(DATA).join(relative))?;
+                    info!("Uploading {} to {}", path.display(), store.url(&key));
+                    progress.file_started(path);
+
+                    let file = File::open(path).context(IoReadSnafu { path })?;
+                    let mut reader = MultiDigestReader::new(
+                        &algorithms,
+                        ProgressReader::new(file, path, progress),
+                    );
+                    store.put(&key, &mut reader, *size_bytes)?;
+
+                    progress.file_finished(path);
+                    payload.push(FileMeta {
+                        path: Path::new(DATA).join(relative),
+                        size_bytes: *size_bytes,
+                        digests: reader.finalize_hex(),
+                    })
+                })
+        })?;
+
+        bag_info.add_payload_oxum(payload.payload_oxum())?;
+        if self.calculate_bag_size {
+            add_calculated_bag_size(&mut bag_info)?;
+        }
+
+        // The directory is created with a unique name that only the current user can access
+        let staging_dir = tempfile::Builder::new()
+            .prefix("bagr-staging-")
+            .tempdir()
+            .context(IoCreateSnafu {
+                path: std::env::temp_dir(),
+            })?
+            .keep();
+
+        let written = write_payload_manifests(
+            &algorithms,
+            payload,
+            &staging_dir,
+            self.path_encoding,
+            |_| Ok(()),
+        )
+        .and_then(|_| write_bag_declaration(&BagDeclaration::new(), &staging_dir))
+        .and_then(|_| write_bag_info(&bag_info, &staging_dir, self.tag_line_width))
+        .and_then(|_| update_tag_manifests(&staging_dir, &algorithms, self.path_encoding, &pool))
+        .and_then(|_| upload_tag_files(&staging_dir, store, prefix));
+
+        info!("Deleting staging directory {}", staging_dir.display());
+        let _ = fs::remove_dir_all(&staging_dir);
+
+        written.map(|_| bag_info)
+    }
+}
+
+impl BagWriter {
+    /// Starts writing a new bag in `base_dir` that uses the specified digest `algorithms`. If no
+    /// algorithms are provided, then `sha512` is used. The directory is created if it does not
+    /// exist, but it must not already contain a payload directory.
+    pub fn create<P: AsRef<Path>>(base_dir: P, algorithms: &[DigestAlgorithm]) -> Result<Self> {
+        let base_dir = base_dir.as_ref();
+        let data_dir = base_dir.join(DATA);
+
+        info!("Writing bag in {}", base_dir.display());
+
+        fs::create_dir_all(base_dir).context(IoCreateSnafu { path: base_dir })?;
+        fs::create_dir(&data_dir).context(IoCreateSnafu { path: &data_dir })?;
+
+        Ok(Self {
+            base_dir: base_dir.into(),
+            bag_info: BagInfo::new(),
+            algorithms: defaulted_algorithms(algorithms),
+            path_encoding: PathEncoding::Standard,
+            tag_line_width: Some(DEFAULT_TAG_LINE_WIDTH),
+            calculate_bag_size: false,
+            payload_paths: HashSet::new(),
+            payload: DigestSpool::new(),
+        })
+    }
+
+    /// Sets the tags to write to bag-info.txt. Bagging-Date and Bag-Software-Agent are added if
+    /// they are not set, and Payload-Oxum is always calculated.
+    pub fn with_bag_info(mut self, bag_info: BagInfo) -> Self {
+        self.bag_info = bag_info;
+        self
+    }
+
+    /// Sets the length that lines in bag-info.txt are folded at. Long values are wrapped onto
+    /// indented continuation lines. The default is 79 characters, and `None` disables folding.
+    pub fn with_tag_line_width(mut self, tag_line_width: Option<usize>) -> Self {
+        self.tag_line_width = tag_line_width;
+        self
+    }
+
+    /// When true, the Bag-Size in bag-info.txt is set to the human readable size of the payload,
+    /// eg. `42.6 MB`, replacing any existing value. The default is false.
+    pub fn with_calculate_bag_size(mut self, calculate_bag_size: bool) -> Self {
+        self.calculate_bag_size = calculate_bag_size;
+        self
+    }
+
+    /// Sets the profile that's used to percent encode file paths in manifests. The default,
+    /// `PathEncoding::Standard`, only encodes the characters the BagIt spec requires.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Writes the contents of `reader` to a payload file at `path`, which is relative to the
+    /// payload directory, and returns the number of bytes that were written. The file's digests
+    /// are calculated while it's written.
+    pub fn add_payload_file<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        mut reader: R,
+    ) -> Result<u64> {
+        let relative = Path::new(DATA).join(validate_payload_path(path.as_ref())?);
+
+        if self.payload_paths.contains(&relative) {
+            return Err(InvalidPayloadPath {
+                path: path.as_ref().into(),
+                details: "A file was already written to this path".into(),
+            });
+        }
+
+        let file_path = self.base_dir.join(&relative);
+        info!("Writing {}", file_path.display());
+
+        let (size_bytes, digests) =
+            write_file(&file_path, &mut reader, &self.algorithms, &NoProgress)?;
+
+        self.payload_paths.insert(relative.clone());
+        self.payload.push(FileMeta {
+            path: relative,
+            size_bytes,
+            digests,
+        })?;
+
+        Ok(size_bytes)
+    }
+
+    /// Adds a payload file at `path`, which is relative to the payload directory, that was
+    /// already written to the payload directory by the caller, using `digests` that the caller
+    /// calculated, eg. with a `MultiDigestWriter`, instead of reading the file again. There must
+    /// be a digest for each of the bag's algorithms; other digests are ignored. Returns the size
+    /// of the file.
+    pub fn add_hashed_payload_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        digests: HashMap<DigestAlgorithm, HexDigest>,
+    ) -> Result<u64> {
+        let relative = Path::new(DATA).join(validate_payload_path(path.as_ref())?);
+
+        if self.payload_paths.contains(&relative) {
+            return Err(InvalidPayloadPath {
+                path: path.as_ref().into(),
+                details: "A file was already written to this path".into(),
+            });
+        }
+        if let Some(missing) = self
+            .algorithms
+            .iter()
+            .find(|algorithm| !digests.contains_key(algorithm))
+        {
+            return Err(InvalidPayloadPath {
+                path: path.as_ref().into(),
+                details: format!("Missing its {missing} digest"),
+            });
+        }
+
+        let file_path = self.base_dir.join(&relative);
+        let metadata = fs::metadata(&file_path).context(IoStatSnafu { path: &file_path })?;
+        if !metadata.is_file() {
+            return Err(UnsupportedFile { path: file_path });
+        }
+
+        self.payload_paths.insert(relative.clone());
+        self.payload.push(FileMeta {
+            path: relative,
+            size_bytes: metadata.len(),
+            digests: digests
+                .into_iter()
+                .filter(|(algorithm, _)| self.algorithms.contains(algorithm))
+                .collect(),
+        })?;
+
+        Ok(metadata.len())
+    }
+
+    /// The bag's payload directory, which files that are added with `add_hashed_payload_file()`
+    /// must be written to
+    pub fn payload_dir(&self) -> PathBuf {
+        self.base_dir.join(DATA)
+    }
+
+    /// The digest algorithms the bag's manifests are written with
+    pub fn algorithms(&self) -> &[DigestAlgorithm] {
+        &self.algorithms
+    }
+
+    /// Finishes the bag by writing all of the necessary tag files and manifests
+    pub fn finish(mut self) -> Result<Bag> {
+        let base_dir = self.base_dir.as_path();
+
+        let payload_oxum = write_payload_manifests(
+            &self.algorithms,
+            std::mem::take(&mut self.payload),
+            base_dir,
+            self.path_encoding,
+            |_| Ok(()),
+        )?;
+
+        let declaration = BagDeclaration::new();
+        write_bag_declaration(&declaration, base_dir)?;
+
+        if self.bag_info.bagging_date().is_none() {
+            self.bag_info.add_bagging_date(current_date_str())?;
+        }
+        if self.bag_info.software_agent().is_none() {
+            self.bag_info.add_software_agent(bagr_software_agent())?;
+        }
+
+        self.bag_info.add_payload_oxum(payload_oxum)?;
+        if self.calculate_bag_size {
+            add_calculated_bag_size(&mut self.bag_info)?;
+        }
+
+        write_bag_info(&self.bag_info, base_dir, self.tag_line_width)?;
+
+        // Tag files are small, so there's no benefit to hashing them concurrently
+        update_tag_manifests(
+            base_dir,
+            &self.algorithms,
+            self.path_encoding,
+            &thread_pool(1)?,
+        )?;
+
+        Ok(Bag::new(
+            base_dir,
+            declaration,
+            self.bag_info,
+            self.algorithms,
+        ))
+    }
+}
+
+impl BagUpdater {
+    pub fn new(bag: Bag) -> Self {
+        Self {
+            bag,
+            recalculate_payload_manifests: None,
+            algorithms: Vec::new(),
+            bagging_date: None,
+            software_agent: None,
+            threads: 0,
+            io_retries: IoRetries::NONE,
+            incremental: false,
+            detect_renames: false,
+            prune_missing: false,
+            force: false,
+            digest_cache: false,
+            path_encoding: PathEncoding::Standard,
+            tag_line_width: Some(DEFAULT_TAG_LINE_WIDTH),
+            calculate_bag_size: false,
+            weak_algorithm_policy: WeakAlgorithmPolicy::default(),
+            progress: Arc::new(NoProgress),
+            scanner: None,
+            additions: Vec::new(),
+            removals: Vec::new(),
+            added_algorithms: Vec::new(),
+        }
+    }
+
+    /// Adds a digest algorithm to use for calculating manifests
+    pub fn with_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithms.push(algorithm);
+        self
+    }
+
+    /// Copies the file at `src` into the bag's payload at `path`, which is relative to the payload
+    /// directory, on `finalize()`. It is an error if a payload file already exists at the path.
+    ///
+    /// Unless payload manifest recalculation is explicitly enabled, only the added files are
+    /// hashed. Their digests are appended to the existing payload manifests and Payload-Oxum is
+    /// updated accordingly.
+    pub fn add_file<S: AsRef<Path>, P: AsRef<Path>>(mut self, src: S, path: P) -> Self {
+        self.additions
+            .push((src.as_ref().into(), path.as_ref().into()));
+        self
+    }
+
+    /// Deletes the payload file at `path`, which is relative to the payload directory, on
+    /// `finalize()`. It is an error if the file does not exist. Files are removed before any
+    /// files are added, so a file can be replaced by removing it and then adding it again.
+    ///
+    /// Unless payload manifest recalculation is explicitly enabled, the payload is not rehashed.
+    /// The file's entries are removed from the existing payload manifests and Payload-Oxum is
+    /// updated accordingly.
+    pub fn remove_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.removals.push(path.as_ref().into());
+        self
+    }
+
+    /// Sets the algorithms to use when calculating manifests. An empty slice will result in
+    /// the algorithms that were used to calculate the existing manifests to be used.
+    pub fn with_algorithms(mut self, algorithms: &[DigestAlgorithm]) -> Self {
+        self.algorithms.clear();
+        self.algorithms.extend_from_slice(algorithms);
+        self
+    }
+
+    /// Writes a payload manifest for `algorithm` on `finalize()`, in addition to the existing
+    /// payload manifests. Nothing is done if the bag already has a manifest for the algorithm.
+    ///
+    /// Unless payload manifest recalculation is explicitly enabled, the payload is only hashed
+    /// with the added algorithms and the existing payload manifests are left untouched. The new
+    /// manifests list the same files as the existing ones, and it's an error if any of them no
+    /// longer exist.
+    pub fn add_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.added_algorithms.push(algorithm);
+        self
+    }
+
+    /// Sets the Bagging-Date to add to bag-info.txt. None for the default value.
+    pub fn with_bagging_date(mut self, bagging_date: Option<String>) -> Self {
+        self.bagging_date = bagging_date;
+        self
+    }
+
+    /// Sets the Bag-Software-Agent to add to bag-info.txt. None for the default value.
+    pub fn with_software_agent(mut self, software_agent: Option<String>) -> Self {
+        self.software_agent = software_agent;
+        self
+    }
+
+    /// Sets the maximum number of threads to use when calculating digests. The default, 0, uses
+    /// one thread per CPU.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the number of times that hashing a payload file is retried when it fails with a
+    /// transient IO error, such as a timeout or a stale file handle. The default is 0.
+    pub fn with_io_retries(mut self, io_retries: u32) -> Self {
+        self.io_retries.max_retries = io_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry of a file that failed with a transient IO error. The
+    /// delay doubles after every retry, up to a minute. The default is 1 second.
+    pub fn with_io_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.io_retries.delay = retry_delay;
+        self
+    }
+
+    /// Sets how weak digest algorithms, md5 and sha1, are treated when they're used to calculate
+    /// manifests. By default, a warning is logged.
+    pub fn with_weak_algorithm_policy(mut self, policy: WeakAlgorithmPolicy) -> Self {
+        self.weak_algorithm_policy = policy;
+        self
+    }
+
+    /// Sets the length that lines in bag-info.txt are folded at. Long values are wrapped onto
+    /// indented continuation lines. The default is 79 characters, and `None` disables folding.
+    pub fn with_tag_line_width(mut self, tag_line_width: Option<usize>) -> Self {
+        self.tag_line_width = tag_line_width;
+        self
+    }
+
+    /// When true, the Bag-Size in bag-info.txt is set to the human readable size of the payload,
+    /// eg. `42.6 MB`, replacing any existing value. The default is false.
+    pub fn with_calculate_bag_size(mut self, calculate_bag_size: bool) -> Self {
+        self.calculate_bag_size = calculate_bag_size;
+        self
+    }
+
+    /// Sets the profile that's used to percent encode file paths in manifests. The default,
+    /// `PathEncoding::Standard`, only encodes the characters the BagIt spec requires.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Sets the listener that receives progress updates while payload files are hashed
+    pub fn with_progress_listener(mut self, progress: Arc<dyn ProgressListener>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Sets the scanner that screens payload files, eg. for viruses, as they're read to
+    /// calculate their digests. This includes added files, and all payload files when the
+    /// payload manifests are recalculated, except those whose digests are reused. Rejected files
+    /// fail the update. By default, files are not scanned.
+    pub fn with_file_scanner(mut self, scanner: Option<Arc<dyn FileScanner>>) -> Self {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Enables/disables incremental payload manifest recalculation on `finalize()`. This is
+    /// disabled by default. When enabled, the digests in the existing payload manifests are reused
+    /// for files whose size and modification time are unchanged since they were last hashed, and
+    /// only new or modified files are hashed.
+    ///
+    /// Manifests do not record file sizes or modification times, so they're recorded in the
+    /// digest cache, see `digest_cache()`, which is written by every incremental update. Every file is
+    /// hashed by the first incremental update of a bag without a digest cache. This should not be
+    /// used if the payload may have been changed in a way that preserves sizes and modification
+    /// times.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Enables/disables rename detection on `finalize()`. This is disabled by default. When
+    /// enabled, payload files that are not in the payload manifests are hashed first and matched
+    /// to manifest entries whose files no longer exist. Matches are treated as renames, and their
+    /// digests are carried over to the new paths rather than being hashed again.
+    ///
+    /// Renames are only detected when the payload manifests are recalculated using the bag's
+    /// existing algorithms.
+    pub fn detect_renames(mut self, detect_renames: bool) -> Self {
+        self.detect_renames = detect_renames;
+        self
+    }
+
+    /// Enables/disables pruning of missing payload files on `finalize()`. This is disabled by
+    /// default, in which case it's an error to recalculate the payload manifests when files that
+    /// are listed in them no longer exist, so that deleted files are not dropped from the bag
+    /// unnoticed. When enabled, the missing files are removed from the manifests, and each one is
+    /// logged and recorded in the bag's history.txt.
+    ///
+    /// Files that are matched to a rename by `detect_renames()` are not missing.
+    pub fn prune_missing(mut self, prune_missing: bool) -> Self {
+        self.prune_missing = prune_missing;
+        self
+    }
+
+    /// Sets whether the bag is updated even though another bagr process holds its lock. The bag
+    /// is locked while it's updated, and, by default, it's an error if it's already locked. Only
+    /// enable this when the lock was left behind by a process that did not exit cleanly.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Enables/disables the bag's digest cache, see `BagCreator::digest_cache()`, on
+    /// `finalize()`. This is disabled by default. When enabled and the payload manifests are
+    /// recalculated, the cached digests are reused for files whose size and modification time
+    /// have not changed since they were cached, and the cache is then rewritten with the new
+    /// digests. The cache is not changed when the payload manifests are not recalculated.
+    pub fn digest_cache(mut self, digest_cache: bool) -> Self {
+        self.digest_cache = digest_cache;
+        self
+    }
+
+    /// Enables/disables payload manifest recalculation on `finalize()`. This is enabled by default,
+    /// unless files were added or removed using `add_file()` or `remove_file()`, or algorithms
+    /// were added using `add_algorithm()`, but can be
+    /// disabled if the digest algorithms in use have not changed and there were no changes to
+    /// the payload.
+    pub fn recalculate_payload_manifests(mut self, recalculate: bool) -> Self {
+        self.recalculate_payload_manifests = Some(recalculate);
+        self
+    }
+
+    /// Writes the changes to disk and recalculates manifests. Bags with tag files in an encoding
+    /// other than UTF-8 are converted to UTF-8.
+    pub fn finalize(mut self) -> Result<Bag> {
+        let stats = Arc::new(StatsListener::new(self.progress.clone()));
+        self.progress = stats.clone();
+
+        let base_dir = &self.bag.base_dir;
+        let _lock = BagLock::acquire(base_dir, self.force)?;
+        let pool = thread_pool(self.threads)?;
+        let recalculate = self.recalculate_payload_manifests.unwrap_or(
+            self.additions.is_empty()
+                && self.removals.is_empty()
+                && self.added_algorithms.is_empty(),
+        );
+
+        let mut algorithms = if !recalculate || self.algorithms.is_empty() {
+            // must reuse same algorithms if payload manifests are not recalculated
+            self.bag.algorithms.clone()
+        } else {
+            self.algorithms.sort();
+            self.algorithms.dedup();
+            // Only newly requested algorithms are checked, so existing bags can still be updated
+            check_weak_algorithms(&self.algorithms, self.weak_algorithm_policy)?;
+            self.algorithms.clone()
+        };
+
+        self.added_algorithms.sort();
+        self.added_algorithms.dedup();
+        self.added_algorithms
+            .retain(|algorithm| !algorithms.contains(algorithm));
+        check_weak_algorithms(&self.added_algorithms, self.weak_algorithm_policy)?;
+        algorithms.extend_from_slice(&self.added_algorithms);
+        // Manifests are only written for the added algorithms when the payload is not rehashed
+        let adding_manifests = !recalculate && !self.added_algorithms.is_empty();
+
+        // Missing files are found before anything is changed, so that the bag is left untouched
+        // if they are not pruned
+        let unlisted = if recalculate && self.detect_renames && algorithms == self.bag.algorithms {
+            find_renames(
+                base_dir,
+                &algorithms,
+                self.path_encoding,
+                &pool,
+                self.scanner.as_deref(),
+            )?
+        } else {
+            Vec::new()
+        };
+        let missing = if recalculate || adding_manifests {
+            find_missing_files(
+                base_dir,
+                &self.bag.algorithms,
+                self.path_encoding,
+                &unlisted,
+            )?
+        } else {
+            Vec::new()
+        };
+        if !missing.is_empty() && (!self.prune_missing || !recalculate) {
+            return Err(MissingPayloadFiles { paths: missing });
+        }
+
+        let bagging_date = self.bagging_date.unwrap_or_else(current_date_str);
+        self.bag.bag_info.add_bagging_date(bagging_date.clone())?;
+        self.bag
+            .bag_info
+            .add_software_agent(self.software_agent.unwrap_or_else(bagr_software_agent))?;
+
+        if !self.bag.declaration.is_utf8() {
+            self.bag.declaration = convert_to_utf8(base_dir, &self.bag.declaration)?;
+        }
+        strip_byte_order_marks(base_dir)?;
+
+        let removed = remove_payload_files(base_dir, &self.removals)?;
+        let mut added = add_payload_files(
+            base_dir,
+            &self.additions,
+            &self.bag.algorithms,
+            self.progress.as_ref(),
+            self.scanner.as_deref(),
+        )?;
+
+        if recalculate {
+            let mut known = if self.incremental {
+                load_known_digests(base_dir, &algorithms, self.path_encoding)?
+            } else {
+                None
+            };
+
+            for file in unlisted {
+                if let Some(from) = &file.renamed_from {
+                    info!(
+                        "Detected that {} was renamed to {}",
+                        Path::new(DATA).join(from).display(),
+                        Path::new(DATA).join(&file.path).display()
+                    );
+                }
+                // Unlisted files were just hashed, so they do not need to be hashed again
+                known
+                    .get_or_insert_with(KnownDigests::empty)
+                    .verified
+                    .insert(file.path, file.digests);
+            }
+
+            if self.digest_cache {
+                let cache = DigestCache::load(base_dir)?;
+                let verified = &mut known.get_or_insert_with(KnownDigests::empty).verified;
+                for (path, digests) in cache.unchanged(base_dir, &algorithms) {
+                    if let Ok(relative) = path.strip_prefix(DATA) {
+                        verified
+                            .entry(relative.into())
+                            .or_insert_with(|| digests.clone());
+                    }
+                }
+            }
+
+            if !missing.is_empty() {
+                for path in &missing {
+                    warn!("Pruned missing file {path} from the payload manifests");
+                }
+                record_history(base_dir, &bagging_date, "pruned", &missing)?;
+            }
+
+            delete_payload_manifests(base_dir)?;
+            let payload_oxum = update_payload_manifests(
+                base_dir,
+                &algorithms,
+                known.as_ref(),
+                self.path_encoding,
+                &pool,
+                self.progress.as_ref(),
+                self.scanner.as_deref(),
+                self.io_retries,
+                // Incremental updates record the size and modification time of every file
+                self.digest_cache || self.incremental,
+            )?;
+            self.bag.bag_info.add_payload_oxum(payload_oxum)?;
+        } else if !added.is_empty() || !removed.is_empty() {
+            if !removed.is_empty() {
+                strip_payload_manifests(base_dir, &removed, self.path_encoding)?;
+            }
+            if !added.is_empty() {
+                append_payload_manifests(
+                    base_dir,
+                    &self.bag.algorithms,
+                    &mut added,
+                    self.path_encoding,
+                )?;
+            }
+
+            adjust_payload_oxum(base_dir, &mut self.bag.bag_info, &added, &removed)?;
+        }
+
+        if adding_manifests {
+            add_payload_manifests(
+                base_dir,
+                &self.bag.algorithms,
+                &self.added_algorithms,
+                self.path_encoding,
+                &pool,
+                self.progress.as_ref(),
+                self.scanner.as_deref(),
+                self.io_retries,
+            )?;
+        }
+
+        if self.calculate_bag_size {
+            add_calculated_bag_size(&mut self.bag.bag_info)?;
+        }
+
+        write_bag_info(&self.bag.bag_info, base_dir, self.tag_line_width)?;
+
+        delete_tag_manifests(base_dir)?;
+        update_tag_manifests(base_dir, &algorithms, self.path_encoding, &pool)?;
+
+        self.bag.algorithms = algorithms;
+        self.bag.stats = Some(stats.stats(self.bag.bag_info.payload_oxum()));
+        Ok(self.bag)
+    }
+}
+
+/// Copies/moves the contents of the `src_dir` into the `dst_dir` and returns a spool of meta about
+/// all of the moved files, whose paths are prefixed with `data/`. If `copy_op` is true the files are copied, otherwise they're moved. Files are
+/// processed concurrently using the threads in `pool`, hashing progress is reported to
+/// `progress`, and files are screened by `scanner` as they're hashed. Hidden files that are not
+/// included, and files that match the `exclude` patterns, are deleted when they're moved, and are
+/// recorded in `excluded`. Files are renamed using `transforms`. Files that cannot be read are
+/// recorded in `skipped`, and `excluded`, instead of failing, if `skipped` is specified.
+#[allow(clippy::too_many_arguments)]
+fn move_into_dir<S, D, P>(
+    copy_op: bool,
+    src_dir: S,
+    dst_dir: D,
+    discard_dir: &Path,
+    algorithms: &[DigestAlgorithm],
+    exclude_hidden: Option<HiddenFilePolicy>,
+    exclude: &ExcludePatterns,
+    transforms: &NameTransforms,
+    pool: &ThreadPool,
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+    imported: Option<&ImportedDigests>,
+    retries: IoRetries,
+    journal: &Journal,
+    excluded: &mut Vec<Exclusion>,
+    mut skipped: Option<&mut Vec<SkippedFile>>,
+    predicate: P,
+) -> Result<DigestSpool>
+where
+    S: AsRef<Path>,
+    D: AsRef<Path>,
+    P: FnMut(&DirEntry) -> bool,
+{
+    let src_dir = src_dir.as_ref();
+    let dst_dir = dst_dir.as_ref();
+
+    let (files, dirs, discarded) = walk_source(
+        copy_op,
+        src_dir,
+        exclude_hidden,
+        exclude,
+        transforms,
+        excluded,
+        skipped.as_deref_mut(),
+        predicate,
+    )?;
+
+    // Excluded files are only deleted once the bag is complete, so that they can be restored
+    for path in discarded {
+        let discard_path = discard_dir.join(path.strip_prefix(src_dir).unwrap());
+        fs::create_dir_all(discard_path.parent().unwrap()).context(IoCreateSnafu {
+            path: &discard_path,
+        })?;
+        journal.rename(&path, &discard_path)?;
+    }
+
+    progress.hashing_started(
+        files.len() as u64,
+        files.iter().map(|(_, _, size_bytes)| size_bytes).sum(),
+    );
+
+    // Imported digests are keyed on canonical paths
+    let canonical_src_dir = match imported {
+        Some(_) => fs::canonicalize(src_dir).context(IoStatSnafu { path: src_dir })?,
+        None => PathBuf::new(),
+    };
+
+    let skip_unreadable = skipped.is_some();
+    let unreadable = Mutex::new(Vec::new());
+
+    let file_meta = DigestSpool::new();
+    pool.install(|| {
+        files
+            .par_iter()
+            .try_for_each(|(path, relative, size_bytes)| {
+                let file_dst = dst_dir.join(relative);
+                let digests = retries.run(path, || match imported {
+                    Some(imported) => digest_or_import_file(
+                        path,
+                        &canonical_src_dir.join(path.strip_prefix(src_dir).unwrap()),
+                        *size_bytes,
+                        algorithms,
+                        imported,
+                        progress,
+                        scanner,
+                    ),
+                    // Copies are hashed as they're written, so that the digests match the copies
+                    // even if the source files change
+                    None if copy_op => {
+                        copy_and_digest_file(path, &file_dst, algorithms, progress, scanner)
+                    }
+                    None => digest_file(path, algorithms, progress, scanner),
+                });
+                let digests = match digests {
+                    Ok(digests) => digests,
+                    Err(e @ IoRead { .. }) if skip_unreadable => {
+                        unreadable.lock().unwrap().push((path.clone(), e));
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                fs::create_dir_all(file_dst.parent().unwrap())
+                    .context(IoCreateSnafu { path: &file_dst })?;
+
+                if !copy_op {
+                    journal.rename(path, &file_dst)?;
+                } else if imported.is_some() {
+                    retries.run(path, || copy(path, &file_dst))?;
+                }
+
+                file_meta.push(FileMeta {
+                    path: Path::new(DATA).join(relative),
+                    size_bytes: *size_bytes,
+                    digests,
+                })
+            })
+    })?;
+
+    if let Some(skipped) = skipped {
+        for (path, error) in unreadable.into_inner().unwrap() {
+            skip_unreadable_file(&path, false, error, src_dir, skipped, excluded);
+        }
+    }
+
+    // Delete any dangling directories left after moving out all of the files
+    for dir in dirs {
+        if dir == src_dir {
+            continue;
+        }
+        if let Err(e) = journal.remove_dir_all(&dir) {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(IoDelete {
+                    path: dir,
+                    source: e,
+                });
+            }
+        }
+    }
+
+    Ok(file_meta)
+}
+
+/// Walks `src_dir` and returns the source path, renamed relative path, and size of every file to
+/// include in the payload, and, when the files are moved rather than copied, the directories to
+/// delete afterwards and the excluded files and directories to delete. Files that are not
+/// included are recorded in `excluded`. Hidden files are only excluded when `exclude_hidden` is
+/// the policy that recognizes them. Files and directories that cannot be read are recorded in
+/// `skipped`, and `excluded`, instead of failing, if `skipped` is specified.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn walk_source<P>(
+    copy_op: bool,
+    src_dir: &Path,
+    exclude_hidden: Option<HiddenFilePolicy>,
+    exclude: &ExcludePatterns,
+    transforms: &NameTransforms,
+    excluded: &mut Vec<Exclusion>,
+    mut skipped: Option<&mut Vec<SkippedFile>>,
+    predicate: P,
+) -> Result<(Vec<(PathBuf, PathBuf, u64)>, Vec<PathBuf>, Vec<PathBuf>)>
+where
+    P: FnMut(&DirEntry) -> bool,
+{
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    let mut discarded = Vec::new();
+    // The source path of every file, keyed on its renamed path
+    let mut renamed: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    let mut walker = WalkDir::new(src_dir).into_iter().filter_entry(predicate);
+
+    while let Some(file) = walker.next() {
+        let file = match (file, skipped.as_deref_mut()) {
+            (Ok(file), _) => file,
+            (Err(e), Some(skipped)) if e.io_error().is_some() && e.path().is_some() => {
+                let path = e.path().unwrap().to_path_buf();
+                let is_dir = path.is_dir();
+                let error = WalkFile { source: e };
+                skip_unreadable_file(&path, is_dir, error, src_dir, skipped, excluded);
+                continue;
+            }
+            (Err(e), _) => return Err(WalkFile { source: e }),
+        };
+
+        let is_dir = file.file_type().is_dir();
+        let reason = if file.depth() == 0 {
+            None
+        } else if exclude_hidden.is_some_and(|policy| policy.is_hidden(file.path())) {
+            Some(ExclusionReason::Hidden)
+        } else if exclude.is_excluded(file.path().strip_prefix(src_dir).unwrap(), is_dir) {
+            Some(ExclusionReason::Pattern)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            excluded.push(Exclusion {
+                path: Path::new(DATA).join(file.path().strip_prefix(src_dir).unwrap()),
+                is_dir,
+                reason,
+            });
+
+            if is_dir {
+                walker.skip_current_dir();
+            }
+
+            if copy_op {
+                info!("Skipping {reason} file {}", file.path().display());
+            } else {
+                let kind = if is_dir { "directory" } else { "file" };
+                info!("Excluding {reason} {kind} {}", file.path().display());
+                discarded.push(file.into_path());
+            }
+            continue;
+        }
+
+        if file.file_type().is_file() {
+            let metadata = file.metadata().context(WalkFileSnafu {})?;
+            let relative = file.path().strip_prefix(src_dir).unwrap();
+            let dst_relative = transforms.apply_path(relative)?;
+
+            if let Some(other) = renamed.insert(dst_relative.clone(), relative.to_path_buf()) {
+                return Err(InvalidPayloadPath {
+                    path: relative.to_path_buf(),
+                    details: format!(
+                        "It would be renamed to {}, which is also the new name of {}",
+                        dst_relative.display(),
+                        other.display()
+                    ),
+                });
+            }
+
+            files.push((file.into_path(), dst_relative, metadata.len()));
+        } else if file.file_type().is_dir() {
+            if !copy_op {
+                dirs.push(file.path().to_path_buf());
+            }
+        } else {
+            return Err(UnsupportedFile {
+                path: file.path().to_path_buf(),
+            });
+        }
+    }
+
+    Ok((files, dirs, discarded))
+}
+
+/// Records the source file or directory at `path`, which could not be read because of `error`,
+/// in `skipped` and `excluded`
+fn skip_unreadable_file(
+    path: &Path,
+    is_dir: bool,
+    error: Error,
+    src_dir: &Path,
+    skipped: &mut Vec<SkippedFile>,
+    excluded: &mut Vec<Exclusion>,
+) {
+    let path = Path::new(DATA).join(path.strip_prefix(src_dir).unwrap_or(path));
+    excluded.push(Exclusion {
+        path: path.clone(),
+        is_dir,
+        reason: ExclusionReason::Unreadable,
+    });
+    skipped.push(SkippedFile { path, error });
+}
+
+/// Adds the patterns in the `.bagignore` file at the root of `src_dir`, if there is one and
+/// `use_bagignore` is true, to the `exclude` patterns
+fn with_bagignore_patterns(
+    src_dir: &Path,
+    use_bagignore: bool,
+    exclude: ExcludePatterns,
+) -> Result<ExcludePatterns> {
+    let bagignore = src_dir.join(BAGIGNORE);
+    if use_bagignore && bagignore.is_file() {
+        // Patterns that are passed explicitly take precedence over the ignore file's
+        let mut patterns = ExcludePatterns::read_ignore_file(&bagignore)?;
+        patterns.extend_from_slice(exclude.patterns());
+        ExcludePatterns::new(&patterns)
+    } else {
+        Ok(exclude)
+    }
+}
+
+/// Returns the key of the object that the file at `path`, which is relative to the bag's base
+/// directory, is stored at in an object store
+fn object_key(prefix: &str, path: &Path) -> Result<String> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| InvalidUtf8Path { path: path.into() })?;
+    Ok(store_key(prefix, &convert_path_separator(path)))
+}
+
+/// Uploads the tag files that were staged in `staging_dir` to an object store. The tag manifests
+/// are uploaded last.
+fn upload_tag_files(staging_dir: &Path, store: &dyn ObjectStore, prefix: &str) -> Result<()> {
+    let mut files = Vec::new();
+    for file in fs::read_dir(staging_dir).context(IoReadDirSnafu { path: staging_dir })? {
+        let file = file.context(IoReadDirSnafu { path: staging_dir })?;
+        files.push(file.path());
+    }
+
+    files.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        (TAG_MANIFEST_MATCHER.is_match(&name), path.clone())
+    });
+
+    for path in files {
+        let key = object_key(prefix, path.strip_prefix(staging_dir).unwrap())?;
+        let size = fs::metadata(&path)
+            .context(IoReadSnafu { path: &path })?
+            .len();
+        let mut file = File::open(&path).context(IoReadSnafu { path: &path })?;
+        store.put(&key, &mut file, size)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of the files directly in `base_dir` that have the names of tag files bagr
+/// writes
+fn find_bag_artifacts(base_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut artifacts = Vec::new();
+
+    for file in fs::read_dir(base_dir).context(IoReadDirSnafu { path: base_dir })? {
+        let file = file.context(IoReadDirSnafu { path: base_dir })?;
+        let is_file = file
+            .file_type()
+            .context(IoStatSnafu { path: file.path() })?
+            .is_file();
+        let name = file.file_name();
+        let name = name.to_string_lossy();
+
+        if is_file && is_reserved_tag_file(&name) {
+            artifacts.push(file.path());
+        }
+    }
+
+    artifacts.sort();
+    Ok(artifacts)
+}
+
+/// Checks if `base_dir` contains manifests from a previous bag. If it does, then it is an error
+/// unless `force` is true, in which case all of the tag files from the previous bag are deleted.
+/// Files that bagr does not write are never touched.
+fn remove_existing_artifacts(base_dir: &Path, force: bool) -> Result<()> {
+    let artifacts = find_bag_artifacts(base_dir)?;
+
+    let has_manifest = artifacts.iter().any(|path| {
+        path.file_name()
+            .is_some_and(|name| is_manifest_name(&name.to_string_lossy()))
+    });
+
+    if !has_manifest {
+        return Ok(());
+    }
+
+    if !force {
+        return Err(ExistingBag {
+            path: base_dir.into(),
+        });
+    }
+
+    for path in artifacts {
+        info!("Deleting existing bag file {}", path.display());
+        fs::remove_file(&path).context(IoDeleteSnafu { path: &path })?;
+    }
+
+    Ok(())
+}
+
+pub fn is_manifest_name(name: &str) -> bool {
+    PAYLOAD_MANIFEST_MATCHER.is_match(name) || TAG_MANIFEST_MATCHER.is_match(name)
+}
+
+/// Returns true if `name` is the name of a tag file in the bag's base directory that's written
+/// by bagr
+fn is_reserved_tag_file(name: &str) -> bool {
+    name == BAGIT_TXT
+        || name == BAG_INFO_TXT
+        || name == FETCH_TXT
+        || name == EXCLUSIONS_TXT
+        || name == TRANSFER_CONSISTENCY_TXT
+        || name == HISTORY_TXT
+        || name == BAG_LOCK
+        || name == DIGEST_CACHE
+        || name == AUDIT_STATE
+        || is_manifest_name(name)
+}
+
+/// Returns the sources and destinations of the custom tag files, including every file in the tag
+/// directories
+fn list_tag_files(
+    tag_files: &[(PathBuf, PathBuf)],
+    tag_dirs: &[(PathBuf, PathBuf)],
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut files = tag_files.to_vec();
+
+    for (src_dir, path) in tag_dirs {
+        for file in WalkDir::new(src_dir).sort_by_file_name() {
+            let file = file.context(WalkFileSnafu {})?;
+            if file.file_type().is_file() {
+                let relative = file.path().strip_prefix(src_dir).unwrap();
+                files.push((file.path().to_path_buf(), path.join(relative)));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Validates the destinations of custom tag files and reads the contents of their sources
+fn read_tag_files(tag_files: &[(PathBuf, PathBuf)]) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut files: Vec<(PathBuf, Vec<u8>)> = Vec::with_capacity(tag_files.len());
+
+    for (src, path) in tag_files {
+        let path = validate_tag_file_path(path)?;
+        if files.iter().any(|(existing, _)| *existing == path) {
+            return Err(InvalidTagFilePath {
+                path,
+                details: "Only one tag file may be written to a path".into(),
+            });
+        }
+        let contents = fs::read(src).context(IoReadSnafu { path: src })?;
+        files.push((path, contents));
+    }
+
+    Ok(files)
+}
+
+fn write_file_contents(path: &Path, contents: &[u8]) -> Result<()> {
+    info!("Writing tag file {}", path.display());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+    }
+    fs::write(path, contents).context(IoWriteSnafu { path })
+}
+
+/// Moves the tag files in `base_dir` into `work_dir`, and then moves the contents of the payload
+/// directory up into `base_dir`, recording every change in `journal`
+fn move_payload_up(base_dir: &Path, work_dir: &Path, journal: &Journal) -> Result<()> {
+    let tags_dir = work_dir.join("tags");
+    let payload_dir = work_dir.join(DATA);
+
+    journal.creating(work_dir);
+    fs::create_dir(work_dir).context(IoCreateSnafu { path: work_dir })?;
+    fs::create_dir(&tags_dir).context(IoCreateSnafu { path: &tags_dir })?;
+
+    // The tag files are moved aside first so that payload files with the same names do not
+    // collide with them
+    for path in list_dir(base_dir)? {
+        let name = path.file_name().unwrap_or_default();
+        if name != DATA && name != BAG_LOCK && path != work_dir {
+            journal.rename(&path, &tags_dir.join(name))?;
+        }
+    }
+
+    journal.rename(&base_dir.join(DATA), &payload_dir)?;
+
+    for path in list_dir(&payload_dir)? {
+        let name = path.file_name().unwrap_or_default();
+        journal.rename(&path, &base_dir.join(name))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of the entries in `dir`, sorted by name
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)
+        .context(IoReadDirSnafu { path: dir })?
+        .map(|entry| {
+            entry
+                .map(|entry| entry.path())
+                .context(IoReadDirSnafu { path: dir })
+        })
+        .collect::<Result<Vec<PathBuf>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Compares the source directory to the `snapshot` that was taken before its files were copied,
+/// and returns the transfer consistency statement if nothing changed
+fn verify_snapshot(snapshot: &SourceSnapshot, src_dir: &Path, dst_dir: &Path) -> Result<TagList> {
+    let current = SourceSnapshot::capture(src_dir, |path| path == dst_dir)?;
+    let changes = snapshot.changes(&current);
+
+    if changes.is_empty() {
+        info!(
+            "Verified that {} source files did not change",
+            snapshot.file_count()
+        );
+        return snapshot.to_statement(src_dir, &current);
+    }
+
+    let mut details = changes
+        .iter()
+        .take(10)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if changes.len() > 10 {
+        details.push_str(&format!(", and {} more", changes.len() - 10));
+    }
+    Err(SourceChanged {
+        path: src_dir.into(),
+        details,
+    })
+}
+
+/// Writes exclusions.txt, which lists the files that were not included in the bag
+fn write_exclusion_report(base_dir: &Path, excluded: &mut [Exclusion]) -> Result<()> {
+    let path = base_dir.join(EXCLUSIONS_TXT);
+    info!("Writing exclusion report {}", path.display());
+
+    excluded.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut writer = BufWriter::new(File::create(&path).context(IoCreateSnafu { path: &path })?);
+
+    for exclusion in excluded.iter() {
+        let suffix = if exclusion.is_dir { "/" } else { "" };
+        writeln!(
+            writer,
+            "{}  {}{suffix}",
+            exclusion.reason,
+            manifest_path(&exclusion.path, PathEncoding::Strict)?
+        )
+        .context(IoWriteSnafu { path: &path })?;
+    }
+
+    writer.flush().context(IoWriteSnafu { path: &path })
+}
+
+/// Calculates the digests for all of the payload files in the bag, writes the manifests, and
+/// returns the payload's Payload-Oxum. Files that are hashed are screened by `scanner`, and the
+/// digest cache is rewritten if `digest_cache` is true.
+#[allow(clippy::too_many_arguments)]
+fn update_payload_manifests<P: AsRef<Path>>(
+    base_dir: P,
+    algorithms: &[DigestAlgorithm],
+    known: Option<&KnownDigests>,
+    encoding: PathEncoding,
+    pool: &ThreadPool,
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+    retries: IoRetries,
+    digest_cache: bool,
+) -> Result<PayloadOxum> {
+    let base_dir = base_dir.as_ref();
+    let payload = calculate_digests(
+        base_dir,
+        DATA,
+        algorithms,
+        known,
+        pool,
+        progress,
+        scanner,
+        retries,
+        |_| true,
+    )?;
+
+    let mut cache = digest_cache
+        .then(|| DigestCacheWriter::create(base_dir))
+        .transpose()?;
+
+    let payload_oxum =
+        write_payload_manifests(
+            algorithms,
+            payload,
+            base_dir,
+            encoding,
+            |meta| match &mut cache {
+                Some(cache) => cache.write(&meta.path, &meta.digests),
+                None => Ok(()),
+            },
+        )?;
+
+    if let Some(cache) = cache {
+        cache.finish()?;
+    }
+
+    Ok(payload_oxum)
+}
+
+/// Writes a payload manifest for each of the `added` algorithms that lists the same files as the
+/// payload manifest of the first of the `existing` algorithms. Only the `added` algorithms are
+/// calculated, and the existing manifests are not changed.
+#[allow(clippy::too_many_arguments)]
+fn add_payload_manifests(
+    base_dir: &Path,
+    existing: &[DigestAlgorithm],
+    added: &[DigestAlgorithm],
+    encoding: PathEncoding,
+    pool: &ThreadPool,
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+    retries: IoRetries,
+) -> Result<()> {
+    let algorithm = existing.first().ok_or_else(|| General {
+        message: "Algorithms cannot be added to a bag without payload manifests".to_string(),
+    })?;
+    let manifest = base_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"));
+
+    let file_size = |path: &str| -> Result<u64> {
+        let file_path = base_dir.join(path);
+        Ok(fs::metadata(&file_path)
+            .context(IoStatSnafu { path: &file_path })?
+            .len())
+    };
+
+    // The manifest is read twice so that the files it lists never need to be held in memory:
+    // once to total the files for the progress listener, and again to hash them
+    let (mut hash_count, mut hash_bytes) = (0, 0);
+    for entry in read_manifest_entries(&manifest, encoding)? {
+        let (path, _) = entry?;
+        hash_count += 1;
+        hash_bytes += file_size(&path)?;
+    }
+    progress.hashing_started(hash_count, hash_bytes);
+
+    let file_meta = DigestSpool::new();
+    pool.install(|| {
+        read_manifest_entries(&manifest, encoding)?
+            .par_bridge()
+            .try_for_each(|entry| {
+                let (path, _) = entry?;
+                let size_bytes = file_size(&path)?;
+                let file_path = base_dir.join(&path);
+                let digests = retries.run(&file_path, || {
+                    digest_file(&file_path, added, progress, scanner)
+                })?;
+                file_meta.push(FileMeta {
+                    path: PathBuf::from(path),
+                    size_bytes,
+                    digests,
+                })
+            })
+    })?;
+
+    write_payload_manifests(added, file_meta, base_dir, encoding, |_| Ok(())).map(|_| ())
+}
+
+/// Logs a warning if the payload file looks like it may have been truncated or corrupted
+fn warn_suspicious_file(base_dir: &Path, meta: &FileMeta) -> Result<()> {
+    if let Some(suspicion) = inspect_file(&base_dir.join(&meta.path), meta.size_bytes)? {
+        warn!("Payload file {} {suspicion}", meta.path.display());
+    }
+    Ok(())
+}
+
+/// Copies the source files into the bag's payload, hashing and screening them as they're copied,
+/// and returns meta about the added files. Added files must not overwrite existing payload files,
+/// and files that are rejected by the `scanner` are deleted from the payload.
+fn add_payload_files(
+    base_dir: &Path,
+    additions: &[(PathBuf, PathBuf)],
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<Vec<FileMeta>> {
+    let mut file_meta = Vec::with_capacity(additions.len());
+
+    for (src, path) in additions {
+        let relative = Path::new(DATA).join(validate_payload_path(path)?);
+        let file_path = base_dir.join(&relative);
+
+        if file_path.exists() || file_meta.iter().any(|m: &FileMeta| m.path == relative) {
+            return Err(InvalidPayloadPath {
+                path: path.into(),
+                details: "A payload file already exists at this path".into(),
+            });
+        }
+
+        info!("Adding {} to {}", src.display(), file_path.display());
+        progress.file_started(src);
+
+        let reader = File::open(src).context(IoReadSnafu { path: src })?;
+        let mut reader = ScanReader::new(ProgressReader::new(reader, src, progress), src, scanner)?;
+        let (size_bytes, digests) = write_file(&file_path, &mut reader, algorithms, progress)?;
+        reader.finish().inspect_err(|_| {
+            if fs::remove_file(&file_path).is_ok() {
+                remove_empty_parents(&file_path, &base_dir.join(DATA));
+            }
+        })?;
+
+        progress.file_finished(src);
+
+        file_meta.push(FileMeta {
+            path: relative,
+            size_bytes,
+            digests,
+        });
+    }
+
+    Ok(file_meta)
+}
+
+/// Writes the regular files in the tar archive that's read from `reader` into the payload
+/// directory in `base_dir`, hashing and screening them as they're written. Directory entries are
+/// skipped, and any other type of entry is an error.
+fn extract_payload_tar(
+    reader: &mut dyn Read,
+    base_dir: &Path,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<DigestSpool> {
+    let stream_path = Path::new(TAR_STREAM);
+    let mut archive = tar::Archive::new(reader);
+    let payload = DigestSpool::new();
+    let mut written = HashSet::new();
+
+    for entry in archive
+        .entries()
+        .context(IoReadSnafu { path: stream_path })?
+    {
+        let entry = entry.context(IoReadSnafu { path: stream_path })?;
+        let entry_type = entry.header().entry_type();
+        let path = entry
+            .path()
+            .context(IoReadSnafu { path: stream_path })?
+            .into_owned();
+
+        if entry_type.is_dir() || entry_type.is_pax_global_extensions() {
+            continue;
+        }
+        if !entry_type.is_file() {
+            return Err(UnsupportedFile {
+                path: stream_path.join(path),
+            });
+        }
+
+        let relative = Path::new(DATA).join(validate_payload_path(&path)?);
+        if !written.insert(relative.clone()) {
+            return Err(InvalidPayloadPath {
+                path,
+                details: "The archive contains more than one file at this path".into(),
+            });
+        }
+
+        let file_path = base_dir.join(&relative);
+        info!("Writing {}", file_path.display());
+        progress.file_started(&path);
+
+        let mut reader =
+            ScanReader::new(ProgressReader::new(entry, &path, progress), &path, scanner)?;
+        let (size_bytes, digests) = write_file(&file_path, &mut reader, algorithms, progress)?;
+        reader.finish()?;
+
+        progress.file_finished(&path);
+
+        payload.push(FileMeta {
+            path: relative,
+            size_bytes,
+            digests,
+        })?;
+    }
+
+    Ok(payload)
+}
+
+/// Deletes the payload files, and any directories that are left empty, and returns the bag
+/// relative paths and sizes of the deleted files
+fn remove_payload_files(base_dir: &Path, removals: &[PathBuf]) -> Result<Vec<(PathBuf, u64)>> {
+    let mut removed = Vec::with_capacity(removals.len());
+    let data_dir = base_dir.join(DATA);
+
+    for path in removals {
+        let relative = Path::new(DATA).join(validate_payload_path(path)?);
+        let file_path = base_dir.join(&relative);
+
+        if !file_path.is_file() {
+            return Err(InvalidPayloadPath {
+                path: path.into(),
+                details: "A payload file does not exist at this path".into(),
+            });
+        }
+
+        let size = fs::metadata(&file_path)
+            .context(IoStatSnafu { path: &file_path })?
+            .len();
+
+        info!("Removing {}", file_path.display());
+        fs::remove_file(&file_path).context(IoDeleteSnafu { path: &file_path })?;
+        remove_empty_parents(&file_path, &data_dir);
+
+        removed.push((relative, size));
+    }
 
-    for file in WalkDir::new(src_dir).into_iter().filter_entry(predicate) {
-        let file = file.context(WalkFileSnafu {})?;
+    Ok(removed)
+}
 
-        if !include_hidden_files && is_hidden_file(file.file_name()) {
-            info!("Deleting hidden file {}", file.path().display());
-            if file.file_type().is_dir() {
-                fs::remove_dir_all(file.path()).context(IoDeleteSnafu {
-                    path: file.path().to_path_buf(),
-                })?;
-            } else {
-                fs::remove_file(file.path()).context(IoDeleteSnafu {
-                    path: file.path().to_path_buf(),
-                })?;
-            }
-            continue;
+/// Deletes the parent directories of a deleted file that are left empty, up to the `data_dir`
+fn remove_empty_parents(file_path: &Path, data_dir: &Path) {
+    let mut dir = file_path.parent();
+    while let Some(parent) = dir.filter(|parent| *parent != data_dir) {
+        if fs::remove_dir(parent).is_err() {
+            break;
         }
+        dir = parent.parent();
+    }
+}
 
-        if file.file_type().is_file() {
-            let metadata = file.metadata().context(WalkFileSnafu {})?;
+/// Writes the contents of `reader` to a new file at `file_path`, creating any missing parent
+/// directories, and returns the number of bytes that were written and the file's digests
+fn write_file(
+    file_path: &Path,
+    reader: &mut impl Read,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+) -> Result<(u64, HashMap<DigestAlgorithm, HexDigest>)> {
+    fs::create_dir_all(file_path.parent().unwrap()).context(IoCreateSnafu { path: file_path })?;
+    let file = File::create(file_path).context(IoCreateSnafu { path: file_path })?;
 
-            info!("Calculating digests for {}", file.path().display());
+    let mut writer = MultiDigestWriter::new(algorithms, BufWriter::new(file));
+    let size_bytes = io::copy(reader, &mut writer).context(IoWriteSnafu { path: file_path })?;
+    writer.flush().context(IoWriteSnafu { path: file_path })?;
+    progress.digests_timed(file_path, writer.digest_times());
 
-            let mut writer = MultiDigestWriter::new(algorithms, std::io::sink());
-            let mut reader = File::open(file.path()).context(IoReadSnafu { path: file.path() })?;
+    Ok((size_bytes, writer.finalize_hex()))
+}
 
-            io::copy(&mut reader, &mut writer).context(IoReadSnafu { path: file.path() })?;
+/// Appends entries for the files to the end of the existing payload manifests
+fn append_payload_manifests(
+    base_dir: &Path,
+    algorithms: &[DigestAlgorithm],
+    file_meta: &mut [FileMeta],
+    encoding: PathEncoding,
+) -> Result<()> {
+    file_meta.sort_by(|a, b| a.path.cmp(&b.path));
 
-            let relative = file.path().strip_prefix(src_dir).unwrap();
+    for algorithm in algorithms {
+        let manifest = base_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"));
+        info!("Appending to manifest {}", manifest.display());
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&manifest)
+            .context(IoWriteSnafu { path: &manifest })?;
+        let missing_newline =
+            !ends_with_newline(&mut file).context(IoReadSnafu { path: &manifest })?;
+        let mut writer = BufWriter::new(file);
+
+        if missing_newline {
+            writeln!(writer).context(IoWriteSnafu { path: &manifest })?;
+        }
 
-            file_meta.push(FileMeta {
-                path: relative.to_path_buf(),
-                size_bytes: metadata.len(),
-                digests: writer.finalize_hex(),
-            });
+        for meta in file_meta.iter() {
+            let digest = meta
+                .digests
+                .get(algorithm)
+                .expect("Missing expected file digest");
+            writeln!(writer, "{digest}  {}", manifest_path(&meta.path, encoding)?)
+                .context(IoWriteSnafu { path: &manifest })?;
+        }
 
-            let file_dst = dst_dir.join(relative);
+        writer.flush().context(IoWriteSnafu { path: &manifest })?;
+    }
 
-            fs::create_dir_all(file_dst.parent().unwrap())
-                .context(IoCreateSnafu { path: &file_dst })?;
+    Ok(())
+}
 
-            if copy_op {
-                copy(file.path(), file_dst)?;
-            } else {
-                rename(file.path(), file_dst)?;
-            }
-        } else if file.file_type().is_dir() {
-            if !copy_op {
-                dirs.push(file.path().to_path_buf());
-            }
-        } else {
-            return Err(UnsupportedFile {
-                path: file.path().to_path_buf(),
-            });
-        }
+/// Removes the entries for the `removed` files from all of the existing payload manifests. All
+/// other lines are preserved as is.
+fn strip_payload_manifests(
+    base_dir: &Path,
+    removed: &[(PathBuf, u64)],
+    encoding: PathEncoding,
+) -> Result<()> {
+    let mut removed_paths = HashSet::with_capacity(removed.len());
+    for (path, _) in removed {
+        let path = path
+            .to_str()
+            .ok_or_else(|| InvalidUtf8Path { path: path.into() })?;
+        removed_paths.insert(convert_path_separator(path).into_owned());
     }
 
-    // Delete any dangling directories left after moving out all of the files
-    for dir in dirs {
-        if dir == src_dir {
-            continue;
-        }
-        if let Err(e) = fs::remove_dir_all(&dir) {
-            if e.kind() != ErrorKind::NotFound {
-                return Err(IoDelete {
-                    path: dir,
-                    source: e,
-                });
+    let mut manifests = Vec::new();
+    for_matching_files(base_dir, &PAYLOAD_MANIFEST_MATCHER, |path, _| {
+        manifests.push(path.to_path_buf())
+    })?;
+
+    for manifest in manifests {
+        info!("Removing entries from manifest {}", manifest.display());
+
+        let mut kept = String::new();
+        for line in LineReader::new(BufReader::new(
+            File::open(&manifest).context(IoReadSnafu { path: &manifest })?,
+        )) {
+            let line = line?;
+            let is_removed = split_manifest_line(&line)
+                .map(|(_, path)| removed_paths.contains(encoding.decode(path).as_ref()))
+                .unwrap_or(false);
+            if !is_removed {
+                kept.push_str(&line);
+                kept.push('\n');
             }
         }
+
+        fs::write(&manifest, kept).context(IoWriteSnafu { path: &manifest })?;
     }
 
-    Ok(file_meta)
+    Ok(())
 }
 
-/// Calculates the digests for all of the payload files in the bag and writes the manifests
-fn update_payload_manifests<P: AsRef<Path>>(
-    base_dir: P,
-    algorithms: &[DigestAlgorithm],
-) -> Result<Vec<FileMeta>> {
-    let base_dir = base_dir.as_ref();
-    let mut meta = calculate_digests(base_dir.join(DATA), algorithms, |_| true)?;
-    add_data_prefix(&mut meta);
+/// True if the file is empty or its last byte is a line ending
+fn ends_with_newline(file: &mut File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(true);
+    }
+    let mut last = [0; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] == LF_B || last[0] == CR_B)
+}
 
-    write_payload_manifests(algorithms, &mut meta, base_dir)?;
+/// Adjusts the Payload-Oxum in `bag_info` for the `added` and `removed` files. If the existing
+/// Payload-Oxum is missing or invalid, it's recalculated from the sizes of the payload files
+/// instead.
+fn adjust_payload_oxum(
+    base_dir: &Path,
+    bag_info: &mut BagInfo,
+    added: &[FileMeta],
+    removed: &[(PathBuf, u64)],
+) -> Result<()> {
+    let payload_oxum = match bag_info.payload_oxum() {
+        Some(mut payload_oxum) => {
+            for meta in added {
+                payload_oxum.add_file(meta.size_bytes);
+            }
+            for (_, size) in removed {
+                payload_oxum.remove_file(*size);
+            }
+            payload_oxum
+        }
+        None => {
+            info!("Recalculating Payload-Oxum because the existing value is missing or invalid");
+            let mut payload_oxum = PayloadOxum::default();
+            for file in WalkDir::new(base_dir.join(DATA)) {
+                let file = file.context(WalkFileSnafu {})?;
+                if file.file_type().is_file() {
+                    payload_oxum.add_file(file.metadata().context(WalkFileSnafu {})?.len());
+                }
+            }
+            payload_oxum
+        }
+    };
 
-    Ok(meta)
+    bag_info.add_payload_oxum(payload_oxum)
 }
 
-/// Prefixes all payload files with `data/`
-fn add_data_prefix(file_meta: &mut [FileMeta]) {
-    let relative_data_dir = PathBuf::from(DATA);
-
-    for meta in file_meta {
-        meta.path = relative_data_dir.join(&meta.path);
+/// Sets the Bag-Size in `bag_info` to the size of the payload, as recorded in the Payload-Oxum
+fn add_calculated_bag_size(bag_info: &mut BagInfo) -> Result<()> {
+    match bag_info.payload_oxum() {
+        Some(payload_oxum) => bag_info.add_bag_size(format_bag_size(payload_oxum.bytes)),
+        None => Ok(()),
     }
 }
 
 /// Calculates the digests for all of the tag files in the bag and writes the tag manifests
-fn update_tag_manifests<P: AsRef<Path>>(base_dir: P, algorithms: &[DigestAlgorithm]) -> Result<()> {
+pub fn update_tag_manifests<P: AsRef<Path>>(
+    base_dir: P,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+    pool: &ThreadPool,
+) -> Result<()> {
+    update_tag_manifests_skipping(base_dir, algorithms, encoding, pool, OsStr::new(DATA))
+}
+
+/// Same as `update_tag_manifests()`, but the top-level directory named `skip`, such as a work
+/// directory, is also not included
+fn update_tag_manifests_skipping<P: AsRef<Path>>(
+    base_dir: P,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+    pool: &ThreadPool,
+    skip: &OsStr,
+) -> Result<()> {
     let base_dir = base_dir.as_ref();
-    let mut meta = calculate_digests(base_dir, algorithms, |f| {
-        // Skip the data directory and all tag manifests
-        f.file_name() != DATA
-            && f.file_name()
-                .to_str()
-                .map(|n| !TAG_MANIFEST_MATCHER.is_match(n))
-                .unwrap_or(true)
-    })?;
-    write_tag_manifests(algorithms, &mut meta, base_dir)
+    let meta = calculate_digests(
+        base_dir,
+        "",
+        algorithms,
+        None,
+        pool,
+        &NoProgress,
+        None,
+        IoRetries::NONE,
+        |f| {
+            // Skip the data directory and all tag manifests
+            f.file_name() != DATA
+                && !(f.depth() == 1
+                    && (f.file_name() == skip
+                        || f.file_name() == BAG_LOCK
+                        || f.file_name() == DIGEST_CACHE
+                        || f.file_name() == AUDIT_STATE))
+                && f.file_name()
+                    .to_str()
+                    .map(|n| !TAG_MANIFEST_MATCHER.is_match(n))
+                    .unwrap_or(true)
+        },
+    )?;
+    write_tag_manifests(algorithms, meta, base_dir, encoding)
 }
 
-/// Calculates the digests for all of the files under the `base_dir`
+/// Calculates the digests for all of the files under the `sub_dir` of the `base_dir` and returns
+/// a spool of them with paths relative to the `base_dir`. Files are hashed concurrently using the
+/// threads in `pool`. If `known` digests are provided, they are used instead of hashing
+/// files that have not been modified since the digests were calculated. Hashing progress is
+/// reported to `progress`, hashed files are screened by `scanner`, and files that fail with
+/// transient IO errors are hashed again according to `retries`.
+#[allow(clippy::too_many_arguments)]
 fn calculate_digests<D, P>(
     base_dir: D,
+    sub_dir: &str,
     algorithms: &[DigestAlgorithm],
+    known: Option<&KnownDigests>,
+    pool: &ThreadPool,
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+    retries: IoRetries,
     predicate: P,
-) -> Result<Vec<FileMeta>>
+) -> Result<DigestSpool>
 where
     D: AsRef<Path>,
     P: FnMut(&DirEntry) -> bool,
 {
-    let base_dir = base_dir.as_ref();
-    let mut file_meta = Vec::new();
+    let prefix = Path::new(sub_dir);
+    let base_dir = base_dir.as_ref().join(prefix);
+    let base_dir = base_dir.as_path();
+    let mut files = Vec::new();
 
     for file in WalkDir::new(base_dir).into_iter().filter_entry(predicate) {
         let file = file.context(WalkFileSnafu {})?;
 
         if file.file_type().is_file() {
             let metadata = file.metadata().context(WalkFileSnafu {})?;
+            files.push((file.into_path(), metadata.len(), metadata.modified().ok()));
+        }
+    }
+
+    let known_digests = |path: &Path, size: u64, modified: Option<SystemTime>| {
+        known.and_then(|known| known.get(path.strip_prefix(base_dir).unwrap(), size, modified))
+    };
+
+    let (hash_count, hash_bytes) = files
+        .iter()
+        .filter(|(path, size_bytes, modified)| {
+            known_digests(path, *size_bytes, *modified).is_none()
+        })
+        .fold((0, 0), |(count, bytes), (_, size_bytes, _)| {
+            (count + 1, bytes + size_bytes)
+        });
+    progress.hashing_started(hash_count, hash_bytes);
+
+    let file_meta = DigestSpool::new();
+    pool.install(|| {
+        files
+            .par_iter()
+            .try_for_each(|(path, size_bytes, modified)| {
+                let relative = path.strip_prefix(base_dir).unwrap();
+
+                let digests = match known_digests(path, *size_bytes, *modified) {
+                    Some(digests) => {
+                        info!("Reusing existing digests for {}", path.display());
+                        digests.clone()
+                    }
+                    None => {
+                        retries.run(path, || digest_file(path, algorithms, progress, scanner))?
+                    }
+                };
+
+                file_meta.push(FileMeta {
+                    path: prefix.join(relative),
+                    size_bytes: *size_bytes,
+                    digests,
+                })
+            })
+    })?;
+
+    Ok(file_meta)
+}
+
+/// Returns true if `e` is an IO error that may not happen again if the operation is retried, such
+/// as the timeouts and stale file handles that occur on network mounts
+fn is_transient(e: &Error) -> bool {
+    let source = match e {
+        IoRead { source, .. } | IoCopy { source, .. } => source,
+        _ => return false,
+    };
+
+    matches!(
+        source.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::StaleNetworkFileHandle
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::ResourceBusy
+    ) || source
+        .raw_os_error()
+        .is_some_and(|code| TRANSIENT_OS_ERRORS.contains(&code))
+}
 
-            info!("Calculating digests for {}", file.path().display());
+/// Calculates all of the specified digests of a file in a single pass, screening it with the
+/// `scanner`, if there is one, using the same read
+fn digest_file(
+    path: &Path,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<HashMap<DigestAlgorithm, HexDigest>> {
+    info!("Calculating digests for {}", path.display());
+    progress.file_started(path);
+
+    let mut writer = MultiDigestWriter::new(algorithms, std::io::sink());
+    let reader = ProgressReader::new(
+        File::open(path).context(IoReadSnafu { path })?,
+        path,
+        progress,
+    );
+    let mut reader = ScanReader::new(reader, path, scanner)?;
+
+    io::copy(&mut reader, &mut writer).context(IoReadSnafu { path })?;
+    reader.finish()?;
+
+    progress.digests_timed(path, writer.digest_times());
+    progress.file_finished(path);
+    Ok(writer.finalize_hex())
+}
 
-            let mut writer = MultiDigestWriter::new(algorithms, std::io::sink());
-            let mut reader = File::open(file.path()).context(IoReadSnafu { path: file.path() })?;
+/// Copies the file at `from` to `to`, and calculates all of the specified digests of the bytes
+/// that are written to the copy, screening them with the `scanner`, if there is one, using the
+/// same read. The copy has the same permissions as the source file, like with `fs::copy()`.
+fn copy_and_digest_file(
+    from: &Path,
+    to: &Path,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<HashMap<DigestAlgorithm, HexDigest>> {
+    info!("Copying {} to {}", from.display(), to.display());
+    progress.file_started(from);
+
+    let file = File::open(from).context(IoReadSnafu { path: from })?;
+    let permissions = file
+        .metadata()
+        .context(IoStatSnafu { path: from })?
+        .permissions();
+    fs::create_dir_all(to.parent().unwrap()).context(IoCreateSnafu { path: to })?;
+    let mut reader = ScanReader::new(ProgressReader::new(file, from, progress), from, scanner)?;
+    let mut writer = MultiDigestWriter::new(
+        algorithms,
+        BufWriter::new(File::create(to).context(IoCreateSnafu { path: to })?),
+    );
+
+    io::copy(&mut reader, &mut writer).context(IoCopySnafu { from, to })?;
+    writer.flush().context(IoWriteSnafu { path: to })?;
+    reader.finish()?;
+    fs::set_permissions(to, permissions).context(IoWriteSnafu { path: to })?;
+
+    progress.digests_timed(from, writer.digest_times());
+    progress.file_finished(from);
+    Ok(writer.finalize_hex())
+}
 
-            io::copy(&mut reader, &mut writer).context(IoReadSnafu { path: file.path() })?;
+/// Returns the digests that were imported for the file at `path`, whose canonical path is
+/// `canonical_path`, if they can be used, and otherwise hashes the file and verifies any digests
+/// that were imported for it
+fn digest_or_import_file(
+    path: &Path,
+    canonical_path: &Path,
+    size_bytes: u64,
+    algorithms: &[DigestAlgorithm],
+    imported: &ImportedDigests,
+    progress: &dyn ProgressListener,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<HashMap<DigestAlgorithm, HexDigest>> {
+    let known = imported.get(canonical_path);
+
+    if let Some(known) = known {
+        if scanner.is_none()
+            && algorithms
+                .iter()
+                .all(|algorithm| known.contains_key(algorithm))
+            && !imported.is_sampled(canonical_path)
+        {
+            info!("Using imported digests for {}", path.display());
+            progress.file_started(path);
+            progress.bytes_hashed(path, size_bytes);
+            progress.file_finished(path);
+            return Ok(algorithms
+                .iter()
+                .map(|algorithm| (*algorithm, known[algorithm].clone()))
+                .collect());
+        }
+    }
 
-            file_meta.push(FileMeta {
-                path: file.path().strip_prefix(base_dir).unwrap().to_path_buf(),
-                size_bytes: metadata.len(),
-                digests: writer.finalize_hex(),
+    let mut hash_algorithms = algorithms.to_vec();
+    if let Some(known) = known {
+        hash_algorithms.extend(
+            known
+                .keys()
+                .filter(|algorithm| !algorithms.contains(algorithm)),
+        );
+    }
+    let mut digests = digest_file(path, &hash_algorithms, progress, scanner)?;
+
+    // Checked in a consistent order, so the same mismatch is always reported
+    let mut expected_digests = known.into_iter().flatten().collect::<Vec<_>>();
+    expected_digests.sort_by_key(|(algorithm, _)| **algorithm);
+    for (algorithm, expected) in expected_digests {
+        let actual = &digests[algorithm];
+        if actual != expected {
+            return Err(ImportedDigestMismatch {
+                path: path.into(),
+                algorithm: *algorithm,
+                expected: expected.to_string(),
+                actual: actual.to_string(),
             });
         }
     }
 
-    Ok(file_meta)
+    digests.retain(|algorithm, _| algorithms.contains(algorithm));
+    Ok(digests)
 }
 
-fn write_payload_manifests<P: AsRef<Path>>(
+/// Writes the payload manifests for the spooled `payload` files, whose paths include the `data/`
+/// prefix, and returns the payload's Payload-Oxum. `on_file` is called with each file after its
+/// entries are written.
+fn write_payload_manifests<P, F>(
     algorithms: &[DigestAlgorithm],
-    file_meta: &mut [FileMeta],
+    payload: DigestSpool,
     base_dir: P,
-) -> Result<()> {
-    // TODO this is currently not taking into account fetch.txt
-    write_manifests(algorithms, file_meta, PAYLOAD_MANIFEST_PREFIX, base_dir)
+    encoding: PathEncoding,
+    on_file: F,
+) -> Result<PayloadOxum>
+where
+    P: AsRef<Path>,
+    F: FnMut(&FileMeta) -> Result<()>,
+{
+    let payload_oxum = payload.payload_oxum();
+    write_manifests(
+        algorithms,
+        payload,
+        PAYLOAD_MANIFEST_PREFIX,
+        base_dir,
+        encoding,
+        on_file,
+    )?;
+    Ok(payload_oxum)
 }
 
 fn write_tag_manifests<P: AsRef<Path>>(
     algorithms: &[DigestAlgorithm],
-    file_meta: &mut [FileMeta],
+    files: DigestSpool,
     base_dir: P,
+    encoding: PathEncoding,
 ) -> Result<()> {
-    write_manifests(algorithms, file_meta, TAG_MANIFEST_PREFIX, base_dir)
+    write_manifests(
+        algorithms,
+        files,
+        TAG_MANIFEST_PREFIX,
+        base_dir,
+        encoding,
+        |_| Ok(()),
+    )
 }
 
-// TODO remember to consider * when reading
-// TODO note when reading these files that `./data/` is ALLOWED
-fn write_manifests<P: AsRef<Path>>(
+/// Writes the spooled `files` to the manifests in path order, so that they're written
+/// deterministically, without reading all of them into memory
+fn write_manifests<P, F>(
     algorithms: &[DigestAlgorithm],
-    file_meta: &mut [FileMeta],
+    files: DigestSpool,
     prefix: &str,
     base_dir: P,
-) -> Result<()> {
-    let base_dir = base_dir.as_ref();
-
-    let mut manifests = HashMap::with_capacity(algorithms.len());
+    encoding: PathEncoding,
+    mut on_file: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&FileMeta) -> Result<()>,
+{
+    let mut writer = ManifestWriter::create(base_dir.as_ref(), prefix, algorithms, encoding)?;
 
-    for algorithm in algorithms {
-        let manifest = base_dir.join(format!("{prefix}-{algorithm}.txt"));
-        info!("Writing manifest {}", manifest.display());
-        let file = File::create(&manifest).context(IoCreateSnafu { path: manifest })?;
-        manifests.insert(algorithm, BufWriter::new(file));
+    for meta in files.into_sorted()? {
+        let meta = meta?;
+        writer.write(&meta)?;
+        on_file(&meta)?;
     }
 
-    // Sort files so that they're written to the manifest deterministically
-    file_meta.sort_by(|a, b| a.path.cmp(&b.path));
+    writer.finish()
+}
 
-    for meta in file_meta {
-        let path = meta.path.to_str().ok_or_else(|| InvalidUtf8Path {
-            path: meta.path.to_path_buf(),
-        })?;
-        let encoded = percent_encode(path);
-        let normalized = convert_path_separator(encoded.as_ref());
+/// Writes a manifest for each algorithm one file at a time, in the order the files are written
+struct ManifestWriter {
+    encoding: PathEncoding,
+    manifests: Vec<(DigestAlgorithm, PathBuf, BufWriter<File>)>,
+}
+
+impl ManifestWriter {
+    /// Creates the manifests in `base_dir` that are named with `prefix`, replacing any existing
+    /// manifests
+    fn create(
+        base_dir: &Path,
+        prefix: &str,
+        algorithms: &[DigestAlgorithm],
+        encoding: PathEncoding,
+    ) -> Result<Self> {
+        let mut manifests = Vec::with_capacity(algorithms.len());
 
         for algorithm in algorithms {
+            let manifest = base_dir.join(format!("{prefix}-{algorithm}.txt"));
+            info!("Writing manifest {}", manifest.display());
+            let file = File::create(&manifest).context(IoCreateSnafu { path: &manifest })?;
+            manifests.push((*algorithm, manifest, BufWriter::new(file)));
+        }
+
+        Ok(Self {
+            encoding,
+            manifests,
+        })
+    }
+
+    /// Writes the file's digest to each manifest
+    fn write(&mut self, meta: &FileMeta) -> Result<()> {
+        let normalized = manifest_path(&meta.path, self.encoding)?;
+
+        for (algorithm, path, manifest) in &mut self.manifests {
             let digest = meta
                 .digests
                 .get(algorithm)
                 .expect("Missing expected file digest");
-            let manifest = manifests
-                .get_mut(algorithm)
-                .expect("Missing expected file digest");
-            writeln!(manifest, "{digest}  {normalized}").context(IoGeneralSnafu {})?;
+            writeln!(manifest, "{digest}  {normalized}").context(IoWriteSnafu { path: &*path })?;
         }
+
+        Ok(())
     }
 
-    Ok(())
+    /// Flushes the manifests to disk
+    fn finish(self) -> Result<()> {
+        for (_, path, mut manifest) in self.manifests {
+            manifest.flush().context(IoWriteSnafu { path })?;
+        }
+        Ok(())
+    }
 }
 
-fn rename<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<()> {
-    let from = from.as_ref();
-    let to = to.as_ref();
-    info!("Moving {} to {}", from.display(), to.display());
-    fs::rename(from, to).context(IoMoveSnafu { from, to })
+/// Converts a path into the form that's written to manifests by percent encoding it and
+/// converting its separators to `/`
+fn manifest_path(path: &Path, encoding: PathEncoding) -> Result<String> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| InvalidUtf8Path { path: path.into() })?;
+    let encoded = encoding.encode(path);
+    Ok(convert_path_separator(encoded.as_ref()).into_owned())
 }
 
 fn copy<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<()> {
@@ -553,6 +3331,92 @@ fn delete_payload_manifests<P: AsRef<Path>>(base_dir: P) -> Result<()> {
     delete_matching_files(base_dir, &PAYLOAD_MANIFEST_MATCHER)
 }
 
+/// Rewrites the bag's payload manifests, fetch.txt, and bagit.txt, which are encoded as described
+/// in `declaration`, as UTF-8 and returns the new declaration. bag-info.txt and the tag manifests
+/// are not touched because they are always rewritten when a bag is updated.
+fn convert_to_utf8(base_dir: &Path, declaration: &BagDeclaration) -> Result<BagDeclaration> {
+    info!(
+        "Converting tag files from {} to {UTF_8}",
+        declaration.encoding()
+    );
+
+    let mut files = Vec::new();
+    for_matching_files(base_dir, &PAYLOAD_MANIFEST_MATCHER, |path, _| {
+        files.push(path.to_path_buf())
+    })?;
+    let fetch = base_dir.join(FETCH_TXT);
+    if fetch.is_file() {
+        files.push(fetch);
+    }
+
+    for path in files {
+        let mut contents = String::new();
+        File::open(&path)
+            .and_then(|file| decode_reader(file, declaration).read_to_string(&mut contents))
+            .context(IoReadSnafu { path: &path })?;
+        fs::write(&path, contents).context(IoWriteSnafu { path: &path })?;
+    }
+
+    let utf8 = BagDeclaration::with_values(declaration.version(), UTF_8)?;
+    write_bag_declaration(&utf8, base_dir)?;
+    Ok(utf8)
+}
+
+/// Rewrites bagit.txt, fetch.txt, and the payload manifests without the UTF-8 byte order mark
+/// that they start with, if any. bag-info.txt and the tag manifests are always rewritten by bagr,
+/// which never writes byte order marks.
+fn strip_byte_order_marks(base_dir: &Path) -> Result<()> {
+    let mut files = vec![base_dir.join(BAGIT_TXT)];
+    for_matching_files(base_dir, &PAYLOAD_MANIFEST_MATCHER, |path, _| {
+        files.push(path.to_path_buf())
+    })?;
+    let fetch = base_dir.join(FETCH_TXT);
+    if fetch.is_file() {
+        files.push(fetch);
+    }
+
+    for path in files {
+        let mut file = File::open(&path).context(IoReadSnafu { path: &path })?;
+        let mut start = Vec::with_capacity(UTF_8_BOM.len());
+        Read::by_ref(&mut file)
+            .take(UTF_8_BOM.len() as u64)
+            .read_to_end(&mut start)
+            .context(IoReadSnafu { path: &path })?;
+        if start != UTF_8_BOM {
+            continue;
+        }
+
+        info!("Removing the byte order mark from {}", path.display());
+        // The rest of the file is streamed into a new file that then replaces the original
+        let mut temp =
+            tempfile::NamedTempFile::new_in(base_dir).context(IoCreateSnafu { path: base_dir })?;
+        io::copy(&mut file, &mut temp).context(IoWriteSnafu { path: temp.path() })?;
+        let permissions = file
+            .metadata()
+            .context(IoStatSnafu { path: &path })?
+            .permissions();
+        fs::set_permissions(temp.path(), permissions)
+            .context(IoWriteSnafu { path: temp.path() })?;
+        let temp_path = temp.path().to_path_buf();
+        temp.persist(&path)
+            .map_err(|e| e.error)
+            .context(IoMoveSnafu {
+                from: temp_path,
+                to: &path,
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if there is at least one tag manifest in the base directory, including manifests
+/// that use unsupported algorithms
+pub fn has_tag_manifests<P: AsRef<Path>>(base_dir: P) -> Result<bool> {
+    let mut found = false;
+    for_matching_files(base_dir, &TAG_MANIFEST_MATCHER, |_, _| found = true)?;
+    Ok(found)
+}
+
 /// Deletes all tag manifests in the base directory
 fn delete_tag_manifests<P: AsRef<Path>>(base_dir: P) -> Result<()> {
     delete_matching_files(base_dir, &TAG_MANIFEST_MATCHER)
@@ -624,13 +3488,189 @@ fn defaulted_algorithms(algorithms: &[DigestAlgorithm]) -> Vec<DigestAlgorithm>
     }
 }
 
-fn build_payload_oxum(file_meta: &[FileMeta]) -> String {
-    let count = file_meta.len();
-    let mut sum = 0;
-    for meta in file_meta {
-        sum += meta.size_bytes;
+/// Loads the digests from the existing payload manifests for the specified algorithms, along with
+/// the digest cache that records the size and modification time of each file when it was last
+/// hashed. `None` is returned if there is not an existing manifest for every algorithm. Only files
+/// that are listed in every manifest are included.
+fn load_known_digests<P: AsRef<Path>>(
+    base_dir: P,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+) -> Result<Option<KnownDigests>> {
+    let base_dir = base_dir.as_ref();
+    let data_prefix = format!("{DATA}/");
+
+    let mut digests: HashMap<PathBuf, HashMap<DigestAlgorithm, HexDigest>> = HashMap::new();
+
+    for algorithm in algorithms {
+        let manifest = base_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"));
+        if !manifest.is_file() {
+            info!(
+                "Not reusing existing digests because {} does not exist",
+                manifest.display()
+            );
+            return Ok(None);
+        }
+
+        for (path, digest) in read_manifest_file(&manifest, encoding)? {
+            if let Some(relative) = path.strip_prefix(&data_prefix) {
+                digests
+                    .entry(PathBuf::from(relative))
+                    .or_default()
+                    .insert(*algorithm, digest);
+            }
+        }
+    }
+
+    digests.retain(|_, file_digests| file_digests.len() == algorithms.len());
+
+    Ok(Some(KnownDigests {
+        cache: DigestCache::load(base_dir)?,
+        digests,
+        verified: HashMap::new(),
+    }))
+}
+
+/// Calculates the digests of the payload files that are not listed in the payload manifests,
+/// and matches them to listed files that no longer exist by their digests. When several missing
+/// files have the same digests, they're matched in path order. Nothing is hashed if no listed
+/// files are missing.
+fn find_renames(
+    base_dir: &Path,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+    pool: &ThreadPool,
+    scanner: Option<&dyn FileScanner>,
+) -> Result<Vec<UnlistedFile>> {
+    let data_dir = base_dir.join(DATA);
+    let data_prefix = format!("{DATA}/");
+
+    let mut listed: BTreeMap<PathBuf, HashMap<DigestAlgorithm, HexDigest>> = BTreeMap::new();
+    for algorithm in algorithms {
+        let manifest = base_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"));
+        for (path, digest) in read_manifest_file(&manifest, encoding)? {
+            if let Some(relative) = path.strip_prefix(&data_prefix) {
+                listed
+                    .entry(PathBuf::from(relative))
+                    .or_default()
+                    .insert(*algorithm, digest);
+            }
+        }
+    }
+
+    // Missing files are keyed on the digest of the first algorithm
+    let mut missing: BTreeMap<&HexDigest, Vec<&PathBuf>> = BTreeMap::new();
+    for (path, digests) in &listed {
+        if let Some(digest) = digests.get(&algorithms[0]) {
+            if !data_dir.join(path).exists() {
+                missing.entry(digest).or_default().push(path);
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut unlisted = Vec::new();
+    for file in WalkDir::new(&data_dir).sort_by_file_name() {
+        let file = file.context(WalkFileSnafu {})?;
+        if file.file_type().is_file() {
+            let relative = file.path().strip_prefix(&data_dir).unwrap();
+            if !listed.contains_key(relative) {
+                unlisted.push(file.into_path());
+            }
+        }
+    }
+
+    let hashed = pool.install(|| {
+        unlisted
+            .par_iter()
+            .map(|path| Ok((path, digest_file(path, algorithms, &NoProgress, scanner)?)))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut files = Vec::with_capacity(hashed.len());
+    for (path, digests) in hashed {
+        let renamed_from = missing
+            .get_mut(&digests[&algorithms[0]])
+            .and_then(|candidates| {
+                let i = candidates
+                    .iter()
+                    .position(|from| listed[*from] == digests)?;
+                Some(candidates.remove(i).clone())
+            });
+        files.push(UnlistedFile {
+            path: path.strip_prefix(&data_dir).unwrap().to_path_buf(),
+            digests,
+            renamed_from,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Returns the paths, as they appear in the payload manifests written with the `algorithms`, of
+/// the listed files that no longer exist, excluding files that were matched to an `unlisted`
+/// file by rename detection
+fn find_missing_files(
+    base_dir: &Path,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+    unlisted: &[UnlistedFile],
+) -> Result<Vec<String>> {
+    let renamed = unlisted
+        .iter()
+        .filter_map(|file| file.renamed_from.as_ref())
+        .map(|from| Path::new(DATA).join(from))
+        .collect::<HashSet<_>>();
+
+    let mut missing = BTreeSet::new();
+    for algorithm in algorithms {
+        let manifest = base_dir.join(format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"));
+        for entry in read_manifest_entries(&manifest, encoding)? {
+            let (path, _) = entry?;
+            let relative = PathBuf::from(&path);
+            if !renamed.contains(&relative) && !base_dir.join(&relative).exists() {
+                missing.insert(path);
+            }
+        }
+    }
+
+    Ok(missing.into_iter().collect())
+}
+
+/// Appends an entry for every one of the payload `paths` to history.txt, which records changes
+/// to the payload that are not otherwise evident from the manifests
+fn record_history(base_dir: &Path, date: &str, operation: &str, paths: &[String]) -> Result<()> {
+    let path = base_dir.join(HISTORY_TXT);
+    info!("Writing {}", path.display());
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(IoWriteSnafu { path: &path })?;
+    let mut writer = BufWriter::new(file);
+
+    for payload_path in paths {
+        writeln!(
+            writer,
+            "{date}  {operation}  {}",
+            manifest_path(Path::new(payload_path), PathEncoding::Strict)?
+        )
+        .context(IoWriteSnafu { path: &path })?;
     }
-    format!("{sum}.{count}")
+
+    writer.flush().context(IoWriteSnafu { path: &path })
+}
+
+/// Builds a thread pool with the specified number of threads. 0 uses one thread per CPU.
+pub fn thread_pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context(ThreadPoolSnafu {})
 }
 
 fn bagr_software_agent() -> String {
@@ -641,17 +3681,104 @@ fn current_date_str() -> String {
     Local::today().format("%Y-%m-%d").to_string()
 }
 
-fn epoch_seconds() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Failed to get system time")
-        .as_secs()
+/// Adds a Bagging-Date and Bag-Software-Agent to `bag_info` if they're not already set. When the
+/// bag is `reproducible`, the date is read from `SOURCE_DATE_EPOCH` and the agent does not
+/// include the bagr version.
+fn add_default_tags(bag_info: &mut BagInfo, reproducible: bool) -> Result<()> {
+    if bag_info.bagging_date().is_none() {
+        let date = if reproducible {
+            source_date_str()?.ok_or_else(|| General {
+                message: "A Bagging-Date or SOURCE_DATE_EPOCH must be set when the bag is \
+                    created reproducibly"
+                    .to_string(),
+            })?
+        } else {
+            current_date_str()
+        };
+        bag_info.add_bagging_date(date)?;
+    }
+    if bag_info.software_agent().is_none() {
+        if reproducible {
+            bag_info.add_software_agent(format!("bagr <{}>", BAGR_SRC_URL))?;
+        } else {
+            bag_info.add_software_agent(bagr_software_agent())?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the UTC date of the Unix timestamp in `SOURCE_DATE_EPOCH`, if it's set
+fn source_date_str() -> Result<Option<String>> {
+    let value = match std::env::var(SOURCE_DATE_EPOCH) {
+        Ok(value) => value,
+        Err(_) => return Ok(None),
+    };
+
+    value
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map(|date| Some(date.format("%Y-%m-%d").to_string()))
+        .ok_or_else(|| General {
+            message: format!("{SOURCE_DATE_EPOCH} must be a Unix timestamp. Found: {value}"),
+        })
+}
+
+/// Ensures that a payload path is relative and does not traverse outside of the payload directory,
+/// and returns it without any `.` components
+pub fn validate_payload_path(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(InvalidPayloadPath {
+                    path: path.into(),
+                    details: "Path must be relative and must not contain '..'".into(),
+                })
+            }
+        }
+    }
+
+    if normalized.as_os_str().is_empty() {
+        return Err(InvalidPayloadPath {
+            path: path.into(),
+            details: "Path must not be empty".into(),
+        });
+    }
+
+    Ok(normalized)
 }
 
-fn is_hidden_file(name: &OsStr) -> bool {
-    name.to_str()
-        .map(|name| name.starts_with('.') && name != "." && name != "..")
-        .unwrap_or(false)
+/// Normalizes the path of a custom tag file, and validates that it's relative, outside of the
+/// payload directory, and is not one of the tag files that bagr writes
+fn validate_tag_file_path(path: &Path) -> Result<PathBuf> {
+    let normalized = match validate_payload_path(path) {
+        Err(InvalidPayloadPath { path, details }) => {
+            return Err(InvalidTagFilePath { path, details })
+        }
+        other => other?,
+    };
+
+    let mut components = normalized.components();
+    let first = components.next().map(|c| c.as_os_str().to_string_lossy());
+
+    match first {
+        Some(name) if name == DATA => Err(InvalidTagFilePath {
+            path: path.into(),
+            details: "Tag files must not be in the payload directory".into(),
+        }),
+        Some(name) if components.next().is_none() && is_reserved_tag_file(&name) => {
+            Err(InvalidTagFilePath {
+                path: path.into(),
+                details: format!("{} is written by bagr", name),
+            })
+        }
+        _ => Ok(normalized),
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -667,3 +3794,218 @@ fn convert_path_separator(path: &str) -> Cow<str> {
 fn convert_path_separator(path: &str) -> Cow<str> {
     path.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{self, ErrorKind, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use crate::bagit::bag::{find_missing_files, find_renames, thread_pool, BagWriter, IoRetries};
+    use crate::bagit::digest::{DigestAlgorithm, MultiDigestWriter};
+    use crate::bagit::encoding::PathEncoding;
+    use crate::bagit::error::Error::IoRead;
+
+    #[test]
+    fn only_transient_io_errors_are_retried() {
+        let retries = IoRetries {
+            max_retries: 3,
+            delay: Duration::ZERO,
+        };
+        let path = Path::new("data/file.txt");
+        let failure = |kind: ErrorKind| IoRead {
+            source: io::Error::from(kind),
+            path: PathBuf::from(path),
+        };
+
+        let mut attempts = 0;
+        let result = retries.run(path, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(failure(ErrorKind::TimedOut))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(3, result.unwrap());
+
+        let mut attempts = 0;
+        let result = retries.run(path, || {
+            attempts += 1;
+            Err::<(), _>(failure(ErrorKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(4, attempts);
+
+        let mut attempts = 0;
+        let result = retries.run(path, || {
+            attempts += 1;
+            Err::<(), _>(failure(ErrorKind::PermissionDenied))
+        });
+        assert!(result.is_err());
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn test_find_renames() {
+        let mut unlisted = find_renames(
+            Path::new("tests/cmd/rebag/detect-renames.in"),
+            &[DigestAlgorithm::Sha512],
+            PathEncoding::Standard,
+            &thread_pool(1).unwrap(),
+            None,
+        )
+        .unwrap();
+        unlisted.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let renames = unlisted
+            .iter()
+            .map(|file| (file.path.to_str().unwrap(), file.renamed_from.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("dir2/file3.txt", None),
+                ("moved/file2.txt", Some(Path::new("dir/file2.txt"))),
+                ("new.txt", None),
+            ],
+            renames
+        );
+    }
+
+    #[test]
+    fn add_payload_file_with_external_digests() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let algorithms = [DigestAlgorithm::Sha256];
+        let mut writer = BagWriter::create(dir, &algorithms).unwrap();
+
+        let file = File::create(writer.payload_dir().join("file.txt")).unwrap();
+        let mut digest_writer =
+            MultiDigestWriter::new(&[DigestAlgorithm::Sha256, DigestAlgorithm::Md5], file);
+        digest_writer.write_all(b"testing\n").unwrap();
+        let (mut file, digests) = digest_writer.into_parts();
+        file.flush().unwrap();
+
+        let missing = writer
+            .add_hashed_payload_file("other.txt", [(DigestAlgorithm::Md5, "abc".into())].into());
+        let size = writer.add_hashed_payload_file("file.txt", digests);
+        let bag = writer.finish().map(|bag| bag.quick_check());
+        let manifest = fs::read_to_string(dir.join("manifest-sha256.txt"));
+
+        assert!(missing.is_err());
+        assert_eq!(8, size.unwrap());
+        assert!(bag.unwrap().unwrap().is_valid());
+        assert_eq!(
+            "12a61f4e173fb3a11c05d6471f74728f76231b4a5fcd9667cef3af87a3ae4dc2  data/file.txt\n",
+            manifest.unwrap()
+        );
+    }
+
+    #[test]
+    fn incremental_updates_only_reuse_digests_of_files_with_unchanged_sizes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir, &[DigestAlgorithm::Sha256]).unwrap();
+        writer
+            .add_payload_file("a.txt", "aaaa\n".as_bytes())
+            .unwrap();
+        writer
+            .add_payload_file("b.txt", "bbbb\n".as_bytes())
+            .unwrap();
+        let bag = writer.finish().unwrap();
+
+        // The first update records the size and modification time of each file
+        let bag = bag.update().incremental(true).finalize().unwrap();
+
+        // a.txt changes size and b.txt does not, and both keep their modification times
+        for (name, contents) in [("a.txt", "aaaaaa\n"), ("b.txt", "cccc\n")] {
+            let path = dir.join("data").join(name);
+            let modified = fs::metadata(&path).unwrap().modified().unwrap();
+            fs::write(&path, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let result = bag.update().incremental(true).finalize();
+        let manifest = fs::read_to_string(dir.join("manifest-sha256.txt"));
+
+        result.unwrap();
+        let digest = |contents: &str| {
+            DigestAlgorithm::Sha256
+                .hash_hex(&mut contents.as_bytes())
+                .unwrap()
+        };
+        let manifest = manifest.unwrap();
+        assert!(manifest.contains(&format!("{}  data/a.txt", digest("aaaaaa\n"))));
+        assert!(manifest.contains(&format!("{}  data/b.txt", digest("bbbb\n"))));
+    }
+
+    #[test]
+    fn digest_for_returns_the_digests_in_lazily_loaded_manifests() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir, &[DigestAlgorithm::Sha256]).unwrap();
+        writer
+            .add_payload_file("file.txt", "testing\n".as_bytes())
+            .unwrap();
+        let bag = writer.finish().unwrap();
+        let expected = DigestAlgorithm::Sha256
+            .hash_hex(&mut "testing\n".as_bytes())
+            .unwrap();
+
+        assert_eq!(
+            Some(&expected),
+            bag.digest_for("data/file.txt", DigestAlgorithm::Sha256)
+                .unwrap()
+                .as_ref()
+        );
+        assert_eq!(
+            None,
+            bag.digest_for("data/other.txt", DigestAlgorithm::Sha256)
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            bag.digest_for("data/file.txt", DigestAlgorithm::Sha512)
+                .unwrap()
+        );
+
+        // The manifest was read by the first call, so it's not read again
+        fs::remove_file(dir.join("manifest-sha256.txt")).unwrap();
+        assert_eq!(
+            Some(expected),
+            bag.digest_for("data/file.txt", DigestAlgorithm::Sha256)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_find_missing_files() {
+        let base_dir = Path::new("tests/cmd/rebag/detect-renames.in");
+        let algorithms = [DigestAlgorithm::Sha512];
+        let pool = thread_pool(1).unwrap();
+        let unlisted =
+            find_renames(base_dir, &algorithms, PathEncoding::Standard, &pool, None).unwrap();
+
+        let renamed =
+            find_missing_files(base_dir, &algorithms, PathEncoding::Standard, &unlisted).unwrap();
+        let missing =
+            find_missing_files(base_dir, &algorithms, PathEncoding::Standard, &[]).unwrap();
+        let deleted = find_missing_files(
+            Path::new("tests/cmd/rebag/missing-files.in"),
+            &algorithms,
+            PathEncoding::Standard,
+            &[],
+        )
+        .unwrap();
+
+        assert!(renamed.is_empty());
+        assert_eq!(vec!["data/dir/file2.txt"], missing);
+        assert_eq!(vec!["data/dir/file2.txt"], deleted);
+    }
+}
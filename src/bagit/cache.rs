@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+use snafu::ResultExt;
+use walkdir::WalkDir;
+
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+
+/// Digests of payload files that were calculated when a bag was created or updated, along with
+/// the size and modification time that each file had when it was hashed. A file's cached digests
+/// are only used while its size and modification time are unchanged.
+///
+/// Each file's entry is stored in its `user.bagr.digests` extended attribute. Files that extended
+/// attributes cannot be set on, such as files on platforms or file systems that do not support
+/// them, are cached in `.bagr.cache` in the bag's base directory instead.
+///
+/// An entry is the file's size, its modification time in nanoseconds since the epoch, and its
+/// digests as comma separated `algorithm:digest` pairs, separated by spaces. Each line of
+/// `.bagr.cache` is an entry followed by a space and the file's strictly percent encoded path
+/// relative to the base directory.
+#[derive(Debug, Default)]
+pub struct DigestCache {
+    /// Cached files keyed on their paths relative to the bag's base directory
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    modified: u128,
+    digests: HashMap<DigestAlgorithm, HexDigest>,
+}
+
+impl DigestCache {
+    /// Reads the digest cache of the payload files in `base_dir` from their extended attributes
+    /// and `.bagr.cache`. The cache is empty if neither exist, and entries that cannot be parsed
+    /// are skipped.
+    pub fn load(base_dir: &Path) -> Result<Self> {
+        let mut entries = load_xattrs(base_dir)?;
+
+        let path = base_dir.join(DIGEST_CACHE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("There is no digest cache at {}", path.display());
+                return Ok(Self { entries });
+            }
+            Err(e) => return Err(e).context(IoReadSnafu { path }),
+        };
+
+        for line in LineReader::new(file) {
+            let line = line?;
+            match parse_line(&line) {
+                Some((path, entry)) => {
+                    entries.insert(path, entry);
+                }
+                None if line.is_empty() => {}
+                None => info!("Skipping invalid digest cache line: {line}"),
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Returns the cached digests of the file at `path`, relative to `base_dir`, when digests for
+    /// all of the `algorithms` are cached and the file's size and modification time have not
+    /// changed since they were calculated
+    pub fn get(
+        &self,
+        base_dir: &Path,
+        path: &Path,
+        algorithms: &[DigestAlgorithm],
+    ) -> Option<&HashMap<DigestAlgorithm, HexDigest>> {
+        let entry = self.entries.get(path)?;
+        let metadata = fs::metadata(base_dir.join(path)).ok()?;
+
+        let unchanged =
+            entry.size == metadata.len() && modified_nanos(&metadata) == Some(entry.modified);
+        let complete = algorithms
+            .iter()
+            .all(|algorithm| entry.digests.contains_key(algorithm));
+
+        (unchanged && complete).then_some(&entry.digests)
+    }
+
+    /// Returns true if the file at `path`, relative to the base directory, is cached with the
+    /// `size` and `modified` time
+    pub(crate) fn is_unchanged(&self, path: &Path, size: u64, modified: SystemTime) -> bool {
+        self.entries.get(path).is_some_and(|entry| {
+            entry.size == size && epoch_nanos(modified) == Some(entry.modified)
+        })
+    }
+
+    /// Returns the paths and cached digests of all of the files that `get()` returns digests for
+    pub fn unchanged<'a>(
+        &'a self,
+        base_dir: &'a Path,
+        algorithms: &'a [DigestAlgorithm],
+    ) -> impl Iterator<Item = (&'a Path, &'a HashMap<DigestAlgorithm, HexDigest>)> + 'a {
+        self.entries
+            .keys()
+            .filter_map(move |path| Some((path.as_path(), self.get(base_dir, path, algorithms)?)))
+    }
+
+    /// Writes the digests of the `files`, whose paths are relative to `base_dir`, to the digest
+    /// cache in `base_dir`, replacing the existing cache. The sizes and modification times of the
+    /// files are read when the cache is written.
+    pub fn write<'a, I>(base_dir: &Path, files: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a Path, &'a HashMap<DigestAlgorithm, HexDigest>)>,
+    {
+        let mut writer = DigestCacheWriter::create(base_dir)?;
+        for (file, digests) in files {
+            writer.write(file, digests)?;
+        }
+        writer.finish()
+    }
+}
+
+/// Writes a digest cache one file at a time, so that the digests of every file do not need to
+/// be held in memory at once
+#[derive(Debug)]
+pub struct DigestCacheWriter {
+    base_dir: PathBuf,
+    path: PathBuf,
+    /// `.bagr.cache`, which is only created once a file's extended attribute cannot be set
+    writer: Option<BufWriter<File>>,
+}
+
+impl DigestCacheWriter {
+    /// Creates the digest cache in `base_dir`, replacing the existing `.bagr.cache`
+    pub fn create(base_dir: &Path) -> Result<Self> {
+        let path = base_dir.join(DIGEST_CACHE);
+        info!("Writing digest cache in {}", base_dir.display());
+
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).context(IoDeleteSnafu { path })
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            base_dir: base_dir.to_path_buf(),
+            path,
+            writer: None,
+        })
+    }
+
+    /// Writes the `digests` of the `file`, whose path is relative to the base directory, to its
+    /// extended attribute, or to `.bagr.cache` if the attribute cannot be set. The file's size and
+    /// modification time are read when it's written.
+    pub fn write(
+        &mut self,
+        file: &Path,
+        digests: &HashMap<DigestAlgorithm, HexDigest>,
+    ) -> Result<()> {
+        let file_path = self.base_dir.join(file);
+        let metadata = fs::metadata(&file_path).context(IoStatSnafu { path: &file_path })?;
+        let modified = match modified_nanos(&metadata) {
+            Some(modified) => modified,
+            None => return Ok(()),
+        };
+
+        let mut digests = digests
+            .iter()
+            .map(|(algorithm, digest)| format!("{algorithm}:{digest}"))
+            .collect::<Vec<String>>();
+        digests.sort();
+        let entry = format!("{} {modified} {}", metadata.len(), digests.join(","));
+
+        if xattr::SUPPORTED_PLATFORM {
+            match xattr::set(&file_path, DIGEST_CACHE_XATTR, entry.as_bytes()) {
+                Ok(_) => return Ok(()),
+                Err(e) => info!(
+                    "Failed to set extended attribute on {}, caching its digests in {}: {}",
+                    file_path.display(),
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+
+        let relative = file
+            .to_str()
+            .ok_or_else(|| InvalidUtf8Path { path: file.into() })?
+            .replace('\\', "/");
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(BufWriter::new(
+                File::create(&self.path).context(IoCreateSnafu { path: &self.path })?,
+            )),
+        };
+
+        writeln!(writer, "{entry} {}", PathEncoding::Strict.encode(&relative))
+            .context(IoWriteSnafu { path: &self.path })
+    }
+
+    /// Flushes the cache to disk
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            Some(mut writer) => writer.flush().context(IoWriteSnafu { path: &self.path }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the cache entries in the extended attributes of the payload files in `base_dir`, keyed
+/// on their paths relative to `base_dir`
+fn load_xattrs(base_dir: &Path) -> Result<HashMap<PathBuf, CacheEntry>> {
+    let mut entries = HashMap::new();
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(entries);
+    }
+
+    for file in WalkDir::new(base_dir.join(DATA)) {
+        let file = match file {
+            Ok(file) => file,
+            Err(e) if e.io_error().map(|e| e.kind()) == Some(ErrorKind::NotFound) => break,
+            Err(e) => return Err(e).context(WalkFileSnafu {}),
+        };
+        if !file.file_type().is_file() {
+            continue;
+        }
+
+        let value = match xattr::get(file.path(), DIGEST_CACHE_XATTR) {
+            Ok(Some(value)) => value,
+            _ => continue,
+        };
+        let relative = file.path().strip_prefix(base_dir).unwrap();
+        match std::str::from_utf8(&value).ok().and_then(parse_entry) {
+            Some(entry) => {
+                entries.insert(relative.to_path_buf(), entry);
+            }
+            None => info!(
+                "Skipping invalid digest cache attribute on {}",
+                file.path().display()
+            ),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Parses a line of `.bagr.cache`, which is an entry followed by the file's path
+fn parse_line(line: &str) -> Option<(PathBuf, CacheEntry)> {
+    let mut parts = line.splitn(4, ' ');
+    let entry = [parts.next()?, parts.next()?, parts.next()?].join(" ");
+    let path = PathEncoding::Strict.decode(parts.next()?);
+    Some((PathBuf::from(path.as_ref()), parse_entry(&entry)?))
+}
+
+fn parse_entry(entry: &str) -> Option<CacheEntry> {
+    let mut parts = entry.splitn(3, ' ');
+    let size = parts.next()?.parse().ok()?;
+    let modified = parts.next()?.parse().ok()?;
+
+    let mut digests = HashMap::new();
+    for pair in parts.next()?.split(',') {
+        let (algorithm, digest) = pair.split_once(':')?;
+        digests.insert(algorithm.try_into().ok()?, HexDigest::from(digest));
+    }
+
+    Some(CacheEntry {
+        size,
+        modified,
+        digests,
+    })
+}
+
+/// Returns the file's modification time in nanoseconds since the epoch, if it's available
+fn modified_nanos(metadata: &Metadata) -> Option<u128> {
+    epoch_nanos(metadata.modified().ok()?)
+}
+
+fn epoch_nanos(time: SystemTime) -> Option<u128> {
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::time::UNIX_EPOCH;
+
+    use crate::bagit::cache::DigestCache;
+    use crate::bagit::consts::{DIGEST_CACHE, DIGEST_CACHE_XATTR};
+    use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+
+    #[test]
+    fn cached_digests_are_only_used_for_unchanged_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data/a file.txt"), "contents").unwrap();
+        fs::write(dir.join("data/changed.txt"), "contents").unwrap();
+
+        let digests = HashMap::from([(DigestAlgorithm::Sha256, HexDigest::from("abc123"))]);
+        let a = Path::new("data/a file.txt");
+        let changed = Path::new("data/changed.txt");
+        DigestCache::write(dir, [(a, &digests), (changed, &digests)]).unwrap();
+        fs::write(dir.join("data/changed.txt"), "changed contents").unwrap();
+
+        let cache = DigestCache::load(dir).unwrap();
+
+        assert_eq!(
+            Some(&digests),
+            cache.get(dir, a, &[DigestAlgorithm::Sha256])
+        );
+        assert_eq!(None, cache.get(dir, a, &[DigestAlgorithm::Sha512]));
+        assert_eq!(None, cache.get(dir, changed, &[DigestAlgorithm::Sha256]));
+    }
+
+    #[test]
+    fn digests_are_cached_in_extended_attributes_when_supported() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data/file.txt"), "contents").unwrap();
+
+        let digests = HashMap::from([(DigestAlgorithm::Sha256, HexDigest::from("abc123"))]);
+        let file = Path::new("data/file.txt");
+        DigestCache::write(dir, [(file, &digests)]).unwrap();
+
+        let attribute = xattr::get(dir.join(file), DIGEST_CACHE_XATTR).unwrap();
+        let sidecar = dir.join(DIGEST_CACHE).exists();
+        let cached = DigestCache::load(dir)
+            .unwrap()
+            .get(dir, file, &[DigestAlgorithm::Sha256])
+            .cloned();
+
+        match attribute {
+            Some(attribute) => {
+                assert!(String::from_utf8(attribute)
+                    .unwrap()
+                    .ends_with(" sha256:abc123"));
+                assert!(!sidecar);
+            }
+            None => assert!(sidecar),
+        }
+        assert_eq!(Some(digests), cached);
+    }
+
+    #[test]
+    fn digests_are_read_from_the_cache_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data/a file.txt"), "contents").unwrap();
+        let modified = fs::metadata(dir.join("data/a file.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        fs::write(
+            dir.join(DIGEST_CACHE),
+            format!("8 {modified} sha256:abc123 data/a file.txt\n"),
+        )
+        .unwrap();
+
+        let cache = DigestCache::load(dir).unwrap();
+        let cached = cache
+            .get(
+                dir,
+                Path::new("data/a file.txt"),
+                &[DigestAlgorithm::Sha256],
+            )
+            .cloned();
+
+        assert_eq!(
+            Some(HashMap::from([(
+                DigestAlgorithm::Sha256,
+                HexDigest::from("abc123")
+            )])),
+            cached
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::BuildHasher;
+use std::io::{BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+use log::info;
+use snafu::ResultExt;
+
+use crate::bagit::bag::Bag;
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+use crate::bagit::validate::sample_rank;
+
+/// Checksum file formats that a bag's payload manifests can be exported to, so that the payload
+/// can be verified by tools that do not support BagIt
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChecksumFormat {
+    /// The format of `md5sum`, which requires an md5 manifest
+    Md5sum,
+    /// The format of `sha1sum`, which requires a sha1 manifest
+    Sha1sum,
+    /// The format of `sha256sum`, which requires a sha256 manifest
+    Sha256sum,
+    /// The format of `sha512sum`, which requires a sha512 manifest
+    Sha512sum,
+    /// The format of `hashdeep`, with a column for each of the bag's md5, sha1, and sha256
+    /// manifests
+    Hashdeep,
+    /// CSV with a header row, and columns for the path, size, and the digest from each manifest
+    Csv,
+}
+
+/// Digests of source files that were already calculated by another tool, such as `sha256sum` or
+/// `hashdeep`, that are used instead of hashing the files again when a bag is created. Only a
+/// random sample of the files, none by default, are hashed to verify the imported digests.
+#[derive(Debug, Clone)]
+pub struct ImportedDigests {
+    /// Keyed on the canonical path of each file
+    digests: HashMap<PathBuf, HashMap<DigestAlgorithm, HexDigest>>,
+    sample_rate: f64,
+    seed: u64,
+}
+
+impl ImportedDigests {
+    pub fn new() -> Self {
+        Self {
+            digests: HashMap::new(),
+            sample_rate: 0.0,
+            seed: RandomState::new().hash_one(std::process::id()),
+        }
+    }
+
+    /// Reads the digests in a checksum file. The output of `md5sum`, `sha1sum`, `sha256sum`, and
+    /// `sha512sum` is supported in both their default and `--tag` formats, as is the output of
+    /// `hashdeep`. Relative paths in the file are resolved against the directory that contains
+    /// it. The algorithm of an untagged digest is inferred from its length.
+    pub fn read_checksum_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let dir = fs::canonicalize(path)
+            .context(IoStatSnafu { path })?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        info!("Importing digests from {}", path.display());
+
+        let file = File::open(path).context(IoReadSnafu { path })?;
+        // The algorithms of each column of a hashdeep file
+        let mut hashdeep_columns: Option<Vec<Option<DigestAlgorithm>>> = None;
+
+        for (i, line) in LineReader::new(BufReader::new(file)).enumerate() {
+            let line = line?;
+            let invalid = || InvalidChecksumFile {
+                path: path.into(),
+                details: format!("Line {} is not in a supported format", i + 1),
+            };
+
+            if let Some(header) = line.strip_prefix("%%%% ") {
+                if !header.starts_with("HASHDEEP") {
+                    hashdeep_columns = Some(
+                        header
+                            .split(',')
+                            .map(|column| column.trim().try_into().ok())
+                            .collect(),
+                    );
+                }
+                continue;
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entries = match &hashdeep_columns {
+                Some(columns) => parse_hashdeep_line(&line, columns),
+                None => parse_tagged_line(&line).or_else(|| parse_untagged_line(&line)),
+            }
+            .ok_or_else(invalid)?;
+
+            for (file, algorithm, digest) in entries {
+                self.digests
+                    .entry(dir.join(normalize(&file)))
+                    .or_default()
+                    .insert(algorithm, digest);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Sets the fraction of files, between 0 and 1, that are hashed to verify their imported
+    /// digests. The default is 0, and files are selected randomly.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The number of files that digests were imported for
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    /// Returns the digests that were imported for the file at the canonical `path`
+    pub(crate) fn get(&self, path: &Path) -> Option<&HashMap<DigestAlgorithm, HexDigest>> {
+        self.digests.get(path)
+    }
+
+    /// Returns true if the file at the canonical `path` was selected to be hashed to verify its
+    /// imported digests
+    pub(crate) fn is_sampled(&self, path: &Path) -> bool {
+        (sample_rank(self.seed, &path.to_string_lossy()) as f64 / u64::MAX as f64)
+            < self.sample_rate
+    }
+}
+
+impl Default for ImportedDigests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the digests in the payload manifests of `bag` to `writer` in a checksum file `format`,
+/// and returns the writer. Paths are relative to the bag's base directory, eg. `data/file.txt`,
+/// and are decoded using `encoding`.
+pub fn export_checksums<W: Write>(
+    bag: &Bag,
+    format: ChecksumFormat,
+    encoding: PathEncoding,
+    mut writer: W,
+) -> Result<W> {
+    let stream_path = Path::new(CHECKSUM_STREAM);
+
+    let algorithms = match format {
+        ChecksumFormat::Md5sum => vec![DigestAlgorithm::Md5],
+        ChecksumFormat::Sha1sum => vec![DigestAlgorithm::Sha1],
+        ChecksumFormat::Sha256sum => vec![DigestAlgorithm::Sha256],
+        ChecksumFormat::Sha512sum => vec![DigestAlgorithm::Sha512],
+        ChecksumFormat::Hashdeep => [
+            DigestAlgorithm::Md5,
+            DigestAlgorithm::Sha1,
+            DigestAlgorithm::Sha256,
+        ]
+        .into_iter()
+        .filter(|algorithm| bag.algorithms().contains(algorithm))
+        .collect(),
+        ChecksumFormat::Csv => bag.algorithms().to_vec(),
+    };
+    if let Some(missing) = algorithms
+        .iter()
+        .find(|algorithm| !bag.algorithms().contains(algorithm))
+    {
+        return Err(General {
+            message: format!("The bag does not have a {missing} payload manifest"),
+        });
+    }
+    if algorithms.is_empty() {
+        return Err(General {
+            message: "The bag does not have an md5, sha1, or sha256 payload manifest, which \
+                hashdeep requires"
+                .to_string(),
+        });
+    }
+
+    let names = algorithms
+        .iter()
+        .map(|algorithm| algorithm.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    match format {
+        ChecksumFormat::Hashdeep => write!(
+            writer,
+            "%%%% HASHDEEP-1.0\n%%%% size,{names},filename\n## Exported by bagr\n##\n"
+        ),
+        ChecksumFormat::Csv => writeln!(writer, "path,size,{names}"),
+        _ => Ok(()),
+    }
+    .context(IoWriteSnafu { path: stream_path })?;
+
+    for file in bag.payload_files_with_encoding(encoding)? {
+        let digests = algorithms
+            .iter()
+            .map(|algorithm| file.digest(*algorithm).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        match format {
+            ChecksumFormat::Hashdeep => {
+                let size = file.size().ok_or_else(|| General {
+                    message: format!("Payload file {} does not exist", file.path()),
+                })?;
+                writeln!(writer, "{size},{},{}", digests.join(","), file.path())
+            }
+            ChecksumFormat::Csv => writeln!(
+                writer,
+                "{},{},{}",
+                csv_field(file.path()),
+                file.size().map(|size| size.to_string()).unwrap_or_default(),
+                digests.join(",")
+            ),
+            // Like the sum tools, names that contain a backslash or a newline are escaped
+            _ if file.path().contains(['\\', '\n']) => writeln!(
+                writer,
+                "\\{}  {}",
+                digests[0],
+                file.path().replace('\\', "\\\\").replace('\n', "\\n")
+            ),
+            _ => writeln!(writer, "{}  {}", digests[0], file.path()),
+        }
+        .context(IoWriteSnafu { path: stream_path })?;
+    }
+
+    writer.flush().context(IoWriteSnafu { path: stream_path })?;
+    Ok(writer)
+}
+
+/// Quotes a CSV field if it contains a comma, a quote, or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parses a line in the BSD style that's written by `sha256sum --tag`, eg.
+/// `SHA256 (file.txt) = 1234...`
+fn parse_tagged_line(line: &str) -> Option<Vec<(String, DigestAlgorithm, HexDigest)>> {
+    let (algorithm, rest) = line.split_once(" (")?;
+    let (file, digest) = rest.rsplit_once(") = ")?;
+    let algorithm = DigestAlgorithm::try_from(algorithm.to_ascii_lowercase().as_str()).ok()?;
+    Some(vec![(file.to_string(), algorithm, HexDigest::from(digest))])
+}
+
+/// Parses a line in the default format that's written by `sha256sum`, eg. `1234...  file.txt`.
+/// File names that contain a backslash or a newline are escaped, and the line starts with a
+/// backslash.
+fn parse_untagged_line(line: &str) -> Option<Vec<(String, DigestAlgorithm, HexDigest)>> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (digest, file) = line.split_once(' ')?;
+    // The file name is preceded by a second space, or a '*' when it was read in binary mode
+    let file = file.strip_prefix([' ', '*'])?;
+
+    let algorithm = match digest.len() {
+        32 => DigestAlgorithm::Md5,
+        40 => DigestAlgorithm::Sha1,
+        64 => DigestAlgorithm::Sha256,
+        128 => DigestAlgorithm::Sha512,
+        _ => return None,
+    };
+    if !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let file = if escaped {
+        file.replace("\\n", "\n").replace("\\\\", "\\")
+    } else {
+        file.to_string()
+    };
+
+    Some(vec![(file, algorithm, HexDigest::from(digest))])
+}
+
+/// Parses a line of `hashdeep` output, which contains the columns that are named in its header,
+/// eg. `size,md5,sha256,filename`. The file name is always last, and may contain commas.
+fn parse_hashdeep_line(
+    line: &str,
+    columns: &[Option<DigestAlgorithm>],
+) -> Option<Vec<(String, DigestAlgorithm, HexDigest)>> {
+    let values = line.splitn(columns.len(), ',').collect::<Vec<_>>();
+    if values.len() != columns.len() {
+        return None;
+    }
+    let file = values.last()?;
+
+    Some(
+        columns
+            .iter()
+            .zip(&values)
+            .filter_map(|(algorithm, value)| {
+                algorithm.map(|algorithm| (file.to_string(), algorithm, HexDigest::from(*value)))
+            })
+            .collect(),
+    )
+}
+
+/// Removes `.` components from a path, because the source files are compared by their canonical
+/// paths
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::checksums::{parse_hashdeep_line, parse_tagged_line, parse_untagged_line};
+    use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+
+    #[test]
+    fn parse_supported_formats() {
+        let md5 = "d41d8cd98f00b204e9800998ecf8427e";
+
+        assert_eq!(
+            Some(vec![(
+                "dir/a file.txt".to_string(),
+                DigestAlgorithm::Md5,
+                HexDigest::from(md5)
+            )]),
+            parse_untagged_line(&format!("{md5}  dir/a file.txt"))
+        );
+        assert_eq!(
+            Some(vec![(
+                "a\\b\nc".to_string(),
+                DigestAlgorithm::Md5,
+                HexDigest::from(md5)
+            )]),
+            parse_untagged_line(&format!("\\{md5} *a\\\\b\\nc"))
+        );
+        assert_eq!(
+            Some(vec![(
+                "x (1).txt".to_string(),
+                DigestAlgorithm::Md5,
+                HexDigest::from(md5)
+            )]),
+            parse_tagged_line(&format!("MD5 (x (1).txt) = {md5}"))
+        );
+        assert_eq!(
+            Some(vec![(
+                "a,b.txt".to_string(),
+                DigestAlgorithm::Md5,
+                HexDigest::from(md5)
+            )]),
+            parse_hashdeep_line(
+                &format!("0,{md5},abc,a,b.txt"),
+                &[None, Some(DigestAlgorithm::Md5), None, None]
+            )
+        );
+        assert_eq!(None, parse_untagged_line("abc  file.txt"));
+    }
+}
@@ -4,9 +4,9 @@ use once_cell::sync::Lazy;
 use regex::Regex;
 
 pub static PAYLOAD_MANIFEST_MATCHER: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^manifest-([[:alnum:]]+)\.txt$").unwrap());
+    Lazy::new(|| Regex::new(r"^manifest-([[:alnum:]-]+)\.txt$").unwrap());
 pub static TAG_MANIFEST_MATCHER: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^tagmanifest-([[:alnum:]]+)\.txt$").unwrap());
+    Lazy::new(|| Regex::new(r"^tagmanifest-([[:alnum:]-]+)\.txt$").unwrap());
 
 pub const BAGR_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const BAGR_SRC_URL: &str = "https://github.com/pwinckles/bagr";
@@ -16,6 +16,35 @@ pub const BAGIT_DEFAULT_VERSION: BagItVersion = BAGIT_1_0;
 
 pub const DEFAULT_ALGORITHM: DigestAlgorithm = DigestAlgorithm::Sha512;
 
+/// The length that tag lines are folded at when they're written
+pub const DEFAULT_TAG_LINE_WIDTH: usize = 79;
+/// The whitespace that folded tag lines are indented with
+pub const TAG_LINE_INDENT: &str = "  ";
+/// The number of bytes that files prefetched during validation may occupy in memory
+pub const DEFAULT_PREFETCH_MEMORY: u64 = 256 * 1024 * 1024;
+/// The longest payload path, relative to the bag's base directory, that's portable. This leaves
+/// room within the 260 character Windows limit for the directory the bag is in.
+pub const DEFAULT_MAX_PATH_LENGTH: usize = 200;
+/// The longest file name that's portable, which is the limit of most filesystems
+pub const MAX_FILE_NAME_LENGTH: usize = 255;
+/// Names that Windows reserves for devices, with or without an extension
+pub const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// OS error codes of IO errors that are transient on network mounts: EIO and ESTALE
+#[cfg(unix)]
+pub const TRANSIENT_OS_ERRORS: [i32; 2] = [5, 116];
+/// OS error codes of IO errors that are transient on network shares: ERROR_UNEXP_NET_ERR,
+/// ERROR_NETNAME_DELETED, and ERROR_SEM_TIMEOUT
+#[cfg(windows)]
+pub const TRANSIENT_OS_ERRORS: [i32; 3] = [59, 64, 121];
+#[cfg(not(any(unix, windows)))]
+pub const TRANSIENT_OS_ERRORS: [i32; 0] = [];
+
+/// The environment variable that holds the Unix timestamp to date reproducible bags with
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
 pub const UTF_8: &str = "UTF-8";
 
 pub const CR: char = '\r';
@@ -24,16 +53,42 @@ pub const TAB: char = '\t';
 pub const SPACE: char = ' ';
 pub const CR_B: u8 = b'\r';
 pub const LF_B: u8 = b'\n';
+/// The byte order mark that some Windows tools write at the start of UTF-8 text files
+pub const UTF_8_BOM: &[u8] = b"\xEF\xBB\xBF";
 pub const BUF_SIZE: usize = 8 * 1024;
 
 // Filenames
 pub const BAGIT_TXT: &str = "bagit.txt";
 pub const BAG_INFO_TXT: &str = "bag-info.txt";
 pub const FETCH_TXT: &str = "fetch.txt";
+pub const EXCLUSIONS_TXT: &str = "exclusions.txt";
+/// Records that the source did not change while a bag was copied from it
+pub const TRANSFER_CONSISTENCY_TXT: &str = "transfer-consistency.txt";
+/// Records changes to the payload, such as pruned files, that are not evident from the manifests
+pub const HISTORY_TXT: &str = "history.txt";
+/// Held while a bag is created or updated, and contains the ID of the process that holds it
+pub const BAG_LOCK: &str = ".bagr.lock";
+/// Caches the digests, sizes, and modification times of payload files
+pub const DIGEST_CACHE: &str = ".bagr.cache";
+/// The extended attribute that caches the digest, size, and modification time of a payload file
+pub const DIGEST_CACHE_XATTR: &str = "user.bagr.digests";
+/// Records when the digests of each payload file were last verified by an audit
+pub const AUDIT_STATE: &str = ".bagr.audit";
+/// Lists gitignore-style patterns of source files to exclude from a new bag
+pub const BAGIGNORE: &str = ".bagignore";
 pub const DATA: &str = "data";
+/// The directory that excluded source files are moved aside to while a bag is created in place
+pub const EXCLUDED_DIR: &str = "excluded";
 pub const PAYLOAD_MANIFEST_PREFIX: &str = "manifest";
 pub const TAG_MANIFEST_PREFIX: &str = "tagmanifest";
 
+/// The name used in place of the archive path in errors about tar archives that are streamed
+pub const TAR_STREAM: &str = "<tar stream>";
+/// The name used in place of the output path in errors about exported checksum files
+pub const CHECKSUM_STREAM: &str = "<checksum stream>";
+/// The name used in place of the output path in errors about file listings
+pub const LIST_STREAM: &str = "<list stream>";
+
 // bagit.txt tag labels
 pub const LABEL_BAGIT_VERSION: &str = "BagIt-Version";
 pub const LABEL_FILE_ENCODING: &str = "Tag-File-Character-Encoding";
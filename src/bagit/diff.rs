@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::Path;
+
+use log::info;
+use rayon::prelude::*;
+use snafu::ResultExt;
+use walkdir::WalkDir;
+
+use crate::bagit::bag::{open_bag, thread_pool, Bag};
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::manifest::read_payload_manifest;
+
+/// The differences between two bags. Paths are relative to the bags' base directories, and
+/// everything is reported from the perspective of the first bag, so files that are only in the
+/// second bag are added.
+#[derive(Debug, Default)]
+pub struct BagDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+    tags: Vec<TagDiff>,
+}
+
+/// A bag-info.txt label whose values differ between two bags. A label that's missing from one of
+/// the bags has no values in it.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TagDiff {
+    label: String,
+    old_values: Vec<String>,
+    new_values: Vec<String>,
+}
+
+/// Compares two bags' payloads and bag-info.txt tags. By default, the payloads are compared by
+/// the digests in the bags' payload manifests, using the strongest algorithm the bags have in
+/// common. When `compare_content` is true, the digests of the payload files are calculated
+/// instead, which catches files that do not match their manifests, and makes it possible to
+/// compare bags that do not share an algorithm.
+pub fn diff_bags<L: AsRef<Path>, R: AsRef<Path>>(
+    left: L,
+    right: R,
+    encoding: PathEncoding,
+    compare_content: bool,
+) -> Result<BagDiff> {
+    let (left, right) = (left.as_ref(), right.as_ref());
+    info!("Comparing bag {} to {}", left.display(), right.display());
+
+    let left_bag = open_bag(left)?;
+    let right_bag = open_bag(right)?;
+
+    let common = left_bag
+        .algorithms()
+        .iter()
+        .filter(|algorithm| right_bag.algorithms().contains(algorithm))
+        .copied()
+        .max_by_key(algorithm_strength);
+
+    let (left_digests, right_digests) = if compare_content {
+        let algorithm = common.unwrap_or(DigestAlgorithm::Sha512);
+        (
+            payload_digests(left, algorithm)?,
+            payload_digests(right, algorithm)?,
+        )
+    } else {
+        let algorithm = common.ok_or_else(|| General {
+            message: "The bags do not have payload manifests with an algorithm in common. \
+                Compare their content instead"
+                .to_string(),
+        })?;
+        info!("Comparing the bags' {algorithm} payload manifests");
+        (
+            manifest_digests(left, algorithm, encoding)?,
+            manifest_digests(right, algorithm, encoding)?,
+        )
+    };
+
+    let mut diff = BagDiff::default();
+
+    for (path, digest) in &left_digests {
+        match right_digests.get(path) {
+            Some(other) if other != digest => diff.changed.push(path.clone()),
+            Some(_) => {}
+            None => diff.removed.push(path.clone()),
+        }
+    }
+    diff.added = right_digests
+        .into_keys()
+        .filter(|path| !left_digests.contains_key(path))
+        .collect();
+
+    diff.tags = diff_tags(&left_bag, &right_bag);
+
+    Ok(diff)
+}
+
+impl BagDiff {
+    /// True if the bags have the same payload and bag-info.txt tags
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.tags.is_empty()
+    }
+
+    /// Payload files that are only in the second bag
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    /// Payload files that are only in the first bag
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// Payload files that are in both bags, but have different digests
+    pub fn changed(&self) -> &[String] {
+        &self.changed
+    }
+
+    /// bag-info.txt labels that have different values, ordered by label
+    pub fn tags(&self) -> &[TagDiff] {
+        &self.tags
+    }
+}
+
+impl TagDiff {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The label's values in the first bag
+    pub fn old_values(&self) -> &[String] {
+        &self.old_values
+    }
+
+    /// The label's values in the second bag
+    pub fn new_values(&self) -> &[String] {
+        &self.new_values
+    }
+}
+
+/// Weak algorithms are the least preferred, and longer digests are preferred over shorter ones
+fn algorithm_strength(algorithm: &DigestAlgorithm) -> u8 {
+    match algorithm {
+        DigestAlgorithm::Md5 => 0,
+        DigestAlgorithm::Sha1 => 1,
+        DigestAlgorithm::Sha256 => 2,
+        DigestAlgorithm::Sha3_256 => 3,
+        DigestAlgorithm::Blake2b256 => 4,
+        DigestAlgorithm::Sha512 => 5,
+        DigestAlgorithm::Sha3_512 => 6,
+        DigestAlgorithm::Blake2b512 => 7,
+    }
+}
+
+fn manifest_digests(
+    base_dir: &Path,
+    algorithm: DigestAlgorithm,
+    encoding: PathEncoding,
+) -> Result<BTreeMap<String, HexDigest>> {
+    Ok(read_payload_manifest(base_dir, algorithm, encoding)?
+        .iter()
+        .map(|(path, digest)| (path.clone(), digest.clone()))
+        .collect())
+}
+
+/// Calculates the digest of every file in the bag's payload directory
+fn payload_digests(
+    base_dir: &Path,
+    algorithm: DigestAlgorithm,
+) -> Result<BTreeMap<String, HexDigest>> {
+    let mut paths = Vec::new();
+    for file in WalkDir::new(base_dir.join(DATA)) {
+        let file = file.context(WalkFileSnafu {})?;
+        if file.file_type().is_file() {
+            let relative = file.path().strip_prefix(base_dir).unwrap();
+            let path = relative.to_str().ok_or_else(|| InvalidUtf8Path {
+                path: relative.to_path_buf(),
+            })?;
+            paths.push((path.replace('\\', "/"), file.into_path()));
+        }
+    }
+
+    info!(
+        "Calculating {algorithm} digests of {} payload files in {}",
+        paths.len(),
+        base_dir.display()
+    );
+
+    thread_pool(0)?.install(|| {
+        paths
+            .into_par_iter()
+            .map(|(path, file_path)| {
+                let mut file = File::open(&file_path).context(IoReadSnafu { path: &file_path })?;
+                Ok((path, algorithm.hash_hex(&mut file)?))
+            })
+            .collect()
+    })
+}
+
+/// Compares the values of every bag-info.txt label, ignoring the order of the values of
+/// repeated labels. Labels are compared case insensitively.
+fn diff_tags(left: &Bag, right: &Bag) -> Vec<TagDiff> {
+    let left_tags = tag_values(left);
+    let right_tags = tag_values(right);
+
+    left_tags
+        .keys()
+        .chain(right_tags.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|key| {
+            let old = left_tags.get(key);
+            let new = right_tags.get(key);
+            let sorted = |tags: Option<&(String, Vec<String>)>| {
+                let mut values = tags.map(|(_, values)| values.clone()).unwrap_or_default();
+                values.sort();
+                values
+            };
+            if sorted(old) == sorted(new) {
+                return None;
+            }
+            let label = old.or(new).map(|(label, _)| label.clone()).unwrap();
+            Some(TagDiff {
+                label,
+                old_values: old.map(|(_, values)| values.clone()).unwrap_or_default(),
+                new_values: new.map(|(_, values)| values.clone()).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// The label and values of every tag, keyed by the lowercase label
+fn tag_values(bag: &Bag) -> BTreeMap<String, (String, Vec<String>)> {
+    let mut tags: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    for tag in bag.bag_info().as_ref() {
+        tags.entry(tag.label().to_lowercase())
+            .or_insert_with(|| (tag.label().to_string(), Vec::new()))
+            .1
+            .push(tag.value().to_string());
+    }
+    tags
+}
@@ -1,26 +1,32 @@
-use core::{cmp, fmt};
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter};
-use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
 
 use blake2::digest::consts::U32;
 use blake2::{Blake2b, Blake2b512};
 use digest::{Digest, DynDigest};
+use log::warn;
 use md5::Md5;
 use sha1::Sha1;
 use sha2::{Sha256, Sha512};
+use sha3::{Sha3_256, Sha3_512};
 use snafu::ResultExt;
-use strum_macros::{Display as EnumDisplay, EnumString};
+use strum_macros::{Display as EnumDisplay, EnumIter, EnumString};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::bagit::error::Error::{DigestMismatch, WeakAlgorithm};
 use crate::bagit::error::*;
 
 type Blake2b256 = Blake2b<U32>;
 
 /// Enum of all supported digest algorithms
-#[derive(Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, EnumString, EnumDisplay)]
+#[derive(
+    Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, EnumString, EnumDisplay, EnumIter,
+)]
 pub enum DigestAlgorithm {
     #[strum(serialize = "md5")]
     Md5,
@@ -30,35 +36,107 @@ pub enum DigestAlgorithm {
     Sha256,
     #[strum(serialize = "sha512")]
     Sha512,
+    #[strum(serialize = "sha3-256")]
+    Sha3_256,
+    #[strum(serialize = "sha3-512")]
+    Sha3_512,
     #[strum(serialize = "blake2b256")]
     Blake2b256,
     #[strum(serialize = "blake2b512")]
     Blake2b512,
 }
 
-/// Reader wrapper that calculates a digest while reading
+/// How to treat weak digest algorithms, md5 and sha1, when they're used to create manifests or
+/// are the only algorithms a bag's payload manifests use
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum WeakAlgorithmPolicy {
+    /// Weak algorithms are used without complaint
+    Allow,
+    /// Weak algorithms are used, but a warning is emitted
+    #[default]
+    Warn,
+    /// Weak algorithms are rejected
+    Forbid,
+}
+
+/// Reader wrapper that calculates a digest while reading. Created by `DigestAlgorithm::reader()`.
 pub struct DigestReader<R: Read> {
     digest: Box<dyn DynDigest>,
     inner: R,
 }
 
-/// Writer wrapper that calculates a digest while writing
+/// Reader wrapper that calculates multiple digests while reading, in a single pass
+pub struct MultiDigestReader<R: Read> {
+    digests: HashMap<DigestAlgorithm, Box<dyn DynDigest + Send>>,
+    inner: R,
+}
+
+/// Reader wrapper that calculates digests while reading, and checks them against the expected
+/// digests when the end of the reader is reached. A mismatch is returned from `read()` as an
+/// `io::ErrorKind::InvalidData` error that wraps an `Error::DigestMismatch`, so content is only
+/// verified once it has been read to the end. Created by `DigestAlgorithm::verifying_reader()`.
+pub struct VerifyingReader<R: Read> {
+    digests: BTreeMap<DigestAlgorithm, (Box<dyn DynDigest + Send>, HexDigest)>,
+    inner: R,
+    verified: bool,
+    mismatch: Option<(DigestAlgorithm, HexDigest)>,
+}
+
+/// Writer wrapper that calculates a digest while writing. Created by `DigestAlgorithm::writer()`.
 pub struct DigestWriter<W: Write> {
     digest: Box<dyn DynDigest>,
     inner: W,
 }
 
-/// Writer wrapper that calculates multiple digests while writing
+/// Writer wrapper that calculates multiple digests while writing, in a single pass. This is how
+/// bagr hashes payload files, so the digests are the same as the ones bagr writes to manifests.
+/// Wrap `io::sink()` to hash data without writing it anywhere.
 pub struct MultiDigestWriter<W: Write> {
-    digests: HashMap<DigestAlgorithm, Box<dyn DynDigest>>,
+    digests: HashMap<DigestAlgorithm, Box<dyn DynDigest + Send>>,
+    times: HashMap<DigestAlgorithm, Duration>,
     inner: W,
 }
 
-/// A digest encoded as a hex string
-#[derive(Debug, Eq, Clone)]
+/// Applies the `policy` to the `algorithms` that are going to be used to create manifests. Weak
+/// algorithms are logged as warnings, or are an error if they're forbidden.
+pub fn check_weak_algorithms(
+    algorithms: &[DigestAlgorithm],
+    policy: WeakAlgorithmPolicy,
+) -> Result<()> {
+    for algorithm in algorithms.iter().filter(|algorithm| algorithm.is_weak()) {
+        match policy {
+            WeakAlgorithmPolicy::Allow => {}
+            WeakAlgorithmPolicy::Warn => warn!(
+                "{algorithm} is a weak digest algorithm and is deprecated. Use sha256 or sha512 \
+                instead"
+            ),
+            WeakAlgorithmPolicy::Forbid => {
+                return Err(WeakAlgorithm {
+                    algorithm: *algorithm,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A digest encoded as a lowercase hex string.
+///
+/// A `HexDigest` is created from a `String` or `&str` that's already hex encoded, or from the raw
+/// bytes of a digest in a `Vec<u8>`, which are hex encoded. Hex strings are canonicalized by
+/// trimming surrounding whitespace and converting them to lowercase, because manifests that are
+/// produced by other tools sometimes use uppercase hex, so digests are compared case
+/// insensitively and are always written in lowercase.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
 pub struct HexDigest(String);
 
 impl DigestAlgorithm {
+    /// Returns true if the algorithm is no longer considered collision resistant. These
+    /// algorithms are supported for compatibility with existing bags.
+    pub fn is_weak(&self) -> bool {
+        matches!(self, DigestAlgorithm::Md5 | DigestAlgorithm::Sha1)
+    }
+
     /// Hashes the input and returns its hex encoded digest
     pub fn hash_hex(&self, data: &mut impl Read) -> Result<HexDigest> {
         let mut hasher = self.reader(data);
@@ -66,22 +144,44 @@ impl DigestAlgorithm {
         Ok(hasher.finalize_hex())
     }
 
+    /// Hashes the asynchronous input and returns its hex encoded digest
+    #[cfg(feature = "tokio")]
+    pub async fn hash_hex_async<R: AsyncRead + Unpin>(&self, data: &mut R) -> Result<HexDigest> {
+        let mut digest = self.new_digest();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = data.read(&mut buf).await.context(IoGeneralSnafu {})?;
+            if read == 0 {
+                break;
+            }
+            digest.update(&buf[..read]);
+        }
+        Ok(digest.finalize().to_vec().into())
+    }
+
     /// Wraps the specified reader in a `DigestReader`
     pub fn reader<R: Read>(&self, reader: R) -> DigestReader<R> {
         DigestReader::new(self.new_digest(), reader)
     }
 
+    /// Wraps the specified reader in a `VerifyingReader` that checks that its digest is `expected`
+    pub fn verifying_reader<R: Read>(&self, expected: HexDigest, reader: R) -> VerifyingReader<R> {
+        VerifyingReader::new(*self, expected, reader)
+    }
+
     /// Wraps the specified writer in a `DigestWriter`
     pub fn writer<W: Write>(&self, writer: W) -> DigestWriter<W> {
         DigestWriter::new(self.new_digest(), writer)
     }
 
-    fn new_digest(&self) -> Box<dyn DynDigest> {
+    fn new_digest(&self) -> Box<dyn DynDigest + Send> {
         match self {
             DigestAlgorithm::Md5 => Box::new(Md5::new()),
             DigestAlgorithm::Sha1 => Box::new(Sha1::new()),
             DigestAlgorithm::Sha256 => Box::new(Sha256::new()),
             DigestAlgorithm::Sha512 => Box::new(Sha512::new()),
+            DigestAlgorithm::Sha3_256 => Box::new(Sha3_256::new()),
+            DigestAlgorithm::Sha3_512 => Box::new(Sha3_512::new()),
             DigestAlgorithm::Blake2b256 => Box::new(Blake2b256::new()),
             DigestAlgorithm::Blake2b512 => Box::new(Blake2b512::new()),
         }
@@ -89,6 +189,7 @@ impl DigestAlgorithm {
 }
 
 impl<R: Read> DigestReader<R> {
+    /// Wraps `reader` and updates `digest` with everything that's read from it
     pub fn new(digest: Box<dyn DynDigest>, reader: R) -> Self {
         Self {
             digest,
@@ -96,6 +197,7 @@ impl<R: Read> DigestReader<R> {
         }
     }
 
+    /// Returns the digest of everything that was read
     pub fn finalize_hex(self) -> HexDigest {
         self.digest.finalize().to_vec().into()
     }
@@ -113,7 +215,120 @@ impl<R: Read> Read for DigestReader<R> {
     }
 }
 
+impl<R: Read> MultiDigestReader<R> {
+    /// Wraps `reader` and calculates a digest for each of the `algorithms` of everything that's
+    /// read from it
+    pub fn new(algorithms: &[DigestAlgorithm], reader: R) -> Self {
+        let mut digests = HashMap::with_capacity(algorithms.len());
+        for algorithm in algorithms {
+            digests.insert(*algorithm, algorithm.new_digest());
+        }
+
+        Self {
+            digests,
+            inner: reader,
+        }
+    }
+
+    /// Returns the digests of everything that was read, keyed by algorithm
+    pub fn finalize_hex(self) -> HashMap<DigestAlgorithm, HexDigest> {
+        finalize_all(self.digests)
+    }
+}
+
+impl<R: Read> Read for MultiDigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf)?;
+
+        if result > 0 {
+            let part = &buf[0..result];
+            self.digests
+                .values_mut()
+                .for_each(|digest| digest.update(part));
+        }
+
+        Ok(result)
+    }
+}
+
+impl<R: Read> VerifyingReader<R> {
+    /// Wraps `reader` and checks that the `algorithm` digest of everything that's read from it is
+    /// `expected`
+    pub fn new(algorithm: DigestAlgorithm, expected: HexDigest, reader: R) -> Self {
+        Self {
+            digests: BTreeMap::from([(algorithm, (algorithm.new_digest(), expected))]),
+            inner: reader,
+            verified: false,
+            mismatch: None,
+        }
+    }
+
+    /// Also checks that the `algorithm` digest of everything that's read is `expected`, replacing
+    /// the digest that was expected for the algorithm, if any. Must be called before reading.
+    pub fn with_expected(mut self, algorithm: DigestAlgorithm, expected: HexDigest) -> Self {
+        self.digests
+            .insert(algorithm, (algorithm.new_digest(), expected));
+        self
+    }
+
+    /// Returns true once the end of the reader was reached and every digest matched
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Returns the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Compares every digest to the one that's expected. The digests are only finalized once, and
+    /// the first mismatch, in algorithm order, is remembered so that it's returned on every read
+    /// after the end is reached.
+    fn verify(&mut self) -> io::Result<()> {
+        if self.mismatch.is_none() && !self.verified {
+            for (algorithm, (digest, expected)) in &mut self.digests {
+                let actual = HexDigest::from(digest.finalize_reset().to_vec());
+                if actual != *expected {
+                    self.mismatch = Some((*algorithm, actual));
+                    break;
+                }
+            }
+            self.verified = self.mismatch.is_none();
+        }
+
+        match &self.mismatch {
+            Some((algorithm, actual)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                DigestMismatch {
+                    algorithm: *algorithm,
+                    expected: self.digests[algorithm].1.to_string(),
+                    actual: actual.to_string(),
+                },
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf)?;
+
+        if result > 0 {
+            let part = &buf[0..result];
+            self.digests
+                .values_mut()
+                .for_each(|(digest, _)| digest.update(part));
+        } else if !buf.is_empty() {
+            self.verify()?;
+        }
+
+        Ok(result)
+    }
+}
+
 impl<W: Write> DigestWriter<W> {
+    /// Wraps `writer` and updates `digest` with everything that's written to it
     pub fn new(digest: Box<dyn DynDigest>, writer: W) -> Self {
         Self {
             digest,
@@ -121,6 +336,7 @@ impl<W: Write> DigestWriter<W> {
         }
     }
 
+    /// Returns the digest of everything that was written
     pub fn finalize_hex(self) -> HexDigest {
         self.digest.finalize().to_vec().into()
     }
@@ -143,6 +359,8 @@ impl<W: Write> Write for DigestWriter<W> {
 }
 
 impl<W: Write> MultiDigestWriter<W> {
+    /// Wraps `writer` and calculates a digest for each of the `algorithms` of everything that's
+    /// written to it
     pub fn new(algorithms: &[DigestAlgorithm], writer: W) -> Self {
         let mut digests = HashMap::with_capacity(algorithms.len());
         for algorithm in algorithms {
@@ -151,21 +369,36 @@ impl<W: Write> MultiDigestWriter<W> {
 
         Self {
             digests,
+            times: HashMap::with_capacity(algorithms.len()),
             inner: writer,
         }
     }
 
-    #[allow(unused)]
+    /// The time that was spent calculating each digest so far, keyed by algorithm
+    pub fn digest_times(&self) -> &HashMap<DigestAlgorithm, Duration> {
+        &self.times
+    }
+
+    /// The algorithms that digests are calculated with, in no particular order
+    pub fn algorithms(&self) -> impl Iterator<Item = &DigestAlgorithm> {
+        self.digests.keys()
+    }
+
+    /// The wrapped writer
     pub fn inner(&self) -> &W {
         &self.inner
     }
 
+    /// Returns the digests of everything that was written, keyed by algorithm. The wrapped
+    /// writer is not flushed.
     pub fn finalize_hex(self) -> HashMap<DigestAlgorithm, HexDigest> {
-        let mut results = HashMap::with_capacity(self.digests.len());
-        for (algorithm, digest) in self.digests {
-            results.insert(algorithm, digest.finalize().to_vec().into());
-        }
-        results
+        finalize_all(self.digests)
+    }
+
+    /// Returns the wrapped writer along with the digests of everything that was written to it.
+    /// The wrapped writer is not flushed.
+    pub fn into_parts(self) -> (W, HashMap<DigestAlgorithm, HexDigest>) {
+        (self.inner, finalize_all(self.digests))
     }
 }
 
@@ -175,9 +408,11 @@ impl<W: Write> Write for MultiDigestWriter<W> {
 
         if result > 0 {
             let part = &buf[0..result];
-            self.digests
-                .values_mut()
-                .for_each(|digest| digest.update(part));
+            for (algorithm, digest) in self.digests.iter_mut() {
+                let started = Instant::now();
+                digest.update(part);
+                *self.times.entry(*algorithm).or_default() += started.elapsed();
+            }
         }
 
         Ok(result)
@@ -188,6 +423,17 @@ impl<W: Write> Write for MultiDigestWriter<W> {
     }
 }
 
+impl HexDigest {
+    /// The hex encoded digest, in lowercase
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    fn canonicalize(digest: &str) -> String {
+        digest.trim().to_ascii_lowercase()
+    }
+}
+
 impl From<Vec<u8>> for HexDigest {
     fn from(bytes: Vec<u8>) -> Self {
         Self(hex::encode(bytes))
@@ -196,13 +442,13 @@ impl From<Vec<u8>> for HexDigest {
 
 impl From<&str> for HexDigest {
     fn from(digest: &str) -> Self {
-        Self(digest.to_string())
+        Self(Self::canonicalize(digest))
     }
 }
 
 impl From<String> for HexDigest {
     fn from(digest: String) -> Self {
-        Self(digest)
+        Self::from(digest.as_str())
     }
 }
 
@@ -218,67 +464,31 @@ impl AsRef<str> for HexDigest {
     }
 }
 
-impl Ord for HexDigest {
-    /// Case insensitive string comparison
-    fn cmp(&self, other: &Self) -> Ordering {
-        // Based on SliceOrd::compare()
-        // This is slightly more efficient than converting the entire str to lower case and then
-        // comparing because only a single iteration is needed.
-
-        let left = self.0.as_bytes();
-        let right = other.0.as_bytes();
-
-        let l = cmp::min(left.len(), right.len());
-
-        // Slice to the loop iteration range to enable bound check
-        // elimination in the compiler
-        let lhs = &left[..l];
-        let rhs = &right[..l];
-
-        for i in 0..l {
-            match lhs[i]
-                .to_ascii_lowercase()
-                .cmp(&rhs[i].to_ascii_lowercase())
-            {
-                Ordering::Equal => (),
-                non_eq => return non_eq,
-            }
-        }
-
-        left.len().cmp(&right.len())
-    }
-}
-
-impl PartialOrd for HexDigest {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl PartialEq for HexDigest {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.eq_ignore_ascii_case(&other.0)
-    }
-}
-
-impl Hash for HexDigest {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.to_ascii_lowercase().hash(state);
-    }
-}
-
 impl Display for HexDigest {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+fn finalize_all(
+    digests: HashMap<DigestAlgorithm, Box<dyn DynDigest + Send>>,
+) -> HashMap<DigestAlgorithm, HexDigest> {
+    digests
+        .into_iter()
+        .map(|(algorithm, digest)| (algorithm, digest.finalize().to_vec().into()))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
 
+    use std::io::Read;
+
     use crate::bagit::digest::DigestAlgorithm;
     use crate::bagit::digest::MultiDigestWriter;
+    use crate::bagit::digest::{HexDigest, VerifyingReader};
+    use crate::bagit::error::Error;
 
     #[test]
     fn calculate_digest_while_reading() {
@@ -363,6 +573,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn verify_digests_at_the_end_of_the_reader() {
+        let sha256 = "12A61F4E173FB3A11C05D6471F74728F76231B4A5FCD9667CEF3AF87A3AE4DC2";
+        let md5 = "eb1a3227cdc3fedbaec2fe38bf6c044a";
+
+        let mut reader = DigestAlgorithm::Sha256
+            .verifying_reader(HexDigest::from(sha256), "testing\n".as_bytes())
+            .with_expected(DigestAlgorithm::Md5, HexDigest::from(md5));
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+
+        assert_eq!("testing\n", output);
+        assert!(reader.is_verified());
+
+        let mut reader = VerifyingReader::new(
+            DigestAlgorithm::Sha256,
+            HexDigest::from(sha256),
+            "tested\n".as_bytes(),
+        );
+        let error = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(matches!(
+            error.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+            Some(Error::DigestMismatch {
+                algorithm: DigestAlgorithm::Sha256,
+                ..
+            })
+        ));
+        assert!(!reader.is_verified());
+        assert!(reader.read(&mut [0; 8]).is_err());
+    }
+
+    #[test]
+    fn hex_digests_are_canonicalized() {
+        let digest =
+            HexDigest::from(" 12A61f4E173FB3A11C05D6471F74728F76231B4A5FCD9667CEF3AF87A3AE4DC2\t");
+
+        assert_eq!(
+            "12a61f4e173fb3a11c05d6471f74728f76231b4a5fcd9667cef3af87a3ae4dc2",
+            digest.as_str()
+        );
+        assert_eq!(
+            DigestAlgorithm::Sha256
+                .hash_hex(&mut "testing\n".as_bytes())
+                .unwrap(),
+            digest
+        );
+        assert_eq!(HexDigest::from("ABC"), HexDigest::from("abc".to_string()));
+    }
+
+    #[test]
+    fn sha3_test() {
+        let digest = DigestAlgorithm::Sha3_256
+            .hash_hex(&mut "test".as_bytes())
+            .unwrap();
+        assert_eq!(
+            "36f028580bb02cc8272a9a020f4200e346e276ae664e45ee80745574e2f5ab80".to_string(),
+            digest.to_string()
+        );
+
+        let digest = DigestAlgorithm::Sha3_512
+            .hash_hex(&mut "test".as_bytes())
+            .unwrap();
+        assert_eq!("9ece086e9bac491fac5c1d1046ca11d737b92a2b2ebd93f005d7b710110c0a678288166e7fbe796883a4f2e9b3ca9f484f521d0ce464345cc1aec96779149c14".to_string(),
+                   digest.to_string());
+    }
+
     #[test]
     fn blake2b_test() {
         let digest = DigestAlgorithm::Blake2b256
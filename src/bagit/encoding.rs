@@ -4,6 +4,47 @@ use std::borrow::Cow;
 const CR_ENCODED: &str = "%0D";
 const LF_ENCODED: &str = "%0A";
 const PERCENT_ENCODED: &str = "%25";
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// The profile that's used to percent encode file paths in manifests
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum PathEncoding {
+    /// Only CR, LF, and % are encoded, as required by the BagIt spec
+    #[default]
+    Standard,
+    /// In addition to CR, LF, and %, all other C0 control characters, including tabs, and DEL
+    /// are encoded. This is intended for exchanging bags with systems that reject raw control
+    /// characters in manifests.
+    Strict,
+}
+
+impl PathEncoding {
+    /// Percent encodes the characters in the input string that this profile encodes
+    pub fn encode<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self {
+            PathEncoding::Standard => percent_encode(value),
+            PathEncoding::Strict => percent_encode_strict(value),
+        }
+    }
+
+    /// Decodes the percent encoded characters in the input string that this profile encodes
+    pub fn decode<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self {
+            PathEncoding::Standard => percent_decode(value),
+            PathEncoding::Strict => percent_decode_strict(value),
+        }
+    }
+
+    /// Returns the first percent encoded sequence in the input string that this profile does not
+    /// decode, eg. `%20`, or a `%` that's not followed by two hex digits. A literal % must be
+    /// encoded as `%25`, so any other sequence means that the string was not encoded correctly.
+    pub fn invalid_escape<'a>(&self, value: &'a str) -> Option<&'a str> {
+        match self {
+            PathEncoding::Standard => find_invalid_escape(value, decode_escape),
+            PathEncoding::Strict => find_invalid_escape(value, decode_strict_escape),
+        }
+    }
+}
 
 /// Percent encodes any CR, LF, or % characters in the input string
 pub fn percent_encode(value: &str) -> Cow<str> {
@@ -29,9 +70,131 @@ pub fn percent_encode(value: &str) -> Cow<str> {
     }
 }
 
+/// Percent encodes any C0 control characters, DEL, or % characters in the input string
+pub fn percent_encode_strict(value: &str) -> Cow<'_, str> {
+    if let Some(i) = value.find(is_strict_encoded) {
+        let mut encoded = Vec::with_capacity(value.len() + 2);
+        encoded.extend_from_slice(&value.as_bytes()[..i]);
+
+        for c in value[i..].bytes() {
+            if is_strict_encoded(c as char) {
+                encoded.push(b'%');
+                encoded.push(HEX_DIGITS[(c >> 4) as usize]);
+                encoded.push(HEX_DIGITS[(c & 0xF) as usize]);
+            } else {
+                encoded.push(c);
+            }
+        }
+
+        // This is fine because only ASCII characters were replaced with ASCII sequences
+        Cow::Owned(unsafe { String::from_utf8_unchecked(encoded) })
+    } else {
+        value.into()
+    }
+}
+
+/// Decodes any percent encoded CR, LF, or % characters in the input string. All other characters,
+/// including other percent encoded sequences, are left as is.
+pub fn percent_decode(value: &str) -> Cow<'_, str> {
+    decode_with(value, decode_escape)
+}
+
+/// Decodes any percent encoded C0 control characters, DEL, or % characters in the input string.
+/// All other characters, including other percent encoded sequences, are left as is.
+pub fn percent_decode_strict(value: &str) -> Cow<'_, str> {
+    decode_with(value, decode_strict_escape)
+}
+
+/// Returns true if the character is a C0 control character, DEL, or %
+pub fn is_strict_encoded(c: char) -> bool {
+    c.is_ascii_control() || c == '%'
+}
+
+/// Decodes the escape sequences in the input string that `decode_escape` recognizes
+fn decode_with(value: &str, decode_escape: fn(&[u8]) -> Option<u8>) -> Cow<'_, str> {
+    if let Some(i) = value.find('%') {
+        let bytes = value.as_bytes();
+        let mut decoded = Vec::with_capacity(value.len());
+        decoded.extend_from_slice(&bytes[..i]);
+
+        let mut i = i;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                if let Some(c) = decode_escape(&bytes[i + 1..]) {
+                    decoded.push(c);
+                    i += 3;
+                    continue;
+                }
+            }
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+
+        // This is fine because only ASCII sequences were replaced with ASCII characters
+        Cow::Owned(unsafe { String::from_utf8_unchecked(decoded) })
+    } else {
+        value.into()
+    }
+}
+
+/// Returns the first escape sequence in the input string that `decode_escape` does not recognize,
+/// which is the `%` and up to two of the characters that follow it
+fn find_invalid_escape(value: &str, decode_escape: fn(&[u8]) -> Option<u8>) -> Option<&str> {
+    let bytes = value.as_bytes();
+
+    let mut i = 0;
+    while let Some(offset) = value[i..].find('%') {
+        i += offset;
+        if decode_escape(&bytes[i + 1..]).is_some() {
+            i += 3;
+            continue;
+        }
+
+        let end = value[i..]
+            .char_indices()
+            .nth(3)
+            .map(|(j, _)| i + j)
+            .unwrap_or(value.len());
+        return Some(&value[i..end]);
+    }
+
+    None
+}
+
+/// Returns the decoded character if the input starts with one of the supported escape sequences
+fn decode_escape(bytes: &[u8]) -> Option<u8> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    match (bytes[0], bytes[1].to_ascii_uppercase()) {
+        (b'0', b'D') => Some(CR_B),
+        (b'0', b'A') => Some(LF_B),
+        (b'2', b'5') => Some(b'%'),
+        _ => None,
+    }
+}
+
+/// Returns the decoded character if the input starts with an escape sequence for one of the
+/// characters that `percent_encode_strict` encodes
+fn decode_strict_escape(bytes: &[u8]) -> Option<u8> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let high = (bytes[0] as char).to_digit(16)?;
+    let low = (bytes[1] as char).to_digit(16)?;
+    let c = (high << 4 | low) as u8;
+    if is_strict_encoded(c as char) {
+        Some(c)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::bagit::encoding::percent_encode;
+    use crate::bagit::encoding::{
+        percent_decode, percent_decode_strict, percent_encode, percent_encode_strict, PathEncoding,
+    };
 
     #[test]
     fn test_percent_encoding() {
@@ -41,4 +204,48 @@ mod tests {
         );
         assert_eq!("nothing to see here", percent_encode("nothing to see here"));
     }
+
+    #[test]
+    fn test_percent_decoding() {
+        assert_eq!(
+            "a\tbc%123\rqwe\n%%asd\r\n !%20",
+            percent_decode("a\tbc%25123%0Dqwe%0a%25%25asd%0D%0A !%20")
+        );
+        assert_eq!("nothing to see here", percent_decode("nothing to see here"));
+    }
+
+    #[test]
+    fn test_strict_percent_encoding() {
+        assert_eq!(
+            "a%09bc%25123%0Dqwe%0A%25%7F%00asd%1F !é",
+            percent_encode_strict("a\tbc%123\rqwe\n%\x7f\0asd\x1f !é")
+        );
+        assert_eq!(
+            "nothing to see here",
+            percent_encode_strict("nothing to see here")
+        );
+    }
+
+    #[test]
+    fn test_strict_percent_decoding() {
+        assert_eq!(
+            "a\tbc%123\rqwe\n%\x7f\0asd\x1f !%20%7E%0",
+            percent_decode_strict("a%09bc%25123%0Dqwe%0a%25%7f%00asd%1F !%20%7E%0")
+        );
+    }
+
+    #[test]
+    fn find_escapes_that_are_not_decoded() {
+        let standard = PathEncoding::Standard;
+        let strict = PathEncoding::Strict;
+
+        assert_eq!(None, standard.invalid_escape("data/a%25b%0a%0D.txt"));
+        assert_eq!(Some("%20"), standard.invalid_escape("data/a%25%20b.txt"));
+        assert_eq!(Some("%%2"), standard.invalid_escape("data/%%25.txt"));
+        assert_eq!(Some("%2"), standard.invalid_escape("data/a%2"));
+        assert_eq!(Some("%é"), standard.invalid_escape("data/a%é"));
+        assert_eq!(Some("%09"), standard.invalid_escape("data/a%09b.txt"));
+        assert_eq!(None, strict.invalid_escape("data/a%09b%7F.txt"));
+        assert_eq!(Some("%7E"), strict.invalid_escape("data/a%7E.txt"));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::bagit::bag::BagItVersion;
+use crate::bagit::digest::DigestAlgorithm;
 use snafu::prelude::*;
 use std::path::PathBuf;
 use std::string::FromUtf8Error;
@@ -58,6 +59,36 @@ pub enum Error {
     WalkFile { source: walkdir::Error },
     #[snafu(display("Encountered an unsupported file type at {}", path.display()))]
     UnsupportedFile { path: PathBuf },
+    #[snafu(display(
+        "{algorithm} is a weak digest algorithm and is forbidden. Use sha256 or sha512 instead"
+    ))]
+    WeakAlgorithm { algorithm: DigestAlgorithm },
+    #[snafu(display("Invalid checksum file {}: {details}", path.display()))]
+    InvalidChecksumFile { path: PathBuf, details: String },
+    #[snafu(display(
+        "The {algorithm} digest of {} is {actual}, but {expected} was imported for it",
+        path.display()
+    ))]
+    ImportedDigestMismatch {
+        path: PathBuf,
+        algorithm: DigestAlgorithm,
+        expected: String,
+        actual: String,
+    },
+    #[snafu(display(
+        "The {algorithm} digest {actual} does not match the expected digest {expected}"
+    ))]
+    DigestMismatch {
+        algorithm: DigestAlgorithm,
+        expected: String,
+        actual: String,
+    },
+    #[snafu(display("Invalid payload path {}: {details}", path.display()))]
+    InvalidPayloadPath { path: PathBuf, details: String },
+    #[snafu(display("Invalid tag file path {}: {details}", path.display()))]
+    InvalidTagFilePath { path: PathBuf, details: String },
+    #[snafu(display("Invalid bag-info template {}: {details}", path.display()))]
+    InvalidTemplate { path: PathBuf, details: String },
     #[snafu(display("Invalid tag line: {details}"))]
     InvalidTagLine { details: String },
     #[snafu(display("Tag number {num} in file {} is invalid: {details}", path.display()))]
@@ -68,6 +99,8 @@ pub enum Error {
     },
     #[snafu(display("Invalid tag with label '{label}': {details}"))]
     InvalidTag { label: String, details: String },
+    #[snafu(display("Line {num} in manifest {} must contain a digest followed by a file path", path.display()))]
+    InvalidManifestLine { path: PathBuf, num: u32 },
     #[snafu(display("Invalid BagIt version: {value}"))]
     InvalidBagItVersion { value: String },
     #[snafu(display("Missing required tag {tag}"))]
@@ -80,4 +113,52 @@ pub enum Error {
     InvalidString { source: FromUtf8Error },
     #[snafu(display("Path cannot be encoded as UTF-8: {}", path.display()))]
     InvalidUtf8Path { path: PathBuf },
+    #[snafu(display("Unsupported archive format {}: only zip and tar archives are supported", path.display()))]
+    UnsupportedArchive { path: PathBuf },
+    #[snafu(display("Invalid archive {}: {details}", path.display()))]
+    InvalidArchive { path: PathBuf, details: String },
+    #[snafu(display("File {path} does not exist in archive {}", archive.display()))]
+    MissingArchiveFile { archive: PathBuf, path: String },
+    #[snafu(display("Archive {} is encrypted, and a password is required to read it", path.display()))]
+    PasswordRequired { path: PathBuf },
+    #[snafu(display("Incorrect password for archive {}", path.display()))]
+    IncorrectPassword { path: PathBuf },
+    #[snafu(display("{} already contains bag manifests. Delete them, or force their removal, to create a new bag", path.display()))]
+    ExistingBag { path: PathBuf },
+    #[snafu(display("{} is locked by process {pid}. If it's no longer running, force the removal of the lock", path.display()))]
+    BagLocked { path: PathBuf, pid: String },
+    #[snafu(display("Failed to create thread pool: {source}"))]
+    ThreadPool { source: rayon::ThreadPoolBuildError },
+    #[snafu(display("Failed to scan {}: {source}", path.display()))]
+    Scan {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("File {} was rejected by the scanner: {details}", path.display()))]
+    RejectedFile { path: PathBuf, details: String },
+    #[snafu(display("Failed to upload to S3: {details}"))]
+    S3Upload { details: String },
+    #[snafu(display("Failed to fetch {path} from {url}: {details}"))]
+    Fetch {
+        path: String,
+        url: String,
+        details: String,
+    },
+    #[snafu(display("Failed to access {url}: {details}"))]
+    StoreRequest { url: String, details: String },
+    #[snafu(display("Invalid upload state file {}: {details}", path.display()))]
+    InvalidUploadState { path: PathBuf, details: String },
+    #[snafu(display("Source directory {} changed while the bag was created: {details}", path.display()))]
+    SourceChanged { path: PathBuf, details: String },
+    #[snafu(display("{} payload files that are listed in the manifests do not exist", paths.len()))]
+    MissingPayloadFiles { paths: Vec<String> },
+    #[snafu(display("{} is not a valid bag: {} validation errors", path.display(), errors.len()))]
+    InvalidBag { path: PathBuf, errors: Vec<String> },
+    #[snafu(display("Invalid configuration: {details}"))]
+    InvalidConfig { details: String },
+    #[snafu(display("Inventory database {} error: {source}", path.display()))]
+    Inventory {
+        source: rusqlite::Error,
+        path: PathBuf,
+    },
 }
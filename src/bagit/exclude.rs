@@ -0,0 +1,213 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::info;
+
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+
+/// Glob patterns that select source files and directories to leave out of a bag's payload.
+///
+/// Patterns are matched against paths relative to the source directory, using `/` as the
+/// separator. `*` and `?` do not match `/`, and `**` matches any number of directories. Patterns
+/// that do not contain a `/` match a file or directory with that name at any depth, eg. `*.tmp`
+/// or `Thumbs.db`, and patterns that end in `/`, eg. `node_modules/`, only match directories.
+/// Everything within an excluded directory is excluded. Patterns that start with `!` re-include
+/// paths that were excluded by an earlier pattern, and the last pattern that matches a path wins.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludePatterns {
+    patterns: Vec<String>,
+    globs: GlobSet,
+    rules: Vec<Rule>,
+}
+
+/// How hidden source files and directories are recognized when they're left out of a bag
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum HiddenFilePolicy {
+    /// Only files and directories whose names begin with a `.` are hidden
+    DotFiles,
+    /// Names that begin with a `.` are hidden, as are files and directories that have the hidden
+    /// attribute on Windows
+    #[default]
+    Platform,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+}
+
+impl ExcludePatterns {
+    /// Compiles the `patterns`. It is an error if any of them are not valid globs.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let mut globs = GlobSetBuilder::new();
+        let mut rules = Vec::with_capacity(patterns.len());
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let (glob, negated) = match pattern.strip_prefix('!') {
+                Some(glob) => (glob, true),
+                None => (pattern.strip_prefix('\\').unwrap_or(pattern), false),
+            };
+            let (glob, dir_only) = match glob.strip_suffix('/') {
+                Some(glob) => (glob, true),
+                None => (glob, false),
+            };
+            let glob = if glob.contains('/') {
+                glob.trim_start_matches('/').to_string()
+            } else {
+                format!("**/{glob}")
+            };
+
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| General {
+                    message: format!("Invalid exclude pattern '{pattern}': {e}"),
+                })?;
+
+            globs.add(glob);
+            rules.push(Rule { negated, dir_only });
+        }
+
+        Ok(Self {
+            patterns: patterns.iter().map(|p| p.as_ref().to_string()).collect(),
+            globs: globs.build().map_err(|e| General {
+                message: format!("Invalid exclude patterns: {e}"),
+            })?,
+            rules,
+        })
+    }
+
+    /// Reads gitignore-style patterns from the file at `path`, which has one pattern per line.
+    /// Blank lines and lines that start with `#` are ignored, and a leading `\` escapes a `#` or
+    /// `!` at the start of a pattern. No patterns are returned if the file does not exist.
+    pub fn read_ignore_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(IoRead {
+                    path: path.into(),
+                    source: e,
+                })
+            }
+        };
+
+        info!("Reading exclude patterns from {}", path.display());
+
+        Ok(contents
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The patterns as they were specified
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Returns true if the file or directory at `path`, which is relative to the source
+    /// directory, matches any of the patterns
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.globs
+            .matches(path)
+            .into_iter()
+            .filter(|i| is_dir || !self.rules[*i].dir_only)
+            .max()
+            .is_some_and(|i| !self.rules[i].negated)
+    }
+}
+
+impl HiddenFilePolicy {
+    /// Returns true if the file or directory at `path` is hidden. The file's attributes are only
+    /// read on Windows.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        let is_dot_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.') && name != "." && name != "..");
+
+        is_dot_file || (*self == HiddenFilePolicy::Platform && has_hidden_attribute(path))
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    fs::symlink_metadata(path)
+        .map(|meta| meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::bagit::exclude::{ExcludePatterns, HiddenFilePolicy};
+
+    #[test]
+    fn match_names_at_any_depth() {
+        let patterns = ExcludePatterns::new(&["*.tmp", "Thumbs.db"]).unwrap();
+
+        assert!(patterns.is_excluded(Path::new("file.tmp"), false));
+        assert!(patterns.is_excluded(Path::new("a/b/file.tmp"), false));
+        assert!(patterns.is_excluded(Path::new("a/Thumbs.db"), false));
+        assert!(!patterns.is_excluded(Path::new("file.tmp.txt"), false));
+        assert!(!patterns.is_excluded(Path::new("thumbs.db"), false));
+    }
+
+    #[test]
+    fn match_directories_and_paths() {
+        let patterns = ExcludePatterns::new(&["node_modules/", "docs/*.pdf"]).unwrap();
+
+        assert!(patterns.is_excluded(Path::new("node_modules"), true));
+        assert!(patterns.is_excluded(Path::new("web/node_modules"), true));
+        assert!(!patterns.is_excluded(Path::new("node_modules"), false));
+        assert!(patterns.is_excluded(Path::new("docs/a.pdf"), false));
+        assert!(!patterns.is_excluded(Path::new("docs/sub/a.pdf"), false));
+        assert!(!patterns.is_excluded(Path::new("other/docs/a.pdf"), false));
+    }
+
+    #[test]
+    fn negated_patterns_include_files() {
+        let patterns = ExcludePatterns::new(&["*.log", "!keep.log", "\\!bang.txt"]).unwrap();
+
+        assert!(patterns.is_excluded(Path::new("a/debug.log"), false));
+        assert!(!patterns.is_excluded(Path::new("a/keep.log"), false));
+        assert!(patterns.is_excluded(Path::new("!bang.txt"), false));
+    }
+
+    #[test]
+    fn dot_files_are_hidden() {
+        for policy in [HiddenFilePolicy::DotFiles, HiddenFilePolicy::Platform] {
+            assert!(policy.is_hidden(Path::new("a/.DS_Store")));
+            assert!(policy.is_hidden(Path::new(".git")));
+            assert!(!policy.is_hidden(Path::new("a/b.txt")));
+            assert!(!policy.is_hidden(Path::new("a/..")));
+        }
+    }
+
+    #[test]
+    fn reject_invalid_patterns() {
+        assert!(ExcludePatterns::new(&["[a-"]).is_err());
+    }
+}
@@ -0,0 +1,1320 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tokio")]
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::{info, warn};
+use rayon::prelude::*;
+use snafu::ResultExt;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use url::Url;
+
+#[cfg(feature = "tokio")]
+use crate::bagit::asynchronous::{concurrency, hash_file_async, run_concurrently};
+use crate::bagit::bag::{
+    has_tag_manifests, thread_pool, update_tag_manifests, validate_payload_path, Bag,
+};
+use crate::bagit::consts::*;
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+use crate::bagit::manifest::{read_payload_manifest, Manifest};
+#[cfg(feature = "tokio")]
+use crate::bagit::progress::NoProgress;
+use crate::bagit::tag::decode_reader;
+
+/// Partially downloaded files are written next to their final path with this extension
+const PART_EXTENSION: &str = "part";
+
+/// The URL schemes that files can be fetched from
+const SUPPORTED_SCHEMES: [&str; 3] = ["https", "http", "file"];
+
+/// The maximum number of redirects that are followed when a file is downloaded
+const MAX_REDIRECTS: u32 = 5;
+
+/// A line in fetch.txt, which lists a payload file that's downloaded from a URL instead of being
+/// included in the bag
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FetchEntry {
+    url: String,
+    length: Option<u64>,
+    /// The file's path relative to the bag's base directory, eg. `data/file.txt`, decoded
+    path: String,
+}
+
+/// The URLs that files may be fetched from. A bag's fetch.txt may come from an untrusted source,
+/// so by default only `https` URLs are allowed. Files are not fetched from URLs that are not
+/// allowed, and redirects to them are not followed.
+///
+/// Host patterns are globs that are matched against a URL's host, ignoring case, eg.
+/// `*.example.org`. Every host is allowed when there are no patterns. URLs that do not have a
+/// host, such as `file` URLs, are only checked against the schemes.
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    schemes: Vec<String>,
+    hosts: Vec<String>,
+    host_globs: GlobSet,
+}
+
+/// Downloads the payload files that are listed in a bag's fetch.txt, which completes a holey
+/// bag. Created by `Bag::resolve_fetch()`.
+///
+/// Files are downloaded concurrently. Each file is downloaded to a `.part` file next to its
+/// final path, and downloads that fail are retried with an exponential backoff. Retries, and
+/// later runs after an interruption, resume from the end of the `.part` file using HTTP range
+/// requests, when the server supports them. A completed download is verified against every
+/// payload manifest before it's moved to its final path, and its entry is then removed from
+/// fetch.txt. fetch.txt is deleted once every file was fetched.
+#[derive(Debug)]
+pub struct FetchResolver {
+    bag: Bag,
+    threads: usize,
+    max_retries: u32,
+    retry_delay: Duration,
+    path_encoding: PathEncoding,
+    policy: FetchPolicy,
+    agent: ureq::Agent,
+}
+
+/// The outcome of resolving a bag's fetch.txt
+#[derive(Debug, Default)]
+pub struct FetchSummary {
+    downloaded: usize,
+    resumed: usize,
+    present: usize,
+    failed: Vec<Error>,
+}
+
+/// The entries in a bag's fetch.txt, and the payload manifests that they're verified against
+struct PendingFetch {
+    fetch_file: PathBuf,
+    entries: Vec<FetchEntry>,
+    manifests: HashMap<DigestAlgorithm, Manifest>,
+}
+
+/// How an entry was resolved
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Resolution {
+    Downloaded {
+        resumed: bool,
+    },
+    /// The file was already in the payload and matched the manifests
+    Present,
+}
+
+/// Why an attempt to download a file failed
+#[derive(Debug)]
+enum AttemptError {
+    /// The download may succeed if it's resumed later
+    Retryable(String),
+    Fatal(String),
+}
+
+impl FetchEntry {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The expected size of the file, if it's known
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// The file's path relative to the bag's base directory, eg. `data/file.txt`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FetchPolicy {
+    /// Allows URLs that use any of the `schemes` and whose host matches any of the `hosts`
+    /// patterns. The supported schemes are `https`, `http`, and `file`. It is an error if any
+    /// of the schemes are not supported or any of the patterns are not valid globs.
+    pub fn new<S: AsRef<str>, H: AsRef<str>>(schemes: &[S], hosts: &[H]) -> Result<Self> {
+        let schemes = schemes
+            .iter()
+            .map(|scheme| scheme.as_ref().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        if let Some(scheme) = schemes
+            .iter()
+            .find(|scheme| !SUPPORTED_SCHEMES.contains(&scheme.as_str()))
+        {
+            return Err(General {
+                message: format!(
+                    "Unsupported fetch URL scheme '{scheme}'. Supported schemes: {}",
+                    SUPPORTED_SCHEMES.join(", ")
+                ),
+            });
+        }
+
+        let mut globs = GlobSetBuilder::new();
+        for host in hosts {
+            let host = host.as_ref();
+            globs.add(
+                GlobBuilder::new(host)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| General {
+                        message: format!("Invalid host pattern '{host}': {e}"),
+                    })?,
+            );
+        }
+
+        Ok(Self {
+            schemes,
+            hosts: hosts.iter().map(|h| h.as_ref().to_string()).collect(),
+            host_globs: globs.build().map_err(|e| General {
+                message: format!("Invalid host patterns: {e}"),
+            })?,
+        })
+    }
+
+    /// The URL schemes that are allowed
+    pub fn schemes(&self) -> &[String] {
+        &self.schemes
+    }
+
+    /// The patterns of the hosts that are allowed. Every host is allowed when it's empty.
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Returns true if files may be fetched from `url`
+    pub fn allows(&self, url: &str) -> bool {
+        Url::parse(url)
+            .map_err(|e| e.to_string())
+            .and_then(|url| self.check(&url))
+            .is_ok()
+    }
+
+    /// Returns the reason `url` is not allowed, if it's not
+    fn check(&self, url: &Url) -> std::result::Result<(), String> {
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(format!(
+                "{} URLs are not allowed. Allowed schemes: {}",
+                url.scheme(),
+                self.schemes.join(", ")
+            ));
+        }
+        match url.host_str() {
+            Some(host) if !self.hosts.is_empty() && !self.host_globs.is_match(host) => {
+                Err(format!("Host {host} is not allowed"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for FetchPolicy {
+    /// Allows `https` URLs with any host
+    fn default() -> Self {
+        Self {
+            schemes: vec!["https".to_string()],
+            hosts: Vec::new(),
+            host_globs: GlobSet::empty(),
+        }
+    }
+}
+
+impl FetchResolver {
+    pub fn new(bag: Bag) -> Self {
+        Self {
+            bag,
+            threads: 0,
+            max_retries: 5,
+            retry_delay: Duration::from_secs(1),
+            path_encoding: PathEncoding::Standard,
+            policy: FetchPolicy::default(),
+            // Redirects are followed by download_once(), so that their URLs are checked
+            agent: ureq::AgentBuilder::new().redirects(0).build(),
+        }
+    }
+
+    /// Sets the maximum number of files that are downloaded at the same time. The default, 0,
+    /// uses one thread per CPU.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the number of times a failed download is retried. The default is 5.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry of a failed download. The delay doubles after
+    /// every attempt, up to a minute. The default is 1 second.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Sets the profile that's used to decode the file paths in fetch.txt and the manifests, and
+    /// to encode them when fetch.txt is rewritten. The default is `PathEncoding::Standard`.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Sets the URLs that files may be fetched from. The default only allows `https` URLs.
+    pub fn with_policy(mut self, policy: FetchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Downloads every file in fetch.txt. Files that could not be fetched are listed in the
+    /// summary, and are left in fetch.txt so that they're retried by the next run. Files that
+    /// are already in the payload, and match the manifests, are not downloaded again. The tag
+    /// manifests are updated if fetch.txt changed.
+    pub fn resolve(self) -> Result<FetchSummary> {
+        let pending = match self.read_pending()? {
+            Some(pending) => pending,
+            None => return Ok(FetchSummary::default()),
+        };
+
+        let pool = thread_pool(self.threads)?;
+        let results = pool.install(|| {
+            pending
+                .entries
+                .par_iter()
+                .map(|entry| self.resolve_entry(entry, &pending.manifests))
+                .collect::<Vec<_>>()
+        });
+
+        self.finish(&pending.fetch_file, pending.entries, results)
+    }
+
+    /// Reads the entries in fetch.txt and the payload manifests, or returns `None` if the bag does
+    /// not have a fetch.txt
+    fn read_pending(&self) -> Result<Option<PendingFetch>> {
+        let base_dir = self.bag.base_dir();
+        let fetch_file = base_dir.join(FETCH_TXT);
+
+        if !fetch_file.is_file() {
+            info!("Bag at {} does not have a {FETCH_TXT}", base_dir.display());
+            return Ok(None);
+        }
+        if !self.bag.declaration().is_utf8() {
+            return Err(General {
+                message: format!(
+                    "Cannot update the {FETCH_TXT} of a bag that is not UTF-8 encoded. Run \
+                    `bagr upgrade` first"
+                ),
+            });
+        }
+
+        let entries = read_fetch_file(&fetch_file, &self.bag, self.path_encoding)?;
+        let manifests = self
+            .bag
+            .algorithms()
+            .iter()
+            .map(|algorithm| {
+                read_payload_manifest(base_dir, *algorithm, self.path_encoding)
+                    .map(|manifest| (*algorithm, manifest))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        info!(
+            "Fetching {} files into {}",
+            entries.len(),
+            base_dir.display()
+        );
+
+        Ok(Some(PendingFetch {
+            fetch_file,
+            entries,
+            manifests,
+        }))
+    }
+
+    /// Summarizes the `results` of resolving each pending entry, and rewrites fetch.txt so that it
+    /// only lists the entries that could not be fetched
+    fn finish(
+        &self,
+        fetch_file: &Path,
+        entries: Vec<FetchEntry>,
+        results: Vec<Result<Resolution>>,
+    ) -> Result<FetchSummary> {
+        let base_dir = self.bag.base_dir();
+        let mut summary = FetchSummary::default();
+
+        let mut remaining = Vec::new();
+        for (entry, result) in entries.into_iter().zip(results) {
+            match result {
+                Ok(Resolution::Downloaded { resumed }) => {
+                    summary.downloaded += 1;
+                    if resumed {
+                        summary.resumed += 1;
+                    }
+                }
+                Ok(Resolution::Present) => summary.present += 1,
+                Err(e) => {
+                    summary.failed.push(e);
+                    remaining.push(entry);
+                }
+            }
+        }
+
+        if summary.fetched() > 0 {
+            write_fetch_file(fetch_file, &remaining, self.path_encoding)?;
+            if has_tag_manifests(base_dir)? {
+                update_tag_manifests(
+                    base_dir,
+                    self.bag.algorithms(),
+                    self.path_encoding,
+                    &thread_pool(1)?,
+                )?;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    fn resolve_entry(
+        &self,
+        entry: &FetchEntry,
+        manifests: &HashMap<DigestAlgorithm, Manifest>,
+    ) -> Result<Resolution> {
+        let error = |details: String| Fetch {
+            path: entry.path.clone(),
+            url: entry.url.clone(),
+            details,
+        };
+
+        let file_path = self.bag.base_dir().join(&entry.path);
+        if file_path.is_file() {
+            match verify_file(&file_path, &entry.path, manifests) {
+                Ok(()) => {
+                    info!("{} was already fetched", entry.path);
+                    return Ok(Resolution::Present);
+                }
+                Err(details) => warn!("Fetching {} again: {details}", entry.path),
+            }
+        }
+
+        let part_path = part_path(&file_path);
+        if let Some(parent) = part_path.parent() {
+            fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+        }
+
+        let resumed = self.download(entry, &part_path).map_err(error)?;
+
+        let size = fs::metadata(&part_path)
+            .context(IoStatSnafu { path: &part_path })?
+            .len();
+        let verified = match entry.length {
+            Some(length) if length != size => Err(format!(
+                "Downloaded {size} bytes, but {FETCH_TXT} lists its length as {length}"
+            )),
+            _ => verify_file(&part_path, &entry.path, manifests),
+        };
+        if let Err(details) = verified {
+            // The download is not resumable because the bytes that were already written are bad
+            info!("Deleting {}", part_path.display());
+            let _ = fs::remove_file(&part_path);
+            return Err(error(details));
+        }
+
+        fs::rename(&part_path, &file_path).context(IoMoveSnafu {
+            from: &part_path,
+            to: &file_path,
+        })?;
+
+        info!("Fetched {}", entry.path);
+        Ok(Resolution::Downloaded { resumed })
+    }
+
+    /// Downloads the entry to `part_path`, retrying failed attempts. Returns true if the download
+    /// was resumed from a file that was partially downloaded by an earlier attempt or run.
+    fn download(&self, entry: &FetchEntry, part_path: &Path) -> std::result::Result<bool, String> {
+        let url = Url::parse(&entry.url).map_err(|e| format!("Invalid URL: {e}"))?;
+        self.policy.check(&url)?;
+
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            match self.download_once(entry, &url, part_path) {
+                Ok(resumed) => return Ok(resumed),
+                Err(AttemptError::Retryable(details)) if attempt < self.max_retries => {
+                    attempt += 1;
+                    info!(
+                        "Retrying download of {} in {}s, attempt {attempt} of {}: {details}",
+                        entry.path,
+                        delay.as_secs_f64(),
+                        self.max_retries
+                    );
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_secs(60));
+                }
+                Err(AttemptError::Retryable(details)) | Err(AttemptError::Fatal(details)) => {
+                    return Err(details)
+                }
+            }
+        }
+    }
+
+    /// Makes a single attempt to download the entry from `url`, appending to `part_path` if it
+    /// exists and the server supports range requests. Returns true if the download was resumed.
+    fn download_once(
+        &self,
+        entry: &FetchEntry,
+        url: &Url,
+        part_path: &Path,
+    ) -> std::result::Result<bool, AttemptError> {
+        let offset = fs::metadata(part_path).map(|meta| meta.len()).unwrap_or(0);
+        if offset > 0 && entry.length == Some(offset) {
+            return Ok(true);
+        }
+        if offset > 0 {
+            info!("Resuming download of {} at byte {offset}", entry.path);
+        }
+        if url.scheme() == "file" {
+            return copy_local_file(url, offset, part_path);
+        }
+
+        let mut url = url.clone();
+        let mut redirects = 0;
+        let response = loop {
+            let mut request = self.agent.request_url("GET", &url);
+            if offset > 0 {
+                request = request.set("Range", &format!("bytes={offset}-"));
+            }
+
+            let response = match request.call() {
+                Ok(response) => response,
+                // The part file already contains the entire file
+                Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(true),
+                Err(ureq::Error::Status(status, _)) => return Err(status_error(status)),
+                Err(ureq::Error::Transport(e)) => {
+                    return Err(AttemptError::Retryable(e.to_string()))
+                }
+            };
+            if !(300..400).contains(&response.status()) {
+                break response;
+            }
+
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(AttemptError::Fatal(format!(
+                    "Server redirected more than {MAX_REDIRECTS} times"
+                )));
+            }
+            url = response
+                .header("Location")
+                .and_then(|location| url.join(location).ok())
+                .ok_or_else(|| {
+                    AttemptError::Fatal(format!(
+                        "Server responded with {} without a valid Location",
+                        response.status()
+                    ))
+                })?;
+            self.policy
+                .check(&url)
+                .map_err(|e| AttemptError::Fatal(format!("Redirected to {url}: {e}")))?;
+            info!("Following redirect of {} to {url}", entry.path);
+        };
+
+        let resumed = offset > 0 && response.status() == 206;
+        if offset > 0 && !resumed {
+            info!(
+                "Server does not support resuming downloads; downloading {} from the start",
+                entry.path
+            );
+        }
+
+        write_part_file(&mut response.into_reader(), part_path, resumed)?;
+        Ok(resumed)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl FetchResolver {
+    /// Async variant of `resolve()`. Files are downloaded with an async HTTP client, and are
+    /// written and verified with `tokio::fs`, on the calling runtime, with at most as many
+    /// downloads in progress at a time as the number of threads. fetch.txt and the tag manifests
+    /// are read and rewritten on the calling task. This must be called within a tokio runtime.
+    pub async fn resolve_async(self) -> Result<FetchSummary> {
+        let PendingFetch {
+            fetch_file,
+            entries,
+            manifests,
+        } = match self.read_pending()? {
+            Some(pending) => pending,
+            None => return Ok(FetchSummary::default()),
+        };
+
+        // Redirects are followed by download_once_async(), so that their URLs are checked
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| General {
+                message: format!("Failed to create HTTP client: {e}"),
+            })?;
+
+        let resolver = Arc::new(self);
+        let manifests = Arc::new(manifests);
+        let tasks = entries
+            .iter()
+            .map(|entry| {
+                let resolver = resolver.clone();
+                let client = client.clone();
+                let entry = entry.clone();
+                let manifests = manifests.clone();
+                async move {
+                    resolver
+                        .resolve_entry_async(&client, &entry, &manifests)
+                        .await
+                }
+            })
+            .collect::<Vec<_>>();
+        let results = run_concurrently(concurrency(resolver.threads), tasks).await;
+
+        resolver.finish(&fetch_file, entries, results)
+    }
+
+    /// Async variant of `resolve_entry()`
+    async fn resolve_entry_async(
+        &self,
+        client: &reqwest::Client,
+        entry: &FetchEntry,
+        manifests: &HashMap<DigestAlgorithm, Manifest>,
+    ) -> Result<Resolution> {
+        let error = |details: String| Fetch {
+            path: entry.path.clone(),
+            url: entry.url.clone(),
+            details,
+        };
+
+        let file_path = self.bag.base_dir().join(&entry.path);
+        let is_file = tokio::fs::metadata(&file_path)
+            .await
+            .is_ok_and(|meta| meta.is_file());
+        if is_file {
+            match verify_file_async(&file_path, &entry.path, manifests).await {
+                Ok(()) => {
+                    info!("{} was already fetched", entry.path);
+                    return Ok(Resolution::Present);
+                }
+                Err(details) => warn!("Fetching {} again: {details}", entry.path),
+            }
+        }
+
+        let part_path = part_path(&file_path);
+        if let Some(parent) = part_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context(IoCreateSnafu { path: parent })?;
+        }
+
+        let resumed = self
+            .download_async(client, entry, &part_path)
+            .await
+            .map_err(error)?;
+
+        let size = tokio::fs::metadata(&part_path)
+            .await
+            .context(IoStatSnafu { path: &part_path })?
+            .len();
+        let verified = match entry.length {
+            Some(length) if length != size => Err(format!(
+                "Downloaded {size} bytes, but {FETCH_TXT} lists its length as {length}"
+            )),
+            _ => verify_file_async(&part_path, &entry.path, manifests).await,
+        };
+        if let Err(details) = verified {
+            // The download is not resumable because the bytes that were already written are bad
+            info!("Deleting {}", part_path.display());
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(error(details));
+        }
+
+        tokio::fs::rename(&part_path, &file_path)
+            .await
+            .context(IoMoveSnafu {
+                from: &part_path,
+                to: &file_path,
+            })?;
+
+        info!("Fetched {}", entry.path);
+        Ok(Resolution::Downloaded { resumed })
+    }
+
+    /// Async variant of `download()`
+    async fn download_async(
+        &self,
+        client: &reqwest::Client,
+        entry: &FetchEntry,
+        part_path: &Path,
+    ) -> std::result::Result<bool, String> {
+        let url = Url::parse(&entry.url).map_err(|e| format!("Invalid URL: {e}"))?;
+        self.policy.check(&url)?;
+
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            match self
+                .download_once_async(client, entry, &url, part_path)
+                .await
+            {
+                Ok(resumed) => return Ok(resumed),
+                Err(AttemptError::Retryable(details)) if attempt < self.max_retries => {
+                    attempt += 1;
+                    info!(
+                        "Retrying download of {} in {}s, attempt {attempt} of {}: {details}",
+                        entry.path,
+                        delay.as_secs_f64(),
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_secs(60));
+                }
+                Err(AttemptError::Retryable(details)) | Err(AttemptError::Fatal(details)) => {
+                    return Err(details)
+                }
+            }
+        }
+    }
+
+    /// Async variant of `download_once()`
+    async fn download_once_async(
+        &self,
+        client: &reqwest::Client,
+        entry: &FetchEntry,
+        url: &Url,
+        part_path: &Path,
+    ) -> std::result::Result<bool, AttemptError> {
+        let offset = tokio::fs::metadata(part_path)
+            .await
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        if offset > 0 && entry.length == Some(offset) {
+            return Ok(true);
+        }
+        if offset > 0 {
+            info!("Resuming download of {} at byte {offset}", entry.path);
+        }
+        if url.scheme() == "file" {
+            return copy_local_file_async(url, offset, part_path).await;
+        }
+
+        let mut url = url.clone();
+        let mut redirects = 0;
+        let mut response = loop {
+            let mut request = client.get(url.as_str());
+            if offset > 0 {
+                request = request.header("Range", format!("bytes={offset}-"));
+            }
+
+            let response = request
+                .send()
+                .await
+                .map_err(|e| AttemptError::Retryable(e.to_string()))?;
+            let status = response.status().as_u16();
+            if !(300..400).contains(&status) {
+                match status {
+                    // The part file already contains the entire file
+                    416 if offset > 0 => return Ok(true),
+                    400.. => return Err(status_error(status)),
+                    _ => break response,
+                }
+            }
+
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(AttemptError::Fatal(format!(
+                    "Server redirected more than {MAX_REDIRECTS} times"
+                )));
+            }
+            url = response
+                .headers()
+                .get("Location")
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+                .ok_or_else(|| {
+                    AttemptError::Fatal(format!(
+                        "Server responded with {status} without a valid Location"
+                    ))
+                })?;
+            self.policy
+                .check(&url)
+                .map_err(|e| AttemptError::Fatal(format!("Redirected to {url}: {e}")))?;
+            info!("Following redirect of {} to {url}", entry.path);
+        };
+
+        let resumed = offset > 0 && response.status().as_u16() == 206;
+        if offset > 0 && !resumed {
+            info!(
+                "Server does not support resuming downloads; downloading {} from the start",
+                entry.path
+            );
+        }
+
+        let mut file = open_part_file_async(part_path, resumed).await?;
+        // A failure while reading the response, eg. a dropped connection, is resumed by the next
+        // attempt
+        let interrupted =
+            |e: &dyn Display| AttemptError::Retryable(format!("Download was interrupted: {e}"));
+        while let Some(chunk) = response.chunk().await.map_err(|e| interrupted(&e))? {
+            file.write_all(&chunk).await.map_err(|e| interrupted(&e))?;
+        }
+        file.flush().await.map_err(|e| interrupted(&e))?;
+
+        Ok(resumed)
+    }
+}
+
+impl FetchSummary {
+    /// The number of files that were downloaded
+    pub fn downloaded(&self) -> usize {
+        self.downloaded
+    }
+
+    /// The number of downloaded files that were resumed from a partial download
+    pub fn resumed(&self) -> usize {
+        self.resumed
+    }
+
+    /// The number of files that were already in the payload and did not need to be downloaded
+    pub fn present(&self) -> usize {
+        self.present
+    }
+
+    /// The number of files that were removed from fetch.txt
+    pub fn fetched(&self) -> usize {
+        self.downloaded + self.present
+    }
+
+    /// The errors of the files that could not be fetched
+    pub fn failed(&self) -> &[Error] {
+        &self.failed
+    }
+}
+
+impl Display for FetchSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Fetched {} files", self.downloaded)?;
+        if self.resumed > 0 {
+            write!(f, ", {} of which were resumed", self.resumed)?;
+        }
+        if self.present > 0 {
+            write!(f, "; {} files were already present", self.present)?;
+        }
+        if !self.failed.is_empty() {
+            write!(f, "; {} files could not be fetched", self.failed.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Bag {
+    /// Creates a `FetchResolver` that downloads the files that are listed in the bag's
+    /// fetch.txt
+    pub fn resolve_fetch(self) -> FetchResolver {
+        FetchResolver::new(self)
+    }
+}
+
+/// Reads the entries in fetch.txt. Every line must contain a URL, a length or `-`, and the path
+/// of a payload file, separated by whitespace. The path must be percent encoded correctly.
+pub fn read_fetch_file(path: &Path, bag: &Bag, encoding: PathEncoding) -> Result<Vec<FetchEntry>> {
+    let file = File::open(path).context(IoReadSnafu { path })?;
+    let reader = LineReader::new(BufReader::new(decode_reader(file, bag.declaration())));
+    let mut entries = Vec::new();
+
+    for (i, line) in reader.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid = || General {
+            message: format!(
+                "Line {} in {FETCH_TXT} must contain a URL, a length or '-', and a payload file \
+                path",
+                i + 1
+            ),
+        };
+
+        let (url, rest) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let (length, file_path) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let length = match length {
+            "-" => None,
+            length => Some(length.parse::<u64>().map_err(|_| invalid())?),
+        };
+
+        let file_path = file_path.trim_start();
+        if let Some(escape) = encoding.invalid_escape(file_path) {
+            return Err(General {
+                message: format!(
+                    "Line {} in {FETCH_TXT} contains the invalid percent encoded sequence \
+                    '{escape}'. A % in a path must be encoded as %25",
+                    i + 1
+                ),
+            });
+        }
+
+        let file_path = encoding.decode(file_path);
+        let relative = file_path
+            .strip_prefix(&format!("{DATA}/"))
+            .ok_or_else(invalid)?;
+        validate_payload_path(Path::new(relative))?;
+
+        entries.push(FetchEntry {
+            url: url.to_string(),
+            length,
+            path: file_path.into_owned(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Rewrites fetch.txt so that it only contains the `entries`, or deletes it if there are none
+fn write_fetch_file(path: &Path, entries: &[FetchEntry], encoding: PathEncoding) -> Result<()> {
+    if entries.is_empty() {
+        info!("Deleting {}", path.display());
+        return fs::remove_file(path).context(IoDeleteSnafu { path });
+    }
+
+    info!("Writing {}", path.display());
+    let mut writer = BufWriter::new(File::create(path).context(IoCreateSnafu { path })?);
+    for entry in entries {
+        let length = entry
+            .length
+            .map(|length| length.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        writeln!(
+            writer,
+            "{} {length} {}",
+            entry.url,
+            encoding.encode(&entry.path)
+        )
+        .context(IoWriteSnafu { path })?;
+    }
+    writer.flush().context(IoWriteSnafu { path })
+}
+
+/// Returns the error for an unsuccessful HTTP `status`. Server errors and rate limits are retried.
+fn status_error(status: u16) -> AttemptError {
+    let details = format!("Server responded with {status}");
+    if status == 429 || status >= 500 {
+        AttemptError::Retryable(details)
+    } else {
+        AttemptError::Fatal(details)
+    }
+}
+
+/// Returns the path that the file at `file_path` is downloaded to before it's verified
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut part_path = file_path.to_path_buf().into_os_string();
+    part_path.push(format!(".{PART_EXTENSION}"));
+    PathBuf::from(part_path)
+}
+
+/// Copies the file at the `file` URL to `part_path`, appending to it if it exists. Returns true
+/// if the copy was resumed.
+fn copy_local_file(
+    url: &Url,
+    offset: u64,
+    part_path: &Path,
+) -> std::result::Result<bool, AttemptError> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| AttemptError::Fatal(format!("{url} is not a local file path")))?;
+    let mut file = File::open(&path)
+        .map_err(|e| AttemptError::Fatal(format!("Failed to open {}: {e}", path.display())))?;
+    let size = file
+        .metadata()
+        .map_err(|e| AttemptError::Fatal(format!("Failed to stat {}: {e}", path.display())))?
+        .len();
+
+    let resumed = offset > 0 && offset <= size;
+    if resumed {
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| AttemptError::Fatal(format!("Failed to read {}: {e}", path.display())))?;
+    }
+
+    write_part_file(&mut file, part_path, resumed)?;
+    Ok(resumed)
+}
+
+/// Async variant of `copy_local_file()`
+#[cfg(feature = "tokio")]
+async fn copy_local_file_async(
+    url: &Url,
+    offset: u64,
+    part_path: &Path,
+) -> std::result::Result<bool, AttemptError> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| AttemptError::Fatal(format!("{url} is not a local file path")))?;
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| AttemptError::Fatal(format!("Failed to open {}: {e}", path.display())))?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(|e| AttemptError::Fatal(format!("Failed to stat {}: {e}", path.display())))?
+        .len();
+
+    let resumed = offset > 0 && offset <= size;
+    if resumed {
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| AttemptError::Fatal(format!("Failed to read {}: {e}", path.display())))?;
+    }
+
+    let mut part = open_part_file_async(part_path, resumed).await?;
+    tokio::io::copy(&mut file, &mut part)
+        .await
+        .map_err(|e| AttemptError::Retryable(format!("Download was interrupted: {e}")))?;
+    part.flush()
+        .await
+        .map_err(|e| AttemptError::Retryable(format!("Download was interrupted: {e}")))?;
+
+    Ok(resumed)
+}
+
+/// Opens `part_path` for writing with `tokio::fs`, appending to it if `append` is true and
+/// otherwise replacing it
+#[cfg(feature = "tokio")]
+async fn open_part_file_async(
+    part_path: &Path,
+    append: bool,
+) -> std::result::Result<tokio::fs::File, AttemptError> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part_path)
+        .await
+        .map_err(|e| AttemptError::Fatal(format!("Failed to open {}: {e}", part_path.display())))
+}
+
+/// Writes the `contents` to `part_path`, appending to it if `append` is true and otherwise
+/// replacing it
+fn write_part_file(
+    contents: &mut dyn Read,
+    part_path: &Path,
+    append: bool,
+) -> std::result::Result<(), AttemptError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part_path)
+        .map_err(|e| AttemptError::Fatal(format!("Failed to open {}: {e}", part_path.display())))?;
+    let mut writer = BufWriter::new(file);
+
+    // A failure while reading the contents, eg. a dropped connection, is resumed by the next
+    // attempt
+    io::copy(contents, &mut writer)
+        .and_then(|_| writer.flush())
+        .map_err(|e| AttemptError::Retryable(format!("Download was interrupted: {e}")))?;
+
+    Ok(())
+}
+
+/// Compares the digests of the file at `file_path` to the digests of the payload file at
+/// `path` in every manifest
+fn verify_file(
+    file_path: &Path,
+    path: &str,
+    manifests: &HashMap<DigestAlgorithm, Manifest>,
+) -> std::result::Result<(), String> {
+    let mut algorithms = manifests
+        .iter()
+        .filter(|(_, manifest)| manifest.get(path).is_some())
+        .map(|(algorithm, _)| *algorithm)
+        .collect::<Vec<_>>();
+    if algorithms.is_empty() {
+        return Err("It is not listed in the payload manifests".to_string());
+    }
+    // Checked in a consistent order, so the same mismatch is always reported
+    algorithms.sort();
+
+    let file = File::open(file_path).map_err(|e| e.to_string())?;
+    let expected = |algorithm: &DigestAlgorithm| manifests[algorithm].get(path).unwrap().clone();
+    let mut reader = algorithms.iter().skip(1).fold(
+        algorithms[0].verifying_reader(expected(&algorithms[0]), file),
+        |reader, algorithm| reader.with_expected(*algorithm, expected(algorithm)),
+    );
+
+    io::copy(&mut reader, &mut io::sink())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Async variant of `verify_file()`
+#[cfg(feature = "tokio")]
+async fn verify_file_async(
+    file_path: &Path,
+    path: &str,
+    manifests: &HashMap<DigestAlgorithm, Manifest>,
+) -> std::result::Result<(), String> {
+    let mut algorithms = manifests
+        .iter()
+        .filter(|(_, manifest)| manifest.get(path).is_some())
+        .map(|(algorithm, _)| *algorithm)
+        .collect::<Vec<_>>();
+    if algorithms.is_empty() {
+        return Err("It is not listed in the payload manifests".to_string());
+    }
+    // Checked in a consistent order, so the same mismatch is always reported
+    algorithms.sort();
+
+    let actual = hash_file_async(file_path, &algorithms, &NoProgress)
+        .await
+        .map_err(|e| e.to_string())?;
+    for algorithm in algorithms {
+        let expected = manifests[&algorithm].get(path).unwrap();
+        if actual[&algorithm] != *expected {
+            return Err(DigestMismatch {
+                algorithm,
+                expected: expected.to_string(),
+                actual: actual[&algorithm].to_string(),
+            }
+            .to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::bagit::bag::{open_bag, BagWriter};
+    use crate::bagit::consts::FETCH_TXT;
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::fetch::FetchPolicy;
+
+    const CONTENT: &str = "0123456789abcdefghijklmnopqrstuvwxyz\n";
+
+    /// Serves CONTENT at every path, except /missing.txt, and honors range requests.
+    /// /redirect.txt redirects to /a.txt on localhost.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{port}");
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut offset = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(range) = header.to_lowercase().strip_prefix("range: bytes=") {
+                        offset = range.trim().trim_end_matches('-').parse().unwrap();
+                    }
+                }
+
+                let (status, body) = if request_line.contains("/missing.txt") {
+                    ("404 Not Found", "")
+                } else if request_line.contains("/redirect.txt") {
+                    write!(
+                        stream,
+                        "HTTP/1.1 302 Found\r\nLocation: http://localhost:{port}/a.txt\r\n\
+                        Content-Length: 0\r\nConnection: close\r\n\r\n"
+                    )
+                    .unwrap();
+                    continue;
+                } else if offset > 0 {
+                    ("206 Partial Content", &CONTENT[offset..])
+                } else {
+                    ("200 OK", CONTENT)
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn fetch_files_and_resume_partial_downloads() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir, &[DigestAlgorithm::Sha256]).unwrap();
+        for name in ["a.txt", "sub/b.txt", "c.txt"] {
+            writer.add_payload_file(name, CONTENT.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // a.txt is downloaded, b.txt is resumed, and c.txt fails
+        fs::remove_file(dir.join("data/a.txt")).unwrap();
+        fs::remove_file(dir.join("data/sub/b.txt")).unwrap();
+        fs::write(dir.join("data/sub/b.txt.part"), &CONTENT[..10]).unwrap();
+        fs::remove_file(dir.join("data/c.txt")).unwrap();
+        let url = serve();
+        fs::write(
+            dir.join(FETCH_TXT),
+            format!(
+                "{url}/a.txt {} data/a.txt\n{url}/b.txt - data/sub/b.txt\n\
+                {url}/missing.txt - data/c.txt\n",
+                CONTENT.len()
+            ),
+        )
+        .unwrap();
+
+        let summary = open_bag(dir)
+            .unwrap()
+            .resolve_fetch()
+            .with_retry_delay(Duration::ZERO)
+            .with_policy(FetchPolicy::new(&["http"], &["127.0.0.1"]).unwrap())
+            .resolve();
+        let a = fs::read_to_string(dir.join("data/a.txt"));
+        let b = fs::read_to_string(dir.join("data/sub/b.txt"));
+        let fetch = fs::read_to_string(dir.join(FETCH_TXT));
+
+        let summary = summary.unwrap();
+        assert_eq!(2, summary.downloaded());
+        assert_eq!(1, summary.resumed());
+        assert_eq!(1, summary.failed().len());
+        assert_eq!(CONTENT, a.unwrap());
+        assert_eq!(CONTENT, b.unwrap());
+        assert_eq!(format!("{url}/missing.txt - data/c.txt\n"), fetch.unwrap());
+    }
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn fetch_files_async() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir, &[DigestAlgorithm::Sha256]).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            writer.add_payload_file(name, CONTENT.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // a.txt is downloaded, b.txt is resumed, c.txt fails, and d.txt is redirected
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            fs::remove_file(dir.join("data").join(name)).unwrap();
+        }
+        fs::write(dir.join("data/b.txt.part"), &CONTENT[..10]).unwrap();
+        let url = serve();
+        fs::write(
+            dir.join(FETCH_TXT),
+            format!(
+                "{url}/a.txt {} data/a.txt\n{url}/b.txt - data/b.txt\n\
+                {url}/missing.txt - data/c.txt\n{url}/redirect.txt - data/d.txt\n",
+                CONTENT.len()
+            ),
+        )
+        .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let summary = runtime.block_on(
+            open_bag(dir)
+                .unwrap()
+                .resolve_fetch()
+                .with_retry_delay(Duration::ZERO)
+                .with_policy(FetchPolicy::new(&["http"], &["127.0.0.1", "localhost"]).unwrap())
+                .resolve_async(),
+        );
+        let a = fs::read_to_string(dir.join("data/a.txt"));
+        let b = fs::read_to_string(dir.join("data/b.txt"));
+        let d = fs::read_to_string(dir.join("data/d.txt"));
+        let fetch = fs::read_to_string(dir.join(FETCH_TXT));
+
+        let summary = summary.unwrap();
+        assert_eq!(3, summary.downloaded());
+        assert_eq!(1, summary.resumed());
+        assert_eq!(1, summary.failed().len());
+        assert_eq!(CONTENT, a.unwrap());
+        assert_eq!(CONTENT, b.unwrap());
+        assert_eq!(CONTENT, d.unwrap());
+        assert_eq!(format!("{url}/missing.txt - data/c.txt\n"), fetch.unwrap());
+    }
+
+    #[test]
+    fn only_fetch_from_allowed_urls() {
+        let policy = FetchPolicy::new(&["HTTPS"], &["*.example.org"]).unwrap();
+        assert!(policy.allows("https://data.Example.org/a.txt"));
+        assert!(!policy.allows("http://data.example.org/a.txt"));
+        assert!(!policy.allows("https://example.com/a.txt"));
+        assert!(!policy.allows("https://example.com@evil.test/a.txt"));
+        assert!(!FetchPolicy::default().allows("file:///etc/passwd"));
+        assert!(FetchPolicy::new(&["file"], &["example.org"])
+            .unwrap()
+            .allows("file:///etc/passwd"));
+        assert!(FetchPolicy::new(&["ftp"], &[] as &[&str]).is_err());
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir, &[DigestAlgorithm::Sha256]).unwrap();
+        writer
+            .add_payload_file("a.txt", CONTENT.as_bytes())
+            .unwrap();
+        writer
+            .add_payload_file("b.txt", CONTENT.as_bytes())
+            .unwrap();
+        writer.finish().unwrap();
+        fs::remove_file(dir.join("data/a.txt")).unwrap();
+        fs::remove_file(dir.join("data/b.txt")).unwrap();
+        let url = serve();
+        let fetch = format!("{url}/a.txt - data/a.txt\n{url}/redirect.txt - data/b.txt\n");
+        fs::write(dir.join(FETCH_TXT), &fetch).unwrap();
+
+        let insecure = open_bag(dir).unwrap().resolve_fetch().resolve();
+        let redirected = open_bag(dir)
+            .unwrap()
+            .resolve_fetch()
+            .with_policy(FetchPolicy::new(&["http"], &["127.0.0.1"]).unwrap())
+            .resolve();
+        let remaining = fs::read_to_string(dir.join(FETCH_TXT));
+
+        let insecure = insecure.unwrap();
+        assert_eq!(0, insecure.downloaded());
+        assert_eq!(2, insecure.failed().len());
+        assert!(insecure.failed()[0]
+            .to_string()
+            .contains("http URLs are not allowed"));
+
+        let redirected = redirected.unwrap();
+        assert_eq!(1, redirected.downloaded());
+        assert!(redirected.failed()[0]
+            .to_string()
+            .contains("Host localhost is not allowed"));
+        assert_eq!(
+            format!("{url}/redirect.txt - data/b.txt\n"),
+            remaining.unwrap()
+        );
+    }
+}
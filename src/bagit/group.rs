@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use snafu::ResultExt;
+use strum::IntoEnumIterator;
+use walkdir::WalkDir;
+
+use crate::bagit::bag::{is_manifest_name, Bag, BagWriter, PayloadFile};
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, MultiDigestWriter};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::tag::parse_bag_count;
+
+/// Splits a bag into a group of smaller bags, so that each one fits on transfer media or within
+/// object size limits. Created by `Bag::split()`.
+///
+/// Payload files are distributed in path order, and a new bag is started whenever the next file
+/// would make the current bag's payload larger than the maximum size. Every bag gets a copy of
+/// the original bag-info.txt and other tag files, a shared Bag-Group-Identifier, and a Bag-Count,
+/// eg. `1 of 3`. Files are verified against the original manifests as they're copied.
+#[derive(Debug)]
+pub struct BagSplitter {
+    bag: Bag,
+    dst_dir: PathBuf,
+    max_size: u64,
+    group_identifier: Option<String>,
+    bagging_date: Option<String>,
+    path_encoding: PathEncoding,
+    tag_line_width: Option<usize>,
+}
+
+/// Merges a group of bags, such as the bags that were written by `BagSplitter`, back into a
+/// single bag.
+///
+/// Every bag must have the same Bag-Group-Identifier, and their Bag-Counts must number the bags
+/// from 1 to the total without any gaps or duplicates, so that a missing bag is detected. The
+/// payloads are combined in Bag-Count order, and it is an error if any payload path is used by
+/// more than one bag. Files are verified against their bag's manifests as they're copied. The
+/// new bag uses the digest algorithms that every bag has in common, and gets the first bag's
+/// bag-info.txt, without the group tags, and the tag files of every bag.
+#[derive(Debug)]
+pub struct BagMerger {
+    bags: Vec<Bag>,
+    dst_dir: PathBuf,
+    bagging_date: Option<String>,
+    path_encoding: PathEncoding,
+    tag_line_width: Option<usize>,
+}
+
+impl BagSplitter {
+    /// Splits the `bag` into bags in `dst_dir` whose payloads are at most `max_size` bytes
+    pub fn new<D: AsRef<Path>>(bag: Bag, dst_dir: D, max_size: u64) -> Self {
+        Self {
+            bag,
+            dst_dir: dst_dir.as_ref().into(),
+            max_size,
+            group_identifier: None,
+            bagging_date: None,
+            path_encoding: PathEncoding::Standard,
+            tag_line_width: Some(DEFAULT_TAG_LINE_WIDTH),
+        }
+    }
+
+    /// Sets the Bag-Group-Identifier of the new bags. It defaults to the bag's first
+    /// External-Identifier, or the name of its base directory if it does not have one.
+    pub fn with_group_identifier(mut self, group_identifier: Option<String>) -> Self {
+        self.group_identifier = group_identifier;
+        self
+    }
+
+    /// Sets the Bagging-Date of the new bags. It defaults to the current date.
+    pub fn with_bagging_date(mut self, bagging_date: Option<String>) -> Self {
+        self.bagging_date = bagging_date;
+        self
+    }
+
+    /// Sets the profile that's used to decode the paths in the bag's manifests, and to encode
+    /// them in the new manifests. The default is `PathEncoding::Standard`.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Sets the length that lines in bag-info.txt are folded at. The default is 79 characters,
+    /// and `None` disables folding.
+    pub fn with_tag_line_width(mut self, tag_line_width: Option<usize>) -> Self {
+        self.tag_line_width = tag_line_width;
+        self
+    }
+
+    /// Writes the new bags, which are named after the bag's base directory with a sequence
+    /// number appended, eg. `bag-1`, `bag-2`, and returns them in order
+    pub fn split(self) -> Result<Vec<Bag>> {
+        let base_dir = self.bag.base_dir();
+
+        if base_dir.join(FETCH_TXT).exists() {
+            return Err(General {
+                message: format!("Cannot split a bag that has a {FETCH_TXT}"),
+            });
+        }
+        if !self.bag.declaration().is_utf8() {
+            return Err(General {
+                message: "Cannot split a bag that is not UTF-8 encoded. Run `bagr upgrade` first"
+                    .to_string(),
+            });
+        }
+        if self.max_size == 0 {
+            return Err(General {
+                message: "The maximum bag size must be greater than 0".to_string(),
+            });
+        }
+
+        let name = base_dir
+            .canonicalize()
+            .context(IoStatSnafu { path: base_dir })?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "bag".to_string());
+        let group_identifier = self
+            .group_identifier
+            .clone()
+            .or_else(|| {
+                self.bag
+                    .bag_info()
+                    .external_identifier()
+                    .next()
+                    .map(|tag| tag.value().to_string())
+            })
+            .unwrap_or_else(|| name.clone());
+
+        let groups = self.partition()?;
+        let tag_files = list_tag_files(base_dir)?;
+        let width = groups.len().to_string().len();
+
+        info!(
+            "Splitting {} into {} bags in {}",
+            base_dir.display(),
+            groups.len(),
+            self.dst_dir.display()
+        );
+
+        let mut bags = Vec::with_capacity(groups.len());
+        for (i, files) in groups.iter().enumerate() {
+            let bag_dir = self.dst_dir.join(format!("{name}-{:0width$}", i + 1));
+
+            let mut bag_info = self.bag.bag_info().clone();
+            for label in [
+                LABEL_BAGGING_DATE,
+                LABEL_SOFTWARE_AGENT,
+                LABEL_PAYLOAD_OXUM,
+                LABEL_BAG_SIZE,
+            ] {
+                bag_info.remove_tags(label);
+            }
+            if let Some(date) = &self.bagging_date {
+                bag_info.add_bagging_date(date)?;
+            }
+            bag_info.add_bag_group_identifier(&group_identifier)?;
+            bag_info.add_bag_count(format!("{} of {}", i + 1, groups.len()))?;
+
+            let mut writer = BagWriter::create(&bag_dir, self.bag.algorithms())?
+                .with_bag_info(bag_info)
+                .with_calculate_bag_size(self.bag.bag_info().bag_size().is_some())
+                .with_path_encoding(self.path_encoding)
+                .with_tag_line_width(self.tag_line_width);
+
+            for file in files {
+                copy_payload_file(&self.bag, file, &mut writer)?;
+            }
+            for path in &tag_files {
+                copy_tag_file(base_dir, &bag_dir, path)?;
+            }
+
+            bags.push(writer.finish()?);
+        }
+
+        Ok(bags)
+    }
+
+    /// Groups the payload files into bags that are at most `max_size` bytes
+    fn partition(&self) -> Result<Vec<Vec<PayloadFile>>> {
+        let mut groups: Vec<Vec<PayloadFile>> = vec![Vec::new()];
+        let mut group_size = 0;
+
+        for file in self.bag.payload_files_with_encoding(self.path_encoding)? {
+            let size = file.size().ok_or_else(|| General {
+                message: format!("Payload file {} does not exist", file.path()),
+            })?;
+            if size > self.max_size {
+                return Err(General {
+                    message: format!(
+                        "Payload file {} is {size} bytes, which is larger than the maximum bag \
+                        size of {} bytes",
+                        file.path(),
+                        self.max_size
+                    ),
+                });
+            }
+
+            if group_size + size > self.max_size {
+                groups.push(Vec::new());
+                group_size = 0;
+            }
+            group_size += size;
+            groups.last_mut().unwrap().push(file);
+        }
+
+        Ok(groups)
+    }
+}
+
+impl BagMerger {
+    /// Merges the `bags` into a new bag in `dst_dir`
+    pub fn new<D: AsRef<Path>>(bags: Vec<Bag>, dst_dir: D) -> Self {
+        Self {
+            bags,
+            dst_dir: dst_dir.as_ref().into(),
+            bagging_date: None,
+            path_encoding: PathEncoding::Standard,
+            tag_line_width: Some(DEFAULT_TAG_LINE_WIDTH),
+        }
+    }
+
+    /// Sets the Bagging-Date of the new bag. It defaults to the current date.
+    pub fn with_bagging_date(mut self, bagging_date: Option<String>) -> Self {
+        self.bagging_date = bagging_date;
+        self
+    }
+
+    /// Sets the profile that's used to decode the paths in the bags' manifests, and to encode
+    /// them in the new manifests. The default is `PathEncoding::Standard`.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Sets the length that lines in bag-info.txt are folded at. The default is 79 characters,
+    /// and `None` disables folding.
+    pub fn with_tag_line_width(mut self, tag_line_width: Option<usize>) -> Self {
+        self.tag_line_width = tag_line_width;
+        self
+    }
+
+    /// Writes the merged bag
+    pub fn merge(mut self) -> Result<Bag> {
+        self.check_group()?;
+
+        for bag in &self.bags {
+            if bag.base_dir().join(FETCH_TXT).exists() {
+                return Err(General {
+                    message: format!(
+                        "Cannot merge {}, which has a {FETCH_TXT}",
+                        bag.base_dir().display()
+                    ),
+                });
+            }
+            if !bag.declaration().is_utf8() {
+                return Err(General {
+                    message: format!(
+                        "Cannot merge {}, which is not UTF-8 encoded. Run `bagr upgrade` first",
+                        bag.base_dir().display()
+                    ),
+                });
+            }
+        }
+
+        let algorithms = DigestAlgorithm::iter()
+            .filter(|algorithm| {
+                self.bags
+                    .iter()
+                    .all(|bag| bag.algorithms().contains(algorithm))
+            })
+            .collect::<Vec<_>>();
+        if algorithms.is_empty() {
+            return Err(General {
+                message: "The bags do not have a digest algorithm in common".to_string(),
+            });
+        }
+
+        // Every payload file and the bag it's in, keyed on its path
+        let mut payload: HashMap<String, usize> = HashMap::new();
+        let mut files = Vec::with_capacity(self.bags.len());
+        for (i, bag) in self.bags.iter().enumerate() {
+            let bag_files = bag
+                .payload_files_with_encoding(self.path_encoding)?
+                .collect::<Vec<_>>();
+            for file in &bag_files {
+                if let Some(other) = payload.insert(file.path().to_string(), i) {
+                    return Err(General {
+                        message: format!(
+                            "Payload file {} is in both {} and {}",
+                            file.path(),
+                            self.bags[other].base_dir().display(),
+                            bag.base_dir().display()
+                        ),
+                    });
+                }
+            }
+            files.push(bag_files);
+        }
+
+        let first = &self.bags[0];
+        let mut bag_info = first.bag_info().clone();
+        for label in [
+            LABEL_BAGGING_DATE,
+            LABEL_SOFTWARE_AGENT,
+            LABEL_PAYLOAD_OXUM,
+            LABEL_BAG_SIZE,
+            LABEL_BAG_GROUP_IDENTIFIER,
+            LABEL_BAG_COUNT,
+        ] {
+            bag_info.remove_tags(label);
+        }
+        if let Some(date) = &self.bagging_date {
+            bag_info.add_bagging_date(date)?;
+        }
+
+        info!(
+            "Merging {} bags into {}",
+            self.bags.len(),
+            self.dst_dir.display()
+        );
+
+        let mut writer = BagWriter::create(&self.dst_dir, &algorithms)?
+            .with_bag_info(bag_info)
+            .with_calculate_bag_size(first.bag_info().bag_size().is_some())
+            .with_path_encoding(self.path_encoding)
+            .with_tag_line_width(self.tag_line_width);
+
+        // Tag files that are in more than one bag are expected to be identical
+        let mut tag_files: HashMap<PathBuf, usize> = HashMap::new();
+        for (i, bag) in self.bags.iter().enumerate() {
+            for file in &files[i] {
+                copy_payload_file(bag, file, &mut writer)?;
+            }
+
+            for path in list_tag_files(bag.base_dir())? {
+                match tag_files.get(&path) {
+                    None => {
+                        copy_tag_file(bag.base_dir(), &self.dst_dir, &path)?;
+                        tag_files.insert(path, i);
+                    }
+                    Some(other) => {
+                        let existing = self.bags[*other].base_dir().join(&path);
+                        let contents =
+                            fs::read(&existing).context(IoReadSnafu { path: &existing })?;
+                        let src = bag.base_dir().join(&path);
+                        if contents != fs::read(&src).context(IoReadSnafu { path: &src })? {
+                            warn!(
+                                "Tag file {} differs from {}. Only the first was kept",
+                                src.display(),
+                                existing.display()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        writer.finish()
+    }
+
+    /// Ensures that the bags are a complete group, and sorts them by their Bag-Count
+    fn check_group(&mut self) -> Result<()> {
+        if self.bags.is_empty() {
+            return Err(General {
+                message: "At least one bag must be merged".to_string(),
+            });
+        }
+
+        let mut group_identifier = None;
+        let mut total = None;
+        let mut numbered = Vec::with_capacity(self.bags.len());
+
+        for bag in &self.bags {
+            let path = bag.base_dir().display();
+            let identifier = bag
+                .bag_info()
+                .bag_group_identifier()
+                .map(|tag| tag.value().to_string())
+                .ok_or_else(|| General {
+                    message: format!("{path} does not have a {LABEL_BAG_GROUP_IDENTIFIER}"),
+                })?;
+            match &group_identifier {
+                None => group_identifier = Some(identifier),
+                Some(expected) if *expected != identifier => {
+                    return Err(General {
+                        message: format!(
+                            "{path} is in group '{identifier}', but the other bags are in \
+                            '{expected}'"
+                        ),
+                    })
+                }
+                Some(_) => {}
+            }
+
+            let (number, bag_total) = bag
+                .bag_info()
+                .bag_count()
+                .and_then(|tag| parse_bag_count(tag.value()))
+                .ok_or_else(|| General {
+                    message: format!("{path} does not have a valid {LABEL_BAG_COUNT}"),
+                })?;
+            if let Some(bag_total) = bag_total {
+                match total {
+                    Some(expected) if expected != bag_total => {
+                        return Err(General {
+                            message: format!(
+                                "{path} is {number} of {bag_total}, but another bag's \
+                                {LABEL_BAG_COUNT} has a total of {expected}"
+                            ),
+                        })
+                    }
+                    _ => total = Some(bag_total),
+                }
+            }
+            numbered.push(number);
+        }
+
+        let expected = total.unwrap_or(self.bags.len() as u64);
+        let mut sorted = numbered.clone();
+        sorted.sort_unstable();
+        if sorted != (1..=expected).collect::<Vec<_>>() {
+            return Err(General {
+                message: format!(
+                    "The group has {expected} bags, but the {LABEL_BAG_COUNT}s of the bags that \
+                    were provided are {}",
+                    sorted
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+
+        let mut bags = numbered
+            .into_iter()
+            .zip(self.bags.drain(..))
+            .collect::<Vec<_>>();
+        bags.sort_by_key(|(number, _)| *number);
+        self.bags = bags.into_iter().map(|(_, bag)| bag).collect();
+
+        Ok(())
+    }
+}
+
+/// Merges the group of `bags` into a new bag in `dst_dir`. See `BagMerger`.
+pub fn merge_bags<D: AsRef<Path>>(bags: Vec<Bag>, dst_dir: D) -> Result<Bag> {
+    BagMerger::new(bags, dst_dir).merge()
+}
+
+impl Bag {
+    /// Creates a `BagSplitter` that splits the bag into bags in `dst_dir` whose payloads are at
+    /// most `max_size` bytes
+    pub fn split<D: AsRef<Path>>(self, dst_dir: D, max_size: u64) -> BagSplitter {
+        BagSplitter::new(self, dst_dir, max_size)
+    }
+}
+
+/// Copies the payload `file` of the `bag` into the bag that's being written by the `writer`, and
+/// verifies that its digests match the `bag`'s manifests
+fn copy_payload_file(bag: &Bag, file: &PayloadFile, writer: &mut BagWriter) -> Result<()> {
+    let src = bag.base_dir().join(file.path());
+    let relative = Path::new(file.path())
+        .strip_prefix(DATA)
+        .unwrap_or_else(|_| Path::new(file.path()));
+    let dst = writer.payload_dir().join(relative);
+
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+    }
+
+    let mut reader = File::open(&src).context(IoReadSnafu { path: &src })?;
+    let mut digest_writer = MultiDigestWriter::new(
+        writer.algorithms(),
+        File::create(&dst).context(IoCreateSnafu { path: &dst })?,
+    );
+    io::copy(&mut reader, &mut digest_writer).context(IoCopySnafu {
+        from: &src,
+        to: &dst,
+    })?;
+    let (mut dst_file, digests) = digest_writer.into_parts();
+    dst_file.flush().context(IoWriteSnafu { path: &dst })?;
+
+    for (algorithm, digest) in &digests {
+        if file.digest(*algorithm) != Some(digest.as_str()) {
+            return Err(General {
+                message: format!(
+                    "The {algorithm} digest of {} in {} does not match the manifest. Validate \
+                    the bag first",
+                    file.path(),
+                    bag.base_dir().display()
+                ),
+            });
+        }
+    }
+
+    writer.add_hashed_payload_file(relative, digests)?;
+    Ok(())
+}
+
+/// Copies the tag file at `path`, which is relative to the `src_dir`, to the same path in the
+/// `dst_dir`
+fn copy_tag_file(src_dir: &Path, dst_dir: &Path, path: &Path) -> Result<()> {
+    let src = src_dir.join(path);
+    let dst = dst_dir.join(path);
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+    }
+    fs::copy(&src, &dst).context(IoCopySnafu { from: src, to: dst })?;
+    Ok(())
+}
+
+/// Lists the tag files in `base_dir`, other than bagit.txt, bag-info.txt, and the manifests,
+/// relative to it
+fn list_tag_files(base_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    let mut walker = WalkDir::new(base_dir).sort_by_file_name().into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.context(WalkFileSnafu {})?;
+        let relative = entry.path().strip_prefix(base_dir).unwrap();
+
+        if entry.depth() == 1 && entry.file_type().is_dir() && relative == Path::new(DATA) {
+            walker.skip_current_dir();
+        } else if entry.file_type().is_file() {
+            let name = relative.to_string_lossy();
+            if entry.depth() > 1
+                || !(name == BAGIT_TXT || name == BAG_INFO_TXT || is_manifest_name(&name))
+            {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::bagit::bag::{open_bag, BagWriter};
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::group::merge_bags;
+    use crate::bagit::tag::BagInfo;
+
+    #[test]
+    fn split_and_merge_bags() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut writer = BagWriter::create(dir.join("bag"), &[DigestAlgorithm::Sha256]).unwrap();
+        for (name, contents) in [
+            ("a.txt", "aaaaaaaa\n"),
+            ("b.txt", "bb\n"),
+            ("c/d.txt", "dddd\n"),
+        ] {
+            writer.add_payload_file(name, contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let split = open_bag(dir.join("bag"))
+            .unwrap()
+            .split(dir.join("split"), 12)
+            .split()
+            .unwrap();
+        let counts = split
+            .iter()
+            .map(|bag| bag.bag_info().bag_count().unwrap().value().to_string())
+            .collect::<Vec<_>>();
+        let merged = merge_bags(split.into_iter().rev().collect(), dir.join("merged"));
+
+        // Both bags in the group contain a.txt
+        for number in [1, 2] {
+            let mut bag_info = BagInfo::new();
+            bag_info.add_bag_group_identifier("group").unwrap();
+            bag_info.add_bag_count(format!("{number} of 2")).unwrap();
+            let mut writer = BagWriter::create(
+                dir.join(format!("collide-{number}")),
+                &[DigestAlgorithm::Sha256],
+            )
+            .unwrap()
+            .with_bag_info(bag_info);
+            writer.add_payload_file("a.txt", "a".as_bytes()).unwrap();
+            writer.finish().unwrap();
+        }
+        let collision = merge_bags(
+            vec![
+                open_bag(dir.join("collide-1")).unwrap(),
+                open_bag(dir.join("collide-2")).unwrap(),
+            ],
+            dir.join("collision"),
+        );
+
+        let original = fs::read_to_string(dir.join("bag/manifest-sha256.txt"));
+        let manifest = fs::read_to_string(dir.join("merged/manifest-sha256.txt"));
+
+        assert_eq!(vec!["1 of 2", "2 of 2"], counts);
+        let merged = merged.unwrap();
+        assert!(merged.bag_info().bag_count().is_none());
+        assert_eq!(original.unwrap(), manifest.unwrap());
+        assert!(collision
+            .unwrap_err()
+            .to_string()
+            .contains("data/a.txt is in both"));
+    }
+}
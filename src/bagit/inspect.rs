@@ -0,0 +1,121 @@
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use snafu::ResultExt;
+
+use crate::bagit::error::*;
+
+/// The number of bytes at the start of a file that are used to detect its format
+const HEADER_SIZE: u64 = 8 * 1024;
+
+/// Groups of file extensions that are used for the same format. The first extension in each group
+/// is the one that format detection returns. Formats that are built on top of zip or CFB are
+/// included with them because their contents are frequently detected as the container format.
+const EQUIVALENT_EXTENSIONS: &[&[&str]] = &[
+    &["jpg", "jpeg", "jpe", "jfif"],
+    &["tif", "tiff"],
+    &["mpg", "mpeg"],
+    &["midi", "mid"],
+    &["aiff", "aif"],
+    &["heif", "heic"],
+    &["mov", "qt"],
+    &["ogg", "oga", "ogv", "opus", "spx"],
+    &["mp4", "m4a", "m4v", "m4b", "m4p", "f4v"],
+    &["gz", "gzip", "tgz"],
+    &["bz2", "tbz", "tbz2"],
+    &["xz", "txz"],
+    &["exe", "dll", "sys", "scr"],
+    &["elf", "so", "o", "bin"],
+    &[
+        "zip", "docx", "xlsx", "pptx", "odt", "ods", "odp", "odg", "epub", "jar", "war", "ear",
+        "apk", "xpi", "kmz", "ipa",
+    ],
+    &["doc", "xls", "ppt", "msi", "msg"],
+];
+
+/// A reason to suspect that a payload file may have been truncated or corrupted
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Suspicion {
+    /// The file does not contain any bytes
+    Empty,
+    /// The file's extension does not match the format that was detected from its contents
+    ExtensionMismatch {
+        extension: String,
+        detected: &'static str,
+    },
+}
+
+/// Inspects the file at `path`, which is `size` bytes, for signs that it may have been truncated
+/// or corrupted. `None` is returned when nothing suspicious is found.
+///
+/// This is only a heuristic. It is possible for a valid file to be flagged, and for a corrupt file
+/// not to be.
+pub fn inspect_file(path: &Path, size: u64) -> Result<Option<Suspicion>> {
+    if size == 0 {
+        return Ok(Some(Suspicion::Empty));
+    }
+
+    let extension = match path.extension().and_then(OsStr::to_str) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return Ok(None),
+    };
+
+    let mut header = Vec::with_capacity(HEADER_SIZE.min(size) as usize);
+    File::open(path)
+        .and_then(|file| file.take(HEADER_SIZE).read_to_end(&mut header))
+        .context(IoReadSnafu { path })?;
+
+    let detected = match infer::get(&header) {
+        // Text formats are detected too loosely to be meaningful
+        Some(kind) if !kind.mime_type().starts_with("text/") => kind.extension(),
+        _ => return Ok(None),
+    };
+
+    if is_equivalent_extension(&extension, detected) {
+        Ok(None)
+    } else {
+        Ok(Some(Suspicion::ExtensionMismatch {
+            extension,
+            detected,
+        }))
+    }
+}
+
+impl Display for Suspicion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Suspicion::Empty => write!(f, "is empty"),
+            Suspicion::ExtensionMismatch {
+                extension,
+                detected,
+            } => write!(
+                f,
+                "has a .{extension} extension, but its contents look like a {detected} file"
+            ),
+        }
+    }
+}
+
+fn is_equivalent_extension(extension: &str, detected: &str) -> bool {
+    extension == detected
+        || EQUIVALENT_EXTENSIONS
+            .iter()
+            .any(|group| group.contains(&detected) && group.contains(&extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::inspect::is_equivalent_extension;
+
+    #[test]
+    fn test_equivalent_extensions() {
+        assert!(is_equivalent_extension("png", "png"));
+        assert!(is_equivalent_extension("jpeg", "jpg"));
+        assert!(is_equivalent_extension("docx", "zip"));
+        assert!(!is_equivalent_extension("png", "jpg"));
+        assert!(!is_equivalent_extension("docx", "pdf"));
+    }
+}
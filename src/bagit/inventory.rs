@@ -0,0 +1,343 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use snafu::ResultExt;
+
+use crate::bagit::bag::open_bag;
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS bag (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    indexed TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS payload_file (
+    bag_id INTEGER NOT NULL REFERENCES bag (id),
+    path TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    digest TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS payload_file_digest ON payload_file (digest);
+CREATE INDEX IF NOT EXISTS payload_file_bag ON payload_file (bag_id);
+CREATE TABLE IF NOT EXISTS external_identifier (
+    bag_id INTEGER NOT NULL REFERENCES bag (id),
+    identifier TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS external_identifier_value ON external_identifier (identifier);
+CREATE INDEX IF NOT EXISTS external_identifier_bag ON external_identifier (bag_id);
+";
+
+/// An sqlite database that records the contents of many bags, so that they can be searched
+/// without having to read every bag
+#[derive(Debug)]
+pub struct Inventory {
+    path: PathBuf,
+    connection: Connection,
+}
+
+/// A payload file that was found in the inventory
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InventoryFile {
+    bag_path: PathBuf,
+    path: String,
+    algorithm: DigestAlgorithm,
+    digest: String,
+}
+
+impl Inventory {
+    /// Opens the inventory database at `path`, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        info!("Opening inventory database {}", path.display());
+
+        let connection = Connection::open(path).context(InventorySnafu { path })?;
+        connection
+            .execute_batch(SCHEMA)
+            .context(InventorySnafu { path })?;
+
+        Ok(Self {
+            path: path.into(),
+            connection,
+        })
+    }
+
+    /// Records the digests of all of the payload files in the bag at `base_dir`, and its
+    /// External-Identifiers, replacing any entries from a previous indexing of the same bag.
+    /// Returns the number of payload files that were indexed.
+    ///
+    /// A warning is logged for every External-Identifier that's already used by a different bag
+    /// in the inventory.
+    pub fn index_bag<P: AsRef<Path>>(
+        &mut self,
+        base_dir: P,
+        encoding: PathEncoding,
+    ) -> Result<u64> {
+        let base_dir = fs::canonicalize(base_dir.as_ref()).context(IoStatSnafu {
+            path: base_dir.as_ref(),
+        })?;
+        info!("Indexing bag {}", base_dir.display());
+
+        let bag = open_bag(&base_dir)?;
+        let identifiers = bag
+            .bag_info()
+            .external_identifier()
+            .map(|tag| tag.value().to_string())
+            .collect::<Vec<_>>();
+        let files = bag
+            .payload_files_with_encoding(encoding)?
+            .collect::<Vec<_>>();
+        let bag_path = base_dir.to_str().ok_or_else(|| InvalidUtf8Path {
+            path: base_dir.clone(),
+        })?;
+
+        let db_path = self.path.as_path();
+        let tx = self
+            .connection
+            .transaction()
+            .context(InventorySnafu { path: db_path })?;
+
+        let existing: Option<i64> = tx
+            .query_row("SELECT id FROM bag WHERE path = ?1", [bag_path], |row| {
+                row.get(0)
+            })
+            .optional()
+            .context(InventorySnafu { path: db_path })?;
+        if let Some(id) = existing {
+            tx.execute("DELETE FROM payload_file WHERE bag_id = ?1", [id])
+                .context(InventorySnafu { path: db_path })?;
+            tx.execute("DELETE FROM external_identifier WHERE bag_id = ?1", [id])
+                .context(InventorySnafu { path: db_path })?;
+            tx.execute("DELETE FROM bag WHERE id = ?1", [id])
+                .context(InventorySnafu { path: db_path })?;
+        }
+
+        tx.execute(
+            "INSERT INTO bag (path, indexed) VALUES (?1, ?2)",
+            params![bag_path, Local::now().to_rfc3339()],
+        )
+        .context(InventorySnafu { path: db_path })?;
+        let bag_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO payload_file (bag_id, path, algorithm, digest) \
+                    VALUES (?1, ?2, ?3, ?4)",
+                )
+                .context(InventorySnafu { path: db_path })?;
+            for file in &files {
+                for (algorithm, digest) in file.digests() {
+                    insert
+                        .execute(params![
+                            bag_id,
+                            file.path(),
+                            algorithm.to_string(),
+                            digest.to_ascii_lowercase()
+                        ])
+                        .context(InventorySnafu { path: db_path })?;
+                }
+            }
+
+            let mut duplicates = tx
+                .prepare(
+                    "SELECT bag.path FROM external_identifier \
+                    JOIN bag ON bag.id = external_identifier.bag_id \
+                    WHERE external_identifier.identifier = ?1 AND bag.id != ?2 \
+                    ORDER BY bag.path",
+                )
+                .context(InventorySnafu { path: db_path })?;
+            let mut insert = tx
+                .prepare("INSERT INTO external_identifier (bag_id, identifier) VALUES (?1, ?2)")
+                .context(InventorySnafu { path: db_path })?;
+            for identifier in &identifiers {
+                let others = duplicates
+                    .query_map(params![identifier, bag_id], |row| row.get::<_, String>(0))
+                    .context(InventorySnafu { path: db_path })?;
+                for other in others {
+                    warn!(
+                        "External-Identifier {identifier} of bag {bag_path} is also used by bag {}",
+                        other.context(InventorySnafu { path: db_path })?
+                    );
+                }
+                insert
+                    .execute(params![bag_id, identifier])
+                    .context(InventorySnafu { path: db_path })?;
+            }
+        }
+
+        tx.commit().context(InventorySnafu { path: db_path })?;
+
+        Ok(files.len() as u64)
+    }
+
+    /// Returns all of the indexed payload files that have the specified `digest`. If `algorithm`
+    /// is specified, then only digests that were calculated with it are matched.
+    pub fn find_digest(
+        &self,
+        digest: &str,
+        algorithm: Option<DigestAlgorithm>,
+    ) -> Result<Vec<InventoryFile>> {
+        let db_path = self.path.as_path();
+        let mut query = self
+            .connection
+            .prepare(
+                "SELECT bag.path, payload_file.path, payload_file.algorithm, payload_file.digest \
+                FROM payload_file JOIN bag ON bag.id = payload_file.bag_id \
+                WHERE payload_file.digest = ?1 AND (?2 IS NULL OR payload_file.algorithm = ?2) \
+                ORDER BY bag.path, payload_file.path, payload_file.algorithm",
+            )
+            .context(InventorySnafu { path: db_path })?;
+
+        let rows = query
+            .query_map(
+                params![
+                    digest.to_ascii_lowercase(),
+                    algorithm.map(|algorithm| algorithm.to_string())
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .context(InventorySnafu { path: db_path })?;
+
+        let mut files = Vec::new();
+
+        for row in rows {
+            let (bag_path, path, algorithm, digest) =
+                row.context(InventorySnafu { path: db_path })?;
+            // Unknown algorithms can only come from a newer version of bagr
+            let algorithm = match DigestAlgorithm::try_from(algorithm.as_str()) {
+                Ok(algorithm) => algorithm,
+                Err(_) => continue,
+            };
+            files.push(InventoryFile {
+                bag_path: bag_path.into(),
+                path,
+                algorithm,
+                digest,
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Returns the paths of all of the indexed bags that have the specified External-Identifier
+    pub fn find_external_identifier(&self, identifier: &str) -> Result<Vec<PathBuf>> {
+        let db_path = self.path.as_path();
+        let mut query = self
+            .connection
+            .prepare(
+                "SELECT DISTINCT bag.path FROM external_identifier \
+                JOIN bag ON bag.id = external_identifier.bag_id \
+                WHERE external_identifier.identifier = ?1 ORDER BY bag.path",
+            )
+            .context(InventorySnafu { path: db_path })?;
+
+        let rows = query
+            .query_map([identifier], |row| row.get::<_, String>(0))
+            .context(InventorySnafu { path: db_path })?;
+
+        let mut paths = Vec::new();
+        for row in rows {
+            paths.push(row.context(InventorySnafu { path: db_path })?.into());
+        }
+        Ok(paths)
+    }
+}
+
+impl InventoryFile {
+    /// The absolute path to the base directory of the bag that contains the file
+    pub fn bag_path(&self) -> &Path {
+        &self.bag_path
+    }
+
+    /// The path of the file relative the bag's base directory
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::encoding::PathEncoding;
+    use crate::bagit::inventory::Inventory;
+
+    #[test]
+    fn test_find_indexed_digest() {
+        let mut inventory = Inventory::open(":memory:").unwrap();
+        let indexed = inventory
+            .index_bag("tests/cmd/validate/valid-bag.in", PathEncoding::Standard)
+            .unwrap();
+        assert_eq!(2, indexed);
+
+        // Re-indexing a bag replaces its existing entries
+        inventory
+            .index_bag("tests/cmd/validate/valid-bag.in", PathEncoding::Standard)
+            .unwrap();
+
+        let digest = "5F5D584C5857D85AF911ADE1B2AE7CB593C17654282091F3ACE31EFD9E951360";
+        let found = inventory.find_digest(digest, None).unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!("data/file1.txt", found[0].path());
+        assert_eq!(DigestAlgorithm::Sha256, found[0].algorithm());
+        assert!(found[0].bag_path().ends_with("valid-bag.in"));
+
+        assert!(inventory
+            .find_digest(digest, Some(DigestAlgorithm::Sha512))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_external_identifier() {
+        let base = "tests/cmd/inventory/duplicate-identifiers.in";
+        let mut inventory = Inventory::open(":memory:").unwrap();
+        inventory
+            .index_bag(format!("{base}/bag1"), PathEncoding::Standard)
+            .unwrap();
+        inventory
+            .index_bag(format!("{base}/bag2"), PathEncoding::Standard)
+            .unwrap();
+        // Re-indexing a bag does not duplicate its identifiers
+        inventory
+            .index_bag(format!("{base}/bag2"), PathEncoding::Standard)
+            .unwrap();
+
+        let shared = inventory
+            .find_external_identifier("ark:/12345/shared")
+            .unwrap();
+        assert_eq!(2, shared.len());
+        assert!(shared[0].ends_with("bag1"));
+        assert!(shared[1].ends_with("bag2"));
+
+        let single = inventory
+            .find_external_identifier("ark:/12345/bag2")
+            .unwrap();
+        assert_eq!(1, single.len());
+        assert!(inventory
+            .find_external_identifier("ark:/12345/missing")
+            .unwrap()
+            .is_empty());
+    }
+}
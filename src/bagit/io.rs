@@ -2,15 +2,18 @@ use crate::bagit::consts::*;
 use crate::bagit::error::*;
 use crate::bagit::Error::IoGeneral;
 use snafu::ResultExt;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::mem;
 
-/// Iteratively reads lines. Lines can be terminated by CR, LF, or CRLF.
+/// Iteratively reads lines. Lines can be terminated by CR, LF, or CRLF. A UTF-8 byte order mark
+/// at the start of the first line is stripped.
 pub struct LineReader<R: Read> {
     reader: R,
     buf: [u8; BUF_SIZE],
     position: usize,
     read: usize,
     end: bool,
+    first: bool,
 }
 
 /// Iteratively reads BagIt tag lines. Tag lines can be terminated by CR, LF, or CRLF. Lines
@@ -33,8 +36,18 @@ impl<R: Read> LineReader<R> {
             position: 0,
             read: 0,
             end: false,
+            first: true,
         }
     }
+
+    /// Converts the bytes of a line to a string, stripping the byte order mark if it's the first
+    /// line
+    fn line_to_string(&mut self, mut line: Vec<u8>) -> Result<String> {
+        if mem::take(&mut self.first) && line.starts_with(UTF_8_BOM) {
+            line.drain(..UTF_8_BOM.len());
+        }
+        bytes_to_string(line)
+    }
 }
 
 impl<R: Read> Iterator for LineReader<R> {
@@ -66,7 +79,7 @@ impl<R: Read> Iterator for LineReader<R> {
                 return if line.is_empty() {
                     None
                 } else {
-                    Some(bytes_to_string(line))
+                    Some(self.line_to_string(line))
                 };
             }
 
@@ -98,7 +111,7 @@ impl<R: Read> Iterator for LineReader<R> {
                 continue;
             }
 
-            return Some(bytes_to_string(line));
+            return Some(self.line_to_string(line));
         }
     }
 }
@@ -151,6 +164,45 @@ fn bytes_to_string(bytes: Vec<u8>) -> Result<String> {
     String::from_utf8(bytes).context(InvalidStringSnafu {})
 }
 
+/// Writes everything that's read from the wrapped reader to a writer. Write errors are retained,
+/// so that they can be told apart from read errors.
+pub struct TeeReader<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+    write_error: Option<std::io::Error>,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            write_error: None,
+        }
+    }
+
+    /// Returns the error that occurred while writing, if any
+    pub fn take_write_error(&mut self) -> Option<std::io::Error> {
+        self.write_error.take()
+    }
+
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if let Err(e) = self.writer.write_all(&buf[..read]) {
+            let error = std::io::Error::new(e.kind(), e.to_string());
+            self.write_error = Some(e);
+            return Err(error);
+        }
+        Ok(read)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bagit::io::{LineReader, TagLineReader};
@@ -182,6 +234,16 @@ mod tests {
         assert_eq!(vec!["", "line 1", "line 2", "line 3"], lines);
     }
 
+    #[test]
+    fn strip_byte_order_mark_from_first_line() {
+        let input = "\u{feff}BagIt-Version: 1.0\n\u{feff}line 2\n";
+        let reader = LineReader::new(BufReader::new(input.as_bytes()));
+
+        let lines: Vec<String> = reader.flatten().collect();
+
+        assert_eq!(vec!["BagIt-Version: 1.0", "\u{feff}line 2"], lines);
+    }
+
     #[test]
     fn read_multi_line_tags() {
         let input =
@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{info, warn};
+use snafu::ResultExt;
+use walkdir::WalkDir;
+
+use crate::bagit::error::*;
+
+/// Records the changes that are made to the file system while a bag is created, so that they can
+/// be undone if the bag cannot be completed. Changes are undone in the reverse of the order they
+/// were made in.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Mutex<Vec<Entry>>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// A file or directory was moved from the first path to the second
+    Moved(PathBuf, PathBuf),
+    /// A file or directory was created. Directories are only deleted if they do not contain
+    /// any files, so that files that could not be moved back are never lost.
+    Created(PathBuf),
+    /// A directory was created, and everything in it was written by bagr
+    CreatedTree(PathBuf),
+    /// An empty directory was deleted
+    RemovedDir(PathBuf),
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `path` is about to be created, along with any of its parent directories that
+    /// do not exist. Nothing is recorded if it already exists.
+    pub fn creating(&self, path: &Path) {
+        if let Some(path) = first_missing(path) {
+            self.push(Entry::Created(path));
+        }
+    }
+
+    /// Records that the directory `path` is about to be created, along with any of its parent
+    /// directories that do not exist, and that everything that's written to it can be deleted
+    pub fn creating_tree(&self, path: &Path) {
+        if let Some(path) = first_missing(path) {
+            self.push(Entry::CreatedTree(path));
+        }
+    }
+
+    /// Moves the file or directory at `from` to `to`
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        info!("Moving {} to {}", from.display(), to.display());
+        fs::rename(from, to).context(IoMoveSnafu { from, to })?;
+        self.push(Entry::Moved(from.into(), to.into()));
+        Ok(())
+    }
+
+    /// Deletes the directory at `path`, which must not contain any files. Its subdirectories are
+    /// only restored if they are also deleted through the journal.
+    pub fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        fs::remove_dir_all(path)?;
+        self.push(Entry::RemovedDir(path.into()));
+        Ok(())
+    }
+
+    /// Undoes every recorded change. Changes that cannot be undone are logged and skipped, and
+    /// the journal is empty afterwards.
+    pub fn rollback(&self) {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+
+        for entry in entries.into_iter().rev() {
+            let result = match &entry {
+                Entry::Moved(from, to) => {
+                    info!("Moving {} back to {}", to.display(), from.display());
+                    from.parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::rename(to, from))
+                }
+                Entry::Created(path) => {
+                    if path.is_dir() {
+                        if contains_files(path) {
+                            warn!("Not deleting {} because it contains files", path.display());
+                            Ok(())
+                        } else {
+                            info!("Deleting {}", path.display());
+                            fs::remove_dir_all(path)
+                        }
+                    } else {
+                        info!("Deleting {}", path.display());
+                        remove_file(path)
+                    }
+                }
+                Entry::CreatedTree(path) => {
+                    info!("Deleting {}", path.display());
+                    if path.is_dir() {
+                        fs::remove_dir_all(path)
+                    } else {
+                        remove_file(path)
+                    }
+                }
+                Entry::RemovedDir(path) => fs::create_dir_all(path),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to undo {:?}: {}", entry, e);
+            }
+        }
+    }
+
+    fn push(&self, entry: Entry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+}
+
+/// Returns the top-most ancestor of `path`, or `path` itself, that does not exist
+fn first_missing(path: &Path) -> Option<PathBuf> {
+    let mut missing = None;
+    for ancestor in path.ancestors() {
+        if ancestor.as_os_str().is_empty() || ancestor.exists() {
+            break;
+        }
+        missing = Some(ancestor.to_path_buf());
+    }
+    missing
+}
+
+fn contains_files(dir: &Path) -> bool {
+    WalkDir::new(dir)
+        .into_iter()
+        .any(|entry| entry.map_or(true, |entry| !entry.file_type().is_dir()))
+}
+
+/// Deletes the file at `path`, if it exists
+fn remove_file(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::bagit::journal::Journal;
+
+    #[test]
+    fn rollback_restores_moved_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/file.txt"), "contents").unwrap();
+
+        let journal = Journal::new();
+        let temp = src.join("temp");
+        journal.creating(&temp);
+        fs::create_dir_all(temp.join("sub")).unwrap();
+        journal
+            .rename(&src.join("sub/file.txt"), &temp.join("sub/file.txt"))
+            .unwrap();
+        journal.remove_dir_all(&src.join("sub")).unwrap();
+        journal.creating(&src.join("bagit.txt"));
+        fs::write(src.join("bagit.txt"), "tag").unwrap();
+
+        journal.rollback();
+
+        assert_eq!(
+            "contents",
+            fs::read_to_string(src.join("sub/file.txt")).unwrap()
+        );
+        assert!(!temp.exists());
+        assert!(!src.join("bagit.txt").exists());
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use snafu::ResultExt;
+
+use crate::bagit::bag::Bag;
+use crate::bagit::consts::*;
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::manifest::read_tag_manifest;
+
+/// Formats that the files in a bag can be listed in
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ListFormat {
+    /// Tab separated values with a header row, and columns for the path, size, and the digest
+    /// from each manifest. Tabs, newlines, and backslashes in paths are escaped with a backslash.
+    Tsv,
+    /// A JSON array with an object for each file that has its `path`, `size`, and `digests`,
+    /// keyed by algorithm
+    Json,
+}
+
+/// Writes the files that are listed in the payload manifests of `bag`, and its tag manifests if
+/// `include_tags` is true, to `writer` in the `format`, and returns the writer. Sizes are read
+/// from the filesystem, and digests from the manifests, so nothing is hashed. Files that are
+/// listed but do not exist do not have a size.
+///
+/// Only the files that match one of the glob `patterns` are listed, or every file if there are
+/// none. Patterns are matched against paths relative to the bag's base directory, eg.
+/// `data/file.txt`, and patterns that do not contain a `/` match names at any depth. Manifest
+/// paths are decoded using `encoding`.
+pub fn list_files<S: AsRef<str>, W: Write>(
+    bag: &Bag,
+    patterns: &[S],
+    include_tags: bool,
+    format: ListFormat,
+    encoding: PathEncoding,
+    mut writer: W,
+) -> Result<W> {
+    let stream_path = Path::new(LIST_STREAM);
+    let globs = compile_patterns(patterns)?;
+
+    let mut files: BTreeMap<String, HashMap<DigestAlgorithm, String>> = BTreeMap::new();
+    for file in bag.payload_files_with_encoding(encoding)? {
+        files.insert(file.path().to_string(), file.digests().clone());
+    }
+    if include_tags {
+        for algorithm in bag.algorithms() {
+            let manifest = format!("{TAG_MANIFEST_PREFIX}-{algorithm}.txt");
+            if !bag.base_dir().join(manifest).is_file() {
+                continue;
+            }
+            for (path, digest) in read_tag_manifest(bag.base_dir(), *algorithm, encoding)?.iter() {
+                files
+                    .entry(path.clone())
+                    .or_default()
+                    .insert(*algorithm, digest.as_str().to_string());
+            }
+        }
+    }
+
+    let algorithms = bag.algorithms();
+    match format {
+        ListFormat::Tsv => {
+            let names = algorithms
+                .iter()
+                .map(|algorithm| algorithm.to_string())
+                .collect::<Vec<_>>();
+            writeln!(writer, "path\tsize\t{}", names.join("\t"))
+        }
+        ListFormat::Json => write!(writer, "["),
+    }
+    .context(IoWriteSnafu { path: stream_path })?;
+
+    let mut first = true;
+    for (path, digests) in files
+        .iter()
+        .filter(|(path, _)| patterns.is_empty() || globs.is_match(path.as_str()))
+    {
+        let size = fs::metadata(bag.base_dir().join(path))
+            .ok()
+            .map(|meta| meta.len());
+
+        match format {
+            ListFormat::Tsv => {
+                let digests = algorithms
+                    .iter()
+                    .map(|algorithm| digests.get(algorithm).map(String::as_str))
+                    .map(Option::unwrap_or_default)
+                    .collect::<Vec<_>>();
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}",
+                    tsv_field(path),
+                    size.map(|size| size.to_string()).unwrap_or_default(),
+                    digests.join("\t")
+                )
+            }
+            ListFormat::Json => {
+                let digests = algorithms
+                    .iter()
+                    .filter_map(|algorithm| {
+                        digests
+                            .get(algorithm)
+                            .map(|digest| format!("\"{algorithm}\":\"{digest}\""))
+                    })
+                    .collect::<Vec<_>>();
+                write!(
+                    writer,
+                    "{}\n  {{\"path\":{},\"size\":{},\"digests\":{{{}}}}}",
+                    if first { "" } else { "," },
+                    json_string(path),
+                    size.map(|size| size.to_string())
+                        .unwrap_or_else(|| "null".to_string()),
+                    digests.join(",")
+                )
+            }
+        }
+        .context(IoWriteSnafu { path: stream_path })?;
+        first = false;
+    }
+
+    if format == ListFormat::Json {
+        writeln!(writer, "{}]", if first { "" } else { "\n" })
+            .context(IoWriteSnafu { path: stream_path })?;
+    }
+
+    writer.flush().context(IoWriteSnafu { path: stream_path })?;
+    Ok(writer)
+}
+
+/// Compiles the glob `patterns`. Patterns that do not contain a `/` match names at any depth.
+fn compile_patterns<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet> {
+    let mut globs = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{pattern}")
+        };
+
+        globs.add(
+            GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| General {
+                    message: format!("Invalid pattern '{pattern}': {e}"),
+                })?,
+        );
+    }
+
+    globs.build().map_err(|e| General {
+        message: format!("Invalid patterns: {e}"),
+    })
+}
+
+fn tsv_field(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::list::{compile_patterns, json_string};
+
+    #[test]
+    fn patterns_without_a_slash_match_names_at_any_depth() {
+        let globs = compile_patterns(&["*.txt", "data/images/*"]).unwrap();
+
+        assert!(globs.is_match("bag-info.txt"));
+        assert!(globs.is_match("data/dir/file.txt"));
+        assert!(globs.is_match("data/images/photo.jpg"));
+        assert!(!globs.is_match("data/images/raw/photo.cr2"));
+        assert!(!globs.is_match("data/file.csv"));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(
+            r#""data/a \"b\"\\c\n\u0007""#,
+            json_string("data/a \"b\"\\c\n\u{7}")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use snafu::ResultExt;
+
+use crate::bagit::consts::*;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+
+/// An advisory lock that's held on a bag's base directory while the bag is created or updated,
+/// so that two processes do not modify the same bag at the same time. The lock is a file named
+/// `.bagr.lock` that contains the ID of the process that holds it, and it's released when it's
+/// dropped.
+#[derive(Debug)]
+pub struct BagLock {
+    path: PathBuf,
+}
+
+impl BagLock {
+    /// Locks the bag in `base_dir`. It is an error if another process holds the lock, unless
+    /// `force` is true, in which case the existing lock is assumed to be stale and is replaced.
+    pub fn acquire<P: AsRef<Path>>(base_dir: P, force: bool) -> Result<Self> {
+        let path = base_dir.as_ref().join(BAG_LOCK);
+        let pid = std::process::id().to_string();
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => file
+                .write_all(pid.as_bytes())
+                .context(IoWriteSnafu { path: &path })?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&path)
+                    .map(|holder| holder.trim().to_string())
+                    .unwrap_or_default();
+                if !force {
+                    return Err(BagLocked {
+                        path: base_dir.as_ref().into(),
+                        pid: holder,
+                    });
+                }
+                warn!(
+                    "Replacing the lock on {} held by process {}",
+                    base_dir.as_ref().display(),
+                    holder
+                );
+                fs::write(&path, &pid).context(IoWriteSnafu { path: &path })?;
+            }
+            Err(e) => return Err(e).context(IoCreateSnafu { path: &path }),
+        }
+
+        info!("Locked {}", base_dir.as_ref().display());
+        Ok(Self { path })
+    }
+}
+
+impl Drop for BagLock {
+    fn drop(&mut self) {
+        // The lock may have already been deleted with the rest of a bag that failed to be created
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("Failed to release lock {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::bagit::consts::BAG_LOCK;
+    use crate::bagit::error::Error::BagLocked;
+    use crate::bagit::lock::BagLock;
+
+    #[test]
+    fn lock_is_exclusive_unless_forced() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let lock = BagLock::acquire(dir, false).unwrap();
+        assert_eq!(
+            std::process::id().to_string(),
+            fs::read_to_string(dir.join(BAG_LOCK)).unwrap()
+        );
+        assert!(matches!(
+            BagLock::acquire(dir, false),
+            Err(BagLocked { .. })
+        ));
+
+        let forced = BagLock::acquire(dir, true).unwrap();
+        drop(lock);
+        drop(forced);
+        assert!(!dir.join(BAG_LOCK).exists());
+    }
+}
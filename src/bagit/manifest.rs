@@ -0,0 +1,371 @@
+use std::collections::hash_map::Iter;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use snafu::ResultExt;
+
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest, MultiDigestWriter};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::{is_space_or_tab, LineReader};
+use crate::bagit::tag::{decode_reader, read_bag_declaration, PayloadOxum};
+
+/// The entries of a payload or tag manifest, keyed by the decoded paths of the files relative the
+/// bag's base directory
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    path: PathBuf,
+    algorithm: DigestAlgorithm,
+    entries: HashMap<String, HexDigest>,
+}
+
+/// Manifest lines and a Payload-Oxum that were generated by `generate_manifests()`
+#[derive(Debug, Clone)]
+pub struct GeneratedManifests {
+    lines: HashMap<DigestAlgorithm, Vec<String>>,
+    bytes: u64,
+    count: u64,
+}
+
+/// Hashes the contents of every file in `files`, which are pairs of paths and readers, using
+/// each of the `algorithms`, and returns the resulting manifest lines and Payload-Oxum. Nothing
+/// is read from or written to the filesystem, so the content may come from anywhere, such as a
+/// database or an object store.
+///
+/// Paths are written to the manifests as they are given, after they are percent encoded using
+/// `encoding`. They must be relative the bag's base directory and use `/` as a separator, eg.
+/// `data/file.txt`. It is an error if a path is absolute, contains `.` or `..` segments, or is
+/// listed more than once.
+pub fn generate_manifests<I, S, R>(
+    files: I,
+    algorithms: &[DigestAlgorithm],
+    encoding: PathEncoding,
+) -> Result<GeneratedManifests>
+where
+    I: IntoIterator<Item = (S, R)>,
+    S: AsRef<str>,
+    R: Read,
+{
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    let mut bytes = 0;
+
+    for (path, mut reader) in files {
+        let path = path.as_ref();
+        validate_manifest_path(path)?;
+        if !seen.insert(path.to_string()) {
+            return Err(InvalidPayloadPath {
+                path: path.into(),
+                details: "It is listed more than once".to_string(),
+            });
+        }
+
+        let mut writer = MultiDigestWriter::new(algorithms, io::sink());
+        bytes += io::copy(&mut reader, &mut writer).context(IoReadSnafu { path })?;
+        entries.push((encoding.encode(path).into_owned(), writer.finalize_hex()));
+    }
+
+    // Sort files so that the lines are in the same order as in manifests that bagr writes
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut lines = HashMap::with_capacity(algorithms.len());
+    for algorithm in algorithms {
+        lines.insert(
+            *algorithm,
+            entries
+                .iter()
+                .map(|(path, digests)| format!("{}  {path}", digests[algorithm]))
+                .collect(),
+        );
+    }
+
+    Ok(GeneratedManifests {
+        lines,
+        bytes,
+        count: entries.len() as u64,
+    })
+}
+
+fn validate_manifest_path(path: &str) -> Result<()> {
+    let details = if path.is_empty() {
+        "It is empty"
+    } else if path.starts_with('/') {
+        "It must be relative the bag's base directory"
+    } else if path
+        .split('/')
+        .any(|segment| segment == "." || segment == "..")
+    {
+        "It must not contain '.' or '..' segments"
+    } else {
+        return Ok(());
+    };
+
+    Err(InvalidPayloadPath {
+        path: path.into(),
+        details: details.to_string(),
+    })
+}
+
+/// Reads the payload manifest for `algorithm` out of the bag in `base_dir`. Paths are percent
+/// decoded using `encoding`, and the manifest is decoded using the bag's tag file encoding.
+pub fn read_payload_manifest<P: AsRef<Path>>(
+    base_dir: P,
+    algorithm: DigestAlgorithm,
+    encoding: PathEncoding,
+) -> Result<Manifest> {
+    read_bag_manifest(
+        base_dir.as_ref(),
+        PAYLOAD_MANIFEST_PREFIX,
+        algorithm,
+        encoding,
+    )
+}
+
+/// Reads the tag manifest for `algorithm` out of the bag in `base_dir`. Paths are percent
+/// decoded using `encoding`, and the manifest is decoded using the bag's tag file encoding.
+pub fn read_tag_manifest<P: AsRef<Path>>(
+    base_dir: P,
+    algorithm: DigestAlgorithm,
+    encoding: PathEncoding,
+) -> Result<Manifest> {
+    read_bag_manifest(base_dir.as_ref(), TAG_MANIFEST_PREFIX, algorithm, encoding)
+}
+
+fn read_bag_manifest(
+    base_dir: &Path,
+    prefix: &str,
+    algorithm: DigestAlgorithm,
+    encoding: PathEncoding,
+) -> Result<Manifest> {
+    let declaration = read_bag_declaration(base_dir)?;
+    let path = base_dir.join(format!("{prefix}-{algorithm}.txt"));
+    let file = File::open(&path).context(IoReadSnafu { path: &path })?;
+    let entries = read_manifest(decode_reader(file, &declaration), &path, encoding)?;
+
+    Ok(Manifest {
+        path,
+        algorithm,
+        entries,
+    })
+}
+
+/// Parses a manifest line into its digest and file path. The file path is percent decoded
+/// using the specified `encoding`. `None` is returned if the line is not a valid manifest line.
+pub fn parse_manifest_line(line: &str, encoding: PathEncoding) -> Option<(HexDigest, String)> {
+    let (digest, path) = split_manifest_line(line)?;
+    Some((digest.into(), encoding.decode(path).into()))
+}
+
+/// Splits a manifest line into its digest and its raw, still encoded, file path. `None` is
+/// returned if the line is not a valid manifest line.
+///
+/// Manifests that were generated with tools like `sha256sum` may mark paths with a `*` to
+/// indicate that the file was read in binary mode, and may prefix paths with `./`. Both are
+/// stripped from the path.
+pub fn split_manifest_line(line: &str) -> Option<(&str, &str)> {
+    let (digest, path) = line.split_once(is_space_or_tab)?;
+    let path = path.trim_start_matches(is_space_or_tab);
+    let path = path.strip_prefix('*').unwrap_or(path);
+    let path = path.strip_prefix("./").unwrap_or(path);
+
+    if digest.is_empty() || path.is_empty() {
+        None
+    } else {
+        Some((digest, path))
+    }
+}
+
+/// Reads all of the entries in a manifest file and returns them keyed by file path
+pub fn read_manifest_file<P: AsRef<Path>>(
+    path: P,
+    encoding: PathEncoding,
+) -> Result<HashMap<String, HexDigest>> {
+    let path = path.as_ref();
+    read_manifest(
+        File::open(path).context(IoReadSnafu { path })?,
+        path,
+        encoding,
+    )
+}
+
+/// Reads all of the entries in a manifest out of `reader` and returns them keyed by file path.
+/// The `path` is only used to identify the manifest in errors.
+pub fn read_manifest<R: Read>(
+    reader: R,
+    path: &Path,
+    encoding: PathEncoding,
+) -> Result<HashMap<String, HexDigest>> {
+    manifest_entries(reader, path, encoding).collect()
+}
+
+/// Returns an iterator over the file paths and digests in a manifest file, in the order they're
+/// listed, so that large manifests can be processed without reading them into memory
+pub fn read_manifest_entries<P: AsRef<Path>>(
+    path: P,
+    encoding: PathEncoding,
+) -> Result<impl Iterator<Item = Result<(String, HexDigest)>> + Send> {
+    let path = path.as_ref();
+    Ok(manifest_entries(
+        File::open(path).context(IoReadSnafu { path })?,
+        path,
+        encoding,
+    ))
+}
+
+fn manifest_entries<R: Read>(
+    reader: R,
+    path: &Path,
+    encoding: PathEncoding,
+) -> impl Iterator<Item = Result<(String, HexDigest)>> {
+    let path = path.to_path_buf();
+
+    LineReader::new(BufReader::new(reader))
+        .enumerate()
+        .map(move |(i, line)| {
+            let (digest, file_path) =
+                parse_manifest_line(&line?, encoding).ok_or_else(|| InvalidManifestLine {
+                    path: path.clone(),
+                    num: i as u32 + 1,
+                })?;
+            Ok((file_path, digest))
+        })
+}
+
+impl Manifest {
+    /// The path to the manifest file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the digest of the file at `path`, relative the bag's base directory, if it is
+    /// listed in the manifest
+    pub fn get<S: AsRef<str>>(&self, path: S) -> Option<&HexDigest> {
+        self.entries.get(path.as_ref())
+    }
+
+    pub fn contains<S: AsRef<str>>(&self, path: S) -> bool {
+        self.entries.contains_key(path.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the path and digest pairs in the manifest in no particular order
+    pub fn iter(&self) -> Iter<'_, String, HexDigest> {
+        self.entries.iter()
+    }
+}
+
+impl GeneratedManifests {
+    /// Returns the lines of the manifest for `algorithm`, sorted by path and without line
+    /// endings, or `None` if the algorithm was not used
+    pub fn lines(&self, algorithm: DigestAlgorithm) -> Option<&[String]> {
+        self.lines.get(&algorithm).map(Vec::as_slice)
+    }
+
+    /// The total number of bytes in all of the files
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The number of files
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The Payload-Oxum of the files
+    pub fn payload_oxum(&self) -> PayloadOxum {
+        PayloadOxum::new(self.bytes, self.count)
+    }
+}
+
+impl IntoIterator for Manifest {
+    type Item = (String, HexDigest);
+    type IntoIter = std::collections::hash_map::IntoIter<String, HexDigest>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Manifest {
+    type Item = (&'a String, &'a HexDigest);
+    type IntoIter = Iter<'a, String, HexDigest>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::encoding::PathEncoding;
+    use crate::bagit::manifest::{generate_manifests, split_manifest_line};
+
+    #[test]
+    fn test_split_manifest_line() {
+        assert_eq!(
+            Some(("abc", "data/file.txt")),
+            split_manifest_line("abc  data/file.txt")
+        );
+        assert_eq!(
+            Some(("abc", "data/file.txt")),
+            split_manifest_line("abc *./data/file.txt")
+        );
+        assert_eq!(
+            Some(("abc", "data/a b.txt")),
+            split_manifest_line("abc\t./data/a b.txt")
+        );
+        assert_eq!(None, split_manifest_line("abc"));
+        assert_eq!(None, split_manifest_line("abc  "));
+    }
+
+    #[test]
+    fn test_generate_manifests() {
+        let files = vec![
+            ("data/b.txt", "hello".as_bytes()),
+            ("data/a%b.txt", "".as_bytes()),
+        ];
+        let manifests = generate_manifests(
+            files,
+            &[DigestAlgorithm::Md5, DigestAlgorithm::Sha256],
+            PathEncoding::Standard,
+        )
+        .unwrap();
+
+        assert_eq!("5.2", manifests.payload_oxum().to_string());
+        assert_eq!(
+            Some(
+                &[
+                    "d41d8cd98f00b204e9800998ecf8427e  data/a%25b.txt".to_string(),
+                    "5d41402abc4b2a76b9719d911017c592  data/b.txt".to_string(),
+                ][..]
+            ),
+            manifests.lines(DigestAlgorithm::Md5)
+        );
+        assert_eq!(2, manifests.lines(DigestAlgorithm::Sha256).unwrap().len());
+        assert_eq!(None, manifests.lines(DigestAlgorithm::Sha512));
+
+        assert!(generate_manifests(
+            vec![("data/../b.txt", "".as_bytes())],
+            &[DigestAlgorithm::Md5],
+            PathEncoding::Standard,
+        )
+        .is_err());
+    }
+}
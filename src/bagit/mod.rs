@@ -1,12 +1,92 @@
-pub use crate::bagit::bag::{create_bag, open_bag, Bag, BagItVersion};
-pub use crate::bagit::digest::DigestAlgorithm;
+#[cfg(feature = "tokio")]
+pub use crate::bagit::asynchronous::create_bag_async;
+pub use crate::bagit::audit::{AuditReport, Auditor};
+pub use crate::bagit::bag::{
+    create_bag, open_bag, Bag, BagCreator, BagItVersion, BagUpdater, BagWriter, PayloadFile,
+    SkippedFile,
+};
+pub use crate::bagit::cache::{DigestCache, DigestCacheWriter};
+pub use crate::bagit::checksums::{export_checksums, ChecksumFormat, ImportedDigests};
+pub use crate::bagit::diff::{diff_bags, BagDiff, TagDiff};
+pub use crate::bagit::digest::{
+    DigestAlgorithm, DigestReader, DigestWriter, HexDigest, MultiDigestReader, MultiDigestWriter,
+    VerifyingReader, WeakAlgorithmPolicy,
+};
+pub use crate::bagit::encoding::PathEncoding;
 pub use crate::bagit::error::*;
-pub use crate::bagit::tag::{BagDeclaration, BagInfo};
+pub use crate::bagit::exclude::{ExcludePatterns, HiddenFilePolicy};
+pub use crate::bagit::fetch::{
+    read_fetch_file, FetchEntry, FetchPolicy, FetchResolver, FetchSummary,
+};
+pub use crate::bagit::group::{merge_bags, BagMerger, BagSplitter};
+pub use crate::bagit::inventory::{Inventory, InventoryFile};
+pub use crate::bagit::list::{list_files, ListFormat};
+pub use crate::bagit::lock::BagLock;
+pub use crate::bagit::manifest::{
+    generate_manifests, read_payload_manifest, read_tag_manifest, GeneratedManifests, Manifest,
+};
+pub use crate::bagit::package::{
+    unpack_bag, unpack_bag_with_password, write_bag_archive, BagPackager,
+};
+pub use crate::bagit::progress::{Heartbeat, NoProgress, ProgressListener};
+pub use crate::bagit::replica::{compare_replicas, ReplicaReport, ReplicaStatus};
+pub use crate::bagit::s3::S3Credentials;
+pub use crate::bagit::scan::{CommandScanner, FileScan, FileScanner, ScanOutcome};
+pub use crate::bagit::serialized::{
+    open_serialized_bag, open_serialized_bag_with_password, ArchiveFile, SerializedBag,
+};
+pub use crate::bagit::stats::BagStats;
+pub use crate::bagit::store::{ObjectStore, S3Store, StoredObject};
+pub use crate::bagit::tag::{
+    BagDeclaration, BagInfo, BagInfoBuilder, Contact, PayloadOxum, Tag, TagList,
+};
+pub use crate::bagit::transform::{
+    Lowercase, NameTransform, NameTransforms, NormalizeUnicode, ReplacePattern, SanitizeChars,
+};
+pub use crate::bagit::upload::{S3Uploader, UploadSummary};
+#[cfg(feature = "tokio")]
+pub use crate::bagit::validate::validate_bag_async;
+pub use crate::bagit::validate::{
+    copy_bag, validate_bag, validate_serialized_bag, validate_store_bag, validate_tar_stream,
+    FixitySample, IssueCode, IssueLevel, RuleLevel, ValidationIssue, ValidationOptions,
+    ValidationResult,
+};
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod audit;
 mod bag;
+mod cache;
+mod checksums;
 mod consts;
+mod diff;
 mod digest;
 mod encoding;
 mod error;
+mod exclude;
+mod fetch;
+mod group;
+mod inspect;
+mod inventory;
 mod io;
+mod journal;
+mod list;
+mod lock;
+mod manifest;
+mod package;
+mod prefetch;
+mod progress;
+mod replica;
+mod s3;
+mod scan;
+#[cfg(feature = "serde")]
+mod serialize;
+mod serialized;
+mod snapshot;
+mod spool;
+mod stats;
+mod store;
 mod tag;
+mod transform;
+mod upload;
+mod validate;
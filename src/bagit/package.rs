@@ -0,0 +1,667 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{Datelike, Local, Timelike};
+
+use log::info;
+use snafu::ResultExt;
+use walkdir::{DirEntry, WalkDir};
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::bagit::bag::open_bag;
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, DigestReader, DigestWriter, HexDigest};
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+use crate::bagit::serialized::{detect_format, ArchiveFormat};
+
+/// The extension of the index file that lists the parts of a split archive
+const INDEX_EXTENSION: &str = "index";
+
+/// Serializes a bag into a tar archive, which may be split into parts of a fixed size so that
+/// it fits on removable media or within object store upload limits, or into a zip archive, which
+/// may be encrypted
+#[derive(Debug)]
+pub struct BagPackager {
+    base_dir: PathBuf,
+    archive_path: PathBuf,
+    part_size: Option<u64>,
+    password: Option<String>,
+}
+
+/// A part of a split archive as it's described by the archive's index
+#[derive(Debug)]
+struct ArchivePart {
+    path: PathBuf,
+    size: u64,
+    digest: HexDigest,
+}
+
+/// Writer that splits its output into parts that are at most `part_size` bytes
+struct SplitWriter {
+    archive_path: PathBuf,
+    part_size: u64,
+    current: Option<(DigestWriter<BufWriter<File>>, u64)>,
+    parts: Vec<ArchivePart>,
+}
+
+/// Reader that reads the parts of a split archive in order, and verifies the size and digest of
+/// each part once it has been read
+struct PartsReader {
+    parts: VecDeque<ArchivePart>,
+    current: Option<(ArchivePart, DigestReader<BufReader<File>>, u64)>,
+}
+
+/// Extracts a bag that was serialized by `BagPackager` into `dest_dir`, and returns the path to
+/// the extracted bag. `archive_path` is either a tar or zip archive, or the index file of a split
+/// archive, eg. `bag.tar.index`. The parts of split archives are reassembled in the order they are
+/// listed in the index, and an error is returned if a part's size or digest does not match the
+/// index. Use `unpack_bag_with_password()` to extract encrypted zip archives.
+pub fn unpack_bag<A: AsRef<Path>, D: AsRef<Path>>(archive_path: A, dest_dir: D) -> Result<PathBuf> {
+    unpack(archive_path.as_ref(), dest_dir.as_ref(), None)
+}
+
+/// The same as `unpack_bag()`, except the files in encrypted zip archives are decrypted using
+/// `password`
+pub fn unpack_bag_with_password<A: AsRef<Path>, D: AsRef<Path>>(
+    archive_path: A,
+    dest_dir: D,
+    password: &str,
+) -> Result<PathBuf> {
+    unpack(archive_path.as_ref(), dest_dir.as_ref(), Some(password))
+}
+
+fn unpack(archive_path: &Path, dest_dir: &Path, password: Option<&str>) -> Result<PathBuf> {
+    info!(
+        "Unpacking {} into {}",
+        archive_path.display(),
+        dest_dir.display()
+    );
+
+    fs::create_dir_all(dest_dir).context(IoCreateSnafu { path: dest_dir })?;
+
+    let roots = if let Ok(ArchiveFormat::Zip) = detect_format(archive_path) {
+        unpack_zip(archive_path, dest_dir, password)?
+    } else {
+        unpack_tar(archive_path, dest_dir)?
+    };
+
+    // Bags are usually serialized within a single top-level directory
+    match roots.as_slice() {
+        [root] if dest_dir.join(root).join(BAGIT_TXT).is_file() => Ok(dest_dir.join(root)),
+        _ => Ok(dest_dir.into()),
+    }
+}
+
+/// Extracts a tar archive, or a split tar archive, into `dest_dir`, and returns the names of the
+/// archive's top-level entries
+fn unpack_tar(archive_path: &Path, dest_dir: &Path) -> Result<Vec<OsString>> {
+    let reader: Box<dyn Read> =
+        if archive_path.extension().and_then(|ext| ext.to_str()) == Some(INDEX_EXTENSION) {
+            Box::new(PartsReader::new(read_index(archive_path)?))
+        } else {
+            Box::new(BufReader::new(
+                File::open(archive_path).context(IoReadSnafu { path: archive_path })?,
+            ))
+        };
+
+    let invalid = |e: io::Error| InvalidArchive {
+        path: archive_path.into(),
+        details: e.to_string(),
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    let mut roots = Vec::new();
+
+    for entry in archive.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        let path = entry.path().map_err(invalid)?.into_owned();
+
+        if !entry.unpack_in(dest_dir).map_err(invalid)? {
+            return Err(InvalidArchive {
+                path: archive_path.into(),
+                details: format!("{} is outside of the archive", path.display()),
+            });
+        }
+
+        add_root(&mut roots, &path);
+    }
+
+    Ok(roots)
+}
+
+/// Extracts a zip archive into `dest_dir`, decrypting its files using `password`, and returns
+/// the names of the archive's top-level entries
+fn unpack_zip(
+    archive_path: &Path,
+    dest_dir: &Path,
+    password: Option<&str>,
+) -> Result<Vec<OsString>> {
+    let to_error = |e: ZipError| match e {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => PasswordRequired {
+            path: archive_path.into(),
+        },
+        ZipError::InvalidPassword => IncorrectPassword {
+            path: archive_path.into(),
+        },
+        e => InvalidArchive {
+            path: archive_path.into(),
+            details: e.to_string(),
+        },
+    };
+
+    let file = File::open(archive_path).context(IoReadSnafu { path: archive_path })?;
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(to_error)?;
+    let mut roots = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = match password {
+            Some(password) => archive.by_index_decrypt(i, password.as_bytes()),
+            None => archive.by_index(i),
+        }
+        .map_err(to_error)?;
+
+        let path = file.enclosed_name().ok_or_else(|| InvalidArchive {
+            path: archive_path.into(),
+            details: format!("{} is outside of the archive", file.name()),
+        })?;
+        let dest = dest_dir.join(&path);
+
+        if file.is_dir() {
+            fs::create_dir_all(&dest).context(IoCreateSnafu { path: &dest })?;
+        } else {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+            }
+            let mut writer =
+                BufWriter::new(File::create(&dest).context(IoCreateSnafu { path: &dest })?);
+            io::copy(&mut file, &mut writer).map_err(|e| InvalidArchive {
+                path: archive_path.into(),
+                details: format!("Failed to extract {}: {e}", file.name()),
+            })?;
+            writer.flush().context(IoWriteSnafu { path: &dest })?;
+        }
+
+        add_root(&mut roots, &path);
+    }
+
+    Ok(roots)
+}
+
+/// Records the top-level component of `path` in `roots`, if it's not already there
+fn add_root(roots: &mut Vec<OsString>, path: &Path) {
+    if let Some(Component::Normal(root)) = path.components().next() {
+        if !roots.iter().any(|existing| existing == root) {
+            roots.push(root.to_os_string());
+        }
+    }
+}
+
+/// Writes the bag in `base_dir` to `writer` as a tar archive, in the same layout as
+/// `BagPackager`, and returns the writer. This is used to stream a bag, eg. to stdout, without
+/// writing the archive to a file first.
+pub fn write_bag_archive<P: AsRef<Path>, W: Write>(base_dir: P, writer: W) -> Result<W> {
+    let (bag_dir, name) = resolve_bag_dir(base_dir.as_ref())?;
+    let stream_path = Path::new(TAR_STREAM);
+
+    info!("Writing bag {} as a tar archive", bag_dir.display());
+
+    write_archive(&bag_dir, name.as_ref(), writer)
+        .and_then(|mut writer| writer.flush().map(|_| writer))
+        .context(IoWriteSnafu { path: stream_path })
+}
+
+impl BagPackager {
+    /// Packages the bag in `base_dir` into an archive at `archive_path`. The bag is written as a
+    /// zip archive when the path ends in `.zip`, and as a tar archive otherwise. The bag is
+    /// written within a top-level directory that has the same name as the bag's base directory.
+    pub fn new<B: AsRef<Path>, A: AsRef<Path>>(base_dir: B, archive_path: A) -> Self {
+        Self {
+            base_dir: base_dir.as_ref().into(),
+            archive_path: archive_path.as_ref().into(),
+            part_size: None,
+            password: None,
+        }
+    }
+
+    /// Splits the archive into parts that are at most `part_size` bytes. Parts are named by
+    /// appending a sequence number to the archive path, eg. `bag.tar.001`, and are listed, along
+    /// with their sizes and sha512 digests, in an index file, eg. `bag.tar.index`. By default,
+    /// the archive is not split.
+    pub fn with_part_size(mut self, part_size: Option<u64>) -> Self {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Encrypts every file in the archive with AES-256, using a key that's derived from
+    /// `password`. Only zip archives can be encrypted, and they can't be split. By default, the
+    /// archive is not encrypted.
+    pub fn with_password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Writes the archive, and returns the paths of all of the files that were written
+    pub fn package(self) -> Result<Vec<PathBuf>> {
+        let (bag_dir, name) = resolve_bag_dir(&self.base_dir)?;
+        let archive_path = self.archive_path.as_path();
+        let is_zip = matches!(detect_format(archive_path), Ok(ArchiveFormat::Zip));
+
+        if is_zip && self.part_size.is_some() {
+            return Err(General {
+                message: "Zip archives cannot be split into parts".to_string(),
+            });
+        } else if !is_zip && self.password.is_some() {
+            return Err(General {
+                message: format!(
+                    "Only zip archives can be encrypted, but {} is not a zip archive",
+                    archive_path.display()
+                ),
+            });
+        }
+
+        if let Some(parent) = archive_path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+        }
+        let archive_dir = fs::canonicalize(
+            archive_path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(".")),
+        )
+        .context(IoStatSnafu { path: archive_path })?;
+        if archive_dir.starts_with(&bag_dir) {
+            return Err(General {
+                message: format!(
+                    "The archive {} cannot be written inside of the bag it contains",
+                    archive_path.display()
+                ),
+            });
+        }
+
+        info!(
+            "Packaging bag {} into {}",
+            bag_dir.display(),
+            archive_path.display()
+        );
+
+        match self.part_size {
+            None if is_zip => {
+                let file =
+                    File::create(archive_path).context(IoCreateSnafu { path: archive_path })?;
+                write_zip_archive(
+                    &bag_dir,
+                    name.as_ref(),
+                    BufWriter::new(file),
+                    self.password.as_deref(),
+                )
+                .and_then(|mut writer| writer.flush())
+                .context(IoWriteSnafu { path: archive_path })?;
+                Ok(vec![archive_path.into()])
+            }
+            None => {
+                let file =
+                    File::create(archive_path).context(IoCreateSnafu { path: archive_path })?;
+                write_archive(&bag_dir, name.as_ref(), BufWriter::new(file))
+                    .and_then(|mut writer| writer.flush())
+                    .context(IoWriteSnafu { path: archive_path })?;
+                Ok(vec![archive_path.into()])
+            }
+            Some(part_size) => {
+                let writer = SplitWriter::new(archive_path, part_size.max(1));
+                let parts = write_archive(&bag_dir, name.as_ref(), writer)
+                    .and_then(SplitWriter::finish)
+                    .context(IoWriteSnafu { path: archive_path })?;
+                let index_path = write_index(archive_path, &parts)?;
+
+                let mut paths = parts.into_iter().map(|part| part.path).collect::<Vec<_>>();
+                paths.push(index_path);
+                Ok(paths)
+            }
+        }
+    }
+}
+
+/// Returns the absolute path to the bag in `base_dir`, and the name of the directory that it's
+/// written within in an archive
+fn resolve_bag_dir(base_dir: &Path) -> Result<(PathBuf, OsString)> {
+    let bag_dir = fs::canonicalize(base_dir).context(IoStatSnafu { path: base_dir })?;
+    // Ensures that the directory contains a bag
+    open_bag(&bag_dir)?;
+
+    let name = bag_dir
+        .file_name()
+        .ok_or_else(|| General {
+            message: format!("{} cannot be packaged", bag_dir.display()),
+        })?
+        .to_os_string();
+
+    Ok((bag_dir, name))
+}
+
+/// Returns a walker over every file in `bag_dir`. Tag files are returned before the payload so
+/// that archives can be validated efficiently as they're streamed.
+fn walk_bag(bag_dir: &Path) -> WalkDir {
+    let is_payload_dir = |entry: &DirEntry| entry.depth() == 1 && entry.file_name() == DATA;
+    WalkDir::new(bag_dir).sort_by(move |a, b| {
+        is_payload_dir(a)
+            .cmp(&is_payload_dir(b))
+            .then_with(|| a.file_name().cmp(b.file_name()))
+    })
+}
+
+/// Writes every file in `bag_dir` to a tar archive within the directory `name`
+fn write_archive<W: Write>(bag_dir: &Path, name: &Path, writer: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+
+    for entry in walk_bag(bag_dir) {
+        let entry = entry.map_err(io::Error::from)?;
+        let path = name.join(entry.path().strip_prefix(bag_dir).unwrap());
+        if entry.file_type().is_dir() {
+            builder.append_dir(path, entry.path())?;
+        } else {
+            builder.append_path_with_name(entry.path(), path)?;
+        }
+    }
+
+    builder.into_inner()
+}
+
+/// Writes every file in `bag_dir` to a deflated zip archive within the directory `name`. When
+/// there's a `password`, every file is encrypted using WinZip AES-256.
+fn write_zip_archive<W: Write + io::Seek>(
+    bag_dir: &Path,
+    name: &Path,
+    writer: W,
+    password: Option<&str>,
+) -> io::Result<W> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let options = match password {
+        Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+        None => options,
+    };
+
+    for entry in walk_bag(bag_dir) {
+        let entry = entry.map_err(io::Error::from)?;
+        let path = name.join(entry.path().strip_prefix(bag_dir).unwrap());
+        let path = path
+            .to_str()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not valid UTF-8", path.display()),
+                )
+            })?
+            .replace('\\', "/");
+
+        let metadata = entry.metadata().map_err(io::Error::from)?;
+        let options = match metadata.modified().ok().and_then(zip_time) {
+            Some(time) => options.last_modified_time(time),
+            None => options,
+        };
+
+        if entry.file_type().is_dir() {
+            zip.add_directory(path, options)?;
+        } else {
+            zip.start_file(path, options.large_file(metadata.len() >= u32::MAX as u64))?;
+            io::copy(&mut File::open(entry.path())?, &mut zip)?;
+        }
+    }
+
+    Ok(zip.finish()?)
+}
+
+/// Converts a file's modification time into a zip timestamp. Zip timestamps are in local time, and
+/// can only represent times between 1980 and 2107.
+fn zip_time(modified: SystemTime) -> Option<zip::DateTime> {
+    let time = chrono::DateTime::<Local>::from(modified);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+/// Writes the index of a split archive next to the archive, and returns its path. Each line
+/// contains the sha512 digest of a part, its size, and its file name.
+fn write_index(archive_path: &Path, parts: &[ArchivePart]) -> Result<PathBuf> {
+    let index_path = append_extension(archive_path, INDEX_EXTENSION);
+    info!("Writing archive index {}", index_path.display());
+
+    let mut writer =
+        BufWriter::new(File::create(&index_path).context(IoCreateSnafu { path: &index_path })?);
+    for part in parts {
+        let name = part.path.file_name().unwrap().to_string_lossy();
+        writeln!(writer, "{}  {}  {name}", part.digest, part.size)
+            .context(IoWriteSnafu { path: &index_path })?;
+    }
+    writer.flush().context(IoWriteSnafu { path: &index_path })?;
+
+    Ok(index_path)
+}
+
+/// Reads the parts of a split archive out of its index. Parts must be in the same directory as
+/// the index.
+fn read_index(index_path: &Path) -> Result<VecDeque<ArchivePart>> {
+    let file = File::open(index_path).context(IoReadSnafu { path: index_path })?;
+    let dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut parts = VecDeque::new();
+
+    for (i, line) in LineReader::new(BufReader::new(file)).enumerate() {
+        let line = line?;
+        let part = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [digest, size, name] if !name.contains(['/', '\\']) => {
+                size.parse().ok().map(|size| ArchivePart {
+                    path: dir.join(name),
+                    size,
+                    digest: HexDigest::from(*digest),
+                })
+            }
+            _ => None,
+        };
+        parts.push_back(part.ok_or_else(|| InvalidArchive {
+            path: index_path.into(),
+            details: format!(
+                "Line {} must contain a digest, a size, and a part file name",
+                i + 1
+            ),
+        })?);
+    }
+
+    if parts.is_empty() {
+        return Err(InvalidArchive {
+            path: index_path.into(),
+            details: "The index does not list any parts".to_string(),
+        });
+    }
+
+    Ok(parts)
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+impl SplitWriter {
+    fn new(archive_path: &Path, part_size: u64) -> Self {
+        Self {
+            archive_path: archive_path.into(),
+            part_size,
+            current: None,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Closes the last part, and returns all of the parts that were written
+    fn finish(mut self) -> io::Result<Vec<ArchivePart>> {
+        self.close_part()?;
+        Ok(self.parts)
+    }
+
+    fn open_part(&mut self) -> io::Result<()> {
+        let path = append_extension(&self.archive_path, &format!("{:03}", self.parts.len() + 1));
+        info!("Writing archive part {}", path.display());
+        let writer = DigestAlgorithm::Sha512.writer(BufWriter::new(File::create(&path)?));
+        self.current = Some((writer, 0));
+        self.parts.push(ArchivePart {
+            path,
+            size: 0,
+            digest: HexDigest::from(""),
+        });
+        Ok(())
+    }
+
+    fn close_part(&mut self) -> io::Result<()> {
+        if let Some((mut writer, size)) = self.current.take() {
+            writer.flush()?;
+            let part = self.parts.last_mut().unwrap();
+            part.size = size;
+            part.digest = writer.finalize_hex();
+        }
+        Ok(())
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let full = match &self.current {
+            Some((_, size)) => *size >= self.part_size,
+            None => true,
+        };
+        if full {
+            self.close_part()?;
+            self.open_part()?;
+        }
+
+        let (writer, size) = self.current.as_mut().unwrap();
+        let remaining = (self.part_size - *size).min(buf.len() as u64) as usize;
+        let written = writer.write(&buf[..remaining])?;
+        *size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some((writer, _)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PartsReader {
+    fn new(parts: VecDeque<ArchivePart>) -> Self {
+        Self {
+            parts,
+            current: None,
+        }
+    }
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current.is_none() {
+                let part = match self.parts.pop_front() {
+                    Some(part) => part,
+                    None => return Ok(0),
+                };
+                info!("Reading archive part {}", part.path.display());
+                let file = File::open(&part.path).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("Failed to open part {}: {e}", part.path.display()),
+                    )
+                })?;
+                let reader = DigestAlgorithm::Sha512.reader(BufReader::new(file));
+                self.current = Some((part, reader, 0));
+            }
+
+            let (_, reader, size) = self.current.as_mut().unwrap();
+            let read = reader.read(buf)?;
+            *size += read as u64;
+            if read > 0 {
+                return Ok(read);
+            }
+
+            let (part, reader, size) = self.current.take().unwrap();
+            if size != part.size {
+                return Err(io::Error::other(format!(
+                    "Part {} is {size} bytes, but the index expects {} bytes",
+                    part.path.display(),
+                    part.size
+                )));
+            }
+            let digest = reader.finalize_hex();
+            if digest != part.digest {
+                return Err(io::Error::other(format!(
+                    "Part {} has sha512 digest {digest}, but the index expects {}",
+                    part.path.display(),
+                    part.digest
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::bagit::bag::BagWriter;
+    use crate::bagit::package::write_bag_archive;
+
+    #[test]
+    fn archive_lists_tag_files_before_the_payload() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let bag_dir = dir.join("bag");
+
+        let mut writer = BagWriter::create(&bag_dir, &[]).unwrap();
+        writer
+            .add_payload_file("dir/file.txt", "testing".as_bytes())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let archive = write_bag_archive(&bag_dir, Vec::new()).unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "bag/",
+                "bag/bag-info.txt",
+                "bag/bagit.txt",
+                "bag/manifest-sha512.txt",
+                "bag/tagmanifest-sha512.txt",
+                "bag/data",
+                "bag/data/dir",
+                "bag/data/dir/file.txt",
+            ],
+            paths
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+
+use log::info;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::ThreadPool;
+
+/// Reads files ahead of the threads that process them, so that the latency of high-latency
+/// storage, such as network filesystems or object storage mounts, is hidden behind the
+/// processing of files that have already been read.
+///
+/// Files are read in the order they're listed by dedicated reader threads. A file is buffered in
+/// memory in its entirety when it fits within the memory limit, and its memory is released once
+/// it has been processed. Larger files are opened ahead of time, but are read as they're
+/// processed.
+#[derive(Debug, Copy, Clone)]
+pub struct Prefetcher {
+    readers: usize,
+    max_memory: u64,
+}
+
+/// A file that was opened, and possibly read, by a `Prefetcher`
+pub struct PrefetchedFile<'a> {
+    contents: Contents,
+    /// The memory the buffered contents are counted against, if any
+    reservation: Option<(&'a MemoryBudget, u64)>,
+}
+
+enum Contents {
+    Buffered(Cursor<Vec<u8>>),
+    Unbuffered(File),
+}
+
+/// Limits the number of bytes that may be buffered at the same time
+struct MemoryBudget {
+    max_memory: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+impl Prefetcher {
+    /// Creates a prefetcher that reads up to `readers` files at the same time, and buffers up to
+    /// `max_memory` bytes. There is always at least one reader.
+    pub fn new(readers: usize, max_memory: u64) -> Self {
+        Self {
+            readers: readers.max(1),
+            max_memory,
+        }
+    }
+
+    /// Reads each of the `files`, which are paths and sizes, and calls `process` with the file's
+    /// index and contents using the threads in `pool`. `skip` is called before a file is read,
+    /// and when it returns a value, that's used as the file's result instead of reading it. The
+    /// results are returned in the same order as the files.
+    pub fn read_ahead<T, S, P>(
+        &self,
+        files: &[(&Path, u64)],
+        pool: &ThreadPool,
+        skip: S,
+        process: P,
+    ) -> Vec<T>
+    where
+        T: Send,
+        S: Fn(usize) -> Option<T> + Sync,
+        P: Fn(usize, io::Result<PrefetchedFile<'_>>) -> T + Sync,
+    {
+        info!(
+            "Prefetching files with {} readers and up to {} bytes of memory",
+            self.readers, self.max_memory
+        );
+
+        let budget = MemoryBudget::new(self.max_memory);
+        let next = AtomicUsize::new(0);
+        // Bounds the number of large files that are held open before they're processed
+        let (sender, receiver) = mpsc::sync_channel(self.readers);
+
+        let mut results = thread::scope(|scope| {
+            for _ in 0..self.readers {
+                let sender = sender.clone();
+                let (budget, next, skip) = (&budget, &next, &skip);
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((path, size)) = files.get(index) else {
+                        break;
+                    };
+                    let file = match skip(index) {
+                        Some(skipped) => Err(skipped),
+                        None => Ok(budget.prefetch(path, *size)),
+                    };
+                    if sender.send((index, file)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            pool.install(|| {
+                receiver
+                    .into_iter()
+                    .par_bridge()
+                    .map(|(index, file)| match file {
+                        Ok(file) => (index, process(index, file)),
+                        Err(skipped) => (index, skipped),
+                    })
+                    .collect::<Vec<(usize, T)>>()
+            })
+        });
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+impl Read for PrefetchedFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.contents {
+            Contents::Buffered(cursor) => cursor.read(buf),
+            Contents::Unbuffered(file) => file.read(buf),
+        }
+    }
+}
+
+impl Drop for PrefetchedFile<'_> {
+    fn drop(&mut self) {
+        if let Some((budget, bytes)) = self.reservation.take() {
+            budget.release(bytes);
+        }
+    }
+}
+
+impl MemoryBudget {
+    fn new(max_memory: u64) -> Self {
+        Self {
+            max_memory,
+            available: Mutex::new(max_memory),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Opens the file at `path`, and reads it into memory if it's no larger than the budget.
+    /// Blocks until enough memory has been released by files that have been processed.
+    fn prefetch(&self, path: &Path, size: u64) -> io::Result<PrefetchedFile<'_>> {
+        let mut file = File::open(path)?;
+
+        if size > self.max_memory {
+            return Ok(PrefetchedFile {
+                contents: Contents::Unbuffered(file),
+                reservation: None,
+            });
+        }
+
+        self.acquire(size);
+        // The reservation is released if the read fails
+        let mut prefetched = PrefetchedFile {
+            contents: Contents::Buffered(Cursor::new(Vec::new())),
+            reservation: Some((self, size)),
+        };
+        let mut buf = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut buf)?;
+        prefetched.contents = Contents::Buffered(Cursor::new(buf));
+        Ok(prefetched)
+    }
+
+    fn acquire(&self, bytes: u64) {
+        let mut available = self.available.lock().unwrap();
+        while *available < bytes {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= bytes;
+    }
+
+    fn release(&self, bytes: u64) {
+        *self.available.lock().unwrap() += bytes;
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    use crate::bagit::bag::thread_pool;
+    use crate::bagit::prefetch::Prefetcher;
+
+    #[test]
+    fn read_ahead_returns_results_in_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let files = (0..20)
+            .map(|i| {
+                let path = dir.join(format!("file{i}.txt"));
+                // Every fourth file is larger than the memory limit, and is not buffered
+                let contents = if i % 4 == 0 {
+                    "x".repeat(64)
+                } else {
+                    i.to_string()
+                };
+                fs::write(&path, &contents).unwrap();
+                (path, contents.len() as u64)
+            })
+            .collect::<Vec<(PathBuf, u64)>>();
+        let paths = files
+            .iter()
+            .map(|(path, size)| (path.as_path(), *size))
+            .collect::<Vec<(&Path, u64)>>();
+
+        let results = Prefetcher::new(3, 16).read_ahead(
+            &paths,
+            &thread_pool(2).unwrap(),
+            |i| (i == 5).then(|| "skipped".to_string()),
+            |_, file| {
+                let mut contents = String::new();
+                file.unwrap().read_to_string(&mut contents).unwrap();
+                contents
+            },
+        );
+
+        assert_eq!(20, results.len());
+        assert_eq!("x".repeat(64), results[0]);
+        assert_eq!("1", results[1]);
+        assert_eq!("skipped", results[5]);
+        assert_eq!("19", results[19]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bagit::digest::DigestAlgorithm;
+
+/// Receives progress updates while payload files are hashed, which can take a long time for
+/// large bags. All of the methods default to doing nothing, so implementations only need to
+/// implement the updates they're interested in.
+///
+/// Files are hashed concurrently, so updates for different files may be received at the same time
+/// from different threads.
+pub trait ProgressListener: Send + Sync {
+    /// Called once before any files are hashed with the total number of files and bytes that
+    /// are going to be hashed
+    fn hashing_started(&self, _total_files: u64, _total_bytes: u64) {}
+
+    /// Called when a file starts being hashed
+    fn file_started(&self, _path: &Path) {}
+
+    /// Called every time a chunk of a file is hashed with the number of bytes in the chunk
+    fn bytes_hashed(&self, _path: &Path, _bytes: u64) {}
+
+    /// Called when a file has been completely hashed
+    fn file_finished(&self, _path: &Path) {}
+
+    /// Called after a file has been hashed with the time that was spent calculating each of its
+    /// digests
+    fn digests_timed(&self, _path: &Path, _times: &HashMap<DigestAlgorithm, Duration>) {}
+}
+
+/// A `ProgressListener` that ignores all updates
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoProgress;
+
+/// A `ProgressListener` that periodically writes a single line to stderr with the number of files
+/// that have been hashed, the hashing rate, and the estimated time remaining. Lines are only
+/// written while hashing is progressing, at most once per interval.
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    started: Mutex<Instant>,
+    last_beat: Mutex<Instant>,
+    total_files: AtomicU64,
+    total_bytes: AtomicU64,
+    files_done: AtomicU64,
+    bytes_done: AtomicU64,
+}
+
+/// Wraps a reader and reports the number of bytes that are read to a `ProgressListener`
+pub struct ProgressReader<'a, R: Read> {
+    reader: R,
+    path: &'a Path,
+    listener: &'a dyn ProgressListener,
+}
+
+impl ProgressListener for NoProgress {}
+
+impl Heartbeat {
+    /// Creates a heartbeat that writes a line at most once every `interval`
+    pub fn new(interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            interval,
+            started: Mutex::new(now),
+            last_beat: Mutex::new(now),
+            total_files: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(0),
+            files_done: AtomicU64::new(0),
+            bytes_done: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes a heartbeat line if at least `interval` has passed since the last one
+    fn beat(&self) {
+        let now = Instant::now();
+        {
+            let mut last_beat = self.last_beat.lock().unwrap();
+            if now.duration_since(*last_beat) < self.interval {
+                return;
+            }
+            *last_beat = now;
+        }
+
+        let elapsed = now.duration_since(*self.started.lock().unwrap());
+        let line = format_heartbeat(
+            self.files_done.load(Ordering::Relaxed),
+            self.total_files.load(Ordering::Relaxed),
+            self.bytes_done.load(Ordering::Relaxed),
+            self.total_bytes.load(Ordering::Relaxed),
+            elapsed,
+        );
+        // A heartbeat that can't be written should not fail the operation
+        let _ = writeln!(io::stderr(), "{}", line);
+    }
+}
+
+impl ProgressListener for Heartbeat {
+    fn hashing_started(&self, total_files: u64, total_bytes: u64) {
+        let now = Instant::now();
+        *self.started.lock().unwrap() = now;
+        *self.last_beat.lock().unwrap() = now;
+        self.total_files.store(total_files, Ordering::Relaxed);
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+        self.files_done.store(0, Ordering::Relaxed);
+        self.bytes_done.store(0, Ordering::Relaxed);
+    }
+
+    fn bytes_hashed(&self, _path: &Path, bytes: u64) {
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+        self.beat();
+    }
+
+    fn file_finished(&self, _path: &Path) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.beat();
+    }
+}
+
+impl Debug for dyn ProgressListener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressListener")
+    }
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub fn new(reader: R, path: &'a Path, listener: &'a dyn ProgressListener) -> Self {
+        Self {
+            reader,
+            path,
+            listener,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read > 0 {
+            self.listener.bytes_hashed(self.path, read as u64);
+        }
+        Ok(read)
+    }
+}
+
+/// Formats a heartbeat line, eg: `[HEARTBEAT] 10/40 files, 1.5 GiB/6.0 GiB, 25.0 MiB/s, ETA 3m25s`
+fn format_heartbeat(
+    files_done: u64,
+    total_files: u64,
+    bytes_done: u64,
+    total_bytes: u64,
+    elapsed: Duration,
+) -> String {
+    let rate = bytes_done as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = if rate > 0.0 {
+        let remaining = total_bytes.saturating_sub(bytes_done) as f64 / rate;
+        format_duration(Duration::from_secs(remaining.ceil() as u64))
+    } else {
+        "unknown".to_string()
+    };
+
+    format!(
+        "[HEARTBEAT] {}/{} files, {}/{}, {}/s, ETA {}",
+        files_done,
+        total_files,
+        format_bytes(bytes_done as f64),
+        format_bytes(total_bytes as f64),
+        format_bytes(rate),
+        eta
+    )
+}
+
+/// Formats a number of bytes using binary units, eg: `1.5 GiB`
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", value.round(), UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a duration as hours, minutes, and seconds, eg: `1h2m5s`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}h{}m{}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::bagit::progress::format_heartbeat;
+
+    #[test]
+    fn test_format_heartbeat() {
+        let gib = 1024 * 1024 * 1024;
+        assert_eq!(
+            "[HEARTBEAT] 10/40 files, 1.5 GiB/6.0 GiB, 25.6 MiB/s, ETA 3m0s",
+            format_heartbeat(10, 40, gib + gib / 2, 6 * gib, Duration::from_secs(60))
+        );
+        assert_eq!(
+            "[HEARTBEAT] 0/2 files, 0 B/10 B, 0 B/s, ETA unknown",
+            format_heartbeat(0, 2, 0, 10, Duration::from_secs(5))
+        );
+    }
+}
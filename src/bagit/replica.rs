@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::bagit::bag::open_bag;
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+use crate::bagit::error::*;
+use crate::bagit::manifest::read_payload_manifest;
+use crate::bagit::validate::{validate_bag, ValidationOptions, ValidationResult};
+
+/// The health of every replica of a bag. Replicas are healthy when they are valid and their
+/// payload manifests are identical to the first replica's.
+#[derive(Debug)]
+pub struct ReplicaReport {
+    replicas: Vec<ReplicaStatus>,
+}
+
+/// The health of a single replica of a bag
+#[derive(Debug)]
+pub struct ReplicaStatus {
+    path: PathBuf,
+    validation: ValidationResult,
+    discrepancies: Vec<String>,
+}
+
+/// The entries of all of a replica's payload manifests, keyed by algorithm and then path
+type ReplicaManifests = BTreeMap<DigestAlgorithm, BTreeMap<String, HexDigest>>;
+
+/// Validates every replica in `paths` using `options`, which should usually sample the payload
+/// files, and compares their payload manifests to the manifests of the first replica.
+///
+/// An `Err` is only returned when the comparison itself could not be completed. Problems with
+/// the replicas are reported on the `ReplicaReport`.
+pub fn compare_replicas<P: AsRef<Path>>(
+    paths: &[P],
+    options: &ValidationOptions,
+) -> Result<ReplicaReport> {
+    let mut replicas = Vec::with_capacity(paths.len());
+    let mut reference: Option<(&Path, ReplicaManifests)> = None;
+
+    for path in paths {
+        let path = path.as_ref();
+        info!("Comparing replica {}", path.display());
+
+        let validation = validate_bag(path, options)?;
+        let mut discrepancies = Vec::new();
+
+        match read_replica_manifests(path, options) {
+            Ok(manifests) => match &reference {
+                Some((reference_path, reference_manifests)) => {
+                    compare_manifests(
+                        reference_path,
+                        reference_manifests,
+                        &manifests,
+                        &mut discrepancies,
+                    );
+                }
+                None => reference = Some((path, manifests)),
+            },
+            Err(e) => discrepancies.push(format!("Failed to read payload manifests: {e}")),
+        }
+
+        replicas.push(ReplicaStatus {
+            path: path.into(),
+            validation,
+            discrepancies,
+        });
+    }
+
+    Ok(ReplicaReport { replicas })
+}
+
+fn read_replica_manifests(path: &Path, options: &ValidationOptions) -> Result<ReplicaManifests> {
+    let bag = open_bag(path)?;
+    let mut manifests = BTreeMap::new();
+
+    for algorithm in bag.algorithms() {
+        let manifest = read_payload_manifest(path, *algorithm, options.path_encoding())?;
+        manifests.insert(*algorithm, manifest.into_iter().collect());
+    }
+
+    Ok(manifests)
+}
+
+/// Records every difference between a replica's payload manifests and the reference replica's
+fn compare_manifests(
+    reference_path: &Path,
+    reference: &ReplicaManifests,
+    manifests: &ReplicaManifests,
+    discrepancies: &mut Vec<String>,
+) {
+    let reference_path = reference_path.display();
+    let algorithms = reference
+        .keys()
+        .chain(manifests.keys())
+        .collect::<BTreeSet<_>>();
+
+    for algorithm in algorithms {
+        let name = format!("{PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt");
+
+        let (expected, actual) = match (reference.get(algorithm), manifests.get(algorithm)) {
+            (Some(expected), Some(actual)) => (expected, actual),
+            (Some(_), None) => {
+                discrepancies.push(format!("Missing {name}, which {reference_path} has"));
+                continue;
+            }
+            (None, _) => {
+                discrepancies.push(format!("Has {name}, which {reference_path} does not have"));
+                continue;
+            }
+        };
+
+        for (file, digest) in expected {
+            match actual.get(file) {
+                Some(actual_digest) if actual_digest == digest => {}
+                Some(_) => discrepancies.push(format!(
+                    "{name} has a different digest for {file} than {reference_path}"
+                )),
+                None => discrepancies.push(format!(
+                    "{name} does not list {file}, which is listed in {reference_path}"
+                )),
+            }
+        }
+
+        for file in actual.keys().filter(|file| !expected.contains_key(*file)) {
+            discrepancies.push(format!(
+                "{name} lists {file}, which is not listed in {reference_path}"
+            ));
+        }
+    }
+}
+
+impl ReplicaReport {
+    /// Returns true if every replica is healthy
+    pub fn is_healthy(&self) -> bool {
+        self.replicas.iter().all(ReplicaStatus::is_healthy)
+    }
+
+    /// The replicas in the order they were compared. The first replica is the reference that
+    /// the others were compared to.
+    pub fn replicas(&self) -> &[ReplicaStatus] {
+        &self.replicas
+    }
+}
+
+impl ReplicaStatus {
+    /// Returns true if the replica is valid and its payload manifests match the reference's
+    pub fn is_healthy(&self) -> bool {
+        self.validation.is_valid() && self.discrepancies.is_empty()
+    }
+
+    /// The path to the replica's base directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The result of validating the replica
+    pub fn validation(&self) -> &ValidationResult {
+        &self.validation
+    }
+
+    /// Differences between the replica's payload manifests and the reference's
+    pub fn discrepancies(&self) -> &[String] {
+        &self.discrepancies
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::thread;
+use std::thread::JoinHandle;
+
+use log::warn;
+use snafu::ResultExt;
+
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+
+/// Screens payload files while they're read to calculate their digests, so that tools like
+/// antivirus scanners or format validators can check them without reading them a second time.
+///
+/// Files are hashed concurrently, so scans of different files may run at the same time on
+/// different threads. Files whose digests are reused, rather than recalculated, are not scanned.
+pub trait FileScanner: Send + Sync {
+    /// Called when a payload file starts being hashed. The returned scan receives the file's
+    /// contents as they are read.
+    fn start(&self, path: &Path) -> io::Result<Box<dyn FileScan>>;
+}
+
+/// The scan of a single file
+pub trait FileScan {
+    /// Called with every chunk of the file's contents, in order
+    fn update(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Called once the entire file has been read
+    fn finish(self: Box<Self>) -> io::Result<ScanOutcome>;
+}
+
+/// The verdict of a `FileScan`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ScanOutcome {
+    /// The file is fine
+    Accept,
+    /// The file is suspect, but it's still added to the bag. The reason is logged as a warning.
+    Flag(String),
+    /// The file must not be added to the bag, which fails the operation
+    Reject(String),
+}
+
+/// A `FileScanner` that runs a shell command for every file, and writes the file's contents to
+/// the command's stdin. The path to the file is available to the command in the `BAGR_SCAN_PATH`
+/// environment variable, for tools that read the file themselves.
+///
+/// Files are accepted when the command exits successfully. Otherwise, they're rejected, or only
+/// flagged when `warn_only` is enabled, and the first line the command wrote to stdout is used as
+/// the reason.
+#[derive(Debug, Clone)]
+pub struct CommandScanner {
+    command: String,
+    warn_only: bool,
+}
+
+struct CommandScan {
+    command: String,
+    warn_only: bool,
+    child: Child,
+    /// `None` once the command has stopped reading its stdin
+    stdin: Option<ChildStdin>,
+    stdout: JoinHandle<Vec<u8>>,
+}
+
+/// Wraps a reader and passes everything that's read to a `FileScan`
+pub struct ScanReader<R: Read> {
+    reader: R,
+    path: PathBuf,
+    scan: Option<Box<dyn FileScan>>,
+}
+
+impl Debug for dyn FileScanner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FileScanner")
+    }
+}
+
+impl CommandScanner {
+    /// Creates a scanner that runs `command` using the system shell
+    pub fn new<S: Into<String>>(command: S) -> Self {
+        Self {
+            command: command.into(),
+            warn_only: false,
+        }
+    }
+
+    /// When true, files that fail the scan are flagged instead of rejected. The default is false.
+    pub fn warn_only(mut self, warn_only: bool) -> Self {
+        self.warn_only = warn_only;
+        self
+    }
+}
+
+impl FileScanner for CommandScanner {
+    fn start(&self, path: &Path) -> io::Result<Box<dyn FileScan>> {
+        let mut child = shell_command(&self.command)
+            .env("BAGR_SCAN_PATH", path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().unwrap();
+        // Read stdout concurrently so that the command does not block on a full pipe
+        let stdout = thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stdout.read_to_end(&mut output);
+            output
+        });
+
+        Ok(Box::new(CommandScan {
+            command: self.command.clone(),
+            warn_only: self.warn_only,
+            child,
+            stdin,
+            stdout,
+        }))
+    }
+}
+
+impl FileScan for CommandScan {
+    fn update(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(stdin) = &mut self.stdin {
+            match stdin.write_all(bytes) {
+                Ok(_) => {}
+                // The command does not need the rest of the file
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => self.stdin = None,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<ScanOutcome> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        let output = self.stdout.join().unwrap_or_default();
+
+        if status.success() {
+            return Ok(ScanOutcome::Accept);
+        }
+
+        let reason = scan_failure_reason(&self.command, status, &output);
+        if self.warn_only {
+            Ok(ScanOutcome::Flag(reason))
+        } else {
+            Ok(ScanOutcome::Reject(reason))
+        }
+    }
+}
+
+impl<R: Read> ScanReader<R> {
+    /// Starts scanning the file at `path`, which is read from `reader`. Nothing is scanned when
+    /// `scanner` is `None`.
+    pub fn new(reader: R, path: &Path, scanner: Option<&dyn FileScanner>) -> Result<Self> {
+        let scan = scanner
+            .map(|scanner| scanner.start(path))
+            .transpose()
+            .context(ScanSnafu { path })?;
+        Ok(Self {
+            reader,
+            path: path.into(),
+            scan,
+        })
+    }
+
+    /// Finishes the scan once the entire file has been read. Flagged files are logged, and an
+    /// error is returned if the file was rejected.
+    pub fn finish(self) -> Result<()> {
+        let scan = match self.scan {
+            Some(scan) => scan,
+            None => return Ok(()),
+        };
+
+        match scan.finish().context(ScanSnafu { path: &self.path })? {
+            ScanOutcome::Accept => Ok(()),
+            ScanOutcome::Flag(reason) => {
+                warn!(
+                    "File {} was flagged by the scanner: {reason}",
+                    self.path.display()
+                );
+                Ok(())
+            }
+            ScanOutcome::Reject(reason) => Err(RejectedFile {
+                path: self.path,
+                details: reason,
+            }),
+        }
+    }
+}
+
+impl<R: Read> Read for ScanReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read > 0 {
+            if let Some(scan) = &mut self.scan {
+                scan.update(&buf[..read])?;
+            }
+        }
+        Ok(read)
+    }
+}
+
+fn scan_failure_reason(command: &str, status: ExitStatus, output: &[u8]) -> String {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| format!("`{command}` exited with {status}"))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(target_os = "windows")]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
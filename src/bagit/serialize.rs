@@ -0,0 +1,167 @@
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Serialize, Serializer};
+
+use crate::bagit::bag::{Bag, BagItVersion};
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::fetch::FetchEntry;
+use crate::bagit::tag::{BagDeclaration, BagInfo, PayloadOxum, Tag, TagList};
+use crate::bagit::validate::{
+    FixitySample, IssueCode, IssueLevel, ValidationIssue, ValidationResult,
+};
+
+impl Serialize for Bag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Bag", 4)?;
+        state.serialize_field("base_dir", self.base_dir())?;
+        state.serialize_field("declaration", self.declaration())?;
+        state.serialize_field("bag_info", self.bag_info())?;
+        state.serialize_field("algorithms", self.algorithms())?;
+        state.end()
+    }
+}
+
+impl Serialize for BagDeclaration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("BagDeclaration", 2)?;
+        state.serialize_field("version", &self.version())?;
+        state.serialize_field("encoding", self.encoding())?;
+        state.end()
+    }
+}
+
+/// Serialized as a string, eg. `1.0`
+impl Serialize for BagItVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Serialized as a string, eg. `sha512`
+impl Serialize for DigestAlgorithm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Serialize for BagInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+/// Serialized as a sequence of `label` and `value` pairs, in order, because labels may repeat
+impl Serialize for TagList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for tag in self {
+            seq.serialize_element(tag)?;
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for Tag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Tag", 2)?;
+        state.serialize_field("label", self.label())?;
+        state.serialize_field("value", self.value())?;
+        state.end()
+    }
+}
+
+impl Serialize for PayloadOxum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PayloadOxum", 2)?;
+        state.serialize_field("bytes", &self.bytes)?;
+        state.serialize_field("files", &self.files)?;
+        state.end()
+    }
+}
+
+impl Serialize for FetchEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("FetchEntry", 3)?;
+        state.serialize_field("url", self.url())?;
+        state.serialize_field("length", &self.length())?;
+        state.serialize_field("path", self.path())?;
+        state.end()
+    }
+}
+
+impl Serialize for ValidationResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ValidationResult", 5)?;
+        state.serialize_field("valid", &self.is_valid())?;
+        state.serialize_field("partial", &self.is_partial())?;
+        state.serialize_field("issues", self.issues())?;
+        state.serialize_field("unverified_files", self.unverified_files())?;
+        state.serialize_field("sample", &self.sample())?;
+        state.end()
+    }
+}
+
+impl Serialize for ValidationIssue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ValidationIssue", 5)?;
+        state.serialize_field("level", &self.level())?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", self.message())?;
+        state.serialize_field("suggestion", &self.suggestion())?;
+        state.serialize_field("path", &self.path())?;
+        state.end()
+    }
+}
+
+/// Serialized as `error` or `warning`
+impl Serialize for IssueLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            IssueLevel::Error => "error",
+            IssueLevel::Warning => "warning",
+        })
+    }
+}
+
+/// Serialized as the code's name, eg. `DigestMismatch`
+impl Serialize for IssueCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl Serialize for FixitySample {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("FixitySample", 3)?;
+        state.serialize_field("seed", &self.seed())?;
+        state.serialize_field("sampled", &self.sampled())?;
+        state.serialize_field("total", &self.total())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bagit::tag::BagInfo;
+    use crate::bagit::validate::ValidationResult;
+
+    #[test]
+    fn bag_info_serializes_repeated_tags_in_order() {
+        let mut bag_info = BagInfo::new();
+        bag_info.add_tag("Contact-Name", "Jane Doe").unwrap();
+        bag_info.add_tag("Bagging-Date", "2022-02-16").unwrap();
+        bag_info.add_tag("Contact-Name", "John Roe").unwrap();
+
+        assert_eq!(
+            r#"[{"label":"Contact-Name","value":"Jane Doe"},{"label":"Bagging-Date","value":"2022-02-16"},{"label":"Contact-Name","value":"John Roe"}]"#,
+            serde_json::to_string(&bag_info).unwrap()
+        );
+    }
+
+    #[test]
+    fn validation_result_serializes_its_outcome() {
+        assert_eq!(
+            r#"{"valid":true,"partial":false,"issues":[],"unverified_files":[],"sample":null}"#,
+            serde_json::to_string(&ValidationResult::new()).unwrap()
+        );
+    }
+}
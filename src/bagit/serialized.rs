@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+use flate2::read::DeflateDecoder;
+use log::{info, warn};
+use snafu::ResultExt;
+use zip::result::ZipError;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::bagit::consts::*;
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::tag::{decode_reader, read_tags, BagDeclaration, BagInfo};
+
+/// The size of the chunks that encrypted files are decrypted in
+const DECRYPTED_CHUNK_SIZE: usize = 64 * 1024;
+/// The number of decrypted chunks that may be buffered ahead of the reader that consumes them
+const DECRYPTED_CHUNK_BUFFER: usize = 4;
+
+/// A read-only bag that is serialized in a zip or tar archive. Files are read directly out of
+/// the archive without extracting it.
+#[derive(Debug)]
+pub struct SerializedBag {
+    archive_path: PathBuf,
+    password: Option<String>,
+    declaration: BagDeclaration,
+    bag_info: BagInfo,
+    algorithms: Vec<DigestAlgorithm>,
+    files: BTreeMap<String, ArchiveFile>,
+}
+
+/// A file within a serialized bag
+#[derive(Debug, Clone)]
+pub struct ArchiveFile {
+    /// The path to the file relative to the bag's base directory
+    path: String,
+    size: u64,
+    /// The offset in the archive that the file's data starts at
+    offset: u64,
+    /// The number of bytes the file's data occupies in the archive
+    stored_size: u64,
+    compression: Compression,
+    /// The position of the file's entry in the archive
+    index: usize,
+    /// Whether the file is encrypted using AES
+    encrypted: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Compression {
+    None,
+    Deflate,
+    Unsupported,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+/// Reader over a zip file entry that's encrypted using WinZip AES. zip's decrypting reader
+/// borrows the archive it reads from, so the entry is decrypted on a separate thread that owns
+/// its own handle to the archive, and is received from it in chunks.
+struct DecryptedReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+/// Opens a bag that's serialized in a zip or tar archive, without extracting it. The archive
+/// format is determined by the file's extension. Compressed tar archives are not supported
+/// because their files cannot be read without decompressing everything that precedes them.
+///
+/// The archive must either contain the bag at its root or within a single top-level directory.
+/// Use `open_serialized_bag_with_password()` to open AES encrypted zip archives.
+pub fn open_serialized_bag<P: AsRef<Path>>(archive_path: P) -> Result<SerializedBag> {
+    open_archive(archive_path.as_ref(), None)
+}
+
+/// The same as `open_serialized_bag()`, except the files in AES encrypted zip archives are
+/// transparently decrypted using `password` as they're read
+pub fn open_serialized_bag_with_password<P: AsRef<Path>>(
+    archive_path: P,
+    password: &str,
+) -> Result<SerializedBag> {
+    open_archive(archive_path.as_ref(), Some(password))
+}
+
+fn open_archive(archive_path: &Path, password: Option<&str>) -> Result<SerializedBag> {
+    info!("Opening serialized bag at {}", archive_path.display());
+
+    let files = match detect_format(archive_path)? {
+        ArchiveFormat::Tar => list_tar_files(archive_path)?,
+        ArchiveFormat::Zip => list_zip_files(archive_path, password.is_some())?,
+    };
+
+    let root = find_bag_root(files.iter().map(|file| file.path.as_str())).ok_or_else(|| {
+        InvalidArchive {
+            path: archive_path.into(),
+            details: format!("Could not find {BAGIT_TXT}"),
+        }
+    })?;
+
+    let files: BTreeMap<String, ArchiveFile> = files
+        .into_iter()
+        .filter_map(|mut file| {
+            file.path = file.path.strip_prefix(&root)?.to_string();
+            Some((file.path.clone(), file))
+        })
+        .collect();
+
+    let mut bag = SerializedBag {
+        archive_path: archive_path.into(),
+        password: password.map(String::from),
+        declaration: BagDeclaration::new(),
+        bag_info: BagInfo::new(),
+        algorithms: Vec::new(),
+        files,
+    };
+
+    bag.declaration =
+        read_tags(bag.open_file(BAGIT_TXT)?, &bag.archive_path.join(BAGIT_TXT))?.try_into()?;
+
+    if bag.files.contains_key(BAG_INFO_TXT) {
+        bag.bag_info = read_tags(
+            decode_reader(bag.open_file(BAG_INFO_TXT)?, &bag.declaration),
+            &bag.archive_path.join(BAG_INFO_TXT),
+        )?
+        .into();
+    }
+
+    bag.algorithms = bag
+        .files
+        .keys()
+        .filter_map(|path| PAYLOAD_MANIFEST_MATCHER.captures(path))
+        .filter_map(|captures| {
+            let algorithm_str = captures.get(1).unwrap().as_str();
+            match algorithm_str.try_into() {
+                Ok(algorithm) => Some(algorithm),
+                Err(_) => {
+                    warn!("Detected unsupported digest algorithm: {algorithm_str}");
+                    None
+                }
+            }
+        })
+        .collect();
+
+    Ok(bag)
+}
+
+impl SerializedBag {
+    pub fn declaration(&self) -> &BagDeclaration {
+        &self.declaration
+    }
+
+    pub fn bag_info(&self) -> &BagInfo {
+        &self.bag_info
+    }
+
+    /// The algorithms of the payload manifests in the bag
+    pub fn algorithms(&self) -> &[DigestAlgorithm] {
+        &self.algorithms
+    }
+
+    /// Returns all of the files in the bag's payload directory, ordered by path
+    pub fn payload_files(&self) -> impl Iterator<Item = &ArchiveFile> {
+        self.files
+            .values()
+            .filter(|file| file.path.starts_with(&format!("{DATA}/")))
+    }
+
+    /// Opens a reader over the contents of the payload file at `path`, which is relative to the
+    /// bag's base directory, eg. `data/file.txt`. The contents are streamed directly out of the
+    /// archive.
+    pub fn open_payload_reader(&self, path: &str) -> Result<Box<dyn Read + Send>> {
+        if !path.starts_with(&format!("{DATA}/")) {
+            return Err(MissingArchiveFile {
+                archive: self.archive_path.clone(),
+                path: path.into(),
+            });
+        }
+        self.open_file(path)
+    }
+
+    /// Returns all of the files in the bag, ordered by path
+    pub(crate) fn files(&self) -> impl Iterator<Item = &ArchiveFile> {
+        self.files.values()
+    }
+
+    /// The path to the archive the bag is serialized in
+    pub(crate) fn archive_path(&self) -> &Path {
+        &self.archive_path
+    }
+
+    /// Opens a reader over the contents of any file in the bag, decrypting it if necessary
+    pub(crate) fn open_file(&self, path: &str) -> Result<Box<dyn Read + Send>> {
+        let file = self.files.get(path).ok_or_else(|| MissingArchiveFile {
+            archive: self.archive_path.clone(),
+            path: path.into(),
+        })?;
+
+        let archive_path = &self.archive_path;
+        if file.encrypted {
+            return match &self.password {
+                Some(password) => Ok(Box::new(DecryptedReader::open(
+                    archive_path,
+                    file.index,
+                    password,
+                )?)),
+                None => Err(PasswordRequired {
+                    path: archive_path.into(),
+                }),
+            };
+        }
+
+        let mut reader = File::open(archive_path).context(IoReadSnafu { path: archive_path })?;
+        reader
+            .seek(SeekFrom::Start(file.offset))
+            .context(IoReadSnafu { path: archive_path })?;
+        let reader = reader.take(file.stored_size);
+
+        match file.compression {
+            Compression::None => Ok(Box::new(reader)),
+            Compression::Deflate => Ok(Box::new(DeflateDecoder::new(reader))),
+            Compression::Unsupported => Err(InvalidArchive {
+                path: archive_path.into(),
+                details: format!("{path} is compressed using an unsupported method"),
+            }),
+        }
+    }
+}
+
+impl ArchiveFile {
+    /// The path to the file relative to the bag's base directory
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The uncompressed size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Determines the format of an archive from its file extension
+pub(crate) fn detect_format(archive_path: &Path) -> Result<ArchiveFormat> {
+    let extension = archive_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("tar") => Ok(ArchiveFormat::Tar),
+        Some("zip") => Ok(ArchiveFormat::Zip),
+        _ => Err(UnsupportedArchive {
+            path: archive_path.into(),
+        }),
+    }
+}
+
+fn list_tar_files(archive_path: &Path) -> Result<Vec<ArchiveFile>> {
+    let mut archive =
+        tar::Archive::new(File::open(archive_path).context(IoReadSnafu { path: archive_path })?);
+    let mut files = Vec::new();
+
+    for (index, entry) in archive
+        .entries()
+        .context(IoReadSnafu { path: archive_path })?
+        .enumerate()
+    {
+        let entry = entry.context(IoReadSnafu { path: archive_path })?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path =
+            String::from_utf8(entry.path_bytes().into_owned()).context(InvalidStringSnafu)?;
+
+        files.push(ArchiveFile {
+            path: normalize_path(&path),
+            size: entry.size(),
+            offset: entry.raw_file_position(),
+            stored_size: entry.size(),
+            compression: Compression::None,
+            index,
+            encrypted: false,
+        });
+    }
+
+    Ok(files)
+}
+
+fn list_zip_files(archive_path: &Path, has_password: bool) -> Result<Vec<ArchiveFile>> {
+    let to_error = |e: ZipError| zip_error(archive_path, e);
+
+    let mut archive = open_zip_archive(archive_path)?;
+    let mut files = Vec::with_capacity(archive.len());
+
+    for index in 0..archive.len() {
+        let aes = archive
+            .get_aes_verification_key_and_salt(index)
+            .map_err(to_error)?
+            .is_some();
+        let file = archive.by_index_raw(index).map_err(to_error)?;
+
+        if file.encrypted() {
+            if !aes {
+                return Err(InvalidArchive {
+                    path: archive_path.into(),
+                    details: format!(
+                        "{} is encrypted using ZipCrypto, but only AES encryption is supported",
+                        file.name()
+                    ),
+                });
+            } else if !has_password {
+                return Err(PasswordRequired {
+                    path: archive_path.into(),
+                });
+            }
+        }
+
+        if file.is_dir() {
+            continue;
+        }
+
+        let compression = match file.compression() {
+            CompressionMethod::Stored => Compression::None,
+            CompressionMethod::Deflated => Compression::Deflate,
+            _ => Compression::Unsupported,
+        };
+
+        files.push(ArchiveFile {
+            path: normalize_path(file.name()),
+            size: file.size(),
+            offset: file.data_start(),
+            stored_size: file.compressed_size(),
+            compression,
+            index,
+            encrypted: file.encrypted(),
+        });
+    }
+
+    Ok(files)
+}
+
+fn open_zip_archive(archive_path: &Path) -> Result<ZipArchive<File>> {
+    ZipArchive::new(File::open(archive_path).context(IoReadSnafu { path: archive_path })?)
+        .map_err(|e| zip_error(archive_path, e))
+}
+
+fn zip_error(archive_path: &Path, error: ZipError) -> Error {
+    match error {
+        ZipError::InvalidPassword => IncorrectPassword {
+            path: archive_path.into(),
+        },
+        error => InvalidArchive {
+            path: archive_path.into(),
+            details: error.to_string(),
+        },
+    }
+}
+
+impl DecryptedReader {
+    /// Starts decrypting the entry at `index` in the zip archive at `archive_path`. An error is
+    /// returned if the password is incorrect.
+    fn open(archive_path: &Path, index: usize, password: &str) -> Result<Self> {
+        let (opened_sender, opened) = mpsc::sync_channel(1);
+        let (chunk_sender, chunks) = mpsc::sync_channel(DECRYPTED_CHUNK_BUFFER);
+        let path = archive_path.to_path_buf();
+        let password = password.to_string();
+
+        thread::spawn(move || {
+            send_decrypted_chunks(&path, index, &password, opened_sender, chunk_sender)
+        });
+
+        opened.recv().unwrap_or_else(|_| {
+            Err(InvalidArchive {
+                path: archive_path.into(),
+                details: "Failed to start decrypting an encrypted file".to_string(),
+            })
+        })?;
+
+        Ok(Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+impl Read for DecryptedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                // The sender is dropped once the entry has been completely read
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Decrypts the entry at `index` in the zip archive at `archive_path`, and sends its contents to
+/// `chunks`. The outcome of opening the entry, which fails if the password is incorrect, is sent
+/// to `opened` first. zip verifies the entry's authentication code once all of its data is read.
+fn send_decrypted_chunks(
+    archive_path: &Path,
+    index: usize,
+    password: &str,
+    opened: SyncSender<Result<()>>,
+    chunks: SyncSender<io::Result<Vec<u8>>>,
+) {
+    let mut archive = match open_zip_archive(archive_path) {
+        Ok(archive) => archive,
+        Err(e) => {
+            let _ = opened.send(Err(e));
+            return;
+        }
+    };
+    let mut file = match archive.by_index_decrypt(index, password.as_bytes()) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = opened.send(Err(zip_error(archive_path, e)));
+            return;
+        }
+    };
+
+    if opened.send(Ok(())).is_err() {
+        return;
+    }
+
+    loop {
+        let mut chunk = vec![0; DECRYPTED_CHUNK_SIZE];
+        let result = match file.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(read) => {
+                chunk.truncate(read);
+                Ok(chunk)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+
+        let failed = result.is_err();
+        // The receiver is dropped when the reader is no longer needed
+        if chunks.send(result).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Returns the path prefix of the bag's base directory within an archive that contains files at
+/// `paths`. This is either empty, when the bag is at the root of the archive, or the name of the
+/// top-level directory followed by a `/`.
+pub(crate) fn find_bag_root<'a>(paths: impl Iterator<Item = &'a str>) -> Option<String> {
+    paths
+        .filter_map(|path| path.strip_suffix(BAGIT_TXT))
+        .filter(|prefix| {
+            prefix.is_empty()
+                || (prefix.ends_with('/') && prefix.matches('/').count() == 1 && prefix != &"/")
+        })
+        .min_by_key(|prefix| prefix.len())
+        .map(String::from)
+}
+
+/// Strips leading `./` segments that some archivers add to paths
+pub(crate) fn normalize_path(path: &str) -> String {
+    let mut path = path;
+    while let Some(stripped) = path.strip_prefix("./") {
+        path = stripped;
+    }
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::bagit::bag::BagWriter;
+    use crate::bagit::error::Error;
+    use crate::bagit::package::BagPackager;
+    use crate::bagit::serialized::{open_serialized_bag, open_serialized_bag_with_password};
+
+    #[test]
+    fn encrypted_zip_files_are_decrypted_as_they_are_read() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let bag_dir = dir.join("bag");
+        let archive_path = dir.join("bag.zip");
+
+        let contents = "testing ".repeat(20_000);
+        let mut writer = BagWriter::create(&bag_dir, &[]).unwrap();
+        writer
+            .add_payload_file("dir/file.txt", contents.as_bytes())
+            .unwrap();
+        writer.finish().unwrap();
+
+        BagPackager::new(&bag_dir, &archive_path)
+            .with_password(Some("s3cret".to_string()))
+            .package()
+            .unwrap();
+
+        assert!(matches!(
+            open_serialized_bag(&archive_path),
+            Err(Error::PasswordRequired { .. })
+        ));
+        assert!(matches!(
+            open_serialized_bag_with_password(&archive_path, "incorrect"),
+            Err(Error::IncorrectPassword { .. })
+        ));
+
+        let bag = open_serialized_bag_with_password(&archive_path, "s3cret").unwrap();
+        let mut actual = String::new();
+        bag.open_payload_reader("data/dir/file.txt")
+            .unwrap()
+            .read_to_string(&mut actual)
+            .unwrap();
+
+        assert_eq!(contents, actual);
+    }
+}
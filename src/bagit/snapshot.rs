@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::Local;
+use log::info;
+use snafu::ResultExt;
+use walkdir::WalkDir;
+
+use crate::bagit::error::*;
+use crate::bagit::tag::TagList;
+
+/// The path, size, and modification time of every file in a directory at a point in time. Two
+/// snapshots of the same directory are compared to prove that it did not change in between.
+#[derive(Debug)]
+pub struct SourceSnapshot {
+    files: BTreeMap<PathBuf, FileState>,
+    /// When the snapshot was taken, formatted as RFC 3339
+    taken: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct FileState {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// A difference between two snapshots. Paths are relative to the snapshot's directory.
+#[derive(Debug, Eq, PartialEq)]
+pub enum SourceChange {
+    Added(PathBuf),
+    Removed(PathBuf),
+    Modified(PathBuf),
+}
+
+impl SourceSnapshot {
+    /// Records every file in `dir`. Directories for which `skip` returns true, such as a bag
+    /// that's being created within the directory, are not walked.
+    pub fn capture<F: Fn(&Path) -> bool>(dir: &Path, skip: F) -> Result<Self> {
+        info!("Taking a snapshot of {}", dir.display());
+
+        let taken = Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+        let mut files = BTreeMap::new();
+
+        for file in WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|f| f.depth() == 0 || !skip(f.path()))
+        {
+            let file = file.context(WalkFileSnafu {})?;
+            if file.file_type().is_dir() {
+                continue;
+            }
+            let metadata = file.metadata().context(WalkFileSnafu {})?;
+            files.insert(
+                file.path().strip_prefix(dir).unwrap().to_path_buf(),
+                FileState {
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                },
+            );
+        }
+
+        Ok(Self { files, taken })
+    }
+
+    /// Returns the files that were added, removed, or modified in `current`, which is a later
+    /// snapshot of the same directory, ordered by path. Files are modified if their size or
+    /// modification time changed.
+    pub fn changes(&self, current: &SourceSnapshot) -> Vec<SourceChange> {
+        let mut changes = Vec::new();
+
+        for (path, state) in &self.files {
+            match current.files.get(path) {
+                Some(other) if other != state => changes.push(SourceChange::Modified(path.clone())),
+                Some(_) => {}
+                None => changes.push(SourceChange::Removed(path.clone())),
+            }
+        }
+        for path in current.files.keys() {
+            if !self.files.contains_key(path) {
+                changes.push(SourceChange::Added(path.clone()));
+            }
+        }
+
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        changes
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.files.values().map(|state| state.size).sum()
+    }
+
+    /// Describes the snapshot, and the `current` snapshot that it was verified against, as the
+    /// tags of a transfer consistency statement
+    pub fn to_statement(&self, src_dir: &Path, current: &SourceSnapshot) -> Result<TagList> {
+        let mut tags = TagList::with_capacity(6);
+        tags.add_tag("Source-Directory", src_dir.display().to_string())?;
+        tags.add_tag("Snapshot-Date", &self.taken)?;
+        tags.add_tag("Verification-Date", &current.taken)?;
+        tags.add_tag("Source-File-Count", self.file_count().to_string())?;
+        tags.add_tag("Source-Octet-Count", self.total_bytes().to_string())?;
+        tags.add_tag(
+            "Source-Status",
+            "Unchanged; the size and modification time of every source file matched the \
+            snapshot after the payload was copied",
+        )?;
+        Ok(tags)
+    }
+}
+
+impl SourceChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            SourceChange::Added(path)
+            | SourceChange::Removed(path)
+            | SourceChange::Modified(path) => path,
+        }
+    }
+}
+
+impl Display for SourceChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceChange::Added(path) => write!(f, "{} was added", path.display()),
+            SourceChange::Removed(path) => write!(f, "{} was removed", path.display()),
+            SourceChange::Modified(path) => write!(f, "{} was modified", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::bagit::snapshot::{SourceChange, SourceSnapshot};
+
+    #[test]
+    fn detect_changes_between_snapshots() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join("skipped")).unwrap();
+        fs::write(dir.join("same.txt"), "same").unwrap();
+        fs::write(dir.join("sub/changed.txt"), "before").unwrap();
+        fs::write(dir.join("removed.txt"), "removed").unwrap();
+
+        let skip = |path: &Path| path.ends_with("skipped");
+        let before = SourceSnapshot::capture(dir, skip).unwrap();
+
+        fs::write(dir.join("sub/changed.txt"), "after!!").unwrap();
+        fs::remove_file(dir.join("removed.txt")).unwrap();
+        fs::write(dir.join("added.txt"), "added").unwrap();
+        fs::write(dir.join("skipped/ignored.txt"), "ignored").unwrap();
+
+        let after = SourceSnapshot::capture(dir, skip).unwrap();
+
+        assert_eq!(3, before.file_count());
+        assert_eq!(17, before.total_bytes());
+        assert_eq!(
+            vec![
+                SourceChange::Added(PathBuf::from("added.txt")),
+                SourceChange::Removed(PathBuf::from("removed.txt")),
+                SourceChange::Modified(PathBuf::from("sub/changed.txt")),
+            ],
+            before.changes(&after)
+        );
+        assert!(after.changes(&after).is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{info, warn};
+use snafu::ResultExt;
+
+use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+use crate::bagit::encoding::PathEncoding;
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::io::LineReader;
+use crate::bagit::tag::PayloadOxum;
+
+/// The number of files that are held in memory before they're spilled to disk
+const SPOOL_CAPACITY: usize = 100_000;
+
+/// A file and the digests that were calculated for it
+#[derive(Debug)]
+pub struct FileMeta {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub digests: HashMap<DigestAlgorithm, HexDigest>,
+}
+
+/// Collects the digests of files, in any order, so that they can be written to manifests in path
+/// order without holding all of them in memory. Files are buffered in memory until there are too
+/// many, and then they are sorted and spilled to a run file in a temporary directory. The runs are
+/// merged when the files are read back out.
+///
+/// Each line of a run describes one file: its size, its digests as comma separated
+/// `algorithm:digest` pairs, and its strictly percent encoded path, separated by spaces.
+#[derive(Debug)]
+pub struct DigestSpool {
+    capacity: usize,
+    state: Mutex<SpoolState>,
+}
+
+#[derive(Debug, Default)]
+struct SpoolState {
+    buffer: Vec<FileMeta>,
+    runs: Vec<PathBuf>,
+    dir: Option<SpoolDir>,
+    payload_oxum: PayloadOxum,
+}
+
+/// The temporary directory that runs are written to. It's deleted when it's dropped.
+#[derive(Debug)]
+struct SpoolDir(PathBuf);
+
+/// Iterates over spooled files in path order
+pub struct SortedFiles {
+    sources: Vec<Box<dyn Iterator<Item = Result<FileMeta>> + Send>>,
+    heads: Vec<Option<Result<FileMeta>>>,
+    _dir: Option<SpoolDir>,
+}
+
+impl DigestSpool {
+    pub fn new() -> Self {
+        Self::with_capacity(SPOOL_CAPACITY)
+    }
+
+    /// Creates a spool that holds up to `capacity` files in memory
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(SpoolState::default()),
+        }
+    }
+
+    /// Adds a file to the spool, spilling the buffered files to disk if the buffer is full
+    pub fn push(&self, meta: FileMeta) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.payload_oxum.add_file(meta.size_bytes);
+        state.buffer.push(meta);
+
+        if state.buffer.len() >= self.capacity {
+            state.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// The Payload-Oxum of the files that were added to the spool
+    pub fn payload_oxum(&self) -> PayloadOxum {
+        self.state.lock().unwrap().payload_oxum
+    }
+
+    /// Returns all of the files in the spool sorted by path
+    pub fn into_sorted(self) -> Result<SortedFiles> {
+        let mut state = self.state.into_inner().unwrap();
+        state.buffer.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<FileMeta>> + Send>> =
+            Vec::with_capacity(state.runs.len() + 1);
+        for run in &state.runs {
+            sources.push(Box::new(read_run(run)?));
+        }
+        sources.push(Box::new(
+            std::mem::take(&mut state.buffer).into_iter().map(Ok),
+        ));
+
+        let heads = sources.iter_mut().map(|source| source.next()).collect();
+
+        Ok(SortedFiles {
+            sources,
+            heads,
+            _dir: state.dir.take(),
+        })
+    }
+}
+
+impl Default for DigestSpool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpoolState {
+    /// Sorts the buffered files and writes them to a new run
+    fn spill(&mut self) -> Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir.0.clone(),
+            None => {
+                // The directory is created with a unique name that only the current user can access
+                let dir = tempfile::Builder::new()
+                    .prefix("bagr-spool-")
+                    .tempdir()
+                    .context(IoCreateSnafu {
+                        path: std::env::temp_dir(),
+                    })?
+                    .keep();
+                self.dir = Some(SpoolDir(dir.clone()));
+                dir
+            }
+        };
+
+        let path = dir.join(format!("run-{}", self.runs.len()));
+        info!(
+            "Spilling digests for {} files to {}",
+            self.buffer.len(),
+            path.display()
+        );
+
+        self.buffer.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut writer =
+            BufWriter::new(File::create(&path).context(IoCreateSnafu { path: &path })?);
+        for meta in self.buffer.drain(..) {
+            let encoded = meta
+                .path
+                .to_str()
+                .ok_or_else(|| InvalidUtf8Path {
+                    path: meta.path.clone(),
+                })?
+                .replace('\\', "/");
+            let mut digests = meta
+                .digests
+                .iter()
+                .map(|(algorithm, digest)| format!("{algorithm}:{digest}"))
+                .collect::<Vec<String>>();
+            digests.sort();
+
+            writeln!(
+                writer,
+                "{} {} {}",
+                meta.size_bytes,
+                digests.join(","),
+                PathEncoding::Strict.encode(&encoded)
+            )
+            .context(IoWriteSnafu { path: &path })?;
+        }
+        writer.flush().context(IoWriteSnafu { path: &path })?;
+
+        self.runs.push(path);
+        Ok(())
+    }
+}
+
+impl Iterator for SortedFiles {
+    type Item = Result<FileMeta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Errors are returned as soon as they're encountered
+        if let Some(i) = self
+            .heads
+            .iter()
+            .position(|head| matches!(head, Some(Err(_))))
+        {
+            return self.advance(i);
+        }
+
+        let i = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| match head {
+                Some(Ok(meta)) => Some((i, meta)),
+                _ => None,
+            })
+            .min_by(|(_, a), (_, b)| a.path.cmp(&b.path))
+            .map(|(i, _)| i)?;
+
+        self.advance(i)
+    }
+}
+
+impl SortedFiles {
+    /// Returns the head of the `i`th source and replaces it with the source's next file
+    fn advance(&mut self, i: usize) -> Option<Result<FileMeta>> {
+        let next = self.sources[i].next();
+        std::mem::replace(&mut self.heads[i], next)
+    }
+}
+
+impl Drop for SpoolDir {
+    fn drop(&mut self) {
+        info!("Deleting spool directory {}", self.0.display());
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            warn!("Failed to delete {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Reads the files in a run in the order they were written
+fn read_run(path: &Path) -> Result<impl Iterator<Item = Result<FileMeta>> + Send> {
+    let file = File::open(path).context(IoReadSnafu { path })?;
+    let path = path.to_path_buf();
+
+    Ok(LineReader::new(BufReader::new(file)).map(move |line| {
+        let line = line?;
+        parse_run_line(&line).ok_or_else(|| General {
+            message: format!("Invalid line in {}: {line}", path.display()),
+        })
+    }))
+}
+
+fn parse_run_line(line: &str) -> Option<FileMeta> {
+    let mut parts = line.splitn(3, ' ');
+    let size_bytes = parts.next()?.parse().ok()?;
+
+    let mut digests = HashMap::new();
+    for pair in parts.next()?.split(',') {
+        let (algorithm, digest) = pair.split_once(':')?;
+        digests.insert(algorithm.try_into().ok()?, HexDigest::from(digest));
+    }
+
+    let path = PathEncoding::Strict.decode(parts.next()?);
+
+    Some(FileMeta {
+        path: PathBuf::from(path.as_ref()),
+        size_bytes,
+        digests,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::bagit::digest::{DigestAlgorithm, HexDigest};
+    use crate::bagit::spool::{DigestSpool, FileMeta};
+    use crate::bagit::tag::PayloadOxum;
+
+    #[test]
+    fn spilled_files_are_merged_in_path_order() {
+        let spool = DigestSpool::with_capacity(2);
+        for (i, name) in ["data/c d.txt", "data/a.txt", "data/b/x.txt", "data/a-b.txt"]
+            .iter()
+            .enumerate()
+        {
+            spool
+                .push(FileMeta {
+                    path: PathBuf::from(name),
+                    size_bytes: name.len() as u64,
+                    digests: HashMap::from([
+                        (DigestAlgorithm::Sha256, HexDigest::from(format!("abc{i}"))),
+                        (DigestAlgorithm::Md5, HexDigest::from("abc")),
+                    ]),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(PayloadOxum::new(46, 4), spool.payload_oxum());
+
+        let files = spool
+            .into_sorted()
+            .unwrap()
+            .collect::<Result<Vec<FileMeta>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            vec!["data/a-b.txt", "data/a.txt", "data/b/x.txt", "data/c d.txt"],
+            files
+                .iter()
+                .map(|meta| meta.path.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&HexDigest::from("abc0")),
+            files[3].digests.get(&DigestAlgorithm::Sha256)
+        );
+        assert_eq!(12, files[3].size_bytes);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::bagit::digest::DigestAlgorithm;
+use crate::bagit::progress::{format_bytes, ProgressListener};
+use crate::bagit::tag::PayloadOxum;
+
+/// Statistics about how a bag was created or updated, such as how long it took and how quickly
+/// its payload was hashed, which are useful for planning the capacity of large jobs
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BagStats {
+    payload_files: u64,
+    payload_bytes: u64,
+    hashed_files: u64,
+    hashed_bytes: u64,
+    elapsed: Duration,
+    digest_times: BTreeMap<DigestAlgorithm, Duration>,
+}
+
+/// A `ProgressListener` that records hashing statistics and passes every update on to another
+/// listener
+pub struct StatsListener {
+    inner: Arc<dyn ProgressListener>,
+    started: Instant,
+    hashed_files: AtomicU64,
+    hashed_bytes: AtomicU64,
+    digest_times: Mutex<HashMap<DigestAlgorithm, Duration>>,
+}
+
+impl BagStats {
+    /// The number of files in the bag's payload
+    pub fn payload_files(&self) -> u64 {
+        self.payload_files
+    }
+
+    /// The number of bytes in the bag's payload
+    pub fn payload_bytes(&self) -> u64 {
+        self.payload_bytes
+    }
+
+    /// The number of payload files that were hashed, which is fewer than the number of files in
+    /// the payload when existing digests were reused
+    pub fn hashed_files(&self) -> u64 {
+        self.hashed_files
+    }
+
+    /// The number of bytes that were hashed
+    pub fn hashed_bytes(&self) -> u64 {
+        self.hashed_bytes
+    }
+
+    /// How long it took to create or update the bag
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The number of bytes that were hashed per second, over the time it took to create or
+    /// update the bag
+    pub fn throughput(&self) -> f64 {
+        self.hashed_bytes as f64 / self.elapsed.as_secs_f64().max(0.001)
+    }
+
+    /// The total time that was spent calculating the digests of each algorithm. Files are hashed
+    /// with every algorithm in a single pass, and concurrently, so the times may add up to more
+    /// than the elapsed time.
+    pub fn digest_times(&self) -> &BTreeMap<DigestAlgorithm, Duration> {
+        &self.digest_times
+    }
+}
+
+impl Display for BagStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Payload: {} files, {}",
+            self.payload_files,
+            format_bytes(self.payload_bytes as f64)
+        )?;
+        writeln!(f, "Elapsed: {:.2}s", self.elapsed.as_secs_f64())?;
+        write!(
+            f,
+            "Hashed: {} files, {}, {}/s",
+            self.hashed_files,
+            format_bytes(self.hashed_bytes as f64),
+            format_bytes(self.throughput())
+        )?;
+        for (algorithm, time) in &self.digest_times {
+            write!(f, "\n{algorithm}: {:.2}s", time.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+impl StatsListener {
+    /// Creates a listener that starts timing immediately and passes updates on to `inner`
+    pub fn new(inner: Arc<dyn ProgressListener>) -> Self {
+        Self {
+            inner,
+            started: Instant::now(),
+            hashed_files: AtomicU64::new(0),
+            hashed_bytes: AtomicU64::new(0),
+            digest_times: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the statistics that were recorded so far for a bag with the `payload_oxum`
+    pub fn stats(&self, payload_oxum: Option<PayloadOxum>) -> BagStats {
+        let oxum = payload_oxum.unwrap_or(PayloadOxum { bytes: 0, files: 0 });
+        BagStats {
+            payload_files: oxum.files,
+            payload_bytes: oxum.bytes,
+            hashed_files: self.hashed_files.load(Ordering::Relaxed),
+            hashed_bytes: self.hashed_bytes.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+            digest_times: self
+                .digest_times
+                .lock()
+                .unwrap()
+                .clone()
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl ProgressListener for StatsListener {
+    fn hashing_started(&self, total_files: u64, total_bytes: u64) {
+        self.inner.hashing_started(total_files, total_bytes);
+    }
+
+    fn file_started(&self, path: &Path) {
+        self.inner.file_started(path);
+    }
+
+    fn bytes_hashed(&self, path: &Path, bytes: u64) {
+        self.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.bytes_hashed(path, bytes);
+    }
+
+    fn file_finished(&self, path: &Path) {
+        self.hashed_files.fetch_add(1, Ordering::Relaxed);
+        self.inner.file_finished(path);
+    }
+
+    fn digests_timed(&self, path: &Path, times: &HashMap<DigestAlgorithm, Duration>) {
+        {
+            let mut digest_times = self.digest_times.lock().unwrap();
+            for (algorithm, time) in times {
+                *digest_times.entry(*algorithm).or_default() += *time;
+            }
+        }
+        self.inner.digests_timed(path, times);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::stats::BagStats;
+
+    #[test]
+    fn stats_display_totals_and_digest_times() {
+        let stats = BagStats {
+            payload_files: 40,
+            payload_bytes: 6 * 1024 * 1024 * 1024,
+            hashed_files: 10,
+            hashed_bytes: 1024 * 1024 * 1024,
+            elapsed: Duration::from_secs(8),
+            digest_times: BTreeMap::from([
+                (DigestAlgorithm::Sha256, Duration::from_millis(5250)),
+                (DigestAlgorithm::Sha512, Duration::from_millis(3500)),
+            ]),
+        };
+
+        assert_eq!(
+            "Payload: 40 files, 6.0 GiB\n\
+            Elapsed: 8.00s\n\
+            Hashed: 10 files, 1.0 GiB, 128.0 MiB/s\n\
+            sha256: 5.25s\n\
+            sha512: 3.50s",
+            stats.to_string()
+        );
+    }
+}
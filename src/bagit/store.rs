@@ -0,0 +1,293 @@
+use std::fmt::Debug;
+use std::io::Read;
+use std::time::Duration;
+
+use log::info;
+use snafu::ResultExt;
+
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::s3::{S3Client, S3Credentials};
+use crate::bagit::upload::{DEFAULT_PART_SIZE, MIN_PART_SIZE};
+
+/// Storage where every file is an object that's addressed by a key, such as an S3 bucket. Bags
+/// can be created in, and validated in, an object store with `BagCreator::create_in_store()` and
+/// `validate_store_bag()`. Keys use `/` as the separator.
+pub trait ObjectStore: Debug + Send + Sync {
+    /// Writes the `size` bytes that are read from `contents` to the object `key`, replacing the
+    /// object if it exists
+    fn put(&self, key: &str, contents: &mut dyn Read, size: u64) -> Result<()>;
+
+    /// Opens the object `key` for reading
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>>;
+
+    /// Lists the objects whose keys start with `prefix`, ordered by key
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>>;
+
+    /// A URL that identifies the object `key`, eg. `s3://bucket/key`, which is used in messages
+    fn url(&self, key: &str) -> String;
+}
+
+/// An object that was listed in an `ObjectStore`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StoredObject {
+    key: String,
+    size: u64,
+}
+
+/// An `ObjectStore` that's a bucket in S3, or an S3 compatible object store. Objects that are
+/// larger than the part size are written using multipart uploads. Every request is sent with a
+/// SHA-256 checksum of its contents that S3 verifies, and requests that fail are retried.
+#[derive(Debug, Clone)]
+pub struct S3Store {
+    client: S3Client,
+    part_size: u64,
+}
+
+impl StoredObject {
+    pub fn new<K: Into<String>>(key: K, size: u64) -> Self {
+        Self {
+            key: key.into(),
+            size,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl S3Store {
+    /// Stores objects in `bucket`. By default, the bucket is in us-east-1, and objects that are
+    /// larger than 64 MiB are uploaded in parts.
+    pub fn new<B: Into<String>>(bucket: B, credentials: S3Credentials) -> Self {
+        Self {
+            client: S3Client::new(bucket, credentials),
+            part_size: DEFAULT_PART_SIZE,
+        }
+    }
+
+    /// Sets the region the bucket is in
+    pub fn with_region<S: Into<String>>(mut self, region: S) -> Self {
+        self.client.set_region(region.into());
+        self
+    }
+
+    /// Sets the base URL of an S3 compatible object store, eg. `http://localhost:9000`. Objects
+    /// are addressed using path-style URLs when an endpoint is set.
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> Self {
+        self.client.set_endpoint(endpoint);
+        self
+    }
+
+    /// Sets the size of the parts that large objects are uploaded in. It must be at least 5 MiB.
+    pub fn with_part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Sets the number of times a failed request is retried. The default is 5.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.client.set_max_retries(max_retries);
+        self
+    }
+
+    /// Sets the delay before the first retry of a failed request. The delay doubles after every
+    /// attempt, up to a minute. The default is 1 second.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.client.set_retry_delay(retry_delay);
+        self
+    }
+
+    fn put_parts(&self, key: &str, contents: &mut dyn Read, size: u64) -> Result<()> {
+        if self.part_size < MIN_PART_SIZE {
+            return Err(General {
+                message: format!("The part size must be at least {MIN_PART_SIZE} bytes"),
+            });
+        }
+
+        let error = |details| StoreRequest {
+            url: self.url(key),
+            details,
+        };
+        let upload_id = self.client.create_multipart_upload(key).map_err(error)?;
+        let mut parts = Vec::new();
+        let mut buffer = Vec::with_capacity(self.part_size as usize);
+
+        for number in 1..=size.div_ceil(self.part_size) {
+            buffer.clear();
+            contents
+                .take(self.part_size)
+                .read_to_end(&mut buffer)
+                .context(IoGeneralSnafu {})?;
+            parts.push(
+                self.client
+                    .upload_part(key, &upload_id, number, &buffer)
+                    .map_err(error)?,
+            );
+        }
+
+        self.client
+            .complete_multipart_upload(key, &upload_id, &parts)
+            .map_err(error)
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn put(&self, key: &str, contents: &mut dyn Read, size: u64) -> Result<()> {
+        info!("Writing {}", self.url(key));
+
+        if size > self.part_size {
+            return self.put_parts(key, contents, size);
+        }
+
+        let mut buffer = Vec::with_capacity(size as usize);
+        contents
+            .read_to_end(&mut buffer)
+            .context(IoGeneralSnafu {})?;
+        self.client
+            .put_object(key, &buffer)
+            .map_err(|details| StoreRequest {
+                url: self.url(key),
+                details,
+            })
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>> {
+        let response = self
+            .client
+            .get_object(key)
+            .map_err(|details| StoreRequest {
+                url: self.url(key),
+                details,
+            })?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let objects = self
+            .client
+            .list_objects(prefix)
+            .map_err(|details| StoreRequest {
+                url: self.url(prefix),
+                details,
+            })?;
+        Ok(objects
+            .into_iter()
+            .map(|object| StoredObject::new(object.key, object.size))
+            .collect())
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{key}", self.client.bucket())
+    }
+}
+
+/// Joins a key `prefix`, which may be empty, and a `path` within it
+pub fn store_key(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        path.to_string()
+    } else {
+        format!("{prefix}/{path}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    use crate::bagit::bag::BagCreator;
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::error::Result;
+    use crate::bagit::store::{ObjectStore, StoredObject};
+    use crate::bagit::tag::PayloadOxum;
+    use crate::bagit::validate::{validate_store_bag, ValidationOptions};
+
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    }
+
+    impl ObjectStore for MemoryStore {
+        fn put(&self, key: &str, contents: &mut dyn Read, _size: u64) -> Result<()> {
+            let mut buffer = Vec::new();
+            contents.read_to_end(&mut buffer).unwrap();
+            self.objects.lock().unwrap().insert(key.to_string(), buffer);
+            Ok(())
+        }
+
+        fn get(&self, key: &str) -> Result<Box<dyn Read + Send>> {
+            let contents = self.objects.lock().unwrap()[key].clone();
+            Ok(Box::new(std::io::Cursor::new(contents)))
+        }
+
+        fn list(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+            Ok(self
+                .objects
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, contents)| StoredObject::new(key, contents.len() as u64))
+                .collect())
+        }
+
+        fn url(&self, key: &str) -> String {
+            format!("mem://{key}")
+        }
+    }
+
+    #[test]
+    fn create_and_validate_bag_in_store() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "testing\n").unwrap();
+        fs::write(dir.join("sub/b.txt"), "more testing\n").unwrap();
+
+        let store = MemoryStore::default();
+        let created = BagCreator::new(dir, dir)
+            .with_algorithm(DigestAlgorithm::Sha256)
+            .create_in_store(&store, "bags/one/");
+        let again = BagCreator::new(dir, dir).create_in_store(&store, "bags/one");
+
+        let bag_info = created.unwrap();
+        assert_eq!(Some(PayloadOxum::new(21, 2)), bag_info.payload_oxum());
+        assert!(again.is_err());
+        assert_eq!(
+            vec![
+                "bags/one/bag-info.txt",
+                "bags/one/bagit.txt",
+                "bags/one/data/a.txt",
+                "bags/one/data/sub/b.txt",
+                "bags/one/manifest-sha256.txt",
+                "bags/one/tagmanifest-sha256.txt",
+            ],
+            store.objects.lock().unwrap().keys().collect::<Vec<_>>()
+        );
+
+        let options = ValidationOptions::new();
+        assert!(validate_store_bag(&store, "bags/one", &options)
+            .unwrap()
+            .is_valid());
+
+        store
+            .objects
+            .lock()
+            .unwrap()
+            .insert("bags/one/data/a.txt".to_string(), b"changed\n".to_vec());
+        let result = validate_store_bag(&store, "bags/one", &options).unwrap();
+        assert!(!result.is_valid());
+        assert!(result
+            .errors()
+            .any(|issue| issue.message().contains("data/a.txt")));
+    }
+}
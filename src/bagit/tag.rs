@@ -1,9 +1,15 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::collections::hash_map::RandomState;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::hash::BuildHasher;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
 use std::slice::Iter;
 use std::vec::IntoIter;
 
+use chrono::{Local, NaiveDate};
+use encoding_rs::{Encoding, REPLACEMENT, UTF_8 as UTF_8_ENCODING};
+use encoding_rs_io::DecodeReaderBytesBuilder;
 use log::{debug, info};
 use snafu::ResultExt;
 
@@ -19,18 +25,64 @@ pub struct BagDeclaration {
     encoding: String,
 }
 
-#[derive(Debug)]
+/// The total size and number of the files in a bag's payload, as recorded in the Payload-Oxum
+/// tag in the form `OCTETCOUNT.STREAMCOUNT`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PayloadOxum {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct BagInfo {
     tags: TagList,
 }
 
-#[derive(Debug)]
+/// Builds a `BagInfo` from typed values, which are validated when `build()` is called
+#[derive(Debug, Default)]
+pub struct BagInfoBuilder {
+    bagging_date: Option<NaiveDate>,
+    software_agent: Option<String>,
+    bag_group_identifier: Option<String>,
+    bag_count: Option<(u64, Option<u64>)>,
+    bag_size: Option<String>,
+    source_organizations: Vec<String>,
+    organization_addresses: Vec<String>,
+    contacts: Vec<Contact>,
+    external_descriptions: Vec<String>,
+    external_identifiers: Vec<String>,
+    internal_sender_descriptions: Vec<String>,
+    internal_sender_identifiers: Vec<String>,
+    bagit_profile_identifiers: Vec<String>,
+    tags: Vec<(String, String)>,
+    expand_placeholders: bool,
+}
+
+/// A person to contact about a bag. At least one of the name, phone, or email must be set.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Contact {
+    name: Option<String>,
+    phone: Option<String>,
+    email: Option<String>,
+}
+
+/// Reads one of the fields of a `Contact`
+type ContactField = fn(&Contact) -> Option<&str>;
+
+/// The contact tags, and the fields of `Contact` they hold
+const CONTACT_FIELDS: [(&str, ContactField); 3] = [
+    (LABEL_CONTACT_NAME, Contact::name),
+    (LABEL_CONTACT_PHONE, Contact::phone),
+    (LABEL_CONTACT_EMAIL, Contact::email),
+];
+
+#[derive(Debug, Clone)]
 pub struct Tag {
     label: String,
     value: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TagList {
     tags: Vec<Tag>,
 }
@@ -43,12 +95,51 @@ pub fn write_bag_declaration<P: AsRef<Path>>(
     write_tag_file(
         &bag_declaration.to_tags(),
         base_dir.as_ref().join(BAGIT_TXT),
+        None,
+    )
+}
+
+/// Writes bag-info.txt to the bag's base directory. Tags that are longer than `line_width` are
+/// folded onto multiple lines.
+pub fn write_bag_info<P: AsRef<Path>>(
+    bag_info: &BagInfo,
+    base_dir: P,
+    line_width: Option<usize>,
+) -> Result<()> {
+    write_tag_file(
+        bag_info.as_ref(),
+        base_dir.as_ref().join(BAG_INFO_TXT),
+        line_width,
     )
 }
 
-/// Writes bag-info.txt to the bag's base directory
-pub fn write_bag_info<P: AsRef<Path>>(bag_info: &BagInfo, base_dir: P) -> Result<()> {
-    write_tag_file(bag_info.as_ref(), base_dir.as_ref().join(BAG_INFO_TXT))
+/// Formats a number of bytes as a human readable Bag-Size using decimal units, eg. `42.6 MB`
+pub fn format_bag_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Parses a Bag-Count value in the form `N of T`, where the total may be `?` when it is not
+/// known, into the bag number and the total
+pub fn parse_bag_count(value: &str) -> Option<(u64, Option<u64>)> {
+    let (number, total) = value.split_once(" of ")?;
+    let total = match total.trim() {
+        "?" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((number.trim().parse().ok()?, total))
 }
 
 /// Reads a bag declaration out of the specified `base_dir`
@@ -58,13 +149,31 @@ pub fn read_bag_declaration<P: AsRef<Path>>(base_dir: P) -> Result<BagDeclaratio
     tags.try_into()
 }
 
-/// Reads bag info out of the specified `base_dir`
-pub fn read_bag_info<P: AsRef<Path>>(base_dir: P) -> Result<BagInfo> {
-    let bagit_file = base_dir.as_ref().join(BAG_INFO_TXT);
-    let tags = read_tag_file(&bagit_file)?;
+/// Reads bag info out of the specified `base_dir`. The file is decoded using the tag file
+/// encoding in the bag's `declaration`.
+pub fn read_bag_info<P: AsRef<Path>>(base_dir: P, declaration: &BagDeclaration) -> Result<BagInfo> {
+    let path = base_dir.as_ref().join(BAG_INFO_TXT);
+    let file = File::open(&path).context(IoReadSnafu { path: &path })?;
+    let tags = read_tags(decode_reader(file, declaration), &path)?;
     Ok(tags.into())
 }
 
+/// Wraps `reader` so that the tag file it reads is transcoded from the tag file encoding in the
+/// bag's `declaration` to UTF-8. UTF-8 tag files are read as is.
+pub fn decode_reader<'a, R: Read + 'a>(
+    reader: R,
+    declaration: &BagDeclaration,
+) -> Box<dyn Read + 'a> {
+    match tag_file_encoding(&declaration.encoding) {
+        Some(encoding) if encoding != UTF_8_ENCODING => Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .build(reader),
+        ),
+        _ => Box::new(reader),
+    }
+}
+
 impl BagDeclaration {
     pub fn new() -> Self {
         Self {
@@ -80,7 +189,7 @@ impl BagDeclaration {
             return Err(UnsupportedVersion { version });
         }
 
-        if UTF_8 != encoding {
+        if tag_file_encoding(encoding).is_none() {
             return Err(UnsupportedEncoding {
                 encoding: encoding.into(),
             });
@@ -92,6 +201,21 @@ impl BagDeclaration {
         })
     }
 
+    pub fn version(&self) -> BagItVersion {
+        self.version
+    }
+
+    /// The declared tag file character encoding
+    pub fn encoding(&self) -> &str {
+        &self.encoding
+    }
+
+    /// True if the tag files are UTF-8 encoded. Other encodings, such as ISO-8859-1 or UTF-16,
+    /// are supported for reading only.
+    pub fn is_utf8(&self) -> bool {
+        tag_file_encoding(&self.encoding) == Some(UTF_8_ENCODING)
+    }
+
     pub fn to_tags(&self) -> TagList {
         let mut tags = TagList::with_capacity(2);
         // Safe to unwrap because it's not possible to create this object with invalid values
@@ -150,13 +274,7 @@ impl BagInfo {
     pub fn add_tag<L: AsRef<str>, S: AsRef<str>>(&mut self, label: L, value: S) -> Result<()> {
         let label = label.as_ref();
 
-        let repeatable = LABEL_REPEATABLE
-            .iter()
-            .find(|(reserved_label, _)| reserved_label.eq_ignore_ascii_case(label))
-            .map(|(_, repeatable)| *repeatable)
-            .unwrap_or(true);
-
-        if repeatable {
+        if is_repeatable(label) {
             self.add_repeatable(label, value)
         } else {
             self.add_non_repeatable(label, value)
@@ -174,6 +292,11 @@ impl BagInfo {
         self.tags.get_tags(label.as_ref())
     }
 
+    /// Removes all of the tags that match the specified label. Labels are case insensitive.
+    pub fn remove_tags<L: AsRef<str>>(&mut self, label: L) {
+        self.tags.remove_tags(label)
+    }
+
     pub fn add_bagging_date<S: AsRef<str>>(&mut self, value: S) -> Result<()> {
         self.add_non_repeatable(LABEL_BAGGING_DATE, value)
     }
@@ -182,12 +305,15 @@ impl BagInfo {
         self.get_tag(LABEL_BAGGING_DATE)
     }
 
-    pub fn add_payload_oxum<S: AsRef<str>>(&mut self, value: S) -> Result<()> {
-        self.add_non_repeatable(LABEL_PAYLOAD_OXUM, value)
+    pub fn add_payload_oxum(&mut self, value: PayloadOxum) -> Result<()> {
+        self.add_non_repeatable(LABEL_PAYLOAD_OXUM, value.to_string())
     }
 
-    pub fn payload_oxum(&self) -> Option<&Tag> {
+    /// Returns the parsed Payload-Oxum, or `None` if it's missing or invalid. Use
+    /// `get_tag("Payload-Oxum")` to get the raw value.
+    pub fn payload_oxum(&self) -> Option<PayloadOxum> {
         self.get_tag(LABEL_PAYLOAD_OXUM)
+            .and_then(|tag| PayloadOxum::try_from(tag.value()).ok())
     }
 
     pub fn add_software_agent<S: AsRef<str>>(&mut self, value: S) -> Result<()> {
@@ -238,6 +364,39 @@ impl BagInfo {
         self.get_tags(LABEL_CONTACT_EMAIL)
     }
 
+    /// Groups the Contact-Name, Contact-Phone, and Contact-Email tags into contacts. Repeated
+    /// tags are matched by position, so the first contact has the first value of each label.
+    pub fn contacts(&self) -> Vec<Contact> {
+        let names = self.contact_name().collect::<Vec<&Tag>>();
+        let phones = self.contact_phone().collect::<Vec<&Tag>>();
+        let emails = self.contact_email().collect::<Vec<&Tag>>();
+        let value = |tags: &[&Tag], i: usize| tags.get(i).map(|tag| tag.value().to_string());
+
+        (0..names.len().max(phones.len()).max(emails.len()))
+            .map(|i| Contact {
+                name: value(&names, i),
+                phone: value(&phones, i),
+                email: value(&emails, i),
+            })
+            .collect()
+    }
+
+    /// Adds the contact's Contact-Name, Contact-Phone, and Contact-Email tags. It is an error if
+    /// the contact has a field that an earlier contact does not, because the tags would not be
+    /// matched to the right contact when they're read.
+    pub fn add_contact(&mut self, contact: &Contact) -> Result<()> {
+        let position = self.contacts().len();
+        let mut counts = CONTACT_FIELDS.map(|(label, _)| self.get_tags(label).count());
+        check_contact(contact, position, &mut counts)?;
+
+        for (label, field) in CONTACT_FIELDS {
+            if let Some(value) = field(contact) {
+                self.add_repeatable(label, value)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn add_external_description<S: AsRef<str>>(&mut self, value: S) -> Result<()> {
         self.add_repeatable(LABEL_EXTERNAL_DESCRIPTION, value)
     }
@@ -302,6 +461,11 @@ impl BagInfo {
         self.get_tags(LABEL_BAGIT_PROFILE_IDENTIFIER)
     }
 
+    /// Returns a builder for creating a `BagInfo` from typed values
+    pub fn builder() -> BagInfoBuilder {
+        BagInfoBuilder::new()
+    }
+
     /// Adds a new tag by first removing all existing tags with the same label.
     fn add_non_repeatable<L: AsRef<str>, S: AsRef<str>>(
         &mut self,
@@ -320,6 +484,316 @@ impl BagInfo {
     }
 }
 
+impl PayloadOxum {
+    pub const fn new(bytes: u64, files: u64) -> Self {
+        Self { bytes, files }
+    }
+
+    /// Adds a file that is `size` bytes to the payload
+    pub fn add_file(&mut self, size: u64) {
+        self.bytes += size;
+        self.files += 1;
+    }
+
+    /// Removes a file that is `size` bytes from the payload. Neither count goes below zero.
+    pub fn remove_file(&mut self, size: u64) {
+        self.bytes = self.bytes.saturating_sub(size);
+        self.files = self.files.saturating_sub(1);
+    }
+}
+
+impl Display for PayloadOxum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.bytes, self.files)
+    }
+}
+
+impl TryFrom<&str> for PayloadOxum {
+    type Error = Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        value
+            .split_once('.')
+            .and_then(|(bytes, files)| Some(Self::new(bytes.parse().ok()?, files.parse().ok()?)))
+            .ok_or_else(|| InvalidTag {
+                label: LABEL_PAYLOAD_OXUM.into(),
+                details: format!("'{value}' must be in the form OCTETCOUNT.STREAMCOUNT"),
+            })
+    }
+}
+
+impl BagInfoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_bagging_date(mut self, date: NaiveDate) -> Self {
+        self.bagging_date = Some(date);
+        self
+    }
+
+    pub fn with_software_agent<S: Into<String>>(mut self, agent: S) -> Self {
+        self.software_agent = Some(agent.into());
+        self
+    }
+
+    pub fn with_bag_group_identifier<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.bag_group_identifier = Some(identifier.into());
+        self
+    }
+
+    /// Sets the bag's position in its group, starting at 1, and the total number of bags in the
+    /// group, if it's known
+    pub fn with_bag_count(mut self, number: u64, total: Option<u64>) -> Self {
+        self.bag_count = Some((number, total));
+        self
+    }
+
+    /// Sets the approximate size of the bag, eg. `260 GB`
+    pub fn with_bag_size<S: Into<String>>(mut self, size: S) -> Self {
+        self.bag_size = Some(size.into());
+        self
+    }
+
+    pub fn with_source_organization<S: Into<String>>(mut self, organization: S) -> Self {
+        self.source_organizations.push(organization.into());
+        self
+    }
+
+    pub fn with_organization_address<S: Into<String>>(mut self, address: S) -> Self {
+        self.organization_addresses.push(address.into());
+        self
+    }
+
+    /// Adds a contact. Contacts are written as Contact-Name, Contact-Phone, and Contact-Email
+    /// tags, grouped by label, and are matched by position when they're read. It is an error if a
+    /// contact has a field that an earlier contact does not.
+    pub fn with_contact(mut self, contact: Contact) -> Self {
+        self.contacts.push(contact);
+        self
+    }
+
+    pub fn with_external_description<S: Into<String>>(mut self, description: S) -> Self {
+        self.external_descriptions.push(description.into());
+        self
+    }
+
+    pub fn with_external_identifier<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.external_identifiers.push(identifier.into());
+        self
+    }
+
+    pub fn with_internal_sender_description<S: Into<String>>(mut self, description: S) -> Self {
+        self.internal_sender_descriptions.push(description.into());
+        self
+    }
+
+    pub fn with_internal_sender_identifier<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.internal_sender_identifiers.push(identifier.into());
+        self
+    }
+
+    pub fn with_bagit_profile_identifier<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.bagit_profile_identifiers.push(identifier.into());
+        self
+    }
+
+    /// Adds a custom tag. Custom tags are written after all of the reserved tags.
+    pub fn with_tag<L: Into<String>, V: Into<String>>(mut self, label: L, value: V) -> Self {
+        self.tags.push((label.into(), value.into()));
+        self
+    }
+
+    /// Adds every tag in a template file. TOML (`.toml`) and YAML (`.yaml` or `.yml`) templates
+    /// map labels to values, and a label whose value is an array is added once for each of its
+    /// values. Other templates have the same format as bag-info.txt, where labels may repeat, and
+    /// must be encoded in UTF-8. The tags are added in order as custom tags, so reserved tags that
+    /// may only be used once must not also be set on the builder.
+    pub fn with_template_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("toml") => self.tags.extend(read_toml_template(path)?),
+            Some("yaml") | Some("yml") => self.tags.extend(read_yaml_template(path)?),
+            _ => {
+                for tag in read_tag_file(path)? {
+                    self.tags
+                        .push((tag.label().to_string(), tag.value().to_string()));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Expands placeholders in the values of custom tags and template tags when the `BagInfo` is
+    /// built. The placeholders are `{date}`, the Bagging-Date or the current date, `{uuid}`, a new
+    /// random UUID for each occurrence, `{hostname}`, and `{user}`. Other text in braces is kept
+    /// as is. Disabled by default.
+    pub fn expand_placeholders(mut self, expand_placeholders: bool) -> Self {
+        self.expand_placeholders = expand_placeholders;
+        self
+    }
+
+    /// Validates the values and creates the `BagInfo`. It is an error if any value is invalid,
+    /// or a tag that may only be used once is set more than once.
+    pub fn build(self) -> Result<BagInfo> {
+        let mut tags = TagList::new();
+
+        if let Some(date) = self.bagging_date {
+            tags.add_tag(LABEL_BAGGING_DATE, date.format("%Y-%m-%d").to_string())?;
+        }
+        if let Some(agent) = self.software_agent {
+            tags.add_tag(LABEL_SOFTWARE_AGENT, agent)?;
+        }
+        if let Some(identifier) = self.bag_group_identifier {
+            tags.add_tag(LABEL_BAG_GROUP_IDENTIFIER, identifier)?;
+        }
+        if let Some((number, total)) = self.bag_count {
+            tags.add_tag(LABEL_BAG_COUNT, format_bag_count(number, total)?)?;
+        }
+        if let Some(size) = self.bag_size {
+            tags.add_tag(LABEL_BAG_SIZE, size)?;
+        }
+
+        add_all(
+            &mut tags,
+            LABEL_SOURCE_ORGANIZATION,
+            self.source_organizations,
+        )?;
+        add_all(
+            &mut tags,
+            LABEL_ORGANIZATION_ADDRESS,
+            self.organization_addresses,
+        )?;
+
+        let mut counts = [0; 3];
+        for (position, contact) in self.contacts.iter().enumerate() {
+            check_contact(contact, position, &mut counts)?;
+        }
+        for (label, field) in CONTACT_FIELDS {
+            for value in self.contacts.iter().filter_map(field) {
+                tags.add_tag(label, value)?;
+            }
+        }
+
+        add_all(
+            &mut tags,
+            LABEL_EXTERNAL_DESCRIPTION,
+            self.external_descriptions,
+        )?;
+        add_all(
+            &mut tags,
+            LABEL_EXTERNAL_IDENTIFIER,
+            self.external_identifiers,
+        )?;
+        add_all(
+            &mut tags,
+            LABEL_INTERNAL_SENDER_DESCRIPTION,
+            self.internal_sender_descriptions,
+        )?;
+        add_all(
+            &mut tags,
+            LABEL_INTERNAL_SENDER_IDENTIFIER,
+            self.internal_sender_identifiers,
+        )?;
+        add_all(
+            &mut tags,
+            LABEL_BAGIT_PROFILE_IDENTIFIER,
+            self.bagit_profile_identifiers,
+        )?;
+
+        let date = self
+            .bagging_date
+            .unwrap_or_else(|| Local::now().date_naive());
+        for (label, value) in self.tags {
+            if !is_repeatable(&label) && tags.get_tag(&label).is_some() {
+                return Err(InvalidTag {
+                    label,
+                    details: "Tag may only be set once".into(),
+                });
+            }
+            let value = if self.expand_placeholders {
+                expand_placeholders(&value, date).map_err(|details| InvalidTag {
+                    label: label.clone(),
+                    details,
+                })?
+            } else {
+                value
+            };
+            tags.add_tag(label, value)?;
+        }
+
+        Ok(BagInfo::with_tags(tags))
+    }
+}
+
+/// Checks that the contact at `position` has at least one field, and that each of its fields would
+/// be at the same position as the contact. `counts` is the number of values of each field that
+/// precede the contact, and is updated with its fields.
+fn check_contact(contact: &Contact, position: usize, counts: &mut [usize; 3]) -> Result<()> {
+    if contact.name.is_none() && contact.phone.is_none() && contact.email.is_none() {
+        return Err(InvalidTag {
+            label: LABEL_CONTACT_NAME.into(),
+            details: "A contact must have a name, phone, or email".into(),
+        });
+    }
+
+    for (count, (label, field)) in counts.iter_mut().zip(CONTACT_FIELDS) {
+        if field(contact).is_some() {
+            if *count != position {
+                return Err(InvalidTag {
+                    label: label.into(),
+                    details: format!(
+                        "Contact {} has a {label}, but an earlier contact does not. Contact \
+                        tags are matched by position, so contacts with fewer fields must be last",
+                        position + 1
+                    ),
+                });
+            }
+            *count += 1;
+        }
+    }
+
+    Ok(())
+}
+
+impl Contact {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_phone<S: Into<String>>(mut self, phone: S) -> Self {
+        self.phone = Some(phone.into());
+        self
+    }
+
+    pub fn with_email<S: Into<String>>(mut self, email: S) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn phone(&self) -> Option<&str> {
+        self.phone.as_deref()
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+}
+
 impl Default for BagInfo {
     fn default() -> Self {
         Self::new()
@@ -359,6 +833,14 @@ impl Tag {
         })
     }
 
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
     fn validate_label(label: &str) -> Result<()> {
         if label.starts_with(is_space_or_tab) || label.ends_with(is_space_or_tab) {
             return Err(InvalidTag {
@@ -457,7 +939,13 @@ impl<'a> IntoIterator for &'a TagList {
 }
 
 /// Writes a tag file to the specified destination
-fn write_tag_file<P: AsRef<Path>>(tags: &TagList, destination: P) -> Result<()> {
+/// Writes the tags to a tag file at `destination`. Tags that are longer than `line_width` are
+/// folded onto multiple lines.
+pub fn write_tag_file<P: AsRef<Path>>(
+    tags: &TagList,
+    destination: P,
+    line_width: Option<usize>,
+) -> Result<()> {
     let destination = destination.as_ref();
     info!("Writing tag file {}", destination.display());
 
@@ -465,26 +953,157 @@ fn write_tag_file<P: AsRef<Path>>(tags: &TagList, destination: P) -> Result<()>
         BufWriter::new(File::create(destination).context(IoCreateSnafu { path: destination })?);
 
     for tag in tags {
-        // TODO handle multi-line tags
-        writeln!(writer, "{}: {}", tag.label, tag.value)
-            .context(IoWriteSnafu { path: destination })?;
+        let line = format!("{}: {}", tag.label, tag.value);
+        let line = match line_width {
+            Some(width) => fold_tag_line(&line, width),
+            None => line,
+        };
+        writeln!(writer, "{}", line).context(IoWriteSnafu { path: destination })?;
     }
 
     Ok(())
 }
 
+/// Folds a tag line that's longer than `width` characters onto multiple lines, if possible.
+/// Lines are only broken at single spaces in the value that are not adjacent to other
+/// whitespace, which are replaced with a line break and indentation. This way, `TagLineReader`
+/// reads the folded lines back as the original line. Words that are longer than `width` are not
+/// broken.
+fn fold_tag_line(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+
+    // The separator between the label and value is never folded
+    let value_start = line.find(':').map(|i| i + 2).unwrap_or(line.len());
+    let bytes = line.as_bytes();
+    let is_ws = |i: usize| bytes[i] == SPACE as u8 || bytes[i] == TAB as u8;
+
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, _) in line.match_indices(SPACE) {
+        if i > value_start && i + 1 < bytes.len() && !is_ws(i - 1) && !is_ws(i + 1) {
+            words.push(&line[start..i]);
+            start = i + 1;
+        }
+    }
+    words.push(&line[start..]);
+
+    let mut words = words.into_iter();
+    let mut folded = words.next().unwrap_or_default().to_string();
+    let mut length = folded.chars().count();
+
+    for word in words {
+        let word_length = word.chars().count();
+        if length + 1 + word_length <= width {
+            folded.push(SPACE);
+            length += 1 + word_length;
+        } else {
+            folded.push(LF);
+            folded.push_str(TAG_LINE_INDENT);
+            length = TAG_LINE_INDENT.len() + word_length;
+        }
+        folded.push_str(word);
+    }
+
+    folded
+}
+
+/// Reads the labels and values in a TOML bag-info template, in the order they appear
+fn read_toml_template(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path).context(IoReadSnafu { path })?;
+    let table = contents
+        .parse::<toml::Table>()
+        .map_err(|e| InvalidTemplate {
+            path: path.into(),
+            details: e
+                .message()
+                .lines()
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(", "),
+        })?;
+
+    let mut tags = Vec::new();
+    for (label, value) in table {
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                toml::Value::Datetime(value) => value.to_string(),
+                _ => return Err(template_value_error(path, &label)),
+            };
+            tags.push((label.clone(), value));
+        }
+    }
+    Ok(tags)
+}
+
+/// Reads the labels and values in a YAML bag-info template, in the order they appear
+fn read_yaml_template(path: &Path) -> Result<Vec<(String, String)>> {
+    let file = File::open(path).context(IoReadSnafu { path })?;
+    let mapping: serde_yaml::Mapping =
+        serde_yaml::from_reader(BufReader::new(file)).map_err(|e| InvalidTemplate {
+            path: path.into(),
+            details: e.to_string(),
+        })?;
+
+    let mut tags = Vec::new();
+    for (label, value) in mapping {
+        let label = yaml_scalar(label).ok_or_else(|| InvalidTemplate {
+            path: path.into(),
+            details: "Labels must be strings".to_string(),
+        })?;
+        let values = match value {
+            serde_yaml::Value::Sequence(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = yaml_scalar(value).ok_or_else(|| template_value_error(path, &label))?;
+            tags.push((label.clone(), value));
+        }
+    }
+    Ok(tags)
+}
+
+/// Converts a YAML string, number, or boolean to a string
+fn yaml_scalar(value: serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(value) => Some(value),
+        serde_yaml::Value::Number(value) => Some(value.to_string()),
+        serde_yaml::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn template_value_error(path: &Path, label: &str) -> Error {
+    InvalidTemplate {
+        path: path.into(),
+        details: format!(
+            "The value of {label} must be a string, number, boolean, or a list of them"
+        ),
+    }
+}
+
 fn read_tag_file<P: AsRef<Path>>(path: P) -> Result<TagList> {
     let path = path.as_ref();
-    let reader = TagLineReader::new(BufReader::new(
-        File::open(path).context(IoReadSnafu { path })?,
-    ));
+    read_tags(File::open(path).context(IoReadSnafu { path })?, path)
+}
+
+/// Reads all of the tags out of `reader`. The `path` is only used to identify the source of the
+/// tags in errors.
+pub fn read_tags<R: Read>(reader: R, path: &Path) -> Result<TagList> {
+    let reader = TagLineReader::new(BufReader::new(reader));
 
     let mut tags = TagList::new();
     let mut tag_num: u32 = 0;
 
-    // TODO this only works for UTF-8
-    // https://crates.io/crates/encoding_rs
-    // https://crates.io/crates/encoding_rs_io
     // TODO how should empty lines be handled?
     for line in reader {
         let line = line?;
@@ -519,6 +1138,114 @@ fn read_tag_file<P: AsRef<Path>>(path: P) -> Result<TagList> {
     Ok(tags)
 }
 
+/// Returns the encoding to decode tag files with if `label` identifies an encoding in the WHATWG
+/// Encoding Standard, which is what encoding_rs implements. Labels are matched the same way that
+/// browsers match them, so, for example, Latin-1 is treated as Windows-1252, which is a superset
+/// of its printable characters.
+fn tag_file_encoding(label: &str) -> Option<&'static Encoding> {
+    // The replacement encoding is only used to neutralize encodings that are unsafe to decode
+    Encoding::for_label(label.trim().as_bytes()).filter(|encoding| *encoding != REPLACEMENT)
+}
+
+/// Returns true if the label is not a reserved label that may only be used once. Labels are
+/// case insensitive.
+fn is_repeatable(label: &str) -> bool {
+    LABEL_REPEATABLE
+        .iter()
+        .find(|(reserved_label, _)| reserved_label.eq_ignore_ascii_case(label))
+        .map(|(_, repeatable)| *repeatable)
+        .unwrap_or(true)
+}
+
+/// Replaces the `{date}`, `{uuid}`, `{hostname}`, and `{user}` placeholders in `value`. Returns
+/// an error message if the hostname or user is needed and can't be determined.
+fn expand_placeholders(value: &str, date: NaiveDate) -> std::result::Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[1..end] {
+            "date" => expanded.push_str(&date.format("%Y-%m-%d").to_string()),
+            "uuid" => expanded.push_str(&random_uuid()),
+            "hostname" => expanded.push_str(&hostname().ok_or("The hostname is not known")?),
+            "user" => expanded.push_str(&username().ok_or("The user is not known")?),
+            _ => expanded.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// A version 4 UUID, eg. `9b2e4c1a-7f3d-4e0b-a5c6-2d8f1e9b7a30`
+fn random_uuid() -> String {
+    // Every RandomState is randomly keyed
+    let state = RandomState::new();
+    let high = (state.hash_one(1u8) & !0xf000) | 0x4000;
+    let low = (state.hash_one(2u8) & !(0b11 << 62)) | (0b10 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn username() -> Option<String> {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// Adds a tag with the same label for every value
+fn add_all(tags: &mut TagList, label: &str, values: Vec<String>) -> Result<()> {
+    for value in values {
+        tags.add_tag(label, value)?;
+    }
+    Ok(())
+}
+
+/// Formats a Bag-Count value, eg. `1 of 3`, or `1 of ?` when the total is not known
+fn format_bag_count(number: u64, total: Option<u64>) -> Result<String> {
+    let invalid = |details: String| InvalidTag {
+        label: LABEL_BAG_COUNT.into(),
+        details,
+    };
+
+    if number == 0 {
+        return Err(invalid("Bag numbers start at 1".into()));
+    }
+
+    match total {
+        Some(total) if number > total => Err(invalid(format!(
+            "Bag number {} is greater than the total {}",
+            number, total
+        ))),
+        Some(total) => Ok(format!("{} of {}", number, total)),
+        None => Ok(format!("{} of ?", number)),
+    }
+}
+
 fn parse_tag_line<S: AsRef<str>>(line: S) -> Result<Tag> {
     let line = line.as_ref();
 
@@ -540,3 +1267,227 @@ fn parse_tag_line<S: AsRef<str>>(line: S) -> Result<Tag> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+
+    use crate::bagit::consts::BAGIT_1_0;
+    use crate::bagit::error::Error::UnsupportedEncoding;
+    use crate::bagit::io::TagLineReader;
+    use crate::bagit::tag::{
+        decode_reader, expand_placeholders, fold_tag_line, format_bag_size, parse_bag_count,
+        BagDeclaration, BagInfo, Contact, PayloadOxum, TagList,
+    };
+
+    #[test]
+    fn test_build_bag_info() {
+        let bag_info = BagInfo::builder()
+            .with_bagging_date(NaiveDate::from_ymd_opt(2022, 2, 16).unwrap())
+            .with_bag_count(2, None)
+            .with_contact(
+                Contact::new()
+                    .with_name("person1")
+                    .with_email("p1@example.com"),
+            )
+            .with_contact(Contact::new().with_name("person2"))
+            .with_tag("custom", "value")
+            .build()
+            .unwrap();
+
+        let tags = TagList::from(bag_info)
+            .into_iter()
+            .map(|tag| format!("{}: {}", tag.label(), tag.value()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "Bagging-Date: 2022-02-16",
+                "Bag-Count: 2 of ?",
+                "Contact-Name: person1",
+                "Contact-Name: person2",
+                "Contact-Email: p1@example.com",
+                "custom: value",
+            ],
+            tags
+        );
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 16).unwrap();
+        assert_eq!(
+            "batch-2022-02-16 {other} {",
+            expand_placeholders("batch-{date} {other} {", date).unwrap()
+        );
+
+        let uuid = expand_placeholders("{uuid}", date).unwrap();
+        assert_eq!(36, uuid.len());
+        assert_eq!(Some('4'), uuid.chars().nth(14));
+        assert!(matches!(uuid.chars().nth(19), Some('8' | '9' | 'a' | 'b')));
+        assert_ne!(uuid, expand_placeholders("{uuid}", date).unwrap());
+    }
+
+    #[test]
+    fn test_contacts_round_trip() {
+        let contacts = vec![
+            Contact::new()
+                .with_name("person1")
+                .with_phone("555-0100")
+                .with_email("p1@example.com"),
+            Contact::new()
+                .with_name("person2")
+                .with_email("p2@example.com"),
+            Contact::new().with_name("person3"),
+        ];
+        let mut bag_info = contacts
+            .iter()
+            .fold(BagInfo::builder(), |builder, contact| {
+                builder.with_contact(contact.clone())
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(contacts, bag_info.contacts());
+
+        bag_info
+            .add_contact(&Contact::new().with_name("person4"))
+            .unwrap();
+        assert_eq!(Some("person4"), bag_info.contacts()[3].name());
+        assert!(bag_info
+            .add_contact(&Contact::new().with_email("p5@example.com"))
+            .is_err());
+        assert!(BagInfo::builder()
+            .with_contact(Contact::new().with_name("person1"))
+            .with_contact(Contact::new().with_name("person2").with_phone("555-0100"))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_fold_long_tag_lines() {
+        let line = "External-Description: A long description  with double spaces, and a \
+        verylongwordthatcannotbebrokenbecauseitislongerthanthewidthofthelines, that is folded";
+        let folded = fold_tag_line(line, 30);
+
+        assert_eq!(
+            "External-Description: A long\n  description  with double\n  spaces, and a\n  \
+            verylongwordthatcannotbebrokenbecauseitislongerthanthewidthofthelines,\n  that is \
+            folded",
+            folded
+        );
+        assert_eq!(
+            vec![line.to_string()],
+            TagLineReader::new(folded.as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        );
+        assert_eq!("Short: value", fold_tag_line("Short: value", 30));
+    }
+
+    #[test]
+    fn test_build_invalid_bag_info() {
+        assert!(BagInfo::builder()
+            .with_bag_count(3, Some(2))
+            .build()
+            .is_err());
+        assert!(BagInfo::builder().with_bag_count(0, None).build().is_err());
+        assert!(BagInfo::builder()
+            .with_contact(Contact::new())
+            .build()
+            .is_err());
+        assert!(BagInfo::builder()
+            .with_bag_size("1 GB")
+            .with_tag("bag-size", "2 GB")
+            .build()
+            .is_err());
+        assert!(BagInfo::builder()
+            .with_external_description("line\nbreak")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_bag_count() {
+        assert_eq!(Some((1, Some(3))), parse_bag_count("1 of 3"));
+        assert_eq!(Some((2, None)), parse_bag_count("2 of ?"));
+        assert_eq!(None, parse_bag_count("1 from 3"));
+        assert_eq!(None, parse_bag_count("one of 3"));
+    }
+
+    #[test]
+    fn test_payload_oxum() {
+        let mut payload_oxum = PayloadOxum::try_from("1024.3").unwrap();
+        assert_eq!(PayloadOxum::new(1024, 3), payload_oxum);
+
+        payload_oxum.add_file(10);
+        payload_oxum.remove_file(24);
+        assert_eq!("1010.3", payload_oxum.to_string());
+
+        payload_oxum.remove_file(2000);
+        assert_eq!(PayloadOxum::new(0, 2), payload_oxum);
+
+        assert!(PayloadOxum::try_from("1024").is_err());
+        assert!(PayloadOxum::try_from("1024.three").is_err());
+    }
+
+    #[test]
+    fn test_format_bag_size() {
+        assert_eq!("0 B", format_bag_size(0));
+        assert_eq!("999 B", format_bag_size(999));
+        assert_eq!("1.0 KB", format_bag_size(1000));
+        assert_eq!("42.6 MB", format_bag_size(42_600_000));
+        assert_eq!("1.5 TB", format_bag_size(1_500_000_000_000));
+    }
+    #[test]
+    fn test_decode_iso_8859_2_tag_files() {
+        let text = "Source-Organization: Żółć Łódź\n";
+        let (encoded, _, unmappable) = encoding_rs::ISO_8859_2.encode(text);
+        assert!(!unmappable);
+
+        assert_eq!(text, decode(&encoded, "ISO-8859-2"));
+    }
+
+    #[test]
+    fn test_decode_utf_16_tag_files_with_byte_order_marks() {
+        let text = "Source-Organization: Żółć Łódź\n";
+        let utf16 = text.encode_utf16().collect::<Vec<u16>>();
+        let little_endian = [0xFF, 0xFE]
+            .into_iter()
+            .chain(utf16.iter().flat_map(|unit| unit.to_le_bytes()))
+            .collect::<Vec<u8>>();
+        let big_endian = [0xFE, 0xFF]
+            .into_iter()
+            .chain(utf16.iter().flat_map(|unit| unit.to_be_bytes()))
+            .collect::<Vec<u8>>();
+
+        assert_eq!(text, decode(&little_endian, "UTF-16"));
+        assert_eq!(text, decode(&little_endian, "UTF-16LE"));
+        assert_eq!(text, decode(&big_endian, "UTF-16BE"));
+    }
+
+    #[test]
+    fn test_reject_unsupported_tag_file_encodings() {
+        for label in ["replacement", "ISO-2022-KR", "not-an-encoding", ""] {
+            assert!(
+                matches!(
+                    BagDeclaration::with_values(BAGIT_1_0, label),
+                    Err(UnsupportedEncoding { encoding }) if encoding == label
+                ),
+                "{label} should be rejected"
+            );
+        }
+        assert!(BagDeclaration::with_values(BAGIT_1_0, "ISO-8859-2").is_ok());
+    }
+
+    /// Decodes `bytes` with the tag file encoding `label`
+    fn decode(bytes: &[u8], label: &str) -> String {
+        let declaration = BagDeclaration::with_values(BAGIT_1_0, label).unwrap();
+        let mut decoded = String::new();
+        decode_reader(bytes, &declaration)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        decoded
+    }
+}
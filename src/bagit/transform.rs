@@ -0,0 +1,210 @@
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use regex::Regex;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+
+/// Characters that are not allowed in file names on common filesystems
+const UNSAFE_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// A rule that changes the name of a file or directory when it's added to a bag's payload. Rules
+/// are applied to each path segment individually.
+pub trait NameTransform: Debug + Send + Sync {
+    fn transform<'a>(&self, name: &'a str) -> Cow<'a, str>;
+}
+
+/// An ordered list of `NameTransform`s. Each transform is applied to the output of the previous
+/// one.
+#[derive(Debug, Clone, Default)]
+pub struct NameTransforms {
+    transforms: Vec<Arc<dyn NameTransform>>,
+}
+
+/// Converts names to Unicode Normalization Form C
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NormalizeUnicode;
+
+/// Replaces control characters and characters that are not allowed in file names on Windows
+#[derive(Debug, Copy, Clone)]
+pub struct SanitizeChars {
+    replacement: char,
+}
+
+/// Converts names to lowercase
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Lowercase;
+
+/// Replaces every match of a regular expression
+#[derive(Debug, Clone)]
+pub struct ReplacePattern {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl NameTransforms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a transform to the end of the list
+    pub fn with_transform(mut self, transform: Arc<dyn NameTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Applies the transforms to a single file or directory name
+    pub fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let mut name = Cow::Borrowed(name);
+        for transform in &self.transforms {
+            if let Cow::Owned(transformed) = transform.transform(&name) {
+                name = Cow::Owned(transformed);
+            }
+        }
+        name
+    }
+
+    /// Applies the transforms to every segment of the relative `path`. It is an error if a
+    /// segment is transformed into a name that is empty, `.`, `..`, or contains a path separator.
+    pub fn apply_path(&self, path: &Path) -> Result<PathBuf> {
+        if self.is_empty() {
+            return Ok(path.to_path_buf());
+        }
+
+        let mut transformed = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::Normal(segment) => {
+                    let segment = segment.to_str().ok_or_else(|| InvalidUtf8Path {
+                        path: path.to_path_buf(),
+                    })?;
+                    let name = self.apply(segment);
+                    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\'])
+                    {
+                        return Err(InvalidPayloadPath {
+                            path: path.to_path_buf(),
+                            details: format!(
+                                "'{segment}' was renamed to the invalid name '{name}'"
+                            ),
+                        });
+                    }
+                    transformed.push(name.as_ref());
+                }
+                other => transformed.push(other),
+            }
+        }
+
+        Ok(transformed)
+    }
+}
+
+impl NameTransform for NormalizeUnicode {
+    fn transform<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if is_nfc(name) {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(name.nfc().collect())
+        }
+    }
+}
+
+impl SanitizeChars {
+    /// Creates a transform that replaces unsafe characters with `replacement`
+    pub fn new(replacement: char) -> Self {
+        Self { replacement }
+    }
+}
+
+impl Default for SanitizeChars {
+    fn default() -> Self {
+        Self::new('_')
+    }
+}
+
+impl NameTransform for SanitizeChars {
+    fn transform<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let is_unsafe = |c: char| c.is_control() || UNSAFE_CHARS.contains(&c);
+        if name.contains(is_unsafe) {
+            Cow::Owned(name.replace(is_unsafe, &self.replacement.to_string()))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
+
+impl NameTransform for Lowercase {
+    fn transform<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if name.chars().any(char::is_uppercase) {
+            Cow::Owned(name.to_lowercase())
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
+
+impl ReplacePattern {
+    /// Creates a transform that replaces every match of `pattern` with `replacement`, which may
+    /// reference capture groups, eg. `$1`
+    pub fn new<S: Into<String>>(pattern: &str, replacement: S) -> Result<Self> {
+        let pattern = Regex::new(pattern).map_err(|e| General {
+            message: format!("Invalid name pattern '{pattern}': {e}"),
+        })?;
+        Ok(Self {
+            pattern,
+            replacement: replacement.into(),
+        })
+    }
+}
+
+impl NameTransform for ReplacePattern {
+    fn transform<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        self.pattern.replace_all(name, self.replacement.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use crate::bagit::transform::{
+        Lowercase, NameTransforms, NormalizeUnicode, ReplacePattern, SanitizeChars,
+    };
+
+    #[test]
+    fn test_transforms_applied_in_order() {
+        let transforms = NameTransforms::new()
+            .with_transform(Arc::new(NormalizeUnicode))
+            .with_transform(Arc::new(SanitizeChars::default()))
+            .with_transform(Arc::new(ReplacePattern::new(r"\s+", "-").unwrap()))
+            .with_transform(Arc::new(Lowercase));
+
+        assert_eq!(
+            Path::new("my-docs/caf\u{e9}-re_port_1.txt"),
+            transforms
+                .apply_path(Path::new("My Docs/Cafe\u{301} Re:port?1.txt"))
+                .unwrap()
+                .as_path()
+        );
+        assert_eq!(
+            "caf\u{e9}.txt",
+            transforms.apply("Cafe\u{301}.txt").as_ref()
+        );
+    }
+
+    #[test]
+    fn test_invalid_transformed_names() {
+        let transforms =
+            NameTransforms::new().with_transform(Arc::new(ReplacePattern::new(".*", "").unwrap()));
+        assert!(transforms.apply_path(Path::new("dir/file.txt")).is_err());
+    }
+}
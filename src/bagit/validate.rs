@@ -0,0 +1,3224 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::info;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use regex::Regex;
+use snafu::ResultExt;
+use strum::{IntoEnumIterator, VariantNames};
+use strum_macros::{Display as EnumDisplay, EnumString, EnumVariantNames, IntoStaticStr};
+use unicode_normalization::UnicodeNormalization;
+use walkdir::WalkDir;
+
+#[cfg(feature = "tokio")]
+use crate::bagit::asynchronous::{concurrency, hash_file_async, run_blocking, run_concurrently};
+use crate::bagit::bag::{has_tag_manifests, thread_pool};
+use crate::bagit::cache::DigestCache;
+use crate::bagit::consts::*;
+use crate::bagit::digest::{DigestAlgorithm, HexDigest, MultiDigestWriter, WeakAlgorithmPolicy};
+use crate::bagit::encoding::{is_strict_encoded, PathEncoding};
+use crate::bagit::error::Error::*;
+use crate::bagit::error::*;
+use crate::bagit::inspect::inspect_file;
+use crate::bagit::io::{LineReader, TeeReader};
+use crate::bagit::journal::Journal;
+use crate::bagit::manifest::split_manifest_line;
+use crate::bagit::prefetch::Prefetcher;
+use crate::bagit::progress::{NoProgress, ProgressListener, ProgressReader};
+use crate::bagit::serialized::{find_bag_root, normalize_path, SerializedBag};
+use crate::bagit::store::{store_key, ObjectStore};
+use crate::bagit::tag::{
+    decode_reader, parse_bag_count, read_bag_declaration, read_bag_info, read_tags, BagDeclaration,
+    BagInfo, PayloadOxum,
+};
+
+/// The severity of a `ValidationIssue`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, EnumDisplay)]
+pub enum IssueLevel {
+    #[strum(serialize = "WARN")]
+    Warning,
+    #[strum(serialize = "ERROR")]
+    Error,
+}
+
+/// How issues with a specific `IssueCode` are reported, overriding their default level
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumDisplay, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum RuleLevel {
+    /// The issues are not reported
+    Ignore,
+    /// The issues are reported as warnings, and do not make the bag invalid
+    Warn,
+    /// The issues are reported as errors
+    Error,
+}
+
+/// Identifies the kind of problem a `ValidationIssue` describes, so that it can be handled without
+/// matching on its message. Paths are relative to the bag's base directory and use `/` as the
+/// separator.
+#[derive(Debug, Clone, Eq, PartialEq, IntoStaticStr, EnumVariantNames)]
+pub enum IssueCode {
+    /// bagit.txt does not exist in a tar stream
+    MissingBagitTxt,
+    /// bagit.txt could not be parsed
+    InvalidBagitTxt,
+    /// bag-info.txt could not be parsed
+    InvalidBagInfo,
+    /// The tag files use an encoding other than UTF-8
+    LegacyEncoding { encoding: String },
+    /// A tag file that bagr reads starts with a UTF-8 byte order mark
+    ByteOrderMark { path: String },
+    /// A bag-info.txt label that may only appear once appears more than once
+    RepeatedTag { label: String },
+    /// Payload-Oxum is missing from bag-info.txt
+    MissingPayloadOxum,
+    /// Payload-Oxum is not formatted as OCTETCOUNT.STREAMCOUNT
+    InvalidPayloadOxum,
+    /// Bag-Count is not formatted as 'N of T' or 'N of ?'
+    InvalidBagCount,
+    /// The payload directory does not exist
+    MissingPayloadDirectory,
+    /// The bag has no payload manifest, or no payload manifest for an algorithm that's being
+    /// validated
+    MissingPayloadManifest { algorithm: Option<DigestAlgorithm> },
+    /// The payload manifests only use weak digest algorithms
+    WeakAlgorithms,
+    /// A manifest uses an algorithm that's not supported, and was skipped
+    UnsupportedAlgorithm { manifest: String },
+    /// A manifest line is not a digest followed by a file path
+    InvalidManifestLine { manifest: String, line: usize },
+    /// A manifest line contains a control character that should have been percent encoded
+    UnencodedControlCharacter { manifest: String, line: usize },
+    /// A manifest or fetch.txt line contains a percent encoded sequence that's not allowed, or a %
+    /// that's not encoded
+    InvalidPercentEncoding { file: String, line: usize },
+    /// A manifest lists a file that's outside of the payload directory
+    ManifestEntryOutsidePayload { manifest: String, path: String },
+    /// A manifest lists the same file more than once with the same digest
+    DuplicateManifestEntry { manifest: String, path: String },
+    /// A manifest lists the same file more than once with different digests
+    ConflictingManifestEntry { manifest: String, path: String },
+    /// A manifest lists files whose paths differ only by case or Unicode normalization, which
+    /// cannot coexist on case insensitive or normalizing filesystems
+    PathCollision { manifest: String, path: String },
+    /// A payload file path is not valid UTF-8
+    NonUtf8PayloadPath,
+    /// A payload file is not listed in a payload manifest
+    PayloadFileNotInManifest { path: String, manifest: String },
+    /// A file that's listed in a payload manifest does not exist
+    MissingPayloadFile { path: String },
+    /// A file that's listed in a tag manifest does not exist
+    MissingTagFile { path: String },
+    /// A file outside of the payload directory is not listed in any tag manifest
+    UnlistedTagFile { path: String },
+    /// The bag has no tag manifests
+    MissingTagManifests,
+    /// A fetch.txt line is not a URL, a length, and a payload file path
+    InvalidFetchLine { line: usize },
+    /// A payload directory could not be read because permission was denied
+    UnreadableDirectory { path: String },
+    /// A file or directory could not be read
+    ReadFailure { path: String },
+    /// A file is empty or its contents do not match its extension
+    SuspiciousFile { path: String },
+    /// A payload file path can't be used on some platforms, such as Windows
+    NonPortablePath { path: String },
+    /// The manifests, Payload-Oxum, and payload directory disagree on the number of payload files
+    PayloadCountMismatch,
+    /// The Payload-Oxum octet count does not match the size of the payload directory
+    PayloadOctetMismatch,
+    /// A file's digest does not match the digest in a manifest
+    DigestMismatch {
+        path: String,
+        algorithm: DigestAlgorithm,
+    },
+    /// A file in a tar stream was not verified because it was read before the manifest
+    UnverifiedStreamFile { path: String, manifest: String },
+}
+
+/// A problem that was identified while validating a bag
+#[derive(Debug)]
+pub struct ValidationIssue {
+    level: IssueLevel,
+    code: IssueCode,
+    message: String,
+    suggestion: Option<String>,
+    path: Option<String>,
+}
+
+/// The outcome of validating a bag. A bag is valid if no errors were identified, but it may
+/// still have warnings. The validation is partial if the time limit was reached before the
+/// digests of every file were verified.
+#[derive(Debug, Default)]
+pub struct ValidationResult {
+    issues: Vec<ValidationIssue>,
+    unverified: Vec<String>,
+    sample: Option<FixitySample>,
+    warnings_as_errors: bool,
+    rules: HashMap<String, RuleLevel>,
+}
+
+/// Describes the random sample of payload files whose digests were verified when a bag was only
+/// partially checked for fixity
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FixitySample {
+    seed: u64,
+    sampled: usize,
+    total: usize,
+}
+
+/// Options that control how a bag is validated. The defaults perform a complete validation of
+/// every manifest.
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    verify_fixity: bool,
+    path_encoding: PathEncoding,
+    warnings_as_errors: bool,
+    rules: HashMap<String, RuleLevel>,
+    algorithms: Vec<DigestAlgorithm>,
+    sample_rate: f64,
+    sample_count: Option<usize>,
+    sample_seed: Option<u64>,
+    payload_files: Option<Arc<HashSet<String>>>,
+    threads: usize,
+    fail_fast: bool,
+    warn_suspicious_files: bool,
+    check_portability: bool,
+    max_path_length: usize,
+    check_tag_structure: bool,
+    digest_cache: bool,
+    max_duration: Option<Duration>,
+    prefetch: usize,
+    prefetch_memory: u64,
+    weak_algorithm_policy: WeakAlgorithmPolicy,
+    progress: Arc<dyn ProgressListener>,
+}
+
+/// Hashes the files that are read out of a stream, such as a tar archive, until a deadline.
+/// Unless the algorithms are specified, files are hashed using the algorithms of the payload
+/// manifests that have been read so far, or every supported algorithm if none have been.
+#[derive(Debug)]
+struct StreamHasher {
+    algorithms: Vec<DigestAlgorithm>,
+    /// True if the algorithms were specified in the validation options
+    fixed_algorithms: bool,
+    read_manifest: bool,
+    verify_fixity: bool,
+    deadline: Option<Instant>,
+}
+
+/// A file that was read out of a tar stream or an object store
+#[derive(Debug)]
+struct StreamFile {
+    size: u64,
+    /// The digests that were calculated while the file was read. `None` if the file was not
+    /// hashed because the time limit was reached or fixity is not being verified.
+    digests: Option<HashMap<DigestAlgorithm, HexDigest>>,
+    /// The contents of the file, which are only retained for tag files
+    contents: Option<Vec<u8>>,
+}
+
+/// A manifest that was read from disk for validation
+#[derive(Debug)]
+struct ManifestEntries {
+    name: String,
+    algorithm: DigestAlgorithm,
+    entries: Vec<(String, HexDigest)>,
+}
+
+/// The payload and tag manifests of a bag
+type BagManifests = (Vec<ManifestEntries>, Vec<ManifestEntries>);
+
+/// Validates the bag in `base_dir`. A bag is valid when it is complete, meaning all of the files
+/// listed in its manifests exist and all of its payload files are listed in every payload manifest,
+/// and every file's digest matches the digests in the manifests. See `ValidationOptions` for how
+/// the validation can be customized.
+///
+/// An `Err` is only returned when the validation itself could not be completed. Problems with the
+/// bag are reported as issues on the `ValidationResult`.
+pub fn validate_bag<P: AsRef<Path>>(
+    base_dir: P,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    let base_dir = base_dir.as_ref();
+    info!("Validating bag at {}", base_dir.display());
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let (mut result, manifests) = check_bag_structure(base_dir, options)?;
+
+    if let Some((payload_manifests, tag_manifests)) = manifests {
+        let pool = thread_pool(options.threads)?;
+        let payload_files = payload_to_verify(base_dir, payload_manifests, options, &mut result)?;
+        check_fixity(
+            payload_files,
+            options,
+            deadline,
+            &pool,
+            options.progress.as_ref(),
+            &mut result,
+        );
+
+        if !options.stop(&result) {
+            let tag_files = expected_digests(base_dir, tag_manifests);
+            check_fixity(
+                tag_files,
+                options,
+                deadline,
+                &pool,
+                &NoProgress,
+                &mut result,
+            );
+        }
+    }
+
+    Ok(result)
+}
+
+/// Async variant of `validate_bag()`. Files are read with `tokio::fs` and hashed on the calling
+/// runtime, with at most as many files in progress at a time as the number of threads in the
+/// `options`. Everything else, such as reading the manifests and walking the bag, is done on the
+/// runtime's blocking threads. Prefetching is not supported, and is ignored. This must be called
+/// within a tokio runtime.
+#[cfg(feature = "tokio")]
+pub async fn validate_bag_async<P: AsRef<Path>>(
+    base_dir: P,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    let base_dir = base_dir.as_ref().to_path_buf();
+    info!("Validating bag at {}", base_dir.display());
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let (mut result, files) = {
+        let base_dir = base_dir.clone();
+        let options = options.clone();
+        run_blocking(move || -> Result<_> {
+            let (mut result, manifests) = check_bag_structure(&base_dir, &options)?;
+            let files = match manifests {
+                Some((payload_manifests, tag_manifests)) => Some((
+                    payload_to_verify(&base_dir, payload_manifests, &options, &mut result)?,
+                    tag_manifests,
+                )),
+                None => None,
+            };
+            Ok((result, files))
+        })
+        .await?
+    };
+
+    if let Some((payload_files, tag_manifests)) = files {
+        check_fixity_async(
+            payload_files,
+            options,
+            deadline,
+            options.progress.clone(),
+            &mut result,
+        )
+        .await;
+
+        if !options.stop(&result) {
+            let tag_files = run_blocking(move || expected_digests(&base_dir, tag_manifests)).await;
+            check_fixity_async(
+                tag_files,
+                options,
+                deadline,
+                Arc::new(NoProgress),
+                &mut result,
+            )
+            .await;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Checks everything about the bag in `base_dir` except the digests of its files. The bag's
+/// payload and tag manifests are returned when the digests of its files should be verified.
+fn check_bag_structure(
+    base_dir: &Path,
+    options: &ValidationOptions,
+) -> Result<(ValidationResult, Option<BagManifests>)> {
+    let mut result = ValidationResult::new();
+    result.warnings_as_errors = options.warnings_as_errors;
+    result.rules = options.rules.clone();
+    let encoding = options.path_encoding;
+
+    let declaration = match read_bag_declaration(base_dir) {
+        Ok(declaration) => declaration,
+        Err(e) => {
+            result.error(
+                IssueCode::InvalidBagitTxt,
+                format!("Invalid {BAGIT_TXT}: {e}"),
+            );
+            return Ok((result, None));
+        }
+    };
+
+    check_declaration_encoding(&declaration, &mut result);
+    check_byte_order_marks(base_dir, &mut result)?;
+
+    let payload_oxum = if base_dir.join(BAG_INFO_TXT).exists() {
+        match read_bag_info(base_dir, &declaration) {
+            Ok(bag_info) => {
+                check_bag_info(&bag_info, &mut result);
+                bag_info.payload_oxum()
+            }
+            Err(e) => {
+                result.error(
+                    IssueCode::InvalidBagInfo,
+                    format!("Invalid {BAG_INFO_TXT}: {e}"),
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if !base_dir.join(DATA).is_dir() {
+        result.error(
+            IssueCode::MissingPayloadDirectory,
+            format!("Missing payload directory {DATA}/"),
+        );
+        return Ok((result, None));
+    }
+
+    let payload_manifests = read_manifests(
+        base_dir,
+        &PAYLOAD_MANIFEST_MATCHER,
+        &declaration,
+        encoding,
+        &options.algorithms,
+        &mut result,
+    )?;
+    let tag_manifests = read_manifests(
+        base_dir,
+        &TAG_MANIFEST_MATCHER,
+        &declaration,
+        encoding,
+        &options.algorithms,
+        &mut result,
+    )?;
+
+    check_manifest_algorithms(&payload_manifests, options, &mut result);
+
+    if options.stop(&result) {
+        return Ok((result, None));
+    }
+
+    let mut unreadable_dirs = Vec::new();
+    let payload_files = list_payload_files(base_dir, &mut unreadable_dirs, &mut result)?;
+
+    if options.warn_suspicious_files {
+        check_suspicious_files(base_dir, &payload_files, &mut result);
+    }
+    if options.check_portability {
+        check_portable_paths(payload_files.keys(), options.max_path_length, &mut result);
+    }
+
+    check_payload_completeness(
+        &payload_manifests,
+        &payload_files,
+        &unreadable_dirs,
+        &mut result,
+    );
+    check_tag_completeness(
+        &tag_manifests,
+        |path| base_dir.join(path).is_file(),
+        &mut result,
+    );
+    if !tag_manifests.is_empty() {
+        let tag_files = list_tag_files(base_dir)?;
+        check_unlisted_tag_files(
+            &tag_manifests,
+            tag_files.iter().map(String::as_str),
+            &mut result,
+        );
+    }
+    let fetch_paths = read_fetch_paths(base_dir, &declaration)?;
+    for (line, file_path) in &fetch_paths {
+        if let Some(file_path) = file_path {
+            check_percent_encoding(file_path, FETCH_TXT, *line, encoding, &mut result);
+        }
+    }
+    if options.check_tag_structure && !has_tag_manifests(base_dir)? {
+        check_tag_structure(base_dir, &fetch_paths, &mut result)?;
+    }
+    check_payload_counts(
+        &payload_manifests,
+        payload_oxum,
+        &payload_files,
+        &mut result,
+    );
+
+    if options.verify_fixity && !options.stop(&result) {
+        Ok((result, Some((payload_manifests, tag_manifests))))
+    } else {
+        Ok((result, None))
+    }
+}
+
+/// Validates a bag that's serialized as an uncompressed tar archive that's read from `reader`,
+/// such as stdin, without writing it to disk. The bag must either be at the root of the archive
+/// or within a single top-level directory.
+///
+/// The archive is only read once. Tag files are buffered in memory, and payload files are hashed
+/// as they are read. Payload files that precede the payload manifests in the archive are hashed
+/// using every supported algorithm, so archives that list the tag files first are validated
+/// faster. Sampling and suspicious file detection are not supported, and are ignored.
+pub fn validate_tar_stream<R: Read>(
+    reader: R,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    info!("Validating bag from a tar stream");
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let mut result = ValidationResult::new();
+    result.warnings_as_errors = options.warnings_as_errors;
+    result.rules = options.rules.clone();
+
+    let mut files = read_tar_stream(reader, options, deadline)?;
+
+    let root = match find_bag_root(files.keys().map(String::as_str)) {
+        Some(root) => root,
+        None => {
+            result.error(
+                IssueCode::MissingBagitTxt,
+                format!("Invalid {BAGIT_TXT}: Could not find {BAGIT_TXT}"),
+            );
+            return Ok(result);
+        }
+    };
+    files = files
+        .into_iter()
+        .filter_map(|(path, file)| Some((path.strip_prefix(&root)?.to_string(), file)))
+        .collect();
+
+    validate_stream_files(&files, Path::new(TAR_STREAM), options, result)
+}
+
+/// Validates a bag in an object store, such as S3, that's stored under the key `prefix`, eg. one
+/// that was created by `BagCreator::create_in_store()`. Each object is a file in the bag, and its
+/// key, minus the prefix, is its path within the bag.
+///
+/// Every object is only read once. Tag files are read first and buffered in memory, and payload
+/// files are hashed as they are read, using the algorithms of the payload manifests. Sampling,
+/// prefetching, digest caches, and suspicious file detection are not supported, and are ignored.
+pub fn validate_store_bag(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    let bag_url = store.url(prefix.trim_matches('/'));
+    info!("Validating bag {bag_url}");
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let mut result = ValidationResult::new();
+    result.warnings_as_errors = options.warnings_as_errors;
+    result.rules = options.rules.clone();
+
+    let key_prefix = store_key(prefix, "");
+    let mut objects = store.list(&key_prefix)?;
+    // The sort is stable, so objects are otherwise still ordered by key
+    objects.sort_by_key(|object| is_stream_payload_path(&object.key()[key_prefix.len()..]));
+
+    let mut hasher = StreamHasher::new(options, deadline);
+    let mut files = BTreeMap::new();
+
+    for object in objects {
+        let path = &object.key()[key_prefix.len()..];
+        // Some tools create empty objects with keys that end in `/` to represent directories
+        if path.is_empty() || path.ends_with('/') {
+            continue;
+        }
+
+        let file = if is_stream_payload_path(path) && !hasher.is_hashing() {
+            StreamFile {
+                size: object.size(),
+                digests: None,
+                contents: None,
+            }
+        } else {
+            let mut reader = store.get(object.key())?;
+            hasher.read_file(
+                path,
+                object.size(),
+                &mut reader,
+                &Path::new(&bag_url).join(path),
+            )?
+        };
+
+        files.insert(path.to_string(), file);
+    }
+
+    validate_stream_files(&files, Path::new(&bag_url), options, result)
+}
+
+/// Validates a bag that's serialized in a zip or tar archive, such as one opened with
+/// `open_serialized_bag()`, without extracting it. Files in encrypted archives are decrypted as
+/// they're read.
+///
+/// Every file is only read once. Tag files are read first and buffered in memory, and payload
+/// files are hashed as they are read, using the algorithms of the payload manifests. Sampling,
+/// prefetching, digest caches, and suspicious file detection are not supported, and are ignored.
+pub fn validate_serialized_bag(
+    bag: &SerializedBag,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    let archive_path = bag.archive_path();
+    info!("Validating serialized bag {}", archive_path.display());
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let mut result = ValidationResult::new();
+    result.warnings_as_errors = options.warnings_as_errors;
+    result.rules = options.rules.clone();
+
+    let mut archive_files = bag.files().collect::<Vec<_>>();
+    // The sort is stable, so files are otherwise still ordered by path
+    archive_files.sort_by_key(|file| is_stream_payload_path(file.path()));
+
+    let mut hasher = StreamHasher::new(options, deadline);
+    let mut files = BTreeMap::new();
+
+    for archive_file in archive_files {
+        let path = archive_file.path();
+        let file = if is_stream_payload_path(path) && !hasher.is_hashing() {
+            StreamFile {
+                size: archive_file.size(),
+                digests: None,
+                contents: None,
+            }
+        } else {
+            let mut reader = bag.open_file(path)?;
+            hasher.read_file(
+                path,
+                archive_file.size(),
+                &mut reader,
+                &archive_path.join(path),
+            )?
+        };
+
+        files.insert(path.to_string(), file);
+    }
+
+    validate_stream_files(&files, archive_path, options, result)
+}
+
+/// Copies the bag in `src_dir` to `dst_dir`, which must either not exist or be empty, and
+/// validates the copy. The digests are calculated from the bytes as they are written to the copy,
+/// so every file is only read once and the copy is never read back. Tag files are copied first,
+/// and payload files are hashed using the algorithms of the payload manifests. Sampling,
+/// prefetching, digest caches, and suspicious file detection are not supported, and are ignored.
+/// The bag's lock, digest cache, and audit state are not copied.
+///
+/// An invalid copy is retained so that it can be inspected, but the copy is deleted when it could
+/// not be completed.
+pub fn copy_bag<S: AsRef<Path>, D: AsRef<Path>>(
+    src_dir: S,
+    dst_dir: D,
+    options: &ValidationOptions,
+) -> Result<ValidationResult> {
+    let src_dir = src_dir.as_ref();
+    let dst_dir = dst_dir.as_ref();
+    info!(
+        "Copying bag at {} to {}",
+        src_dir.display(),
+        dst_dir.display()
+    );
+
+    if dst_dir.is_file()
+        || dst_dir
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(General {
+            message: format!("{} already exists and is not empty", dst_dir.display()),
+        });
+    }
+
+    let mut sources = Vec::new();
+    for file in WalkDir::new(src_dir).min_depth(1).sort_by_file_name() {
+        let file = file.context(WalkFileSnafu {})?;
+        let is_bagr_file = file.depth() == 1
+            && (file.file_name() == BAG_LOCK
+                || file.file_name() == DIGEST_CACHE
+                || file.file_name() == AUDIT_STATE);
+        if file.file_type().is_dir() || is_bagr_file {
+            continue;
+        } else if !file.file_type().is_file() {
+            return Err(UnsupportedFile {
+                path: file.into_path(),
+            });
+        }
+
+        let path = file
+            .path()
+            .strip_prefix(src_dir)
+            .unwrap()
+            .to_str()
+            .ok_or_else(|| InvalidUtf8Path {
+                path: file.path().into(),
+            })?
+            .replace('\\', "/");
+        let size = file.metadata().context(WalkFileSnafu {})?.len();
+        sources.push((path, size, file.into_path()));
+    }
+    // The sort is stable, so files are otherwise still ordered by path
+    sources.sort_by_key(|(path, _, _)| is_stream_payload_path(path));
+
+    let deadline = options
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
+
+    let mut result = ValidationResult::new();
+    result.warnings_as_errors = options.warnings_as_errors;
+    result.rules = options.rules.clone();
+
+    let journal = Journal::new();
+    journal.creating_tree(dst_dir);
+
+    let mut hasher = StreamHasher::new(options, deadline);
+    let mut files = BTreeMap::new();
+
+    for (path, size, src) in sources {
+        match copy_file(&mut hasher, &path, size, &src, &dst_dir.join(&path)) {
+            Ok(file) => {
+                files.insert(path, file);
+            }
+            Err(e) => {
+                info!("Deleting the incomplete copy at {}", dst_dir.display());
+                journal.rollback();
+                return Err(e);
+            }
+        }
+    }
+
+    validate_stream_files(&files, dst_dir, options, result)
+}
+
+/// Copies the file at `src` to `dst`, hashing its contents as they are written
+fn copy_file(
+    hasher: &mut StreamHasher,
+    path: &str,
+    size: u64,
+    src: &Path,
+    dst: &Path,
+) -> Result<StreamFile> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+    }
+
+    let reader = File::open(src).context(IoReadSnafu { path: src })?;
+    let writer = BufWriter::new(File::create(dst).context(IoCreateSnafu { path: dst })?);
+    let mut reader = TeeReader::new(reader, writer);
+
+    let file = hasher
+        .read_file(path, size, &mut reader, src)
+        .and_then(|file| {
+            // Payload files are not read by the hasher once the time limit is reached
+            io::copy(&mut reader, &mut io::sink()).context(IoReadSnafu { path: src })?;
+            Ok(file)
+        });
+
+    if let Some(e) = reader.take_write_error() {
+        return Err(e).context(IoWriteSnafu { path: dst });
+    }
+    let file = file?;
+    reader
+        .writer_mut()
+        .flush()
+        .context(IoWriteSnafu { path: dst })?;
+
+    Ok(file)
+}
+
+/// Validates a bag from the files that were read out of a stream, keyed by their paths relative
+/// to the bag's base directory. `stream_path` identifies the stream in messages.
+fn validate_stream_files(
+    files: &BTreeMap<String, StreamFile>,
+    stream_path: &Path,
+    options: &ValidationOptions,
+    mut result: ValidationResult,
+) -> Result<ValidationResult> {
+    let encoding = options.path_encoding;
+
+    let declaration = match files.get(BAGIT_TXT).and_then(|file| file.contents.as_ref()) {
+        Some(contents) => match read_tags(contents.as_slice(), &stream_path.join(BAGIT_TXT))
+            .and_then(BagDeclaration::try_from)
+        {
+            Ok(declaration) => declaration,
+            Err(e) => {
+                result.error(
+                    IssueCode::InvalidBagitTxt,
+                    format!("Invalid {BAGIT_TXT}: {e}"),
+                );
+                return Ok(result);
+            }
+        },
+        None => {
+            result.error(
+                IssueCode::MissingBagitTxt,
+                format!("Invalid {BAGIT_TXT}: Could not read {BAGIT_TXT}"),
+            );
+            return Ok(result);
+        }
+    };
+
+    check_declaration_encoding(&declaration, &mut result);
+
+    let payload_oxum = match files
+        .get(BAG_INFO_TXT)
+        .and_then(|file| file.contents.as_ref())
+    {
+        Some(contents) => match read_tags(
+            decode_reader(contents.as_slice(), &declaration),
+            &stream_path.join(BAG_INFO_TXT),
+        ) {
+            Ok(tags) => {
+                let bag_info = BagInfo::from(tags);
+                check_bag_info(&bag_info, &mut result);
+                bag_info.payload_oxum()
+            }
+            Err(e) => {
+                result.error(
+                    IssueCode::InvalidBagInfo,
+                    format!("Invalid {BAG_INFO_TXT}: {e}"),
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut payload_manifests = Vec::new();
+    let mut tag_manifests = Vec::new();
+
+    for (name, file) in files {
+        let contents = match &file.contents {
+            Some(contents) if !name.contains('/') => contents,
+            _ => continue,
+        };
+        let (manifests, algorithm) = if let Some(algorithm) = manifest_algorithm(
+            name,
+            &PAYLOAD_MANIFEST_MATCHER,
+            &options.algorithms,
+            &mut result,
+        ) {
+            (&mut payload_manifests, algorithm)
+        } else if let Some(algorithm) = manifest_algorithm(
+            name,
+            &TAG_MANIFEST_MATCHER,
+            &options.algorithms,
+            &mut result,
+        ) {
+            (&mut tag_manifests, algorithm)
+        } else {
+            continue;
+        };
+        let reader = decode_reader(contents.as_slice(), &declaration);
+        manifests.push(ManifestEntries {
+            name: name.clone(),
+            algorithm,
+            entries: read_manifest_entries(reader, name, encoding, &mut result)?,
+        });
+    }
+
+    check_manifest_algorithms(&payload_manifests, options, &mut result);
+
+    if options.stop(&result) {
+        return Ok(result);
+    }
+
+    let payload_files = files
+        .iter()
+        .filter(|(path, _)| path.starts_with(&format!("{DATA}/")))
+        .map(|(path, file)| (path.clone(), file.size))
+        .collect::<BTreeMap<String, u64>>();
+
+    check_payload_completeness(&payload_manifests, &payload_files, &[], &mut result);
+    check_tag_completeness(&tag_manifests, |path| files.contains_key(path), &mut result);
+    if !tag_manifests.is_empty() {
+        let tag_files = files
+            .keys()
+            .map(String::as_str)
+            .filter(|path| !path.starts_with(&format!("{DATA}/")) && !is_untracked_file(path));
+        check_unlisted_tag_files(&tag_manifests, tag_files, &mut result);
+    }
+    check_payload_counts(
+        &payload_manifests,
+        payload_oxum,
+        &payload_files,
+        &mut result,
+    );
+
+    if options.verify_fixity && !options.stop(&result) {
+        check_stream_fixity(&payload_manifests, files, options, &mut result);
+        if !options.stop(&result) {
+            check_stream_fixity(&tag_manifests, files, options, &mut result);
+        }
+    }
+
+    Ok(result)
+}
+
+impl ValidationOptions {
+    pub fn new() -> Self {
+        Self {
+            verify_fixity: true,
+            path_encoding: PathEncoding::Standard,
+            warnings_as_errors: false,
+            rules: HashMap::new(),
+            algorithms: Vec::new(),
+            sample_rate: 1.0,
+            sample_count: None,
+            sample_seed: None,
+            payload_files: None,
+            threads: 0,
+            fail_fast: false,
+            warn_suspicious_files: false,
+            check_portability: false,
+            max_path_length: DEFAULT_MAX_PATH_LENGTH,
+            check_tag_structure: false,
+            digest_cache: false,
+            max_duration: None,
+            prefetch: 0,
+            prefetch_memory: DEFAULT_PREFETCH_MEMORY,
+            weak_algorithm_policy: WeakAlgorithmPolicy::default(),
+            progress: Arc::new(NoProgress),
+        }
+    }
+
+    /// Sets whether the digests of the bag's files are calculated and compared to its manifests.
+    /// This is enabled by default. When disabled, only completeness is checked.
+    pub fn verify_fixity(mut self, verify_fixity: bool) -> Self {
+        self.verify_fixity = verify_fixity;
+        self
+    }
+
+    /// Sets the profile that's used to decode the file paths in manifests. When it is
+    /// `PathEncoding::Strict`, manifest file paths that contain unencoded control characters are
+    /// reported as errors.
+    pub fn with_path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    pub fn path_encoding(&self) -> PathEncoding {
+        self.path_encoding
+    }
+
+    /// Sets whether issues that are normally warnings are reported as errors. This is disabled
+    /// by default.
+    pub fn warnings_as_errors(mut self, warnings_as_errors: bool) -> Self {
+        self.warnings_as_errors = warnings_as_errors;
+        self
+    }
+
+    /// Sets the level that issues with the code named `code`, eg. `MissingTagManifests`, are
+    /// reported at, regardless of their default level or `warnings_as_errors()`. Issues that are
+    /// ignored are not reported at all, so ignoring an error can make an invalid bag valid. See
+    /// `IssueCode::names()` for the codes.
+    pub fn with_rule(mut self, code: &str, level: RuleLevel) -> Self {
+        self.rules.insert(code.to_string(), level);
+        self
+    }
+
+    /// Adds a digest algorithm to validate. See `with_algorithms()`.
+    pub fn with_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithms.push(algorithm);
+        self
+    }
+
+    /// Sets the digest algorithms to validate. Manifests that use other algorithms are ignored,
+    /// and it is an error if the bag does not have a payload manifest for every algorithm. When
+    /// no algorithms are specified, which is the default, all of the bag's manifests are validated.
+    pub fn with_algorithms(mut self, algorithms: &[DigestAlgorithm]) -> Self {
+        self.algorithms.clear();
+        self.algorithms.extend_from_slice(algorithms);
+        self
+    }
+
+    /// Sets the fraction, between 0 and 1, of payload files to randomly select for fixity
+    /// verification. The default, 1, verifies every file. Tag files are always verified.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the number of payload files to randomly select for fixity verification. When a count
+    /// is set, the sample rate is ignored. Tag files are always verified.
+    pub fn with_sample_count(mut self, sample_count: usize) -> Self {
+        self.sample_count = Some(sample_count);
+        self
+    }
+
+    /// Sets the seed that payload files are sampled with, so that the same files are selected
+    /// every time a bag is sampled with the same seed. By default, a random seed is used, which
+    /// is reported in the validation result.
+    pub fn with_sample_seed(mut self, sample_seed: u64) -> Self {
+        self.sample_seed = Some(sample_seed);
+        self
+    }
+
+    /// Restricts fixity verification to the payload files at `paths`, as they're listed in the
+    /// payload manifests. The bag is still checked for completeness, but the digests of other
+    /// payload files are not verified. Tag files are always verified.
+    pub fn with_payload_files<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.payload_files = Some(Arc::new(paths.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Sets the maximum number of threads to use when calculating digests. The default, 0, uses
+    /// one thread per CPU.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets whether validation stops as soon as the first error is identified. This is disabled
+    /// by default.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Sets whether warnings are reported for payload files that are empty or have an extension
+    /// that does not match their detected format. This is disabled by default.
+    pub fn warn_suspicious_files(mut self, warn_suspicious_files: bool) -> Self {
+        self.warn_suspicious_files = warn_suspicious_files;
+        self
+    }
+
+    /// Sets whether warnings are reported for payload paths that would break on other platforms:
+    /// names that Windows reserves, such as `CON` or `nul.txt`, names that end in a dot or space,
+    /// characters that are invalid on NTFS, and paths or names that are too long. Paths that
+    /// differ only by case are always reported. This is disabled by default, and is not supported
+    /// when validating tar streams.
+    pub fn check_portability(mut self, check_portability: bool) -> Self {
+        self.check_portability = check_portability;
+        self
+    }
+
+    /// Sets the longest payload path, in characters and relative to the bag's base directory,
+    /// that's portable. The default is 200.
+    pub fn with_max_path_length(mut self, max_path_length: usize) -> Self {
+        self.max_path_length = max_path_length;
+        self
+    }
+
+    /// Sets whether the structure of the bag's tag files is checked when the bag does not have
+    /// any tag manifests, which are optional, to verify them against. The missing tag manifests
+    /// are reported as a warning, and tag files that are empty or have an extension that does
+    /// not match their detected format are reported. This is disabled by default, and is not
+    /// supported when validating tar streams.
+    pub fn check_tag_structure(mut self, check_tag_structure: bool) -> Self {
+        self.check_tag_structure = check_tag_structure;
+        self
+    }
+
+    /// Sets whether payload files are verified using the digests in the bag's digest cache, see
+    /// `BagCreator::digest_cache()`, instead of being hashed. Only files whose size and modification time
+    /// have not changed since their digests were cached are verified this way, so corruption
+    /// that preserves both is not detected. This is disabled by default.
+    pub fn digest_cache(mut self, digest_cache: bool) -> Self {
+        self.digest_cache = digest_cache;
+        self
+    }
+
+    /// Sets the maximum amount of time to spend validating. Once it has elapsed, no more files are
+    /// hashed, though files that are already being hashed are finished, and the files that were
+    /// not verified are recorded on the result, which is then partial. There is no limit by
+    /// default.
+    pub fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Sets the number of files to read ahead of the threads that hash them, which hides the
+    /// latency of network filesystems and object storage mounts. Files are read concurrently in
+    /// the order they're listed in the manifests. The default, 0, disables prefetching, and files
+    /// are read by the threads that hash them. Tar streams are never prefetched.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Sets the maximum number of bytes that prefetched files may occupy in memory. Files that are
+    /// larger are read while they're hashed. The default is 256 MiB.
+    pub fn with_prefetch_memory(mut self, prefetch_memory: u64) -> Self {
+        self.prefetch_memory = prefetch_memory;
+        self
+    }
+
+    /// Sets how bags whose payload manifests only use weak digest algorithms, md5 and sha1, are
+    /// treated. By default, they are valid with a warning.
+    pub fn with_weak_algorithm_policy(mut self, policy: WeakAlgorithmPolicy) -> Self {
+        self.weak_algorithm_policy = policy;
+        self
+    }
+
+    /// Sets the listener that receives progress updates while payload files are hashed
+    pub fn with_progress_listener(mut self, progress: Arc<dyn ProgressListener>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// True if validation should not continue because an error was identified in fail fast mode
+    fn stop(&self, result: &ValidationResult) -> bool {
+        self.fail_fast && !result.is_valid()
+    }
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IssueCode {
+    /// The name of the code, eg. `DigestMismatch`, which does not change between releases
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    /// The names of all of the codes
+    pub fn names() -> &'static [&'static str] {
+        Self::VARIANTS
+    }
+
+    /// The path of the file or directory that the issue is about, if it is about a specific one
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            IssueCode::MissingBagitTxt | IssueCode::InvalidBagitTxt => Some(BAGIT_TXT),
+            IssueCode::InvalidBagInfo
+            | IssueCode::RepeatedTag { .. }
+            | IssueCode::InvalidPayloadOxum
+            | IssueCode::InvalidBagCount => Some(BAG_INFO_TXT),
+            IssueCode::InvalidFetchLine { .. } => Some(FETCH_TXT),
+            IssueCode::UnsupportedAlgorithm { manifest }
+            | IssueCode::InvalidManifestLine { manifest, .. }
+            | IssueCode::UnencodedControlCharacter { manifest, .. } => Some(manifest),
+            IssueCode::InvalidPercentEncoding { file, .. } => Some(file),
+            IssueCode::ManifestEntryOutsidePayload { path, .. }
+            | IssueCode::DuplicateManifestEntry { path, .. }
+            | IssueCode::ConflictingManifestEntry { path, .. }
+            | IssueCode::PathCollision { path, .. }
+            | IssueCode::PayloadFileNotInManifest { path, .. }
+            | IssueCode::MissingPayloadFile { path }
+            | IssueCode::MissingTagFile { path }
+            | IssueCode::UnlistedTagFile { path }
+            | IssueCode::UnreadableDirectory { path }
+            | IssueCode::ReadFailure { path }
+            | IssueCode::SuspiciousFile { path }
+            | IssueCode::NonPortablePath { path }
+            | IssueCode::ByteOrderMark { path }
+            | IssueCode::DigestMismatch { path, .. }
+            | IssueCode::UnverifiedStreamFile { path, .. } => Some(path),
+            IssueCode::LegacyEncoding { .. }
+            | IssueCode::MissingPayloadOxum
+            | IssueCode::MissingPayloadDirectory
+            | IssueCode::MissingPayloadManifest { .. }
+            | IssueCode::WeakAlgorithms
+            | IssueCode::NonUtf8PayloadPath
+            | IssueCode::MissingTagManifests
+            | IssueCode::PayloadCountMismatch
+            | IssueCode::PayloadOctetMismatch => None,
+        }
+    }
+}
+
+impl Display for IssueCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl ValidationIssue {
+    /// Creates an issue with a human readable `message`. The issue's path is taken from `code`.
+    pub fn new(level: IssueLevel, code: IssueCode, message: String) -> Self {
+        let path = code.path().map(String::from);
+        Self {
+            level,
+            code,
+            message,
+            suggestion: None,
+            path,
+        }
+    }
+
+    /// Attaches a hint about how the issue might be fixed
+    pub fn with_suggestion(mut self, suggestion: String) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+
+    /// Attaches the path, relative to the bag's base directory, of the file or directory that
+    /// the issue is about
+    pub fn with_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn level(&self) -> IssueLevel {
+        self.level
+    }
+
+    /// Identifies the kind of problem
+    pub fn code(&self) -> &IssueCode {
+        &self.code
+    }
+
+    /// A human readable description of the problem
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// A hint about how the issue might be fixed, if one is known
+    pub fn suggestion(&self) -> Option<&str> {
+        self.suggestion.as_deref()
+    }
+
+    /// The path, relative to the bag's base directory, of the file or directory that the issue
+    /// is about, if it is about a specific one. Paths always use `/` as the separator.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.level, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  Suggestion: {suggestion}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FixitySample {
+    /// The seed that the files were sampled with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of payload files that were sampled
+    pub fn sampled(&self) -> usize {
+        self.sampled
+    }
+
+    /// The number of payload files in the manifests
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl ValidationResult {
+    pub fn new() -> Self {
+        Self {
+            issues: Vec::new(),
+            unverified: Vec::new(),
+            sample: None,
+            warnings_as_errors: false,
+            rules: HashMap::new(),
+        }
+    }
+
+    /// True if no errors were identified. Warnings do not affect validity.
+    pub fn is_valid(&self) -> bool {
+        !self
+            .issues
+            .iter()
+            .any(|issue| issue.level == IssueLevel::Error)
+    }
+
+    /// True if the time limit was reached before the digests of every file were verified. A
+    /// partial result may still be invalid if errors were found in the files that were verified.
+    pub fn is_partial(&self) -> bool {
+        !self.unverified.is_empty()
+    }
+
+    /// The paths, relative the bag's base directory, of the files whose digests were not verified
+    /// because the time limit was reached
+    pub fn unverified_files(&self) -> &[String] {
+        &self.unverified
+    }
+
+    /// The sample of payload files whose digests were verified, if only a sample of them was
+    pub fn sample(&self) -> Option<FixitySample> {
+        self.sample
+    }
+
+    /// All of the issues that were identified, in the order they were found
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    /// Returns all of the issues that are errors
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.level == IssueLevel::Error)
+    }
+
+    /// Returns all of the issues that are warnings
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.level == IssueLevel::Warning)
+    }
+
+    fn error(&mut self, code: IssueCode, message: String) {
+        self.push(ValidationIssue::new(IssueLevel::Error, code, message));
+    }
+
+    fn error_with_suggestion(&mut self, code: IssueCode, message: String, suggestion: String) {
+        self.push(
+            ValidationIssue::new(IssueLevel::Error, code, message).with_suggestion(suggestion),
+        );
+    }
+
+    fn warn(&mut self, code: IssueCode, message: String) {
+        self.push(ValidationIssue::new(IssueLevel::Warning, code, message));
+    }
+
+    fn warn_with_suggestion(&mut self, code: IssueCode, message: String, suggestion: String) {
+        self.push(
+            ValidationIssue::new(IssueLevel::Warning, code, message).with_suggestion(suggestion),
+        );
+    }
+
+    fn push(&mut self, issue: ValidationIssue) {
+        if let Some(issue) = self.apply_rules(issue) {
+            self.issues.push(issue);
+        }
+    }
+
+    /// Changes the level of the `issue` to the level of the rule for its code, if there is one.
+    /// Otherwise, warnings are upgraded to errors if warnings are treated as errors. `None` is
+    /// returned if the issue is ignored.
+    fn apply_rules(&self, mut issue: ValidationIssue) -> Option<ValidationIssue> {
+        match self.rules.get(issue.code.name()) {
+            Some(RuleLevel::Ignore) => return None,
+            Some(RuleLevel::Warn) => issue.level = IssueLevel::Warning,
+            Some(RuleLevel::Error) => issue.level = IssueLevel::Error,
+            None if self.warnings_as_errors => issue.level = IssueLevel::Error,
+            None => {}
+        }
+        Some(issue)
+    }
+}
+
+/// Compares the files in the payload directory of the bag in `base_dir` to the Payload-Oxum in
+/// its `bag_info`. Neither the manifests nor the contents of the files are read.
+pub fn check_payload_oxum(base_dir: &Path, bag_info: &BagInfo) -> Result<ValidationResult> {
+    info!("Checking the Payload-Oxum of bag at {}", base_dir.display());
+
+    let mut result = ValidationResult::new();
+
+    let payload_oxum = match bag_info.get_tag(LABEL_PAYLOAD_OXUM) {
+        Some(tag) => match PayloadOxum::try_from(tag.value()) {
+            Ok(payload_oxum) => payload_oxum,
+            Err(_) => {
+                result.error(
+                    IssueCode::InvalidPayloadOxum,
+                    format!(
+                        "Invalid {} '{}' in {BAG_INFO_TXT}",
+                        tag.label(),
+                        tag.value()
+                    ),
+                );
+                return Ok(result);
+            }
+        },
+        None => {
+            result.error_with_suggestion(
+                IssueCode::MissingPayloadOxum,
+                format!("Missing {LABEL_PAYLOAD_OXUM} in {BAG_INFO_TXT}"),
+                "Run `bagr rebag` to calculate it".to_string(),
+            );
+            return Ok(result);
+        }
+    };
+
+    if !base_dir.join(DATA).is_dir() {
+        result.error(
+            IssueCode::MissingPayloadDirectory,
+            format!("Missing payload directory {DATA}/"),
+        );
+        return Ok(result);
+    }
+
+    let mut unreadable_dirs = Vec::new();
+    let payload_files = list_payload_files(base_dir, &mut unreadable_dirs, &mut result)?;
+
+    for dir in &unreadable_dirs {
+        result.error(
+            IssueCode::UnreadableDirectory { path: dir.clone() },
+            format!("Payload directory {dir} cannot be read because permission was denied"),
+        );
+    }
+
+    check_payload_counts(&[], Some(payload_oxum), &payload_files, &mut result);
+
+    Ok(result)
+}
+
+/// Reads all of the files out of a tar stream, keyed by their paths within the archive. Files are
+/// read by a `StreamHasher`, which buffers those that are not within a payload directory.
+fn read_tar_stream<R: Read>(
+    reader: R,
+    options: &ValidationOptions,
+    deadline: Option<Instant>,
+) -> Result<BTreeMap<String, StreamFile>> {
+    let stream_path = Path::new(TAR_STREAM);
+    let mut archive = tar::Archive::new(reader);
+    let mut hasher = StreamHasher::new(options, deadline);
+    let mut files = BTreeMap::new();
+
+    for entry in archive
+        .entries()
+        .context(IoReadSnafu { path: stream_path })?
+    {
+        let mut entry = entry.context(IoReadSnafu { path: stream_path })?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = normalize_path(
+            &String::from_utf8(entry.path_bytes().into_owned()).context(InvalidStringSnafu)?,
+        );
+        let size = entry.size();
+        let file = hasher.read_file(&path, size, &mut entry, &stream_path.join(&path))?;
+        files.insert(path, file);
+    }
+
+    Ok(files)
+}
+
+impl StreamHasher {
+    fn new(options: &ValidationOptions, deadline: Option<Instant>) -> Self {
+        let mut algorithms = options.algorithms.clone();
+        let fixed_algorithms = !algorithms.is_empty();
+        if !fixed_algorithms {
+            algorithms.extend(DigestAlgorithm::iter());
+        }
+
+        Self {
+            algorithms,
+            fixed_algorithms,
+            read_manifest: fixed_algorithms,
+            verify_fixity: options.verify_fixity,
+            deadline,
+        }
+    }
+
+    /// True if the files that are read are hashed, which is until the deadline passes
+    fn is_hashing(&self) -> bool {
+        self.verify_fixity
+            && match self.deadline {
+                Some(deadline) => Instant::now() < deadline,
+                None => true,
+            }
+    }
+
+    /// Reads the `size` byte file at `path` from `reader`. Files that are not within a payload
+    /// directory are buffered. `file_path` identifies the file in errors.
+    fn read_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        reader: &mut dyn Read,
+        file_path: &Path,
+    ) -> Result<StreamFile> {
+        let hash = self.is_hashing();
+        let mut writer =
+            MultiDigestWriter::new(if hash { &self.algorithms } else { &[] }, io::sink());
+
+        let contents = if is_stream_payload_path(path) {
+            if hash {
+                info!("Hashing {path}");
+                io::copy(reader, &mut writer).context(IoReadSnafu { path: file_path })?;
+            }
+            None
+        } else {
+            let mut buffer = Vec::with_capacity(size as usize);
+            reader
+                .read_to_end(&mut buffer)
+                .context(IoReadSnafu { path: file_path })?;
+            writer
+                .write_all(&buffer)
+                .context(IoReadSnafu { path: file_path })?;
+            Some(buffer)
+        };
+        let digests = hash.then(|| writer.finalize_hex());
+
+        // Only the algorithms of the payload manifests need to be calculated once they're known
+        if !self.fixed_algorithms {
+            let name = path.rsplit('/').next().unwrap_or_default();
+            if let Some(captures) = PAYLOAD_MANIFEST_MATCHER.captures(name) {
+                if let Ok(algorithm) = DigestAlgorithm::try_from(captures.get(1).unwrap().as_str())
+                {
+                    if !self.read_manifest {
+                        self.read_manifest = true;
+                        self.algorithms.clear();
+                    }
+                    if !self.algorithms.contains(&algorithm) {
+                        self.algorithms.push(algorithm);
+                    }
+                }
+            }
+        }
+
+        Ok(StreamFile {
+            size,
+            digests,
+            contents,
+        })
+    }
+}
+
+/// True if the file at `path` within a tar archive is within a payload directory, either at the
+/// root of the archive or in a top-level directory
+fn is_stream_payload_path(path: &str) -> bool {
+    let prefix = format!("{DATA}/");
+    path.starts_with(&prefix)
+        || path
+            .split_once('/')
+            .is_some_and(|(_, rest)| rest.starts_with(&prefix))
+}
+
+/// Compares the digests of the files that were read from a tar stream to the digests in the
+/// manifests
+fn check_stream_fixity(
+    manifests: &[ManifestEntries],
+    files: &BTreeMap<String, StreamFile>,
+    options: &ValidationOptions,
+    result: &mut ValidationResult,
+) {
+    let mut expected: BTreeMap<&str, Vec<(&str, DigestAlgorithm, &HexDigest)>> = BTreeMap::new();
+
+    for manifest in manifests {
+        for (path, digest) in &manifest.entries {
+            expected.entry(path.as_str()).or_default().push((
+                manifest.name.as_str(),
+                manifest.algorithm,
+                digest,
+            ));
+        }
+    }
+
+    for (path, digests) in expected {
+        // Missing files were already reported
+        let file = match files.get(path) {
+            Some(file) => file,
+            None => continue,
+        };
+        let actual = match &file.digests {
+            Some(actual) => actual,
+            None => {
+                result.unverified.push(path.to_string());
+                continue;
+            }
+        };
+
+        for (name, algorithm, digest) in digests {
+            let actual_digest = match actual.get(&algorithm) {
+                Some(actual_digest) => Cow::Borrowed(actual_digest),
+                None => match &file.contents {
+                    Some(contents) => match algorithm.hash_hex(&mut contents.as_slice()) {
+                        Ok(actual_digest) => Cow::Owned(actual_digest),
+                        Err(e) => {
+                            result.error(
+                                IssueCode::ReadFailure { path: path.into() },
+                                format!("Failed to hash {path}: {e}"),
+                            );
+                            continue;
+                        }
+                    },
+                    None => {
+                        result.warn(
+                            IssueCode::UnverifiedStreamFile {
+                                path: path.into(),
+                                manifest: name.into(),
+                            },
+                            format!(
+                                "File {path} was not verified against {name} because it precedes \
+                                the manifest in the archive"
+                            ),
+                        );
+                        continue;
+                    }
+                },
+            };
+
+            if *digest != *actual_digest {
+                result.error(
+                    IssueCode::DigestMismatch {
+                        path: path.into(),
+                        algorithm,
+                    },
+                    format!(
+                        "File {path} has {algorithm} digest {actual_digest}, but the manifest \
+                        expects {digest}"
+                    ),
+                );
+                if options.fail_fast {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Warns about the tag files that bagr reads, bagit.txt, bag-info.txt, fetch.txt, and the
+/// manifests, that start with a UTF-8 byte order mark. bagr ignores it, but other tools may not.
+fn check_byte_order_marks(base_dir: &Path, result: &mut ValidationResult) -> Result<()> {
+    let walker = WalkDir::new(base_dir)
+        .min_depth(1)
+        .max_depth(1)
+        .sort_by_file_name();
+
+    for file in walker {
+        let file = file.context(WalkFileSnafu {})?;
+        let name = match file.file_name().to_str() {
+            Some(name) if file.file_type().is_file() => name,
+            _ => continue,
+        };
+        if name != BAGIT_TXT
+            && name != BAG_INFO_TXT
+            && name != FETCH_TXT
+            && !PAYLOAD_MANIFEST_MATCHER.is_match(name)
+            && !TAG_MANIFEST_MATCHER.is_match(name)
+        {
+            continue;
+        }
+
+        let mut start = Vec::with_capacity(UTF_8_BOM.len());
+        File::open(file.path())
+            .and_then(|f| f.take(UTF_8_BOM.len() as u64).read_to_end(&mut start))
+            .context(IoReadSnafu { path: file.path() })?;
+
+        if start == UTF_8_BOM {
+            result.warn_with_suggestion(
+                IssueCode::ByteOrderMark { path: name.into() },
+                format!(
+                    "{name} starts with a UTF-8 byte order mark, which some BagIt tools cannot \
+                    read"
+                ),
+                "Run `bagr rebag` to rewrite it without one".to_string(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Warns if the bag's tag files do not use UTF-8
+fn check_declaration_encoding(declaration: &BagDeclaration, result: &mut ValidationResult) {
+    if !declaration.is_utf8() {
+        result.warn_with_suggestion(
+            IssueCode::LegacyEncoding {
+                encoding: declaration.encoding().to_string(),
+            },
+            format!(
+                "Tag files use the legacy {} encoding",
+                declaration.encoding()
+            ),
+            "Run `bagr upgrade` to rewrite the tag files as UTF-8".to_string(),
+        );
+    }
+}
+
+/// Reserved bag-info.txt labels that are not repeatable should only appear once, and
+/// Payload-Oxum and Bag-Count must be formatted as described in the spec
+fn check_bag_info(bag_info: &BagInfo, result: &mut ValidationResult) {
+    for (label, _) in LABEL_REPEATABLE
+        .iter()
+        .filter(|(_, repeatable)| !repeatable)
+    {
+        let mut tags = bag_info.get_tags(label);
+        if let Some(first) = tags.next() {
+            let count = tags.count() + 1;
+            if count > 1 {
+                result.warn(
+                    IssueCode::RepeatedTag {
+                        label: first.label().to_string(),
+                    },
+                    format!(
+                        "{} appears {count} times in {BAG_INFO_TXT}, but it may only appear once",
+                        first.label()
+                    ),
+                );
+            }
+        }
+    }
+
+    if let Some(tag) = bag_info.get_tag(LABEL_PAYLOAD_OXUM) {
+        if PayloadOxum::try_from(tag.value()).is_err() {
+            result.warn(
+                IssueCode::InvalidPayloadOxum,
+                format!(
+                    "Invalid {} '{}' in {BAG_INFO_TXT}. Expected OCTETCOUNT.STREAMCOUNT",
+                    tag.label(),
+                    tag.value()
+                ),
+            );
+        }
+    }
+
+    if let Some(tag) = bag_info.bag_count() {
+        if parse_bag_count(tag.value()).is_none() {
+            result.warn(
+                IssueCode::InvalidBagCount,
+                format!(
+                    "Invalid {} '{}' in {BAG_INFO_TXT}. Expected 'N of T' or 'N of ?'",
+                    tag.label(),
+                    tag.value()
+                ),
+            );
+        }
+    }
+}
+
+/// The bag must have a payload manifest, and, if specific algorithms are being validated, it
+/// must have a payload manifest for each of them. Bags whose payload manifests only use weak
+/// algorithms are reported according to the weak algorithm policy.
+fn check_manifest_algorithms(
+    payload_manifests: &[ManifestEntries],
+    options: &ValidationOptions,
+    result: &mut ValidationResult,
+) {
+    let algorithms = &options.algorithms;
+
+    if payload_manifests.is_empty() && algorithms.is_empty() {
+        result.error_with_suggestion(
+            IssueCode::MissingPayloadManifest { algorithm: None },
+            "Missing payload manifest".to_string(),
+            "Run `bagr rebag` to generate payload manifests".to_string(),
+        );
+    }
+
+    for algorithm in algorithms {
+        if !payload_manifests.iter().any(|m| m.algorithm == *algorithm) {
+            result.error(
+                IssueCode::MissingPayloadManifest {
+                    algorithm: Some(*algorithm),
+                },
+                format!("Missing payload manifest {PAYLOAD_MANIFEST_PREFIX}-{algorithm}.txt"),
+            );
+        }
+    }
+
+    // When specific algorithms are validated, the other manifests were not read
+    if algorithms.is_empty()
+        && !payload_manifests.is_empty()
+        && payload_manifests.iter().all(|m| m.algorithm.is_weak())
+    {
+        let names = payload_manifests
+            .iter()
+            .map(|m| m.algorithm.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = format!("Payload manifests only use weak digest algorithms: {names}");
+        let suggestion =
+            "Run `bagr rebag --digest-algorithm sha512` to use a stronger algorithm".to_string();
+
+        match options.weak_algorithm_policy {
+            WeakAlgorithmPolicy::Allow => {}
+            WeakAlgorithmPolicy::Warn => {
+                result.warn_with_suggestion(IssueCode::WeakAlgorithms, message, suggestion)
+            }
+            WeakAlgorithmPolicy::Forbid => {
+                result.error_with_suggestion(IssueCode::WeakAlgorithms, message, suggestion)
+            }
+        }
+    }
+}
+
+/// Reads all of the manifests in `base_dir` that match `file_regex`. Manifests that use
+/// unsupported algorithms are skipped with a warning, and, if `algorithms` is not empty, manifests
+/// that use algorithms that are not in it are skipped silently.
+fn read_manifests(
+    base_dir: &Path,
+    file_regex: &Regex,
+    declaration: &BagDeclaration,
+    encoding: PathEncoding,
+    algorithms: &[DigestAlgorithm],
+    result: &mut ValidationResult,
+) -> Result<Vec<ManifestEntries>> {
+    let mut manifests = Vec::new();
+
+    for file in std::fs::read_dir(base_dir).context(IoReadDirSnafu { path: base_dir })? {
+        let file = file.context(IoReadDirSnafu { path: base_dir })?;
+        let name = match file.file_name().to_str() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+
+        let algorithm = match manifest_algorithm(&name, file_regex, algorithms, result) {
+            Some(algorithm) => algorithm,
+            None => continue,
+        };
+
+        let path = file.path();
+        let reader = decode_reader(
+            File::open(&path).context(IoReadSnafu { path: &path })?,
+            declaration,
+        );
+        let entries = read_manifest_entries(reader, &name, encoding, result)?;
+
+        manifests.push(ManifestEntries {
+            name,
+            algorithm,
+            entries,
+        });
+    }
+
+    manifests.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(manifests)
+}
+
+/// Returns the algorithm of the manifest named `name` if it matches `file_regex` and should be
+/// validated. Manifests that use unsupported algorithms are reported as warnings.
+fn manifest_algorithm(
+    name: &str,
+    file_regex: &Regex,
+    algorithms: &[DigestAlgorithm],
+    result: &mut ValidationResult,
+) -> Option<DigestAlgorithm> {
+    let algorithm_str = file_regex.captures(name)?.get(1).unwrap().as_str();
+
+    let algorithm: DigestAlgorithm = match algorithm_str.try_into() {
+        Ok(algorithm) => algorithm,
+        Err(_) => {
+            result.warn(
+                IssueCode::UnsupportedAlgorithm {
+                    manifest: name.into(),
+                },
+                format!("Skipping manifest {name} because it uses an unsupported algorithm"),
+            );
+            return None;
+        }
+    };
+
+    if !algorithms.is_empty() && !algorithms.contains(&algorithm) {
+        info!("Skipping manifest {name} because {algorithm} is not being validated");
+        return None;
+    }
+
+    Some(algorithm)
+}
+
+/// Parses the lines of a manifest file, which are read from the already decoded `reader`, into
+/// path and digest pairs. Invalid lines are reported as errors and skipped. Paths that are listed
+/// more than once are only returned once; repeats with the same digest are warnings, and repeats
+/// with different digests are errors.
+fn read_manifest_entries<R: Read>(
+    reader: R,
+    name: &str,
+    encoding: PathEncoding,
+    result: &mut ValidationResult,
+) -> Result<Vec<(String, HexDigest)>> {
+    let reader = LineReader::new(BufReader::new(reader));
+
+    let mut entries: Vec<(String, HexDigest)> = Vec::new();
+    // The index of each path's first entry
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (i, line) in reader.enumerate() {
+        match split_manifest_line(&line?) {
+            Some((digest, file_path)) => {
+                if encoding == PathEncoding::Strict
+                    && file_path.contains(|c| c != '%' && is_strict_encoded(c))
+                {
+                    result.error_with_suggestion(
+                        IssueCode::UnencodedControlCharacter {
+                            manifest: name.into(),
+                            line: i + 1,
+                        },
+                        format!(
+                            "Line {} in {name} contains an unencoded control character",
+                            i + 1
+                        ),
+                        "If the bag was not created with --strict-encoding, validate it without \
+                        the flag"
+                            .to_string(),
+                    );
+                }
+                check_percent_encoding(file_path, name, i + 1, encoding, result);
+                let path: String = encoding.decode(file_path).into();
+                let digest = HexDigest::from(digest);
+                match seen.get(&path) {
+                    Some(&first) if entries[first].1 == digest => result.warn(
+                        IssueCode::DuplicateManifestEntry {
+                            manifest: name.into(),
+                            path: path.clone(),
+                        },
+                        format!("Line {} in {name} lists {path} again", i + 1),
+                    ),
+                    Some(_) => result.error(
+                        IssueCode::ConflictingManifestEntry {
+                            manifest: name.into(),
+                            path: path.clone(),
+                        },
+                        format!(
+                            "Line {} in {name} lists {path} again with a different digest",
+                            i + 1
+                        ),
+                    ),
+                    None => {
+                        seen.insert(path.clone(), entries.len());
+                        entries.push((path, digest));
+                    }
+                }
+            }
+            None => result.error(
+                IssueCode::InvalidManifestLine {
+                    manifest: name.into(),
+                    line: i + 1,
+                },
+                format!(
+                    "Line {} in {name} must contain a digest followed by a file path",
+                    i + 1
+                ),
+            ),
+        }
+    }
+
+    check_path_collisions(&entries, name, result);
+
+    Ok(entries)
+}
+
+/// Reports an error if the still encoded `path` on `line` of the file named `name` contains a
+/// percent encoded sequence that the `encoding` does not decode
+fn check_percent_encoding(
+    path: &str,
+    name: &str,
+    line: usize,
+    encoding: PathEncoding,
+    result: &mut ValidationResult,
+) {
+    if let Some(escape) = encoding.invalid_escape(path) {
+        let code = IssueCode::InvalidPercentEncoding {
+            file: name.into(),
+            line,
+        };
+        let message = format!(
+            "Line {line} in {name} contains the invalid percent encoded sequence '{escape}'. A % \
+            in a path must be encoded as %25"
+        );
+
+        if encoding == PathEncoding::Standard && PathEncoding::Strict.invalid_escape(path).is_none()
+        {
+            result.error_with_suggestion(
+                code,
+                message,
+                "If the bag was created with --strict-encoding, validate it with the flag"
+                    .to_string(),
+            );
+        } else {
+            result.error(code, message);
+        }
+    }
+}
+
+/// Warns about paths in the manifest named `name` that differ only by case or Unicode
+/// normalization, because only one of the files can exist on filesystems that are case
+/// insensitive or normalize file names
+fn check_path_collisions(
+    entries: &[(String, HexDigest)],
+    name: &str,
+    result: &mut ValidationResult,
+) {
+    let mut folded: HashMap<String, &str> = HashMap::with_capacity(entries.len());
+
+    for (path, _) in entries {
+        let key = path.nfc().collect::<String>().to_lowercase();
+        match folded.get(&key) {
+            Some(other) => result.warn(
+                IssueCode::PathCollision {
+                    manifest: name.into(),
+                    path: path.clone(),
+                },
+                format!(
+                    "{name} lists both {other} and {path}, which differ only by case or Unicode \
+                    normalization and cannot both exist on some filesystems"
+                ),
+            ),
+            None => {
+                folded.insert(key, path);
+            }
+        }
+    }
+}
+
+/// Returns the sizes of all of the files under the payload directory, keyed by their paths
+/// relative the bag's base directory. Directories that can't be read because of their
+/// permissions are skipped and added to `unreadable_dirs`.
+fn list_payload_files(
+    base_dir: &Path,
+    unreadable_dirs: &mut Vec<String>,
+    result: &mut ValidationResult,
+) -> Result<BTreeMap<String, u64>> {
+    let mut files = BTreeMap::new();
+
+    for file in WalkDir::new(base_dir.join(DATA)) {
+        let file = match file {
+            Ok(file) => file,
+            Err(e) if is_permission_denied(&e) && e.path().is_some() => {
+                let relative = e.path().unwrap().strip_prefix(base_dir).unwrap_or(base_dir);
+                info!(
+                    "Skipping unreadable payload directory {}",
+                    relative.display()
+                );
+                unreadable_dirs.push(relative.to_string_lossy().replace('\\', "/"));
+                continue;
+            }
+            Err(e) => return Err(e).context(WalkFileSnafu {}),
+        };
+
+        if file.file_type().is_file() {
+            let relative = file.path().strip_prefix(base_dir).unwrap();
+            match relative.to_str() {
+                Some(path) => {
+                    let metadata = file.metadata().context(WalkFileSnafu {})?;
+                    files.insert(path.replace('\\', "/"), metadata.len());
+                }
+                None => result.error(
+                    IssueCode::NonUtf8PayloadPath,
+                    format!(
+                        "Payload file path cannot be encoded as UTF-8: {}",
+                        relative.display()
+                    ),
+                ),
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Every file listed in a payload manifest must exist, and every payload file must be listed in
+/// every payload manifest. Missing files in `unreadable_dirs` are summarized with a single issue
+/// per directory.
+fn check_payload_completeness(
+    manifests: &[ManifestEntries],
+    payload_files: &BTreeMap<String, u64>,
+    unreadable_dirs: &[String],
+    result: &mut ValidationResult,
+) {
+    let mut missing = BTreeSet::new();
+
+    for manifest in manifests {
+        let mut listed = BTreeSet::new();
+
+        for (path, _) in &manifest.entries {
+            if !path.starts_with(&format!("{DATA}/")) {
+                result.error(
+                    IssueCode::ManifestEntryOutsidePayload {
+                        manifest: manifest.name.clone(),
+                        path: path.clone(),
+                    },
+                    format!(
+                        "Manifest {} lists {path}, which is not in the payload directory",
+                        manifest.name
+                    ),
+                );
+            } else if !payload_files.contains_key(path) {
+                missing.insert(path.as_str());
+            }
+            listed.insert(path.as_str());
+        }
+
+        for path in payload_files.keys() {
+            if !listed.contains(path.as_str()) {
+                result.error_with_suggestion(
+                    IssueCode::PayloadFileNotInManifest {
+                        path: path.clone(),
+                        manifest: manifest.name.clone(),
+                    },
+                    format!(
+                        "Payload file {path} is not listed in manifest {}",
+                        manifest.name
+                    ),
+                    "If the file was added intentionally, run `bagr rebag --fast` to add it to \
+                    the manifests"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    let mut unreadable_counts = vec![0; unreadable_dirs.len()];
+    missing.retain(|path| {
+        match unreadable_dirs
+            .iter()
+            .position(|dir| path.starts_with(&format!("{dir}/")))
+        {
+            Some(i) => {
+                unreadable_counts[i] += 1;
+                false
+            }
+            None => true,
+        }
+    });
+
+    for (dir, count) in unreadable_dirs.iter().zip(unreadable_counts) {
+        result.error(
+            IssueCode::UnreadableDirectory { path: dir.clone() },
+            format!(
+                "Payload directory {dir} cannot be read because permission was denied. {count} \
+                files that are listed under it in the manifests could not be found"
+            ),
+        );
+    }
+
+    for path in missing {
+        let code = IssueCode::MissingPayloadFile { path: path.into() };
+        let message = format!("Payload file {path} is listed in a manifest but does not exist");
+        match find_normalization_variant(path, payload_files) {
+            Some(variant) => result.error_with_suggestion(
+                code,
+                message,
+                format!(
+                    "The file may need Unicode normalization. The payload contains {variant}, \
+                    which only differs from it in its normalization form"
+                ),
+            ),
+            None => result.error(code, message),
+        }
+    }
+}
+
+/// Returns the path of a payload file that is the same as `path` when both are normalized, if one
+/// exists. This typically happens when a bag is moved between filesystems that normalize file
+/// names differently.
+fn find_normalization_variant<'a>(
+    path: &str,
+    payload_files: &'a BTreeMap<String, u64>,
+) -> Option<&'a str> {
+    let normalized = path.nfc().collect::<String>();
+    payload_files
+        .keys()
+        .find(|file| file.as_str() != path && file.nfc().eq(normalized.chars()))
+        .map(String::as_str)
+}
+
+/// Every file listed in a tag manifest must exist, which is determined by `exists`
+fn check_tag_completeness(
+    manifests: &[ManifestEntries],
+    exists: impl Fn(&str) -> bool,
+    result: &mut ValidationResult,
+) {
+    let mut missing = BTreeSet::new();
+
+    for manifest in manifests {
+        for (path, _) in &manifest.entries {
+            if !exists(path) {
+                missing.insert(path.as_str());
+            }
+        }
+    }
+
+    for path in missing {
+        result.error_with_suggestion(
+            IssueCode::MissingTagFile { path: path.into() },
+            format!("Tag file {path} is listed in a tag manifest but does not exist"),
+            "If the file was removed intentionally, run `bagr rebag --only-tags` to update the \
+            tag manifests"
+                .to_string(),
+        );
+    }
+}
+
+/// Every file outside of the payload directory, `tag_files`, should be listed in a tag manifest.
+/// Unlisted files are usually tag files that were added after the tag manifests were written, or
+/// files that do not belong in the bag at all.
+fn check_unlisted_tag_files<'a>(
+    manifests: &[ManifestEntries],
+    tag_files: impl Iterator<Item = &'a str>,
+    result: &mut ValidationResult,
+) {
+    let listed = manifests
+        .iter()
+        .flat_map(|manifest| manifest.entries.iter().map(|(path, _)| path.as_str()))
+        .collect::<HashSet<&str>>();
+
+    let mut unlisted = tag_files
+        .filter(|path| !listed.contains(path))
+        .collect::<Vec<&str>>();
+    unlisted.sort_unstable();
+
+    for path in unlisted {
+        result.warn_with_suggestion(
+            IssueCode::UnlistedTagFile { path: path.into() },
+            format!("File {path} is not listed in any tag manifest"),
+            "If the file belongs in the bag, run `bagr rebag --only-tags` to add it to the tag \
+            manifests"
+                .to_string(),
+        );
+    }
+}
+
+/// Returns the paths, relative to `base_dir`, of all of the files outside of the payload
+/// directory that should be listed in the tag manifests
+fn list_tag_files(base_dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    let walker = WalkDir::new(base_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|f| !(f.depth() == 1 && f.file_name() == DATA));
+
+    for file in walker {
+        let file = file.context(WalkFileSnafu {})?;
+        if file.file_type().is_file() {
+            let relative = file.path().strip_prefix(base_dir).unwrap();
+            if let Some(path) = relative.to_str() {
+                let path = path.replace('\\', "/");
+                if !is_untracked_file(&path) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// True if the file at `path`, relative to the base directory, is never listed in a tag manifest:
+/// the tag manifests themselves, and the state files that bagr keeps in the base directory
+fn is_untracked_file(path: &str) -> bool {
+    !path.contains('/')
+        && (TAG_MANIFEST_MATCHER.is_match(path)
+            || path == BAG_LOCK
+            || path == DIGEST_CACHE
+            || path == AUDIT_STATE)
+}
+
+/// The alternative to verifying the digests of the tag files when the bag has no tag manifests.
+/// The missing manifests are reported, the lines of fetch.txt, whose `fetch_paths` were read by
+/// `read_fetch_paths()`, must be well formed, and tag files that look like they may have been
+/// truncated or corrupted are reported.
+fn check_tag_structure(
+    base_dir: &Path,
+    fetch_paths: &[(usize, Option<String>)],
+    result: &mut ValidationResult,
+) -> Result<()> {
+    result.warn_with_suggestion(
+        IssueCode::MissingTagManifests,
+        "Bag has no tag manifests, so its tag files can only be checked for structural problems"
+            .to_string(),
+        "Run `bagr repair --add-tagmanifests` to generate them".to_string(),
+    );
+
+    for (line, file_path) in fetch_paths {
+        if file_path.is_none() {
+            result.error(
+                IssueCode::InvalidFetchLine { line: *line },
+                format!(
+                    "Line {line} in {FETCH_TXT} must contain a URL, a length or '-', and a \
+                    payload file path"
+                ),
+            );
+        }
+    }
+
+    let walker = WalkDir::new(base_dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|f| f.depth() > 1 || f.file_name() != DATA);
+
+    for file in walker {
+        let file = file.context(WalkFileSnafu {})?;
+        if !file.file_type().is_file() {
+            continue;
+        }
+
+        let path = file
+            .path()
+            .strip_prefix(base_dir)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        // Empty payload manifests are legitimate for bags with empty payloads
+        if PAYLOAD_MANIFEST_MATCHER.is_match(&path) {
+            continue;
+        }
+
+        let size = file.metadata().context(WalkFileSnafu {})?.len();
+        match inspect_file(file.path(), size) {
+            Ok(Some(suspicion)) => result.warn(
+                IssueCode::SuspiciousFile { path: path.clone() },
+                format!("Tag file {path} {suspicion}"),
+            ),
+            Ok(None) => {}
+            Err(e) => result.error(
+                IssueCode::ReadFailure { path: path.clone() },
+                format!("Failed to read {path}: {e}"),
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the still encoded payload file paths from the non-blank lines of the bag's fetch.txt,
+/// if it has one, along with their line numbers. A line's path is `None` if the line does not
+/// contain a URL, a length or `-`, and the path of a payload file, separated by whitespace.
+fn read_fetch_paths(
+    base_dir: &Path,
+    declaration: &BagDeclaration,
+) -> Result<Vec<(usize, Option<String>)>> {
+    let path = base_dir.join(FETCH_TXT);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let file = File::open(&path).context(IoReadSnafu { path: &path })?;
+    let reader = LineReader::new(BufReader::new(decode_reader(file, declaration)));
+    let mut paths = Vec::new();
+
+    for (i, line) in reader.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let file_path = line
+            .split_once(char::is_whitespace)
+            .and_then(|(_, rest)| rest.trim_start().split_once(char::is_whitespace))
+            .filter(|(length, file_path)| {
+                (*length == "-" || length.parse::<u64>().is_ok())
+                    && file_path.trim_start().starts_with(&format!("{DATA}/"))
+            })
+            .map(|(_, file_path)| file_path.trim_start().to_string());
+
+        paths.push((i + 1, file_path));
+    }
+
+    Ok(paths)
+}
+
+/// Cross-checks the number of payload files according to the payload manifests, Payload-Oxum,
+/// and the payload directory. When they disagree, the issue identifies which of the sources is
+/// the odd one out to make it easier to diagnose the problem.
+fn check_payload_counts(
+    manifests: &[ManifestEntries],
+    payload_oxum: Option<PayloadOxum>,
+    payload_files: &BTreeMap<String, u64>,
+    result: &mut ValidationResult,
+) {
+    let manifest_count = manifests
+        .iter()
+        .flat_map(|manifest| manifest.entries.iter().map(|(path, _)| path.as_str()))
+        .collect::<BTreeSet<&str>>()
+        .len() as u64;
+
+    let mut counts = Vec::with_capacity(3);
+    if !manifests.is_empty() {
+        counts.push(("payload manifests", manifest_count));
+    }
+    if let Some(payload_oxum) = payload_oxum {
+        counts.push((LABEL_PAYLOAD_OXUM, payload_oxum.files));
+    }
+    counts.push(("payload directory", payload_files.len() as u64));
+
+    if counts.iter().all(|(_, count)| *count == counts[0].1) {
+        return check_payload_bytes(payload_oxum, payload_files, result);
+    }
+
+    // When there are three sources and two agree, the third is the one that's wrong
+    let odd = if counts.len() == 3 {
+        (0..3).find(|i| counts[(i + 1) % 3].1 == counts[(i + 2) % 3].1)
+    } else {
+        None
+    };
+
+    match odd {
+        Some(i) => {
+            let (odd_name, odd_count) = counts[i];
+            let others = counts
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, (name, _))| *name)
+                .collect::<Vec<&str>>()
+                .join(" and ");
+            let other_count = counts[(i + 1) % 3].1;
+            let message = format!(
+                "Payload file count in {odd_name} ({odd_count}) disagrees with {others} \
+                ({other_count})"
+            );
+            if odd_name == LABEL_PAYLOAD_OXUM {
+                result.error_with_suggestion(
+                    IssueCode::PayloadCountMismatch,
+                    message,
+                    fix_oxum_suggestion(),
+                );
+            } else {
+                result.error(IssueCode::PayloadCountMismatch, message);
+            }
+        }
+        None => {
+            let counts = counts
+                .iter()
+                .map(|(name, count)| format!("{name} ({count})"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            result.error(
+                IssueCode::PayloadCountMismatch,
+                format!("Payload file counts disagree: {counts}"),
+            );
+        }
+    }
+
+    check_payload_bytes(payload_oxum, payload_files, result);
+}
+
+/// The octet count in Payload-Oxum must match the total size of the payload files
+fn check_payload_bytes(
+    payload_oxum: Option<PayloadOxum>,
+    payload_files: &BTreeMap<String, u64>,
+    result: &mut ValidationResult,
+) {
+    if let Some(PayloadOxum { bytes, files }) = payload_oxum {
+        let actual_bytes: u64 = payload_files.values().sum();
+        if bytes != actual_bytes {
+            let message = format!(
+                "Payload-Oxum octet count ({bytes}) does not match the size of the payload \
+                directory ({actual_bytes})"
+            );
+            // When files are missing or extra, the octet count is not the problem to fix
+            if files == payload_files.len() as u64 {
+                result.error_with_suggestion(
+                    IssueCode::PayloadOctetMismatch,
+                    message,
+                    fix_oxum_suggestion(),
+                );
+            } else {
+                result.error(IssueCode::PayloadOctetMismatch, message);
+            }
+        }
+    }
+}
+
+fn fix_oxum_suggestion() -> String {
+    format!(
+        "If the payload files are known to be correct, run `bagr rebag --fast` to recalculate \
+        {LABEL_PAYLOAD_OXUM}"
+    )
+}
+
+/// Warns about payload files that look like they may have been truncated or corrupted
+fn check_suspicious_files(
+    base_dir: &Path,
+    payload_files: &BTreeMap<String, u64>,
+    result: &mut ValidationResult,
+) {
+    for (path, size) in payload_files {
+        match inspect_file(&base_dir.join(path), *size) {
+            Ok(Some(suspicion)) => result.warn(
+                IssueCode::SuspiciousFile { path: path.clone() },
+                format!("Payload file {path} {suspicion}"),
+            ),
+            Ok(None) => {}
+            Err(e) => result.error(
+                IssueCode::ReadFailure { path: path.clone() },
+                format!("Failed to read {path}: {e}"),
+            ),
+        }
+    }
+}
+
+/// Warns about payload `paths`, relative to the bag's base directory, that can't be used on some
+/// platforms, with every reason that applies to each
+fn check_portable_paths<'a>(
+    paths: impl Iterator<Item = &'a String>,
+    max_path_length: usize,
+    result: &mut ValidationResult,
+) {
+    for path in paths {
+        let mut reasons = Vec::new();
+
+        for name in path.split('/') {
+            let stem = name.split('.').next().unwrap_or(name);
+            if WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+            {
+                reasons.push(format!("{name} is a reserved name on Windows"));
+            }
+            if name.ends_with(['.', ' ']) {
+                reasons.push(format!("{name} ends with a dot or space"));
+            }
+            if let Some(c) = name
+                .chars()
+                .find(|c| c.is_ascii_control() || "<>:\"|?*\\".contains(*c))
+            {
+                reasons.push(format!(
+                    "{name} contains {c:?}, which is invalid on Windows"
+                ));
+            }
+            if name.chars().count() > MAX_FILE_NAME_LENGTH {
+                reasons.push(format!(
+                    "a name is longer than {MAX_FILE_NAME_LENGTH} characters"
+                ));
+            }
+        }
+        if path.chars().count() > max_path_length {
+            reasons.push(format!(
+                "the path is longer than {max_path_length} characters"
+            ));
+        }
+
+        if !reasons.is_empty() {
+            result.warn(
+                IssueCode::NonPortablePath { path: path.clone() },
+                format!(
+                    "Payload file {path} is not portable: {}",
+                    reasons.join("; ")
+                ),
+            );
+        }
+    }
+}
+
+/// Selects the payload files in the `manifests` whose digests must be verified. Files may be
+/// sampled, and the files whose digests are cached are verified against the cache.
+fn payload_to_verify(
+    base_dir: &Path,
+    manifests: Vec<ManifestEntries>,
+    options: &ValidationOptions,
+    result: &mut ValidationResult,
+) -> Result<Vec<ExpectedDigests>> {
+    let mut payload_files = expected_digests(base_dir, manifests);
+    if let Some(selected) = &options.payload_files {
+        payload_files.retain(|file| selected.contains(file.path.as_str()));
+    }
+    let mut payload_files = sample_payload(payload_files, options, result);
+    if options.digest_cache {
+        let cache = DigestCache::load(base_dir)?;
+        payload_files = check_cached_fixity(payload_files, base_dir, &cache, result);
+    }
+    Ok(payload_files)
+}
+
+/// A file and the digests it's expected to have according to the manifests
+#[derive(Debug)]
+struct ExpectedDigests {
+    path: String,
+    /// The position of the file's first entry in the manifests
+    order: usize,
+    file_path: PathBuf,
+    size: u64,
+    digests: Vec<(DigestAlgorithm, HexDigest)>,
+}
+
+impl ExpectedDigests {
+    /// The algorithms of the file's expected digests
+    fn algorithms(&self) -> Vec<DigestAlgorithm> {
+        self.digests
+            .iter()
+            .map(|(algorithm, _)| *algorithm)
+            .collect()
+    }
+}
+
+/// Collects the expected digests of all of the files in the manifests. Files that do not exist are
+/// skipped because they were already reported.
+fn expected_digests(base_dir: &Path, manifests: Vec<ManifestEntries>) -> Vec<ExpectedDigests> {
+    // Each file's position in the manifests is kept with its digests
+    let mut expected: BTreeMap<String, (usize, Vec<(DigestAlgorithm, HexDigest)>)> =
+        BTreeMap::new();
+
+    for manifest in manifests {
+        for (path, digest) in manifest.entries {
+            let next = expected.len();
+            expected
+                .entry(path)
+                .or_insert_with(|| (next, Vec::new()))
+                .1
+                .push((manifest.algorithm, digest));
+        }
+    }
+
+    let mut files = Vec::with_capacity(expected.len());
+
+    for (path, (order, digests)) in expected {
+        let file_path = base_dir.join(&path);
+        if let Ok(meta) = std::fs::metadata(&file_path) {
+            if meta.is_file() {
+                files.push(ExpectedDigests {
+                    path,
+                    order,
+                    file_path,
+                    size: meta.len(),
+                    digests,
+                });
+            }
+        }
+    }
+
+    files
+}
+
+/// Calculates the digests of the files and compares them to the expected values. Files are hashed
+/// concurrently using the threads in `pool`, and are read ahead in manifest order when
+/// prefetching is enabled. Files that are not started before `deadline` are recorded as
+/// unverified.
+fn check_fixity(
+    files: Vec<ExpectedDigests>,
+    options: &ValidationOptions,
+    deadline: Option<Instant>,
+    pool: &ThreadPool,
+    progress: &dyn ProgressListener,
+    result: &mut ValidationResult,
+) {
+    progress.hashing_started(files.len() as u64, files.iter().map(|file| file.size).sum());
+
+    let failed = AtomicBool::new(false);
+
+    // None is returned for files that were skipped because the deadline passed
+    let skip = |_: &ExpectedDigests| -> Option<Option<io::Result<Vec<ValidationIssue>>>> {
+        if options.fail_fast && failed.load(Ordering::Relaxed) {
+            return Some(Some(Ok(Vec::new())));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(None);
+        }
+        None
+    };
+    let record = |errors: io::Result<Vec<ValidationIssue>>| {
+        if !errors.as_ref().is_ok_and(|errors| errors.is_empty()) {
+            failed.store(true, Ordering::Relaxed);
+        }
+        Some(errors)
+    };
+
+    let errors: Vec<Option<io::Result<Vec<ValidationIssue>>>> = if options.prefetch == 0 {
+        pool.install(|| {
+            files
+                .par_iter()
+                .map(|file| {
+                    skip(file).unwrap_or_else(|| {
+                        record(
+                            File::open(&file.file_path)
+                                .and_then(|contents| verify_digests(file, contents, progress)),
+                        )
+                    })
+                })
+                .collect()
+        })
+    } else {
+        let mut order = (0..files.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|i| files[*i].order);
+        let paths = order
+            .iter()
+            .map(|i| (files[*i].file_path.as_path(), files[*i].size))
+            .collect::<Vec<(&Path, u64)>>();
+
+        let prefetched = Prefetcher::new(options.prefetch, options.prefetch_memory).read_ahead(
+            &paths,
+            pool,
+            |i| skip(&files[order[i]]),
+            |i, contents| {
+                let file = &files[order[i]];
+                record(contents.and_then(|contents| verify_digests(file, contents, progress)))
+            },
+        );
+
+        let mut errors = order.into_iter().zip(prefetched).collect::<Vec<_>>();
+        errors.sort_unstable_by_key(|(i, _)| *i);
+        errors.into_iter().map(|(_, errors)| errors).collect()
+    };
+
+    record_fixity(&files, errors, options, result);
+}
+
+/// Async variant of `check_fixity()` that reads files with `tokio::fs`
+#[cfg(feature = "tokio")]
+async fn check_fixity_async(
+    files: Vec<ExpectedDigests>,
+    options: &ValidationOptions,
+    deadline: Option<Instant>,
+    progress: Arc<dyn ProgressListener>,
+    result: &mut ValidationResult,
+) {
+    progress.hashing_started(files.len() as u64, files.iter().map(|file| file.size).sum());
+
+    let failed = Arc::new(AtomicBool::new(false));
+    let fail_fast = options.fail_fast;
+
+    let tasks = files
+        .iter()
+        .map(|file| {
+            let file_path = file.file_path.clone();
+            let expected = file
+                .digests
+                .iter()
+                .map(|(algorithm, digest)| (*algorithm, digest.clone()))
+                .collect::<Vec<(DigestAlgorithm, HexDigest)>>();
+            let failed = failed.clone();
+            let progress = progress.clone();
+
+            // None is returned for files that were skipped because the deadline passed, and
+            // Some(None) for files that were skipped because an earlier file failed
+            async move {
+                if fail_fast && failed.load(Ordering::Relaxed) {
+                    return Some(None);
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return None;
+                }
+
+                info!("Verifying digests for {}", file_path.display());
+                let algorithms = expected
+                    .iter()
+                    .map(|(algorithm, _)| *algorithm)
+                    .collect::<Vec<DigestAlgorithm>>();
+                let actual = hash_file_async(&file_path, &algorithms, progress.as_ref()).await;
+                let matches = actual.as_ref().is_ok_and(|actual| {
+                    expected
+                        .iter()
+                        .all(|(algorithm, digest)| actual.get(algorithm) == Some(digest))
+                });
+                if !matches {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                Some(Some(actual))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let errors = run_concurrently(concurrency(options.threads), tasks)
+        .await
+        .into_iter()
+        .zip(&files)
+        .map(|(actual, file)| {
+            actual.map(|actual| match actual {
+                Some(actual) => actual.map(|actual| digest_mismatches(file, &actual)),
+                None => Ok(Vec::new()),
+            })
+        })
+        .collect();
+
+    record_fixity(&files, errors, options, result);
+}
+
+/// Records the issues that were found when the digests of the `files` were verified. `errors`
+/// contains the outcome for each file, which is `None` if the file was not verified because the
+/// time limit was reached.
+fn record_fixity(
+    files: &[ExpectedDigests],
+    errors: Vec<Option<io::Result<Vec<ValidationIssue>>>>,
+    options: &ValidationOptions,
+    result: &mut ValidationResult,
+) {
+    let unverified = files
+        .iter()
+        .zip(errors.iter())
+        .filter(|(_, errors)| errors.is_none())
+        .map(|(file, _)| file.path.to_string())
+        .collect::<Vec<String>>();
+
+    if !unverified.is_empty() {
+        info!(
+            "Time limit reached after verifying {} of {} files",
+            files.len() - unverified.len(),
+            files.len()
+        );
+        result.unverified.extend(unverified);
+    }
+
+    let mut issues = Vec::new();
+    let mut read_failures = Vec::new();
+
+    for (file, errors) in files.iter().zip(errors) {
+        match errors {
+            Some(Ok(errors)) => issues.extend(errors),
+            Some(Err(e)) => read_failures.push((file.path.as_str(), e)),
+            None => {}
+        }
+    }
+
+    issues.extend(summarize_read_failures(read_failures));
+
+    let limit = if options.fail_fast { 1 } else { usize::MAX };
+
+    let issues = issues
+        .into_iter()
+        .filter_map(|issue| result.apply_rules(issue))
+        .take(limit)
+        .collect::<Vec<_>>();
+    result.issues.extend(issues);
+}
+
+/// Compares the digests in the `cache` of the files that have not changed since they were cached
+/// to the expected digests, and returns the files that are not cached, which must be hashed
+fn check_cached_fixity(
+    files: Vec<ExpectedDigests>,
+    base_dir: &Path,
+    cache: &DigestCache,
+    result: &mut ValidationResult,
+) -> Vec<ExpectedDigests> {
+    let mut uncached = Vec::new();
+
+    for file in files {
+        let algorithms = file
+            .digests
+            .iter()
+            .map(|(algorithm, _)| *algorithm)
+            .collect::<Vec<DigestAlgorithm>>();
+
+        match cache.get(base_dir, Path::new(&file.path), &algorithms) {
+            Some(cached) => {
+                info!("Using cached digests for {}", file.file_path.display());
+                let issues = digest_mismatches(&file, cached)
+                    .into_iter()
+                    .filter_map(|issue| result.apply_rules(issue))
+                    .collect::<Vec<_>>();
+                result.issues.extend(issues);
+            }
+            None => uncached.push(file),
+        }
+    }
+
+    uncached
+}
+
+/// Creates an error for every file that could not be read. Files in the same directory that
+/// could not be read because permission was denied are summarized in a single error, so that an
+/// inaccessible directory does not produce thousands of errors.
+fn summarize_read_failures(failures: Vec<(&str, io::Error)>) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut denied: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+    for (path, e) in &failures {
+        if e.kind() == io::ErrorKind::PermissionDenied {
+            let dir = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+            denied.entry(dir).or_default().push(path);
+        } else {
+            issues.push(ValidationIssue::new(
+                IssueLevel::Error,
+                IssueCode::ReadFailure {
+                    path: path.to_string(),
+                },
+                format!("Failed to read {path}: {e}"),
+            ));
+        }
+    }
+
+    for (dir, paths) in denied {
+        if paths.len() == 1 {
+            issues.push(ValidationIssue::new(
+                IssueLevel::Error,
+                IssueCode::ReadFailure {
+                    path: paths[0].to_string(),
+                },
+                format!("Failed to read {}: permission denied", paths[0]),
+            ));
+        } else {
+            issues.push(ValidationIssue::new(
+                IssueLevel::Error,
+                IssueCode::UnreadableDirectory {
+                    path: dir.to_string(),
+                },
+                format!(
+                    "Failed to read {} files in {dir} because permission was denied",
+                    paths.len()
+                ),
+            ));
+        }
+    }
+
+    issues
+}
+
+fn is_permission_denied(e: &walkdir::Error) -> bool {
+    e.io_error()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// Calculates the digests of a file, whose contents are read from `contents`, and returns an issue
+/// for every digest that does not match. An error is returned if the file can't be read.
+fn verify_digests(
+    file: &ExpectedDigests,
+    contents: impl Read,
+    progress: &dyn ProgressListener,
+) -> io::Result<Vec<ValidationIssue>> {
+    info!("Verifying digests for {}", file.file_path.display());
+
+    let actual = hash_file(&file.file_path, contents, &file.algorithms(), progress)?;
+
+    Ok(digest_mismatches(file, &actual))
+}
+
+/// Returns an issue for every expected digest of the file that does not match its `actual` digest
+fn digest_mismatches(
+    file: &ExpectedDigests,
+    actual: &HashMap<DigestAlgorithm, HexDigest>,
+) -> Vec<ValidationIssue> {
+    let path = &file.path;
+    file.digests
+        .iter()
+        .filter_map(|(algorithm, digest)| {
+            let actual_digest = actual.get(algorithm).expect("Missing expected file digest");
+            if digest != actual_digest {
+                let message = format!(
+                    "File {path} has {algorithm} digest {actual_digest}, but the manifest \
+                    expects {digest}"
+                );
+                let code = IssueCode::DigestMismatch {
+                    path: path.into(),
+                    algorithm: *algorithm,
+                };
+                Some(ValidationIssue::new(IssueLevel::Error, code, message))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Selects the payload files to verify the digests of, if the `options` only verify a sample,
+/// and records the sample in the `result`. Files are ranked by a hash of their paths that's keyed
+/// on the seed, and either the `sample_count` lowest ranked files are selected, or every file
+/// whose rank falls within the `sample_rate`. The files stay in path order.
+fn sample_payload(
+    files: Vec<ExpectedDigests>,
+    options: &ValidationOptions,
+    result: &mut ValidationResult,
+) -> Vec<ExpectedDigests> {
+    if options.sample_count.is_none() && options.sample_rate >= 1.0 {
+        return files;
+    }
+
+    let seed = options
+        .sample_seed
+        .unwrap_or_else(|| RandomState::new().hash_one(std::process::id()));
+    let total = files.len();
+
+    let sampled = match options.sample_count {
+        Some(count) => {
+            let mut ranked = files
+                .iter()
+                .map(|file| (sample_rank(seed, &file.path), file.path.as_str()))
+                .collect::<Vec<_>>();
+            ranked.sort_unstable();
+            let selected = ranked
+                .into_iter()
+                .take(count)
+                .map(|(_, path)| path.to_string())
+                .collect::<HashSet<_>>();
+            files
+                .into_iter()
+                .filter(|file| selected.contains(&file.path))
+                .collect::<Vec<_>>()
+        }
+        None => files
+            .into_iter()
+            .filter(|file| {
+                (sample_rank(seed, &file.path) as f64 / u64::MAX as f64) < options.sample_rate
+            })
+            .collect(),
+    };
+
+    info!(
+        "Sampled {} of {total} files using seed {seed}",
+        sampled.len()
+    );
+    result.sample = Some(FixitySample {
+        seed,
+        sampled: sampled.len(),
+        total,
+    });
+
+    sampled
+}
+
+/// Hashes a path with FNV-1a, starting from the `seed`, and mixes the result with the SplitMix64
+/// finalizer. Unlike the standard library's hashers, this is stable across platforms and
+/// releases, so a seed always selects the same sample.
+pub(crate) fn sample_rank(seed: u64, path: &str) -> u64 {
+    let mut hash = mix64(seed ^ 0xcbf2_9ce4_8422_2325);
+    for byte in path.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    mix64(hash)
+}
+
+fn mix64(mut value: u64) -> u64 {
+    value ^= value >> 30;
+    value = value.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value ^= value >> 27;
+    value = value.wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+fn hash_file(
+    path: &Path,
+    contents: impl Read,
+    algorithms: &[DigestAlgorithm],
+    progress: &dyn ProgressListener,
+) -> io::Result<HashMap<DigestAlgorithm, HexDigest>> {
+    progress.file_started(path);
+    let mut writer = MultiDigestWriter::new(algorithms, io::sink());
+    let mut reader = ProgressReader::new(contents, path, progress);
+    io::copy(&mut reader, &mut writer)?;
+    progress.file_finished(path);
+    Ok(writer.finalize_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::bagit::digest::DigestAlgorithm;
+    use crate::bagit::encoding::PathEncoding;
+    use crate::bagit::validate::{
+        check_portable_paths, read_manifest_entries, sample_rank, summarize_read_failures,
+        validate_tar_stream, IssueCode, RuleLevel, ValidationOptions, ValidationResult,
+    };
+
+    fn tar_bag(path: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_dir_all("bag", path).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn paths_that_break_on_windows_are_not_portable() {
+        let paths = [
+            "data/aux.txt",
+            "data/Con/file.txt",
+            "data/notes. ",
+            "data/what?.txt",
+            "data/consent.txt",
+            "data/a/b.txt",
+        ]
+        .map(String::from);
+        let mut result = ValidationResult::new();
+
+        check_portable_paths(paths.iter(), 12, &mut result);
+
+        let messages = result
+            .warnings()
+            .map(|issue| issue.message().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "Payload file data/aux.txt is not portable: aux.txt is a reserved name on Windows",
+                "Payload file data/Con/file.txt is not portable: Con is a reserved name on \
+                Windows; the path is longer than 12 characters",
+                "Payload file data/notes.  is not portable: notes.  ends with a dot or space",
+                "Payload file data/what?.txt is not portable: what?.txt contains '?', which is \
+                invalid on Windows; the path is longer than 12 characters",
+                "Payload file data/consent.txt is not portable: the path is longer than 12 \
+                characters",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn paths_that_differ_by_case_or_normalization_collide() {
+        let manifest = "abc  data/photo.jpg\n\
+                        abc  data/Photo.JPG\n\
+                        abc  data/caf\u{e9}.txt\n\
+                        abc  data/cafe\u{301}.txt\n\
+                        abc  data/other.txt\n";
+        let mut result = ValidationResult::new();
+
+        let entries = read_manifest_entries(
+            manifest.as_bytes(),
+            "manifest-sha256.txt",
+            PathEncoding::Standard,
+            &mut result,
+        )
+        .unwrap();
+
+        assert_eq!(5, entries.len());
+        assert!(result.is_valid());
+        assert_eq!(
+            vec![
+                &IssueCode::PathCollision {
+                    manifest: "manifest-sha256.txt".into(),
+                    path: "data/Photo.JPG".into(),
+                },
+                &IssueCode::PathCollision {
+                    manifest: "manifest-sha256.txt".into(),
+                    path: "data/cafe\u{301}.txt".into(),
+                },
+            ],
+            result
+                .warnings()
+                .map(|issue| issue.code())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn binary_markers_and_dot_prefixes_are_stripped_from_manifest_paths() {
+        let manifest = "abc *./data/file1.txt\n\
+                        def  ./data/dir/file2.txt\n\
+                        abc  data/file1.txt\n";
+        let mut result = ValidationResult::new();
+
+        let entries = read_manifest_entries(
+            manifest.as_bytes(),
+            "manifest-sha256.txt",
+            PathEncoding::Standard,
+            &mut result,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["data/file1.txt", "data/dir/file2.txt"],
+            entries
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(result.is_valid());
+        assert_eq!(
+            vec![&IssueCode::DuplicateManifestEntry {
+                manifest: "manifest-sha256.txt".into(),
+                path: "data/file1.txt".into(),
+            }],
+            result
+                .warnings()
+                .map(|issue| issue.code())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sample_ranks_are_stable_for_a_seed() {
+        // Changing these values changes which files a seed selects
+        assert_eq!(3536534987413926573, sample_rank(10, "data/file1.txt"));
+        assert_eq!(11160098074158993444, sample_rank(10, "data/dir/file2.txt"));
+        assert_ne!(
+            sample_rank(10, "data/file1.txt"),
+            sample_rank(11, "data/file1.txt")
+        );
+    }
+
+    #[test]
+    fn test_validate_tar_stream() {
+        let options = ValidationOptions::new();
+
+        let tar = tar_bag("tests/cmd/validate/valid-bag.in");
+        let result = validate_tar_stream(tar.as_slice(), &options).unwrap();
+        assert!(result.is_valid());
+        assert!(result.issues().is_empty());
+
+        let tar = tar_bag("tests/cmd/validate/corrupt-file.in");
+        let result = validate_tar_stream(tar.as_slice(), &options).unwrap();
+        assert!(!result.is_valid());
+        assert_eq!(1, result.errors().count());
+        assert!(result.issues()[0].message().contains("data/file1.txt"));
+        assert_eq!(
+            &IssueCode::DigestMismatch {
+                path: "data/file1.txt".to_string(),
+                algorithm: DigestAlgorithm::Sha256
+            },
+            result.issues()[0].code()
+        );
+    }
+
+    #[test]
+    fn test_rules_change_issue_levels() {
+        let tar = tar_bag("tests/cmd/validate/corrupt-file.in");
+
+        let options = ValidationOptions::new().with_rule("DigestMismatch", RuleLevel::Warn);
+        let result = validate_tar_stream(tar.as_slice(), &options).unwrap();
+        assert!(result.is_valid());
+        assert_eq!(1, result.warnings().count());
+
+        let options = options.with_rule("DigestMismatch", RuleLevel::Ignore);
+        let result = validate_tar_stream(tar.as_slice(), &options).unwrap();
+        assert!(result.is_valid());
+        assert!(result.issues().is_empty());
+
+        let tar = tar_bag("tests/cmd/validate/weak-algorithm.in");
+        let options = ValidationOptions::new().with_rule("WeakAlgorithms", RuleLevel::Error);
+        let result = validate_tar_stream(tar.as_slice(), &options).unwrap();
+        assert!(!result.is_valid());
+    }
+
+    #[test]
+    fn test_summarize_read_failures() {
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let failures = vec![
+            ("data/private/1.txt", denied()),
+            ("data/private/2.txt", denied()),
+            ("data/private/3.txt", denied()),
+            ("data/other/1.txt", denied()),
+            ("data/broken.txt", io::Error::other("bad sector")),
+        ];
+
+        let issues = summarize_read_failures(failures);
+        assert_eq!(
+            vec![
+                "Failed to read data/broken.txt: bad sector",
+                "Failed to read data/other/1.txt: permission denied",
+                "Failed to read 3 files in data/private because permission was denied",
+            ],
+            issues
+                .iter()
+                .map(|issue| issue.message())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                Some("data/broken.txt"),
+                Some("data/other/1.txt"),
+                Some("data/private")
+            ],
+            issues.iter().map(|issue| issue.path()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["ReadFailure", "ReadFailure", "UnreadableDirectory"],
+            issues
+                .iter()
+                .map(|issue| issue.code().name())
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,13 +1,35 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io};
 
+use chrono::{DateTime, Duration as DateDuration, Local, NaiveDate, NaiveDateTime, SecondsFormat};
 use clap::{ArgEnum, Args, Parser, Subcommand};
 use log::{error, info, LevelFilter};
+use snafu::ResultExt;
+use tempfile::TempDir;
+use walkdir::WalkDir;
 
-use bagr::bagit::Error::InvalidTagLine;
+use bagr::bagit::Error::{
+    General, InvalidBag, InvalidConfig, InvalidTagLine, IoRead, MissingPayloadFiles,
+};
 use bagr::bagit::{
-    create_bag, open_bag, Bag, BagInfo, DigestAlgorithm as BagItDigestAlgorithm, Result,
+    compare_replicas, copy_bag, diff_bags, export_checksums, list_files, open_bag,
+    open_serialized_bag, open_serialized_bag_with_password, unpack_bag, unpack_bag_with_password,
+    validate_bag, validate_serialized_bag, validate_store_bag, validate_tar_stream,
+    write_bag_archive, AuditReport, Auditor, Bag, BagCreator, BagDiff, BagInfo, BagMerger,
+    BagPackager, ChecksumFormat, CommandScanner, Contact, DigestAlgorithm as BagItDigestAlgorithm,
+    ExcludePatterns, FetchPolicy, FetchSummary, FileScanner, Heartbeat, ImportedDigests, Inventory,
+    InventoryFile, IssueCode, ListFormat, Lowercase, NameTransform, NameTransforms, NoProgress,
+    NormalizeUnicode, PathEncoding, ProgressListener, ReplacePattern, ReplicaReport, Result,
+    RuleLevel, S3Credentials, S3Store, S3Uploader, SanitizeChars, ValidationOptions,
+    ValidationResult, WeakAlgorithmPolicy,
 };
+use bagr::bagit::{IoCreateSnafu, IoReadSnafu, IoWriteSnafu, WalkFileSnafu};
 
 // TODO expand docs
 
@@ -23,6 +45,53 @@ pub struct BagrArgs {
     #[clap(short = 'V', long)]
     pub verbose: bool,
 
+    /// Path to the config file
+    ///
+    /// Defaults to bagr/config.toml in the user's config directory, eg. ~/.config/bagr/config.toml.
+    /// Settings in the config file are overridden by environment variables and command line
+    /// flags. Run 'bagr config show' to see where each setting comes from.
+    #[clap(long, global = true, value_name = "PATH", env = "BAGR_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Periodically write a line to stderr with the number of files that have been hashed, the
+    /// hashing rate, and the estimated time remaining
+    ///
+    /// The interval is a duration like 30s, 5m, or 1h. Heartbeats are written even when --quiet
+    /// is used, so that long-running bag, rebag, add, and validate operations can be monitored
+    /// without verbose logging. May also be set with the BAGR_HEARTBEAT environment variable.
+    #[clap(long, global = true, value_name = "INTERVAL", parse(try_from_str = parse_duration))]
+    pub heartbeat: Option<Duration>,
+
+    /// How to treat the weak digest algorithms md5 and sha1
+    ///
+    /// By default, a warning is logged when they're used to create manifests, and validation
+    /// warns when they're the only algorithms a bag's payload manifests use. 'forbid' turns these
+    /// into errors, and 'allow' suppresses them. Defaults to 'warn'. May also be set with the
+    /// BAGR_WEAK_ALGORITHMS environment variable.
+    #[clap(
+        arg_enum,
+        long,
+        global = true,
+        value_name = "POLICY",
+        ignore_case = true
+    )]
+    pub weak_algorithms: Option<WeakAlgorithms>,
+
+    /// Shell command that screens every payload file as it's hashed, eg. with an antivirus
+    ///
+    /// The file's contents are written to the command's stdin, and its path is in the
+    /// BAGR_SCAN_PATH environment variable. Files are rejected, failing the operation, when the
+    /// command exits with a non-zero status, and the first line it writes to stdout is reported
+    /// as the reason. Applies to bag, rebag, and add. Files whose digests are reused by
+    /// 'rebag --fast' are not scanned. May also be set with the BAGR_SCAN_CMD environment
+    /// variable.
+    #[clap(long, global = true, value_name = "CMD")]
+    pub scan_cmd: Option<String>,
+
+    /// Log a warning for files that fail the --scan-cmd, instead of rejecting them
+    #[clap(long, global = true)]
+    pub scan_warn_only: bool,
+
     /// Subcommand to execute
     #[clap(subcommand)]
     pub command: Command,
@@ -35,6 +104,54 @@ pub enum Command {
     Bag(BagCmd),
     #[clap(name = "rebag")]
     Rebag(RebagCmd),
+    #[clap(name = "add")]
+    Add(AddCmd),
+    #[clap(name = "rm")]
+    Rm(RmCmd),
+    #[clap(name = "unbag")]
+    Unbag(UnbagCmd),
+    #[clap(name = "validate")]
+    Validate(ValidateCmd),
+    #[clap(name = "upgrade")]
+    Upgrade(UpgradeCmd),
+    #[clap(name = "repair")]
+    Repair(RepairCmd),
+    #[clap(name = "fetch")]
+    Fetch(FetchCmd),
+    #[clap(name = "package")]
+    Package(PackageCmd),
+    #[clap(name = "unpack")]
+    Unpack(UnpackCmd),
+    #[clap(name = "split")]
+    Split(SplitCmd),
+    #[clap(name = "merge")]
+    Merge(MergeCmd),
+    #[clap(name = "copy")]
+    Copy(CopyCmd),
+    #[clap(name = "upload")]
+    Upload(UploadCmd),
+    #[clap(name = "index-content")]
+    IndexContent(IndexContentCmd),
+    #[clap(name = "find")]
+    Find(FindCmd),
+    #[clap(name = "stats")]
+    Stats(StatsCmd),
+    #[clap(name = "dedupe-report")]
+    DedupeReport(DedupeReportCmd),
+    #[clap(name = "export-checksums")]
+    ExportChecksums(ExportChecksumsCmd),
+    #[clap(name = "ls")]
+    Ls(LsCmd),
+    #[clap(name = "compare-replicas")]
+    CompareReplicas(CompareReplicasCmd),
+    #[clap(name = "audit")]
+    Audit(AuditCmd),
+    #[clap(name = "diff")]
+    Diff(DiffCmd),
+    #[clap(name = "tag")]
+    Tag(TagCmd),
+    #[clap(name = "config")]
+    Config(ConfigCmd),
 }
 
 /// Create a new bag
@@ -54,15 +171,109 @@ pub struct BagCmd {
     #[clap(value_name = "DST_DIR")]
     pub destination: Option<PathBuf>,
 
+    /// Only bag the files listed in FILE, or on stdin if FILE is '-', instead of the whole source
+    /// directory
+    ///
+    /// Each line is the path of a file to copy into the payload, optionally followed by a tab and
+    /// the path to copy it to, relative to the payload directory. Relative source paths are
+    /// resolved against the source directory, and are also used as the destination when one is
+    /// not specified. Absolute source paths are copied to the root of the payload by default.
+    /// Blank lines are ignored. The source files are never moved.
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "destination",
+        conflicts_with_all = &[
+            "exclude",
+            "exclude-hidden-files",
+            "no-bagignore",
+            "exclusion-report",
+            "verify-source",
+            "rename"
+        ]
+    )]
+    pub files_from: Option<PathBuf>,
+
+    /// Create the bag from the files in a tar archive, or from one that's read from stdin if
+    /// FILE is '-'
+    ///
+    /// The bag is created in SRC_DIR. Each file in the archive is written into the payload at its
+    /// path within the archive, and is hashed as it's written, so the archive is never extracted
+    /// first. Entries that are not files or directories, such as links, are not supported.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &[
+            "destination",
+            "s3-destination",
+            "files-from",
+            "exclude",
+            "exclude-hidden-files",
+            "no-bagignore",
+            "exclusion-report",
+            "verify-source",
+            "rename"
+        ]
+    )]
+    pub from_tar: Option<PathBuf>,
+
+    /// Write the bag as a tar archive to FILE, or to stdout if FILE is '-', instead of to a
+    /// directory
+    ///
+    /// The bag is created in a temporary directory, which is deleted after the archive is
+    /// written. Within the archive, the bag is in a directory that has the same name as SRC_DIR.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["destination", "s3-destination", "force", "digest-cache", "database"]
+    )]
+    pub to_tar: Option<PathBuf>,
+
+    /// Create the bag in S3, or an S3 compatible object store, eg. s3://bucket/path/bag
+    ///
+    /// Every payload file is uploaded to an object under the prefix, and is hashed as it's
+    /// uploaded. The tag files are uploaded last. Credentials are read from AWS_ACCESS_KEY_ID and
+    /// AWS_SECRET_ACCESS_KEY.
+    #[clap(
+        long = "destination",
+        value_name = "S3_URL",
+        parse(try_from_str = parse_s3_url),
+        conflicts_with_all = &[
+            "destination",
+            "exclusion-report",
+            "verify-source",
+            "force",
+            "extra-tag-file",
+            "tag-dir",
+            "warn-suspicious-files",
+            "database",
+            "digest-cache"
+        ]
+    )]
+    pub s3_destination: Option<(String, String)>,
+
+    /// The region the bucket is in, when the bag is created in S3
+    #[clap(
+        long,
+        value_name = "REGION",
+        env = "AWS_REGION",
+        default_value = "us-east-1"
+    )]
+    pub region: String,
+
+    /// Base URL of an S3 compatible object store, eg. http://localhost:9000
+    #[clap(long, value_name = "URL")]
+    pub endpoint: Option<String>,
+
     /// Digest algorithms to use when creating manifest files.
     ///
-    /// A manifest is created for each algorithm that's specified
+    /// A manifest is created for each algorithm that's specified. Defaults to sha512, unless
+    /// digest-algorithm is set in the config file or BAGR_DIGEST_ALGORITHM.
     #[clap(
         arg_enum,
         short = 'a',
         long,
         value_name = "ALGORITHM",
-        default_value = "sha512",
         ignore_case = true,
         multiple_occurrences = true
     )]
@@ -70,16 +281,201 @@ pub struct BagCmd {
 
     /// Hidden files, files that begin with a '.', in the bag source are not included in the bag.
     ///
-    /// This is particularly useful for pruning .DS_Store files. Note, if this option is used
-    /// when creating a bag in place, then all hidden files will be **deleted**.
+    /// On Windows, files and directories with the hidden attribute are also excluded. This is
+    /// particularly useful for pruning .DS_Store files. Note, if this option is used when creating
+    /// a bag in place, then all hidden files will be **deleted**. Also enabled by
+    /// exclude-hidden-files in the config file or BAGR_EXCLUDE_HIDDEN_FILES.
     #[clap(long)]
     pub exclude_hidden_files: bool,
 
+    /// Leave source files and directories that match a glob pattern out of the bag
+    ///
+    /// Patterns are matched against paths relative to the source directory. Patterns without a
+    /// '/', eg. '*.tmp' or 'Thumbs.db', match names at any depth, and patterns that end in '/',
+    /// eg. 'node_modules/', only match directories. May be specified multiple times, and is
+    /// combined with the exclude patterns in the config file or BAGR_EXCLUDE. Note, when creating
+    /// a bag in place, the matching files are **deleted**.
+    #[clap(long, value_name = "GLOB", multiple_occurrences = true)]
+    pub exclude: Vec<String>,
+
+    /// Do not read exclude patterns from the .bagignore file in the source directory
+    ///
+    /// By default, the gitignore-style patterns in .bagignore are applied in addition to
+    /// --exclude, and the .bagignore file is not included in the bag.
+    #[clap(long)]
+    pub no_bagignore: bool,
+
+    /// Write exclusions.txt, a tag file that lists the files that were not included in the bag
+    /// and why
+    #[clap(long)]
+    pub exclusion_report: bool,
+
+    /// Verify that the source did not change while it was copied into the bag
+    ///
+    /// The path, size, and modification time of every source file are recorded before the
+    /// payload is copied, and compared to the source afterwards. If anything changed, the bag is
+    /// not created. Otherwise, the snapshot is recorded in transfer-consistency.txt. Not
+    /// supported when the bag is created in place.
+    #[clap(long)]
+    pub verify_source: bool,
+
+    /// Replace the tag files of an existing bag in the destination directory
+    ///
+    /// By default, bags are not created in directories that already contain manifests, such as
+    /// when a previous attempt failed. With this flag, bagit.txt, bag-info.txt, fetch.txt,
+    /// exclusions.txt, and all manifests in the destination are deleted first. Other files are
+    /// never deleted. Also replaces a stale lock left in .bagr.lock by a bagr process that did
+    /// not exit cleanly.
+    #[clap(long)]
+    pub force: bool,
+
+    /// Create a bag that's byte-identical to any other bag created from the same files with the
+    /// same options
+    ///
+    /// The bagr version is left out of the default Bag-Software-Agent, and the Bagging-Date
+    /// defaults to the date in SOURCE_DATE_EPOCH instead of the current date. One of them must
+    /// be set.
+    #[clap(long, conflicts_with = "verify-source")]
+    pub reproducible: bool,
+
+    /// Use the digests in a checksum file instead of hashing the files again
+    ///
+    /// Supports the output of md5sum, sha1sum, sha256sum, and sha512sum, with or without --tag,
+    /// and of hashdeep. Relative paths are resolved against the directory that contains the
+    /// checksum file. Files are still hashed when digests were not imported for all of the bag's
+    /// algorithms. May be repeated.
+    #[clap(
+        long,
+        value_name = "FILE",
+        multiple_occurrences = true,
+        conflicts_with_all = &["s3-destination", "files-from", "from-tar"]
+    )]
+    pub import_checksums: Vec<PathBuf>,
+
+    /// Fraction of the files with imported checksums, between 0 and 1, to hash anyway to verify
+    /// the imported checksums
+    ///
+    /// Files are selected randomly. The bag is not created if any of the checksums are wrong.
+    #[clap(
+        long,
+        value_name = "RATE",
+        default_value = "0",
+        requires = "import-checksums"
+    )]
+    pub checksum_sample_rate: f64,
+
+    /// Cache the digests of the payload files in their extended attributes
+    ///
+    /// The cache records each file's size and modification time, and 'rebag --digest-cache' and
+    /// 'validate --digest-cache' reuse the digests of files that have not changed since. Files
+    /// that extended attributes cannot be set on are cached in .bagr.cache in the bag's base
+    /// directory instead, which is not listed in the tag manifests.
+    #[clap(long)]
+    pub digest_cache: bool,
+
+    /// Rename files and directories as they're added to the payload
+    ///
+    /// Rules are applied in the order they're specified to every segment of a file's path.
+    /// Available rules: 'nfc' normalizes names to Unicode NFC, 'sanitize' replaces control
+    /// characters and characters that are not allowed on Windows with '_', 'lowercase' converts
+    /// names to lowercase, and 'replace:PATTERN=REPLACEMENT' replaces every match of a regular
+    /// expression. Bag creation fails if two files are renamed to the same path.
+    #[clap(
+        long,
+        value_name = "RULE",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_name_transform)
+    )]
+    pub rename: Vec<Arc<dyn NameTransform>>,
+
+    /// Copy a file into the bag as a custom tag file
+    ///
+    /// The destination is relative to the bag's base directory, eg. 'dc.xml=metadata/dc.xml'. When
+    /// it's not specified, the file is copied to the base directory. Custom tag files are
+    /// included in the tag manifests. May be repeated.
+    #[clap(
+        long,
+        value_name = "SRC=DEST",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_extra_tag_file)
+    )]
+    pub extra_tag_file: Vec<(PathBuf, PathBuf)>,
+
+    /// Copy all of the files in a directory into the bag as custom tag files
+    ///
+    /// The destination is relative to the bag's base directory, eg. 'mets=metadata'. When it's
+    /// not specified, the directory is copied to the base directory with the same name. All of
+    /// the files are included in the tag manifests. May be repeated.
+    #[clap(
+        long,
+        value_name = "SRC=DEST",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_extra_tag_file)
+    )]
+    pub tag_dir: Vec<(PathBuf, PathBuf)>,
+
+    /// Warn about payload files that are empty or have an extension that does not match their
+    /// detected format
+    ///
+    /// These often indicate truncated transfers.
+    #[clap(long)]
+    pub warn_suspicious_files: bool,
+
+    /// Leave source files and directories that cannot be read out of the bag, instead of failing
+    ///
+    /// A warning is logged for every file that's skipped, and they are listed in the exclusion
+    /// report as unreadable. Not supported when the bag is created in place.
+    #[clap(long, conflicts_with_all = &["s3-destination", "files-from", "from-tar"])]
+    pub skip_unreadable: bool,
+
+    /// Number of times to retry reading or copying a file that fails with a transient IO error
+    ///
+    /// Transient errors, such as timeouts and stale file handles, are common when the files are
+    /// on a network mount like NFS or SMB. Retries start after 1 second and back off
+    /// exponentially.
+    #[clap(long, value_name = "RETRIES", default_value = "0")]
+    pub io_retries: u32,
+
+    /// Print statistics about how the bag was created to stderr when it's done
+    ///
+    /// The statistics include the size of the payload, the elapsed time, the hashing throughput,
+    /// and how long each digest algorithm took.
+    #[clap(long)]
+    pub stats: bool,
+
+    /// Maximum number of threads to use when calculating digests
+    ///
+    /// Defaults to one thread per CPU, unless threads is set in the config file or BAGR_THREADS
+    #[clap(short = 'j', long, value_name = "THREADS")]
+    pub threads: Option<usize>,
+
+    /// Fold lines in bag-info.txt that are longer than this many characters
+    ///
+    /// Long values are wrapped onto indented continuation lines. Use 0 to disable folding.
+    #[clap(long, value_name = "WIDTH", default_value = "79")]
+    pub tag_line_width: usize,
+
+    /// Percent encode all control characters in manifest file paths, not only CR and LF
+    ///
+    /// Tabs, DEL, and all other C0 control characters are encoded in addition to the characters
+    /// required by the BagIt spec. Use this when exchanging bags with systems that reject raw
+    /// control characters in manifests.
+    #[clap(long)]
+    pub strict_encoding: bool,
+
+    /// Record the new bag in an inventory database, which is created if it does not exist
+    ///
+    /// A warning is logged if one of the bag's External-Identifiers is already used by a
+    /// different bag in the inventory.
+    #[clap(long, value_name = "DB_PATH")]
+    pub database: Option<PathBuf>,
+
     /// Value of the Bagging-Date tag in bag-info.txt
     ///
-    /// Defaults to the current date. Should be in YYYY-MM-DD format.
-    #[clap(long, value_name = "YYYY-MM-DD")]
-    pub bagging_date: Option<String>,
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_date))]
+    pub bagging_date: Option<NaiveDate>,
 
     /// Value of the Bag-Software-Agent tag in bag-info.txt
     ///
@@ -91,15 +487,22 @@ pub struct BagCmd {
     #[clap(long, value_name = "SIZE")]
     pub bag_size: Option<String>,
 
+    /// Set the Bag-Size tag in bag-info.txt to the size of the payload, eg. '42.6 MB'
+    #[clap(long, conflicts_with = "bag-size")]
+    pub calculate_bag_size: bool,
+
     /// Value of the Bag-Group-Identifier tag in bag-info.txt
     #[clap(long, value_name = "BAG_GROUP_ID")]
     pub bag_group_identifier: Option<String>,
 
-    /// Value of the Bag-Count tag in bag-info.txt. Should be in the form "N of T".
-    #[clap(long, value_name = "BAG_COUNT")]
-    pub bag_count: Option<String>,
+    /// Value of the Bag-Count tag in bag-info.txt. Must be in the form "N of T", or "N of ?" when
+    /// the total is not known.
+    #[clap(long, value_name = "BAG_COUNT", parse(try_from_str = parse_bag_count))]
+    pub bag_count: Option<(u64, Option<u64>)>,
 
     /// Value of the Source-Organization tag in bag-info.txt. Maybe repeated.
+    ///
+    /// Replaces the source-organization in the config file or BAGR_SOURCE_ORGANIZATION.
     #[clap(long, value_name = "ORG", multiple_occurrences = true)]
     pub source_organization: Vec<String>,
 
@@ -107,6 +510,21 @@ pub struct BagCmd {
     #[clap(long, value_name = "ORG_ADDR", multiple_occurrences = true)]
     pub organization_address: Vec<String>,
 
+    /// A contact's name, phone, and email, separated by ';', eg. 'Jane Doe;555-0100;jd@example.com'
+    ///
+    /// Trailing fields may be omitted, and empty fields are skipped, eg. 'Jane Doe;;jd@example.com'.
+    /// Written as Contact-Name, Contact-Phone, and Contact-Email tags in bag-info.txt. May be
+    /// repeated. Contacts with fewer fields must be specified last, because contact tags are
+    /// matched by position.
+    #[clap(
+        long,
+        value_name = "CONTACT",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_contact),
+        conflicts_with_all = &["contact-name", "contact-phone", "contact-email"]
+    )]
+    pub contact: Vec<Contact>,
+
     /// Value of the Contact-Name tag in bag-info.txt. Maybe repeated.
     #[clap(long, value_name = "CONTACT_NAME", multiple_occurrences = true)]
     pub contact_name: Vec<String>,
@@ -116,6 +534,9 @@ pub struct BagCmd {
     pub contact_phone: Vec<String>,
 
     /// Value of the Contact-Email tag in bag-info.txt. Maybe repeated.
+    ///
+    /// Replaces the contact-email in the config file or BAGR_CONTACT_EMAIL, which is also not used
+    /// when --contact is specified.
     #[clap(long, value_name = "CONTACT_EMAIL", multiple_occurrences = true)]
     pub contact_email: Vec<String>,
 
@@ -140,8 +561,22 @@ pub struct BagCmd {
     pub bagit_profile_identifier: Vec<String>,
 
     /// A custom tag to add to bag-info.txt. Tags must be formatted as LABEL:VALUE
+    ///
+    /// Values may contain the placeholders {date}, the Bagging-Date or the current date, {uuid},
+    /// a new random UUID, {hostname}, and {user}, which are expanded when the bag is created.
     #[clap(short, long, value_name = "LABEL:VALUE", multiple_occurrences = true)]
     pub tag: Vec<String>,
+
+    /// Path to a TOML, YAML, or bag-info.txt formatted file of tags to add to bag-info.txt
+    ///
+    /// TOML (.toml) and YAML (.yaml or .yml) templates map labels to values, and a label whose
+    /// value is an array is added once for each value. In other files, labels may be repeated, and
+    /// values may be continued on indented lines. The tags are written in order, after the tags
+    /// that are set by other flags, and before --tag. Values may
+    /// contain the same placeholders as --tag. The Source-Organization and Contact-Email defaults
+    /// in the config file are not used.
+    #[clap(long, value_name = "TEMPLATE")]
+    pub info_template: Option<PathBuf>,
 }
 
 /// Update BagIt manifests to match the current state on disk
@@ -153,10 +588,60 @@ pub struct RebagCmd {
 
     /// Only recalculate tag manifests; leave payload manifests alone
     ///
+    /// Use this when the payload is known to be unchanged. bag-info.txt and the tag manifests
+    /// are rewritten, but the payload is not hashed. Also available as --no-recalculate-payload.
     /// When this option is used digest algorithms cannot be specified
-    #[clap(long, conflicts_with = "digest-algorithm")]
+    #[clap(
+        long,
+        alias = "no-recalculate-payload",
+        conflicts_with = "digest-algorithm"
+    )]
     pub only_tags: bool,
 
+    /// Only hash payload files that were added or modified since they were last hashed
+    ///
+    /// The digests in the existing manifests are reused for all other files. Files are considered
+    /// to be unmodified if their size and modification time match the ones recorded in the bag's
+    /// digest cache, which is written by every --fast rebag. Every file is hashed if the bag does
+    /// not have a digest cache.
+    #[clap(long, conflicts_with = "only-tags")]
+    pub fast: bool,
+
+    /// Match payload files that are not in the manifests to missing files by their digests
+    ///
+    /// Matched files are treated as renames: their digests are carried over to the new paths
+    /// instead of being calculated again. Renames are logged, and are only detected when the
+    /// digest algorithms are not changed.
+    #[clap(long, conflicts_with = "only-tags")]
+    pub detect_renames: bool,
+
+    /// Remove payload files that no longer exist from the manifests
+    ///
+    /// Without this flag, rebag fails and lists the missing files when files that are listed in
+    /// the manifests were deleted. Pruned files are logged and recorded in history.txt.
+    #[clap(long, conflicts_with = "only-tags")]
+    pub prune_missing: bool,
+
+    /// Update the bag even though another bagr process holds its lock
+    ///
+    /// Bags are locked while they're updated. Only use this to remove a stale lock, left in
+    /// .bagr.lock by a process that did not exit cleanly.
+    #[clap(long)]
+    pub force: bool,
+
+    /// Reuse the cached digests of files that have not changed, and update the cache
+    ///
+    /// A file is unchanged if its size and modification time match the cache. The cache is
+    /// created if the bag does not have one.
+    #[clap(long, conflicts_with = "only-tags")]
+    pub digest_cache: bool,
+
+    /// Set the Bag-Size tag in bag-info.txt to the size of the payload, eg. '42.6 MB'
+    ///
+    /// Any existing Bag-Size is replaced
+    #[clap(long)]
+    pub calculate_bag_size: bool,
+
     /// Digest algorithms to use when creating manifest files.
     ///
     /// By default, the same algorithms are used as were used to compute the existing manifests.
@@ -172,10 +657,60 @@ pub struct RebagCmd {
     )]
     pub digest_algorithm: Vec<DigestAlgorithm>,
 
+    /// Digest algorithms to add payload manifests for, keeping the existing manifests
+    ///
+    /// The payload is only hashed with the added algorithms, and the existing payload manifests
+    /// are left untouched. Algorithms the bag already has manifests for are ignored.
+    #[clap(
+        arg_enum,
+        long,
+        value_name = "ALGORITHM",
+        ignore_case = true,
+        multiple_occurrences = true,
+        conflicts_with_all = &["digest-algorithm", "only-tags", "fast", "detect-renames", "prune-missing"]
+    )]
+    pub add_algorithm: Vec<DigestAlgorithm>,
+
+    /// Number of times to retry reading or copying a file that fails with a transient IO error
+    ///
+    /// Transient errors, such as timeouts and stale file handles, are common when the files are
+    /// on a network mount like NFS or SMB. Retries start after 1 second and back off
+    /// exponentially.
+    #[clap(long, value_name = "RETRIES", default_value = "0")]
+    pub io_retries: u32,
+
+    /// Print statistics about how the bag was updated to stderr when it's done
+    ///
+    /// The statistics include the size of the payload, the elapsed time, the hashing throughput,
+    /// and how long each digest algorithm took.
+    #[clap(long)]
+    pub stats: bool,
+
+    /// Maximum number of threads to use when calculating digests
+    ///
+    /// Defaults to one thread per CPU, unless threads is set in the config file or BAGR_THREADS
+    #[clap(short = 'j', long, value_name = "THREADS")]
+    pub threads: Option<usize>,
+
+    /// Fold lines in bag-info.txt that are longer than this many characters
+    ///
+    /// Long values are wrapped onto indented continuation lines. Use 0 to disable folding.
+    #[clap(long, value_name = "WIDTH", default_value = "79")]
+    pub tag_line_width: usize,
+
+    /// Percent encode all control characters in manifest file paths, not only CR and LF
+    ///
+    /// Tabs, DEL, and all other C0 control characters are encoded in addition to the characters
+    /// required by the BagIt spec. Use this when exchanging bags with systems that reject raw
+    /// control characters in manifests.
+    #[clap(long)]
+    pub strict_encoding: bool,
+
     /// Value of the Bagging-Date tag in bag-info.txt
     ///
-    /// Defaults to the current date. Should be in YYYY-MM-DD format.
-    #[clap(long, value_name = "YYYY-MM-DD")]
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_bagging_date))]
     pub bagging_date: Option<String>,
 
     /// Value of the Bag-Software-Agent tag in bag-info.txt
@@ -185,141 +720,3442 @@ pub struct RebagCmd {
     pub software_agent: Option<String>,
 }
 
-#[derive(ArgEnum, Debug, Clone, Copy)]
-pub enum DigestAlgorithm {
-    Md5,
-    Sha1,
-    Sha256,
-    Sha512,
-    Blake2b256,
-    Blake2b512,
-}
-
-impl From<DigestAlgorithm> for BagItDigestAlgorithm {
-    fn from(algorithm: DigestAlgorithm) -> Self {
-        match algorithm {
-            DigestAlgorithm::Md5 => BagItDigestAlgorithm::Md5,
-            DigestAlgorithm::Sha1 => BagItDigestAlgorithm::Sha1,
-            DigestAlgorithm::Sha256 => BagItDigestAlgorithm::Sha256,
-            DigestAlgorithm::Sha512 => BagItDigestAlgorithm::Sha512,
-            DigestAlgorithm::Blake2b256 => BagItDigestAlgorithm::Blake2b256,
-            DigestAlgorithm::Blake2b512 => BagItDigestAlgorithm::Blake2b512,
-        }
-    }
-}
+/// Add files to an existing bag's payload
+///
+/// Only the added files are hashed. Their digests are appended to the existing payload manifests,
+/// and Payload-Oxum and the tag manifests are updated.
+#[derive(Args, Debug)]
+pub struct AddCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
 
-fn main() {
-    let args = BagrArgs::parse();
+    /// Files or directories to copy into the payload. Directories are added recursively.
+    #[clap(value_name = "SRC", required = true)]
+    pub sources: Vec<PathBuf>,
 
-    let log_level = if args.quiet {
-        LevelFilter::Off
-    } else if args.verbose {
-        LevelFilter::Info
-    } else {
-        LevelFilter::Warn
-    };
+    /// Directory, relative to the payload directory, to add the files to
+    ///
+    /// Defaults to the root of the payload directory
+    #[clap(long, value_name = "PAYLOAD_DIR")]
+    pub dest: Option<PathBuf>,
 
-    env_logger::builder()
-        .filter_level(log_level)
-        .format_timestamp(None)
-        .format_module_path(false)
-        .format_target(false)
-        .init();
+    /// Recalculate the digests of every payload file instead of only the files that changed
+    #[clap(long)]
+    pub rehash_all: bool,
 
-    match args.command {
-        Command::Bag(cmd) => {
-            if let Err(e) = exec_bag(cmd) {
-                error!("Failed to create bag: {}", e);
-                exit(1);
-            }
-        }
-        Command::Rebag(cmd) => {
-            if let Err(e) = exec_rebag(cmd) {
-                error!("Failed to rebag: {}", e);
-                exit(1);
-            }
-        }
-    }
-}
+    /// Update the bag even though another bagr process holds its lock
+    ///
+    /// Bags are locked while they're updated. Only use this to remove a stale lock, left in
+    /// .bagr.lock by a process that did not exit cleanly.
+    #[clap(long)]
+    pub force: bool,
 
-fn exec_bag(cmd: BagCmd) -> Result<Bag> {
-    let mut bag_info = BagInfo::new();
+    /// Percent encode all control characters in manifest file paths, not only CR and LF
+    ///
+    /// This must be used if the bag was created with --strict-encoding.
+    #[clap(long)]
+    pub strict_encoding: bool,
 
-    if let Some(date) = cmd.bagging_date {
-        bag_info.add_bagging_date(date)?;
-    }
-    if let Some(agent) = cmd.software_agent {
-        bag_info.add_software_agent(agent)?;
-    }
-    if let Some(group_id) = cmd.bag_group_identifier {
-        bag_info.add_bag_group_identifier(group_id)?;
-    }
-    if let Some(count) = cmd.bag_count {
-        bag_info.add_bag_count(count)?;
-    }
-    if let Some(size) = cmd.bag_size {
-        bag_info.add_bag_size(size)?;
-    }
+    /// Value of the Bagging-Date tag in bag-info.txt
+    ///
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_bagging_date))]
+    pub bagging_date: Option<String>,
 
-    for org in cmd.source_organization {
-        bag_info.add_source_organization(org)?;
-    }
-    for address in cmd.organization_address {
-        bag_info.add_organization_address(address)?;
-    }
-    for name in cmd.contact_name {
-        bag_info.add_contact_name(name)?;
-    }
-    for phone in cmd.contact_phone {
-        bag_info.add_contact_phone(phone)?;
-    }
-    for email in cmd.contact_email {
-        bag_info.add_contact_email(email)?;
-    }
-    for desc in cmd.external_description {
-        bag_info.add_external_description(desc)?;
-    }
-    for id in cmd.external_identifier {
-        bag_info.add_external_identifier(id)?;
-    }
-    for desc in cmd.internal_sender_description {
-        bag_info.add_internal_sender_description(desc)?;
-    }
-    for id in cmd.internal_sender_identifier {
-        bag_info.add_internal_sender_identifier(id)?;
-    }
-    for profile in cmd.bagit_profile_identifier {
-        bag_info.add_bagit_profile_identifier(profile)?;
-    }
+    /// Value of the Bag-Software-Agent tag in bag-info.txt
+    ///
+    /// Defaults to this bagr version
+    #[clap(long, value_name = "AGENT")]
+    pub software_agent: Option<String>,
+}
 
-    for tag in cmd.tag {
-        let split = tag.split_once(':').ok_or_else(|| InvalidTagLine {
-            details: format!("Label and value must be separated by a ':'. Found: {}", tag),
-        })?;
-        bag_info.add_tag(split.0.trim(), split.1.trim())?;
-    }
+/// Remove files from an existing bag's payload
+///
+/// The files' entries are removed from the payload manifests, and Payload-Oxum and the tag
+/// manifests are updated. Directories that are left empty are removed.
+#[derive(Args, Debug)]
+pub struct RmCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
 
-    create_bag(
-        cmd.source.clone(),
-        cmd.destination.unwrap_or(cmd.source),
-        bag_info,
-        &map_algorithms(&cmd.digest_algorithm),
-        !cmd.exclude_hidden_files,
-    )
-}
+    /// Paths, relative to the payload directory, of the files to remove
+    #[clap(value_name = "PAYLOAD_PATH", required = true)]
+    pub paths: Vec<PathBuf>,
 
-fn exec_rebag(cmd: RebagCmd) -> Result<Bag> {
-    let bag = open_bag(cmd.bag_path)?;
-    info!("Opened bag: {:?}", bag);
+    /// Recalculate the digests of every payload file instead of only the files that changed
+    #[clap(long)]
+    pub rehash_all: bool,
 
-    bag.update()
-        .recalculate_payload_manifests(!cmd.only_tags)
+    /// Update the bag even though another bagr process holds its lock
+    ///
+    /// Bags are locked while they're updated. Only use this to remove a stale lock, left in
+    /// .bagr.lock by a process that did not exit cleanly.
+    #[clap(long)]
+    pub force: bool,
+
+    /// Percent encode all control characters in manifest file paths, not only CR and LF
+    ///
+    /// This must be used if the bag was created with --strict-encoding.
+    #[clap(long)]
+    pub strict_encoding: bool,
+
+    /// Value of the Bagging-Date tag in bag-info.txt
+    ///
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_bagging_date))]
+    pub bagging_date: Option<String>,
+
+    /// Value of the Bag-Software-Agent tag in bag-info.txt
+    ///
+    /// Defaults to this bagr version
+    #[clap(long, value_name = "AGENT")]
+    pub software_agent: Option<String>,
+}
+
+/// Reverse the creation of a bag in place
+///
+/// The bag is validated, and then the files in its payload directory are moved up to its base
+/// directory and all of its tag files, including the manifests, are deleted. Nothing is changed
+/// if the bag is invalid.
+#[derive(Args, Debug)]
+pub struct UnbagCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+}
+
+/// Validate that a bag is complete and that its payload matches its manifests
+///
+/// The exit code is 0 if the bag is valid, 1 if it is not, and 3 if no errors were found but the
+/// time limit was reached before every file was verified
+#[derive(Args, Debug)]
+pub struct ValidateCmd {
+    /// Absolute or relative path to the bag's base directory
+    ///
+    /// Bags in S3, or an S3 compatible object store, are validated in place when this is an S3
+    /// URL, eg. s3://bucket/path/bag. Credentials are read from AWS_ACCESS_KEY_ID and
+    /// AWS_SECRET_ACCESS_KEY. Bags serialized in zip or tar archives are validated without
+    /// extracting them when this is the path to an archive, eg. bag.zip.
+    #[clap(value_name = "BAG_PATH", required_unless_present = "stdin-format")]
+    pub bag_path: Option<PathBuf>,
+
+    /// The region the bucket is in, when the bag is in S3
+    #[clap(
+        long,
+        value_name = "REGION",
+        env = "AWS_REGION",
+        default_value = "us-east-1"
+    )]
+    pub region: String,
+
+    /// Base URL of an S3 compatible object store, eg. http://localhost:9000
+    #[clap(long, value_name = "URL")]
+    pub endpoint: Option<String>,
+
+    /// Read a serialized bag from stdin instead of a directory
+    ///
+    /// The archive is validated as it's read, without writing it to disk. Payload files that
+    /// precede the manifests in the archive are hashed using every supported algorithm, so
+    /// archives that list the tag files first are validated faster.
+    #[clap(
+        arg_enum,
+        long,
+        value_name = "FORMAT",
+        conflicts_with_all = &[
+            "bag-path",
+            "sample-rate",
+            "sample",
+            "sample-count",
+            "warn-suspicious-files"
+        ]
+    )]
+    pub stdin_format: Option<StdinFormat>,
+
+    /// Only check that the bag is complete; do not verify file digests
+    #[clap(long)]
+    pub skip_fixity: bool,
+
+    /// Only compare the number and total size of the payload files to the Payload-Oxum
+    ///
+    /// Manifests are not read and no digests are calculated. This is a cheap sanity check for
+    /// large bags before a full validation.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "stdin-format",
+            "skip-fixity",
+            "digest-algorithm",
+            "sample-rate",
+            "sample",
+            "sample-count",
+            "max-duration",
+            "warn-suspicious-files"
+        ]
+    )]
+    pub oxum_only: bool,
+
+    /// Digest algorithms to validate
+    ///
+    /// By default, all of the bag's manifests are validated. If algorithms are specified here,
+    /// then manifests that use other algorithms are ignored, and the bag must have a payload
+    /// manifest for every specified algorithm.
+    #[clap(
+        arg_enum,
+        short = 'a',
+        long,
+        value_name = "ALGORITHM",
+        ignore_case = true,
+        multiple_occurrences = true
+    )]
+    pub digest_algorithm: Vec<DigestAlgorithm>,
+
+    /// Fraction of payload files, between 0 and 1, to verify the digests of
+    ///
+    /// Files are selected randomly. Tag files are always verified.
+    #[clap(
+        long,
+        value_name = "RATE",
+        default_value = "1",
+        hide_default_value = true,
+        conflicts_with = "skip-fixity"
+    )]
+    pub sample_rate: f64,
+
+    /// Percentage of payload files to verify the digests of, for example 5%
+    ///
+    /// Files are selected randomly. The number of files that were sampled and the seed that
+    /// selected them are reported. Tag files are always verified.
+    #[clap(
+        long,
+        value_name = "PERCENT",
+        parse(try_from_str = parse_percent),
+        conflicts_with_all = &["skip-fixity", "sample-rate"]
+    )]
+    pub sample: Option<f64>,
+
+    /// Number of payload files to verify the digests of
+    ///
+    /// Files are selected randomly. Tag files are always verified.
+    #[clap(
+        long,
+        value_name = "COUNT",
+        conflicts_with_all = &["skip-fixity", "sample-rate", "sample"]
+    )]
+    pub sample_count: Option<usize>,
+
+    /// Seed used to select the sampled payload files
+    ///
+    /// Sampling a bag with the same seed always selects the same files, so a sample can be
+    /// repeated. A random seed is used by default.
+    #[clap(long, value_name = "SEED")]
+    pub sample_seed: Option<u64>,
+
+    /// Maximum number of threads to use when calculating digests
+    ///
+    /// Defaults to one thread per CPU, unless threads is set in the config file or BAGR_THREADS
+    #[clap(short = 'j', long, value_name = "THREADS")]
+    pub threads: Option<usize>,
+
+    /// Stop validating as soon as the first error is found
+    #[clap(long)]
+    pub fail_fast: bool,
+
+    /// Maximum amount of time to spend validating, for example 2h, 30m, 90s, or 1h30m
+    ///
+    /// Once it has elapsed, no more files are hashed and the files that were not verified are
+    /// listed. Plain numbers are interpreted as seconds.
+    #[clap(
+        long,
+        value_name = "DURATION",
+        parse(try_from_str = parse_duration),
+        conflicts_with = "skip-fixity"
+    )]
+    pub max_duration: Option<Duration>,
+
+    /// Number of files to read ahead of the threads that hash them
+    ///
+    /// Useful for bags on network filesystems or object storage mounts, where the latency of
+    /// opening and reading files dominates. Files are read concurrently in manifest order.
+    #[clap(
+        long,
+        value_name = "FILES",
+        default_value = "0",
+        hide_default_value = true,
+        conflicts_with_all = &["skip-fixity", "stdin-format"]
+    )]
+    pub prefetch: usize,
+
+    /// Maximum amount of memory that prefetched files may occupy
+    ///
+    /// Files that are larger are read while they're hashed. Sizes use decimal units, like MB, or
+    /// binary units, like MiB.
+    #[clap(
+        long,
+        value_name = "SIZE",
+        default_value = "256MiB",
+        parse(try_from_str = parse_size)
+    )]
+    pub prefetch_memory: u64,
+
+    /// Report all warnings as errors
+    #[clap(long)]
+    pub warnings_as_errors: bool,
+
+    /// Report the issues with an issue code at a different level, for example
+    /// MissingTagManifests=error
+    ///
+    /// CODE is the name of an issue code, such as DigestMismatch, SuspiciousFile, or
+    /// WeakAlgorithms, and LEVEL is ignore, warn, or error. Rules take precedence over
+    /// --warnings-as-errors and the validation-rules in the config file. Ignoring errors can make
+    /// an invalid bag valid.
+    #[clap(
+        long,
+        value_name = "CODE=LEVEL",
+        parse(try_from_str = parse_rule),
+        multiple_occurrences = true,
+        conflicts_with = "oxum-only"
+    )]
+    pub rule: Vec<(String, RuleLevel)>,
+
+    /// Check the structure of the tag files when the bag does not have tag manifests
+    ///
+    /// Tag manifests are optional, but without them the contents of the tag files cannot be
+    /// verified. When they're missing, a warning is reported, and tag files that are empty or have
+    /// an extension that does not match their detected format are reported.
+    #[clap(long, conflicts_with_all = &["stdin-format", "oxum-only"])]
+    pub check_tag_structure: bool,
+
+    /// Verify unchanged payload files using the bag's cached digests
+    ///
+    /// Files whose size and modification time match the cache are not hashed, which makes
+    /// periodic fixity checks much faster, but corruption that preserves both is not detected.
+    /// Bags in S3 are always hashed.
+    #[clap(long, conflicts_with_all = &["stdin-format", "oxum-only", "skip-fixity"])]
+    pub digest_cache: bool,
+
+    /// Password to decrypt an encrypted zip archive with
+    ///
+    /// Other users may be able to see passwords on the command line, so prefer --key-file or
+    /// BAGR_ARCHIVE_PASSWORD.
+    #[clap(
+        long,
+        value_name = "PASSWORD",
+        env = "BAGR_ARCHIVE_PASSWORD",
+        hide_env_values = true,
+        conflicts_with = "key-file"
+    )]
+    pub password: Option<String>,
+
+    /// File that contains the password to decrypt an encrypted zip archive with
+    ///
+    /// A trailing line ending is not part of the password.
+    #[clap(long, value_name = "PATH")]
+    pub key_file: Option<PathBuf>,
+
+    /// Print the absolute path of the affected file below each issue
+    ///
+    /// Issues always identify files by their paths relative to the bag's base directory
+    #[clap(long, conflicts_with = "stdin-format")]
+    pub absolute_paths: bool,
+
+    /// Warn about payload files that are empty or have an extension that does not match their
+    /// detected format
+    ///
+    /// These often indicate truncated transfers.
+    #[clap(long)]
+    pub warn_suspicious_files: bool,
+
+    /// Warn about payload paths that would break on other platforms
+    ///
+    /// This includes names that Windows reserves, such as CON or nul.txt, names that end in a dot
+    /// or space, characters that are invalid on Windows, and paths that are too long.
+    #[clap(long, conflicts_with = "stdin-format")]
+    pub check_portability: bool,
+
+    /// Longest payload path, relative to the bag's base directory, that is portable
+    #[clap(
+        long,
+        value_name = "LENGTH",
+        default_value = "200",
+        requires = "check-portability"
+    )]
+    pub max_path_length: usize,
+
+    /// Decode all percent encoded control characters in manifest file paths, and report
+    /// manifest file paths that contain unencoded control characters as errors
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Rewrite the tag files of a bag that uses a legacy character encoding as UTF-8
+///
+/// Bags with tag files in other encodings, such as ISO-8859-1 or UTF-16, can be read, but any
+/// changes to them are written as UTF-8. The bag's payload and bag-info.txt metadata are not changed.
+#[derive(Args, Debug)]
+pub struct UpgradeCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+}
+
+/// Fix problems with a bag that do not require changing its payload
+#[derive(Args, Debug)]
+pub struct RepairCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Generate tag manifests if the bag does not have any
+    ///
+    /// They use the same digest algorithms as the payload manifests. No other files are changed.
+    #[clap(long, required = true)]
+    pub add_tagmanifests: bool,
+
+    /// Percent encode all control characters in tag manifest file paths, not only CR and LF
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Download the payload files that are listed in a bag's fetch.txt
+///
+/// Files are downloaded concurrently and verified against the payload manifests. Interrupted
+/// downloads are resumed, when the server supports range requests, the next time the command is
+/// run. Files that are fetched are removed from fetch.txt, which is deleted once it's empty.
+///
+/// fetch.txt may come from an untrusted source, so files are only fetched from https URLs unless
+/// other schemes are allowed.
+#[derive(Args, Debug)]
+pub struct FetchCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Maximum number of files to download at the same time
+    ///
+    /// Defaults to one thread per CPU
+    #[clap(
+        short = 'j',
+        long,
+        value_name = "THREADS",
+        default_value = "0",
+        hide_default_value = true
+    )]
+    pub threads: usize,
+
+    /// Number of times to retry a download that fails
+    #[clap(long, value_name = "RETRIES", default_value = "5")]
+    pub retries: u32,
+
+    /// URL scheme that files may be fetched from: https, http, or file
+    ///
+    /// Only https URLs are allowed by default, or the fetch-schemes in the config file. May be
+    /// specified multiple times, and replaces the schemes in the config file.
+    #[clap(long, value_name = "SCHEME", multiple_occurrences = true)]
+    pub allow_scheme: Vec<String>,
+
+    /// Glob pattern of the hosts that files may be fetched from, eg. '*.example.org'
+    ///
+    /// Every host is allowed by default, or the fetch-hosts in the config file. May be specified
+    /// multiple times, and replaces the hosts in the config file.
+    #[clap(long, value_name = "PATTERN", multiple_occurrences = true)]
+    pub allow_host: Vec<String>,
+
+    /// Percent encode all control characters in file paths, not only CR and LF
+    ///
+    /// This must be used if the bag was created with --strict-encoding.
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Serialize a bag into a tar archive
+///
+/// The bag is written within a top-level directory that has the same name as the bag's base
+/// directory, and its tag files are written before its payload.
+#[derive(Args, Debug)]
+pub struct PackageCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Path to write the archive to, eg. bag.tar or bag.zip
+    ///
+    /// The bag is written as a zip archive when the path ends in .zip, and as a tar archive
+    /// otherwise.
+    #[clap(value_name = "ARCHIVE")]
+    pub archive: PathBuf,
+
+    /// Split the archive into parts that are at most this size, eg. 4.7GB or 5TB
+    ///
+    /// Parts are named by appending a sequence number to the archive path, eg. bag.tar.001, and
+    /// are listed, along with their sizes and digests, in an index file, eg. bag.tar.index. Use
+    /// `bagr unpack` on the index file to reassemble the bag. Sizes use decimal units, like GB,
+    /// or binary units, like GiB. Plain numbers are interpreted as bytes.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub part_size: Option<u64>,
+
+    /// Password to encrypt the archive with
+    ///
+    /// Every file in the archive is encrypted using AES-256. Only zip archives, eg. bag.zip, can
+    /// be encrypted. Other users may be able to see passwords on the command line, so prefer
+    /// --key-file or BAGR_ARCHIVE_PASSWORD.
+    #[clap(
+        long,
+        value_name = "PASSWORD",
+        env = "BAGR_ARCHIVE_PASSWORD",
+        hide_env_values = true,
+        conflicts_with = "key-file"
+    )]
+    pub password: Option<String>,
+
+    /// File that contains the password to encrypt the archive with
+    ///
+    /// A trailing line ending is not part of the password.
+    #[clap(long, value_name = "PATH")]
+    pub key_file: Option<PathBuf>,
+}
+
+/// Extract a bag that was serialized with `bagr package`
+///
+/// The parts of split archives are reassembled, and the size and digest of each part are
+/// verified against the archive's index as it's read.
+#[derive(Args, Debug)]
+pub struct UnpackCmd {
+    /// Path to the archive, or the index file of a split archive, eg. bag.tar.index
+    #[clap(value_name = "ARCHIVE")]
+    pub archive: PathBuf,
+
+    /// Directory to extract the bag into
+    ///
+    /// Defaults to the current directory
+    #[clap(
+        value_name = "DEST_DIR",
+        default_value = ".",
+        hide_default_value = true
+    )]
+    pub destination: PathBuf,
+
+    /// Password to decrypt an encrypted zip archive with
+    ///
+    /// Other users may be able to see passwords on the command line, so prefer --key-file or
+    /// BAGR_ARCHIVE_PASSWORD.
+    #[clap(
+        long,
+        value_name = "PASSWORD",
+        env = "BAGR_ARCHIVE_PASSWORD",
+        hide_env_values = true,
+        conflicts_with = "key-file"
+    )]
+    pub password: Option<String>,
+
+    /// File that contains the password to decrypt an encrypted zip archive with
+    ///
+    /// A trailing line ending is not part of the password.
+    #[clap(long, value_name = "PATH")]
+    pub key_file: Option<PathBuf>,
+}
+
+/// Split a bag into a group of smaller bags
+///
+/// Payload files are distributed in path order, and a new bag is started whenever the next file
+/// would make the current bag's payload larger than the maximum size. Every bag gets a copy of
+/// bag-info.txt and the other tag files, a shared Bag-Group-Identifier, and a Bag-Count, eg.
+/// '1 of 3'. The bags are named after the original bag with a sequence number appended, eg.
+/// bag-1. Files are verified against the original manifests as they're copied.
+#[derive(Args, Debug)]
+pub struct SplitCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Directory to write the new bags to
+    #[clap(value_name = "DEST_DIR")]
+    pub destination: PathBuf,
+
+    /// Maximum size of each bag's payload, eg. 100GB
+    ///
+    /// Sizes use decimal units, like GB, or binary units, like GiB. Plain numbers are interpreted
+    /// as bytes.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub max_size: u64,
+
+    /// Value of the Bag-Group-Identifier tag in bag-info.txt
+    ///
+    /// Defaults to the bag's External-Identifier, or the name of its base directory
+    #[clap(long, value_name = "GROUP_ID")]
+    pub bag_group_identifier: Option<String>,
+
+    /// Value of the Bagging-Date tag in bag-info.txt
+    ///
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_bagging_date))]
+    pub bagging_date: Option<String>,
+
+    /// Fold lines in bag-info.txt that are longer than this many characters
+    ///
+    /// Long values are wrapped onto indented continuation lines. Use 0 to disable folding.
+    #[clap(long, value_name = "WIDTH", default_value = "79")]
+    pub tag_line_width: usize,
+
+    /// Percent encode all control characters in file paths, not only CR and LF
+    ///
+    /// This must be used if the bag was created with --strict-encoding.
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Merge a group of bags, such as the bags written by `bagr split`, into a single bag
+///
+/// Every bag must have the same Bag-Group-Identifier, and together their Bag-Counts must number
+/// the bags from 1 to the total. It is an error if a payload path is used by more than one bag.
+/// The new bag gets the first bag's bag-info.txt, without the group tags, and the tag files of
+/// every bag. Files are verified against their bag's manifests as they're copied.
+#[derive(Args, Debug)]
+pub struct MergeCmd {
+    /// Absolute or relative paths to the base directories of the bags in the group
+    #[clap(value_name = "BAG_PATH", required = true)]
+    pub bag_paths: Vec<PathBuf>,
+
+    /// Absolute or relative path to the merged bag's base directory
+    #[clap(short, long, value_name = "DEST_DIR")]
+    pub destination: PathBuf,
+
+    /// Value of the Bagging-Date tag in bag-info.txt
+    ///
+    /// Defaults to the current date. Accepts YYYY-MM-DD, ISO 8601 date-times, 'today', and
+    /// relative dates like 'now-1d'. The date is always written as YYYY-MM-DD.
+    #[clap(long, value_name = "DATE", parse(try_from_str = parse_bagging_date))]
+    pub bagging_date: Option<String>,
+
+    /// Fold lines in bag-info.txt that are longer than this many characters
+    ///
+    /// Long values are wrapped onto indented continuation lines. Use 0 to disable folding.
+    #[clap(long, value_name = "WIDTH", default_value = "79")]
+    pub tag_line_width: usize,
+
+    /// Percent encode all control characters in file paths, not only CR and LF
+    ///
+    /// This must be used if the bags were created with --strict-encoding.
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Copy a bag to a new location and validate the copy
+///
+/// Every file is hashed as it's written to the destination, and the digests are compared to the
+/// bag's manifests, so the copy is validated without reading it back. The copy is retained when
+/// it's invalid, and is deleted when it could not be completed. The exit code is 0 if the copy is
+/// valid, and 1 if it is not.
+#[derive(Args, Debug)]
+pub struct CopyCmd {
+    /// Absolute or relative path to the base directory of the bag to copy
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Absolute or relative path to the directory to copy the bag to
+    ///
+    /// The directory must either not exist or be empty
+    #[clap(short, long, value_name = "DST_DIR")]
+    pub destination: PathBuf,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+
+    /// Report all warnings as errors
+    #[clap(long)]
+    pub warnings_as_errors: bool,
+}
+
+/// Upload a packaged bag to S3, or an S3 compatible object store
+///
+/// The archive is uploaded using a multipart upload. S3 verifies the SHA-256 checksum of every
+/// part, and parts that fail to upload are retried. The upload's progress is recorded in a state
+/// file, so that an interrupted upload is resumed when the command is run again. Credentials are
+/// read from the AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and AWS_SESSION_TOKEN environment
+/// variables.
+#[derive(Args, Debug)]
+pub struct UploadCmd {
+    /// Path to the archive to upload, eg. bag.tar
+    #[clap(value_name = "ARCHIVE")]
+    pub archive: PathBuf,
+
+    /// Where to upload the archive to, eg. s3://bucket/path/bag.tar
+    #[clap(value_name = "S3_URL", parse(try_from_str = parse_s3_url))]
+    pub destination: (String, String),
+
+    /// The region the bucket is in
+    #[clap(
+        long,
+        value_name = "REGION",
+        env = "AWS_REGION",
+        default_value = "us-east-1"
+    )]
+    pub region: String,
+
+    /// Base URL of an S3 compatible object store, eg. http://localhost:9000
+    #[clap(long, value_name = "URL")]
+    pub endpoint: Option<String>,
+
+    /// Size of the parts to upload the archive in
+    ///
+    /// Must be at least 5MiB. Sizes use decimal units, like MB, or binary units, like MiB.
+    #[clap(
+        long,
+        value_name = "SIZE",
+        default_value = "64MiB",
+        parse(try_from_str = parse_size)
+    )]
+    pub part_size: u64,
+
+    /// Number of times to retry a part that fails to upload
+    #[clap(long, value_name = "RETRIES", default_value = "5")]
+    pub retries: u32,
+
+    /// File to record the upload's progress in
+    ///
+    /// Defaults to the archive path with an .upload-state extension
+    #[clap(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+}
+
+/// Record the digests of the payload files in bags in an inventory database
+///
+/// Bags that were previously indexed are re-indexed.
+#[derive(Args, Debug)]
+pub struct IndexContentCmd {
+    /// Absolute or relative paths to the base directories of the bags to index
+    #[clap(value_name = "BAG_PATH", required = true)]
+    pub bag_paths: Vec<PathBuf>,
+
+    /// Path to the inventory database, which is created if it does not exist
+    #[clap(
+        short,
+        long,
+        value_name = "DB_PATH",
+        default_value = "bagr-inventory.db"
+    )]
+    pub database: PathBuf,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Find the payload files in an inventory database that have a digest
+///
+/// The exit code is 0 if a file is found and 1 if one is not
+#[derive(Args, Debug)]
+pub struct FindCmd {
+    /// Hex encoded digest to search for
+    #[clap(long, value_name = "DIGEST")]
+    pub digest: String,
+
+    /// Only match digests that were calculated using this algorithm
+    #[clap(
+        arg_enum,
+        short = 'a',
+        long,
+        value_name = "ALGORITHM",
+        ignore_case = true
+    )]
+    pub digest_algorithm: Option<DigestAlgorithm>,
+
+    /// Path to the inventory database
+    #[clap(
+        short,
+        long,
+        value_name = "DB_PATH",
+        default_value = "bagr-inventory.db"
+    )]
+    pub database: PathBuf,
+}
+
+/// Summarize the composition of a bag's payload
+///
+/// Files are counted from the payload manifests, and are grouped by the top-level payload
+/// directory that contains them. Sizes are read from disk.
+#[derive(Args, Debug)]
+pub struct StatsCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Report payload files that have the same content
+///
+/// Files are grouped by their digests in the bag's strongest payload manifest, so the payload is
+/// not read. Sizes are read from disk, and empty files are not reported. The potential savings
+/// are the bytes that would be freed by keeping only one copy of each file.
+#[derive(Args, Debug)]
+pub struct DedupeReportCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Also write the duplicate files to a CSV file, one row per file
+    ///
+    /// The columns are digest, size, copies, and path.
+    #[clap(long, value_name = "CSV_PATH")]
+    pub csv: Option<PathBuf>,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Export a bag's payload manifests to a checksum file format
+///
+/// The exported file can be used to verify the payload with tools that do not support BagIt, eg.
+/// 'sha256sum -c' or 'hashdeep -a -k', from the bag's base directory. The payload is not read, but
+/// the hashdeep and csv formats include the size of each file on disk.
+#[derive(Args, Debug)]
+pub struct ExportChecksumsCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// The format to export to
+    ///
+    /// The sum formats require a payload manifest for their algorithm. The hashdeep format
+    /// includes the bag's md5, sha1, and sha256 digests, and the csv format includes every
+    /// manifest's digests.
+    #[clap(short, long, arg_enum, value_name = "FORMAT")]
+    pub format: ChecksumFileFormat,
+
+    /// Path to write the checksum file to, or '-' for stdout
+    #[clap(short, long, value_name = "PATH", default_value = "-")]
+    pub output: PathBuf,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// List the files in a bag's manifests, with their sizes and digests
+///
+/// The digests are read from the manifests and the sizes from the filesystem, so nothing is
+/// hashed. Files that are listed in a manifest but do not exist do not have a size.
+#[derive(Args, Debug)]
+pub struct LsCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Glob patterns to list only the files that match, eg. 'data/images/**' or '*.tif'
+    ///
+    /// Patterns are matched against paths relative to the bag's base directory, and patterns
+    /// that do not contain a '/' match file names at any depth. Every file is listed if no
+    /// patterns are specified.
+    #[clap(value_name = "PATTERN")]
+    pub patterns: Vec<String>,
+
+    /// Also list the files in the tag manifests
+    #[clap(long)]
+    pub tags: bool,
+
+    /// The format to list the files in
+    #[clap(short, long, arg_enum, value_name = "FORMAT", default_value = "tsv")]
+    pub format: ListFileFormat,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Check that replicas of a bag are intact and identical
+///
+/// Every replica is validated, verifying the digests of a random sample of its payload files, and
+/// its payload manifests are compared to the first replica's. The exit code is 0 if every replica
+/// is healthy, and 1 if any are not.
+#[derive(Args, Debug)]
+pub struct CompareReplicasCmd {
+    /// Absolute or relative paths to the base directories of the replicas
+    #[clap(value_name = "BAG_PATH", required = true, min_values = 2)]
+    pub bag_paths: Vec<PathBuf>,
+
+    /// Fraction of payload files, between 0 and 1, to verify the digests of in each replica
+    ///
+    /// Files are selected randomly for each replica. Tag files are always verified.
+    #[clap(long, value_name = "RATE", default_value = "0.1")]
+    pub sample_rate: f64,
+
+    /// Maximum number of threads to use when calculating digests
+    ///
+    /// Defaults to one thread per CPU, unless threads is set in the config file or BAGR_THREADS
+    #[clap(short = 'j', long, value_name = "THREADS")]
+    pub threads: Option<usize>,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+
+    /// Print the absolute path of the affected file below each issue
+    #[clap(long)]
+    pub absolute_paths: bool,
+}
+
+/// Verify the digests of the payload files that were verified least recently
+///
+/// When each payload file was last verified is recorded in a state file, and every audit starts
+/// with the files that have never been verified, followed by the files that were verified the
+/// longest time ago. Running an audit regularly with a time or byte budget verifies the whole
+/// bag over time. The exit code is 0 if no problems were found, and 1 if any were.
+#[derive(Args, Debug)]
+pub struct AuditCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Path to the file that records when files were last verified
+    ///
+    /// Defaults to .bagr.audit in the bag's base directory
+    #[clap(long, value_name = "STATE_FILE")]
+    pub state: Option<PathBuf>,
+
+    /// Maximum amount of time to spend hashing files, for example 2h, 30m, 90s, or 1h30m
+    ///
+    /// Files that are not verified before it elapses are left for the next audit.
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub max_duration: Option<Duration>,
+
+    /// Maximum number of bytes to hash, for example 500GB or 2TiB
+    ///
+    /// At least one file is always verified, even if it's larger than the budget.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub max_bytes: Option<u64>,
+
+    /// Maximum number of threads to use when calculating digests
+    ///
+    /// Defaults to one thread per CPU, unless threads is set in the config file or BAGR_THREADS
+    #[clap(short = 'j', long, value_name = "THREADS")]
+    pub threads: Option<usize>,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Compare the payloads and bag-info.txt tags of two bags
+///
+/// Payload files are compared by the digests in the bags' payload manifests, unless --content is
+/// specified. The exit code is 0 if the bags are the same, and 1 if they differ.
+#[derive(Args, Debug)]
+pub struct DiffCmd {
+    /// Absolute or relative path to the first bag's base directory
+    #[clap(value_name = "LEFT_BAG")]
+    pub left: PathBuf,
+
+    /// Absolute or relative path to the second bag's base directory
+    #[clap(value_name = "RIGHT_BAG")]
+    pub right: PathBuf,
+
+    /// Compare the digests of the payload files instead of the bags' manifests
+    ///
+    /// This is slower, but finds files that do not match their manifests, and works for bags
+    /// that do not have a digest algorithm in common.
+    #[clap(long)]
+    pub content: bool,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Get or edit the tags in a bag's bag-info.txt
+///
+/// Edits only rewrite bag-info.txt and the tag manifests. The payload is not rehashed, and
+/// Bagging-Date and Bag-Software-Agent are not updated.
+#[derive(Args, Debug)]
+pub struct TagCmd {
+    #[clap(subcommand)]
+    pub command: TagCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagCommand {
+    /// Print every value of a tag, one per line
+    ///
+    /// The exit code is 1 if the bag does not have the tag.
+    #[clap(name = "get")]
+    Get(TagGetCmd),
+    /// Replace every value of a tag
+    #[clap(name = "set")]
+    Set(TagSetCmd),
+    /// Add a value to a tag, keeping its existing values
+    ///
+    /// Tags that the BagIt spec does not allow to be repeated, such as Bagging-Date, are replaced
+    /// instead.
+    #[clap(name = "append")]
+    Append(TagSetCmd),
+    /// Remove every value of a tag
+    #[clap(name = "remove")]
+    Remove(TagGetCmd),
+}
+
+#[derive(Args, Debug)]
+pub struct TagGetCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Tag label. Labels are case insensitive.
+    #[clap(value_name = "LABEL")]
+    pub label: String,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TagSetCmd {
+    /// Absolute or relative path to the bag's base directory
+    #[clap(value_name = "BAG_PATH")]
+    pub bag_path: PathBuf,
+
+    /// Tag label. Labels are case insensitive.
+    #[clap(value_name = "LABEL")]
+    pub label: String,
+
+    /// Tag value
+    #[clap(value_name = "VALUE")]
+    pub value: String,
+
+    /// Decode all percent encoded control characters in manifest file paths
+    #[clap(long)]
+    pub strict_encoding: bool,
+}
+
+/// Show or create the config file
+#[derive(Args, Debug)]
+pub struct ConfigCmd {
+    #[clap(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective value of every setting, and where it comes from
+    ///
+    /// Settings come from, in order of precedence, command line flags, environment variables, the
+    /// config file, and their defaults.
+    #[clap(name = "show")]
+    Show,
+    /// Write a config file with the default settings
+    #[clap(name = "init")]
+    Init(ConfigInitCmd),
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigInitCmd {
+    /// Overwrite the config file if it already exists
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy)]
+pub enum StdinFormat {
+    Tar,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy)]
+pub enum ChecksumFileFormat {
+    Md5sum,
+    Sha1sum,
+    Sha256sum,
+    Sha512sum,
+    Hashdeep,
+    Csv,
+}
+
+impl From<ChecksumFileFormat> for ChecksumFormat {
+    fn from(format: ChecksumFileFormat) -> Self {
+        match format {
+            ChecksumFileFormat::Md5sum => ChecksumFormat::Md5sum,
+            ChecksumFileFormat::Sha1sum => ChecksumFormat::Sha1sum,
+            ChecksumFileFormat::Sha256sum => ChecksumFormat::Sha256sum,
+            ChecksumFileFormat::Sha512sum => ChecksumFormat::Sha512sum,
+            ChecksumFileFormat::Hashdeep => ChecksumFormat::Hashdeep,
+            ChecksumFileFormat::Csv => ChecksumFormat::Csv,
+        }
+    }
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy)]
+pub enum ListFileFormat {
+    Tsv,
+    Json,
+}
+
+impl From<ListFileFormat> for ListFormat {
+    fn from(format: ListFileFormat) -> Self {
+        match format {
+            ListFileFormat::Tsv => ListFormat::Tsv,
+            ListFileFormat::Json => ListFormat::Json,
+        }
+    }
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy)]
+pub enum WeakAlgorithms {
+    Allow,
+    Warn,
+    Forbid,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Sha3_256,
+    Sha3_512,
+    Blake2b256,
+    Blake2b512,
+}
+
+impl From<DigestAlgorithm> for BagItDigestAlgorithm {
+    fn from(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Md5 => BagItDigestAlgorithm::Md5,
+            DigestAlgorithm::Sha1 => BagItDigestAlgorithm::Sha1,
+            DigestAlgorithm::Sha256 => BagItDigestAlgorithm::Sha256,
+            DigestAlgorithm::Sha512 => BagItDigestAlgorithm::Sha512,
+            DigestAlgorithm::Sha3_256 => BagItDigestAlgorithm::Sha3_256,
+            DigestAlgorithm::Sha3_512 => BagItDigestAlgorithm::Sha3_512,
+            DigestAlgorithm::Blake2b256 => BagItDigestAlgorithm::Blake2b256,
+            DigestAlgorithm::Blake2b512 => BagItDigestAlgorithm::Blake2b512,
+        }
+    }
+}
+
+impl From<WeakAlgorithms> for WeakAlgorithmPolicy {
+    fn from(policy: WeakAlgorithms) -> Self {
+        match policy {
+            WeakAlgorithms::Allow => WeakAlgorithmPolicy::Allow,
+            WeakAlgorithms::Warn => WeakAlgorithmPolicy::Warn,
+            WeakAlgorithms::Forbid => WeakAlgorithmPolicy::Forbid,
+        }
+    }
+}
+
+const CONFIG_DIGEST_ALGORITHM: &str = "digest-algorithm";
+const CONFIG_EXCLUDE_HIDDEN_FILES: &str = "exclude-hidden-files";
+const CONFIG_EXCLUDE: &str = "exclude";
+const CONFIG_SOURCE_ORGANIZATION: &str = "source-organization";
+const CONFIG_CONTACT_EMAIL: &str = "contact-email";
+const CONFIG_THREADS: &str = "threads";
+const CONFIG_WEAK_ALGORITHMS: &str = "weak-algorithms";
+const CONFIG_SCAN_CMD: &str = "scan-cmd";
+const CONFIG_SCAN_WARN_ONLY: &str = "scan-warn-only";
+const CONFIG_HEARTBEAT: &str = "heartbeat";
+const CONFIG_VALIDATION_RULES: &str = "validation-rules";
+const CONFIG_FETCH_SCHEMES: &str = "fetch-schemes";
+const CONFIG_FETCH_HOSTS: &str = "fetch-hosts";
+
+/// Settings that can be set in the config file, environment variables, or on the command line.
+/// Command specific flags, like `bag --digest-algorithm`, are not reflected here, and take
+/// precedence over these values.
+#[derive(Debug)]
+pub struct Config {
+    /// The config file, if it exists
+    path: Option<PathBuf>,
+    /// The path the config file was expected at, when it does not exist
+    missing_path: Option<PathBuf>,
+    digest_algorithms: Setting<Vec<DigestAlgorithm>>,
+    exclude_hidden_files: Setting<bool>,
+    exclude: Setting<Vec<String>>,
+    source_organization: Setting<Vec<String>>,
+    contact_email: Setting<Vec<String>>,
+    threads: Setting<usize>,
+    weak_algorithms: Setting<WeakAlgorithms>,
+    scan_cmd: Setting<Option<String>>,
+    scan_warn_only: Setting<bool>,
+    heartbeat: Setting<Option<Duration>>,
+    validation_rules: Setting<Vec<(String, RuleLevel)>>,
+    fetch_schemes: Setting<Vec<String>>,
+    fetch_hosts: Setting<Vec<String>>,
+}
+
+/// The effective value of a setting, and where it came from
+#[derive(Debug)]
+struct Setting<T> {
+    label: &'static str,
+    value: T,
+    display: String,
+    source: SettingSource,
+}
+
+#[derive(Debug)]
+enum SettingSource {
+    Default,
+    ConfigFile,
+    Environment(String),
+    CommandLine,
+}
+
+fn main() {
+    let args = BagrArgs::parse();
+
+    let log_level = if args.quiet {
+        LevelFilter::Off
+    } else if args.verbose {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    };
+
+    env_logger::builder()
+        .filter_level(log_level)
+        .format_timestamp(None)
+        .format_module_path(false)
+        .format_target(false)
+        .init();
+
+    let config_path = args.config.clone().or_else(default_config_path);
+
+    // The config file is not loaded first, so that an invalid file can be replaced
+    if let Command::Config(ConfigCmd {
+        command: ConfigCommand::Init(cmd),
+    }) = &args.command
+    {
+        match exec_config_init(cmd, config_path.as_deref()) {
+            Ok(path) => {
+                if !args.quiet {
+                    println!("Wrote config file {}", path.display());
+                }
+            }
+            Err(e) => {
+                error!("Failed to write config file: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let config = match Config::load(&args, config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load config: {}", e);
+            exit(1);
+        }
+    };
+
+    let progress: Arc<dyn ProgressListener> = match config.heartbeat.value {
+        Some(interval) => Arc::new(Heartbeat::new(interval)),
+        None => Arc::new(NoProgress),
+    };
+    let weak_algorithms = config.weak_algorithms.value.into();
+    let scanner = config.scan_cmd.value.clone().map(|command| {
+        Arc::new(CommandScanner::new(command).warn_only(config.scan_warn_only.value))
+            as Arc<dyn FileScanner>
+    });
+
+    match args.command {
+        Command::Config(_) => println!("{}", config),
+        Command::Bag(cmd) => {
+            if let Err(e) = exec_bag(cmd, &config, progress, scanner, weak_algorithms) {
+                error!("Failed to create bag: {}", e);
+                exit(1);
+            }
+        }
+        Command::Rebag(cmd) => {
+            if let Err(e) = exec_rebag(
+                cmd,
+                progress,
+                scanner,
+                weak_algorithms,
+                config.threads.value,
+            ) {
+                if let MissingPayloadFiles { paths } = &e {
+                    for path in paths {
+                        error!("Missing payload file {}", path);
+                    }
+                    error!(
+                        "Failed to rebag: {}. Use --prune-missing to remove them from the \
+                        manifests",
+                        e
+                    );
+                } else {
+                    error!("Failed to rebag: {}", e);
+                }
+                exit(1);
+            }
+        }
+        Command::Add(cmd) => match exec_add(cmd, progress, scanner) {
+            Ok(summary) => {
+                if !args.quiet {
+                    println!("{}", summary);
+                }
+            }
+            Err(e) => {
+                error!("Failed to add files: {}", e);
+                exit(1);
+            }
+        },
+        Command::Rm(cmd) => match exec_rm(cmd) {
+            Ok(summary) => {
+                if !args.quiet {
+                    println!("{}", summary);
+                }
+            }
+            Err(e) => {
+                error!("Failed to remove files: {}", e);
+                exit(1);
+            }
+        },
+        Command::Unbag(cmd) => {
+            if let Err(e) = exec_unbag(cmd) {
+                if let InvalidBag { errors, .. } = &e {
+                    for issue in errors {
+                        error!("{}", issue);
+                    }
+                }
+                error!("Failed to unbag: {}", e);
+                exit(1);
+            }
+        }
+        Command::Upgrade(cmd) => {
+            if let Err(e) = exec_upgrade(cmd) {
+                error!("Failed to upgrade bag: {}", e);
+                exit(1);
+            }
+        }
+        Command::Repair(cmd) => match exec_repair(cmd) {
+            Ok(summary) => {
+                if !args.quiet {
+                    println!("{}", summary);
+                }
+            }
+            Err(e) => {
+                error!("Failed to repair bag: {}", e);
+                exit(1);
+            }
+        },
+        Command::Fetch(cmd) => match exec_fetch(cmd, &config) {
+            Ok(summary) => {
+                for e in summary.failed() {
+                    error!("{}", e);
+                }
+                if !args.quiet {
+                    println!("{}", summary);
+                }
+                if !summary.failed().is_empty() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Failed to fetch files: {}", e);
+                exit(1);
+            }
+        },
+        Command::Package(cmd) => {
+            if let Err(e) = exec_package(cmd) {
+                error!("Failed to package bag: {}", e);
+                exit(1);
+            }
+        }
+        Command::Unpack(cmd) => {
+            if let Err(e) = exec_unpack(cmd) {
+                error!("Failed to unpack bag: {}", e);
+                exit(1);
+            }
+        }
+        Command::Split(cmd) => match exec_split(cmd) {
+            Ok(bags) => {
+                if !args.quiet {
+                    for bag in bags {
+                        println!("{}", bag.base_dir().display());
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to split bag: {}", e);
+                exit(1);
+            }
+        },
+        Command::Merge(cmd) => match exec_merge(cmd) {
+            Ok(bag) => {
+                if !args.quiet {
+                    println!("{}", bag.base_dir().display());
+                }
+            }
+            Err(e) => {
+                error!("Failed to merge bags: {}", e);
+                exit(1);
+            }
+        },
+        Command::Copy(cmd) => match exec_copy(
+            cmd,
+            progress,
+            weak_algorithms,
+            &config.validation_rules.value,
+        ) {
+            Ok(result) => {
+                if !args.quiet {
+                    print_validation_result(&result, false, None);
+                }
+                if !result.is_valid() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Failed to copy bag: {}", e);
+                exit(1);
+            }
+        },
+        Command::Upload(cmd) => match exec_upload(cmd) {
+            Ok(summary) => {
+                if !args.quiet {
+                    println!("{}", summary);
+                }
+            }
+            Err(e) => {
+                error!("Failed to upload bag: {}", e);
+                exit(1);
+            }
+        },
+        Command::IndexContent(cmd) => {
+            if let Err(e) = exec_index_content(cmd) {
+                error!("Failed to index bag content: {}", e);
+                exit(1);
+            }
+        }
+        Command::Find(cmd) => match exec_find(cmd) {
+            Ok(files) => {
+                for file in &files {
+                    println!("{}", file.bag_path().join(file.path()).display());
+                }
+                if files.is_empty() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Failed to search inventory: {}", e);
+                exit(1);
+            }
+        },
+        Command::Stats(cmd) => match exec_stats(cmd) {
+            Ok(stats) => println!("{}", stats),
+            Err(e) => {
+                error!("Failed to read bag stats: {}", e);
+                exit(1);
+            }
+        },
+        Command::DedupeReport(cmd) => match exec_dedupe_report(cmd) {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                error!("Failed to report duplicate files: {}", e);
+                exit(1);
+            }
+        },
+        Command::ExportChecksums(cmd) => {
+            if let Err(e) = exec_export_checksums(cmd) {
+                error!("Failed to export checksums: {}", e);
+                exit(1);
+            }
+        }
+        Command::Ls(cmd) => {
+            if let Err(e) = exec_ls(cmd) {
+                error!("Failed to list files: {}", e);
+                exit(1);
+            }
+        }
+        Command::CompareReplicas(cmd) => {
+            let absolute_paths = cmd.absolute_paths;
+            match exec_compare_replicas(
+                cmd,
+                progress,
+                weak_algorithms,
+                &config.validation_rules.value,
+                config.threads.value,
+            ) {
+                Ok(report) => {
+                    if !args.quiet {
+                        print_replica_report(&report, absolute_paths);
+                    }
+                    if !report.is_healthy() {
+                        exit(1);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to compare replicas: {}", e);
+                    exit(1);
+                }
+            }
+        }
+        Command::Audit(cmd) => match exec_audit(
+            cmd,
+            progress,
+            weak_algorithms,
+            &config.validation_rules.value,
+            config.threads.value,
+        ) {
+            Ok(report) => {
+                if !args.quiet {
+                    print_audit_report(&report);
+                }
+                if !report.is_valid() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Failed to audit bag: {}", e);
+                exit(1);
+            }
+        },
+        Command::Diff(cmd) => match exec_diff(cmd) {
+            Ok(diff) => {
+                print_bag_diff(&diff);
+                if !diff.is_empty() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Failed to compare bags: {}", e);
+                exit(1);
+            }
+        },
+        Command::Tag(cmd) => match exec_tag(cmd) {
+            Ok(Some(values)) => {
+                for value in &values {
+                    println!("{}", value);
+                }
+                if values.is_empty() {
+                    exit(1);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to update tags: {}", e);
+                exit(1);
+            }
+        },
+        Command::Validate(cmd) => {
+            let oxum_only = cmd.oxum_only;
+            let base_dir = cmd
+                .bag_path
+                .as_deref()
+                .filter(|_| cmd.absolute_paths)
+                .map(absolute_base_dir);
+            match exec_validate(
+                cmd,
+                progress,
+                weak_algorithms,
+                &config.validation_rules.value,
+                config.threads.value,
+            ) {
+                Ok(result) => {
+                    if !args.quiet {
+                        print_validation_result(&result, oxum_only, base_dir.as_deref());
+                    }
+                    if !result.is_valid() {
+                        exit(1);
+                    } else if result.is_partial() {
+                        exit(3);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to validate bag: {}", e);
+                    exit(1);
+                }
+            }
+        }
+    }
+}
+
+fn exec_bag(
+    cmd: BagCmd,
+    config: &Config,
+    progress: Arc<dyn ProgressListener>,
+    scanner: Option<Arc<dyn FileScanner>>,
+    weak_algorithms: WeakAlgorithmPolicy,
+) -> Result<()> {
+    let mut builder = BagInfo::builder().expand_placeholders(true);
+
+    if let Some(date) = cmd.bagging_date {
+        builder = builder.with_bagging_date(date);
+    }
+    if let Some(agent) = cmd.software_agent {
+        builder = builder.with_software_agent(agent);
+    }
+    if let Some(group_id) = cmd.bag_group_identifier {
+        builder = builder.with_bag_group_identifier(group_id);
+    }
+    if let Some((number, total)) = cmd.bag_count {
+        builder = builder.with_bag_count(number, total);
+    }
+    if let Some(size) = cmd.bag_size {
+        builder = builder.with_bag_size(size);
+    }
+
+    let source_organization = if cmd.source_organization.is_empty() && cmd.info_template.is_none() {
+        config.source_organization.value.clone()
+    } else {
+        cmd.source_organization
+    };
+    for org in source_organization {
+        builder = builder.with_source_organization(org);
+    }
+    for address in cmd.organization_address {
+        builder = builder.with_organization_address(address);
+    }
+
+    // Contact details are paired by the order they're specified in
+    let mut names = cmd.contact_name.into_iter();
+    let mut phones = cmd.contact_phone.into_iter();
+    let contact_email =
+        if cmd.contact_email.is_empty() && cmd.contact.is_empty() && cmd.info_template.is_none() {
+            config.contact_email.value.clone()
+        } else {
+            cmd.contact_email
+        };
+    let mut emails = contact_email.into_iter();
+    loop {
+        let (name, phone, email) = (names.next(), phones.next(), emails.next());
+        if name.is_none() && phone.is_none() && email.is_none() {
+            break;
+        }
+        let mut contact = Contact::new();
+        if let Some(name) = name {
+            contact = contact.with_name(name);
+        }
+        if let Some(phone) = phone {
+            contact = contact.with_phone(phone);
+        }
+        if let Some(email) = email {
+            contact = contact.with_email(email);
+        }
+        builder = builder.with_contact(contact);
+    }
+    for contact in cmd.contact {
+        builder = builder.with_contact(contact);
+    }
+
+    for desc in cmd.external_description {
+        builder = builder.with_external_description(desc);
+    }
+    for id in cmd.external_identifier {
+        builder = builder.with_external_identifier(id);
+    }
+    for desc in cmd.internal_sender_description {
+        builder = builder.with_internal_sender_description(desc);
+    }
+    for id in cmd.internal_sender_identifier {
+        builder = builder.with_internal_sender_identifier(id);
+    }
+    for profile in cmd.bagit_profile_identifier {
+        builder = builder.with_bagit_profile_identifier(profile);
+    }
+
+    if let Some(template) = &cmd.info_template {
+        builder = builder.with_template_file(template)?;
+    }
+    for tag in cmd.tag {
+        let split = tag.split_once(':').ok_or_else(|| InvalidTagLine {
+            details: format!("Label and value must be separated by a ':'. Found: {}", tag),
+        })?;
+        builder = builder.with_tag(split.0.trim(), split.1.trim());
+    }
+
+    let bag_info = builder.build()?;
+
+    let name_transforms = cmd
+        .rename
+        .into_iter()
+        .fold(NameTransforms::new(), |transforms, transform| {
+            transforms.with_transform(transform)
+        });
+
+    // Bags that are written as tar archives are staged in a temporary directory
+    let staging_dir = match &cmd.to_tar {
+        Some(_) => Some(create_staging_dir("bagr-bag-")?),
+        None => None,
+    };
+    let dst_dir = match &staging_dir {
+        Some(staging_dir) => staging_dir.path().join(archived_bag_name(&cmd.source)?),
+        None => cmd.destination.unwrap_or_else(|| cmd.source.clone()),
+    };
+    let mut creator = BagCreator::new(&cmd.source, &dst_dir);
+    for (src, dest) in cmd.extra_tag_file {
+        creator = creator.with_tag_file(src, dest);
+    }
+    for (src, dest) in cmd.tag_dir {
+        creator = creator.with_tag_dir(src, dest);
+    }
+    if !cmd.import_checksums.is_empty() {
+        let mut imported = ImportedDigests::new().with_sample_rate(cmd.checksum_sample_rate);
+        for checksum_file in &cmd.import_checksums {
+            imported = imported.read_checksum_file(checksum_file)?;
+        }
+        creator = creator.with_imported_digests(Some(imported));
+    }
+    if let Some(list) = &cmd.files_from {
+        creator = creator.with_payload_files(read_file_list(list, &cmd.source)?);
+    }
+
+    let algorithms = if cmd.digest_algorithm.is_empty() {
+        &config.digest_algorithms.value
+    } else {
+        &cmd.digest_algorithm
+    };
+    // Hidden files and config patterns are not excluded from archives, which are read as they are
+    let exclude_hidden_files =
+        cmd.from_tar.is_none() && (cmd.exclude_hidden_files || config.exclude_hidden_files.value);
+    let mut exclude = cmd.exclude;
+    if cmd.from_tar.is_none() {
+        exclude.extend(config.exclude.value.iter().cloned());
+    }
+
+    let creator = creator
+        .with_bag_info(bag_info)
+        .with_algorithms(&map_algorithms(algorithms))
+        .include_hidden_files(!exclude_hidden_files)
+        .with_exclude_patterns(ExcludePatterns::new(&exclude)?)
+        .use_bagignore(!cmd.no_bagignore)
+        .write_exclusion_report(cmd.exclusion_report)
+        .verify_source(cmd.verify_source)
+        .force(cmd.force)
+        .reproducible(cmd.reproducible)
+        .digest_cache(cmd.digest_cache)
+        .with_name_transforms(name_transforms)
+        .warn_suspicious_files(cmd.warn_suspicious_files)
+        .with_threads(cmd.threads.unwrap_or(config.threads.value))
+        .with_io_retries(cmd.io_retries)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_tag_line_width(tag_line_width(cmd.tag_line_width))
+        .with_calculate_bag_size(cmd.calculate_bag_size)
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress)
+        .with_file_scanner(scanner);
+
+    if let Some((bucket, prefix)) = cmd.s3_destination {
+        let store = S3Store::new(bucket, s3_credentials()?)
+            .with_region(cmd.region)
+            .with_endpoint(cmd.endpoint);
+        creator.create_in_store(&store, &prefix)?;
+        return Ok(());
+    }
+
+    let created = match &cmd.from_tar {
+        Some(archive) if archive == Path::new("-") => creator.create_from_tar(io::stdin().lock()),
+        Some(archive) => fs::File::open(archive)
+            .context(IoReadSnafu { path: archive })
+            .and_then(|file| creator.create_from_tar(io::BufReader::new(file))),
+        None if cmd.skip_unreadable => creator.create_skipping_unreadable().map(|(bag, _)| bag),
+        None => creator.create(),
+    };
+    if let (true, Ok(bag)) = (cmd.stats, &created) {
+        print_stats(bag);
+    }
+
+    if let (Some(staging_dir), Some(archive)) = (&staging_dir, &cmd.to_tar) {
+        let written = created.and_then(|_| write_tar(&dst_dir, archive));
+        info!(
+            "Deleting staging directory {}",
+            staging_dir.path().display()
+        );
+        return written;
+    }
+    created?;
+
+    if let Some(database) = cmd.database {
+        Inventory::open(database)?.index_bag(&dst_dir, path_encoding(cmd.strict_encoding))?;
+    }
+
+    Ok(())
+}
+
+/// Creates a new directory with a unique name that starts with `prefix` in the system's temp
+/// directory. It's only accessible by the current user, and it's deleted when it's dropped.
+fn create_staging_dir(prefix: &str) -> Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix(prefix)
+        .tempdir()
+        .context(IoCreateSnafu {
+            path: std::env::temp_dir(),
+        })
+}
+
+/// The name of the directory that a bag that's created from `source` is within in a tar archive
+fn archived_bag_name(source: &Path) -> Result<OsString> {
+    fs::canonicalize(source)
+        .unwrap_or_else(|_| source.into())
+        .file_name()
+        .map(OsStr::to_os_string)
+        .ok_or_else(|| General {
+            message: format!("{} cannot be used to name the bag", source.display()),
+        })
+}
+
+/// Writes the bag in `bag_dir` as a tar archive to `archive`, or to stdout if it's '-'
+fn write_tar(bag_dir: &Path, archive: &Path) -> Result<()> {
+    if archive == Path::new("-") {
+        write_bag_archive(bag_dir, io::BufWriter::new(io::stdout().lock()))?;
+    } else {
+        let file = fs::File::create(archive).context(IoCreateSnafu { path: archive })?;
+        write_bag_archive(bag_dir, io::BufWriter::new(file))?;
+    }
+    Ok(())
+}
+
+fn exec_rebag(
+    cmd: RebagCmd,
+    progress: Arc<dyn ProgressListener>,
+    scanner: Option<Arc<dyn FileScanner>>,
+    weak_algorithms: WeakAlgorithmPolicy,
+    default_threads: usize,
+) -> Result<Bag> {
+    let bag = open_bag(cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    let mut updater = bag.update();
+    for algorithm in &cmd.add_algorithm {
+        updater = updater.add_algorithm((*algorithm).into());
+    }
+
+    updater
+        .recalculate_payload_manifests(!cmd.only_tags && cmd.add_algorithm.is_empty())
+        .incremental(cmd.fast)
+        .detect_renames(cmd.detect_renames)
+        .prune_missing(cmd.prune_missing)
+        .force(cmd.force)
+        .digest_cache(cmd.digest_cache)
         .with_bagging_date(cmd.bagging_date)
         .with_software_agent(cmd.software_agent)
         .with_algorithms(&map_algorithms(&cmd.digest_algorithm))
+        .with_threads(cmd.threads.unwrap_or(default_threads))
+        .with_io_retries(cmd.io_retries)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_tag_line_width(tag_line_width(cmd.tag_line_width))
+        .with_calculate_bag_size(cmd.calculate_bag_size)
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress)
+        .with_file_scanner(scanner)
+        .finalize()
+        .inspect(|bag| {
+            if cmd.stats {
+                print_stats(bag);
+            }
+        })
+}
+
+/// Prints the statistics about how the `bag` was created or updated to stderr
+fn print_stats(bag: &Bag) {
+    if let Some(stats) = bag.stats() {
+        eprintln!("{}", stats);
+    }
+}
+
+fn exec_add(
+    cmd: AddCmd,
+    progress: Arc<dyn ProgressListener>,
+    scanner: Option<Arc<dyn FileScanner>>,
+) -> Result<String> {
+    let bag = open_bag(&cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    let manifests = bag.algorithms().len();
+    let dest = cmd.dest.unwrap_or_default();
+    let mut updater = bag
+        .update()
+        .with_progress_listener(progress)
+        .with_file_scanner(scanner);
+    let mut files = 0;
+
+    for src in &cmd.sources {
+        let base = match src.file_name() {
+            Some(name) => dest.join(name),
+            None => dest.clone(),
+        };
+
+        for entry in WalkDir::new(src) {
+            let entry = entry.context(WalkFileSnafu {})?;
+            if !entry.file_type().is_dir() {
+                let relative = entry.path().strip_prefix(src).unwrap();
+                let path = if relative.as_os_str().is_empty() {
+                    base.clone()
+                } else {
+                    base.join(relative)
+                };
+                updater = updater.add_file(entry.path(), path);
+                files += 1;
+            }
+        }
+    }
+
+    updater
+        .recalculate_payload_manifests(cmd.rehash_all)
+        .force(cmd.force)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_bagging_date(cmd.bagging_date)
+        .with_software_agent(cmd.software_agent)
+        .finalize()?;
+
+    Ok(payload_summary("Added", files, manifests, cmd.rehash_all))
+}
+
+fn exec_rm(cmd: RmCmd) -> Result<String> {
+    let bag = open_bag(&cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    let manifests = bag.algorithms().len();
+    let files = cmd.paths.len();
+
+    cmd.paths
+        .iter()
+        .fold(bag.update(), |updater, path| updater.remove_file(path))
+        .recalculate_payload_manifests(cmd.rehash_all)
+        .force(cmd.force)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_bagging_date(cmd.bagging_date)
+        .with_software_agent(cmd.software_agent)
+        .finalize()?;
+
+    Ok(payload_summary("Removed", files, manifests, cmd.rehash_all))
+}
+
+fn exec_unbag(cmd: UnbagCmd) -> Result<PathBuf> {
+    let bag = open_bag(cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    bag.unbag()
+}
+
+/// Describes how many files and manifest lines were added or removed
+fn payload_summary(action: &str, files: usize, manifests: usize, rehashed: bool) -> String {
+    let plural = if files == 1 { "" } else { "s" };
+    if rehashed {
+        format!("{action} {files} payload file{plural}; all payload manifests were recalculated")
+    } else {
+        let lines = files * manifests;
+        let line_plural = if lines == 1 { "" } else { "s" };
+        format!("{action} {files} payload file{plural} and {lines} manifest line{line_plural}")
+    }
+}
+
+fn exec_repair(cmd: RepairCmd) -> Result<String> {
+    let bag = open_bag(&cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    let added = bag.add_tag_manifests(path_encoding(cmd.strict_encoding))?;
+
+    if added.is_empty() {
+        Ok("Bag already has tag manifests".to_string())
+    } else {
+        Ok(format!("Added {}", added.join(", ")))
+    }
+}
+
+fn exec_fetch(cmd: FetchCmd, config: &Config) -> Result<FetchSummary> {
+    let schemes = if cmd.allow_scheme.is_empty() {
+        &config.fetch_schemes.value
+    } else {
+        &cmd.allow_scheme
+    };
+    let hosts = if cmd.allow_host.is_empty() {
+        &config.fetch_hosts.value
+    } else {
+        &cmd.allow_host
+    };
+    let policy = FetchPolicy::new(schemes, hosts)?;
+
+    let bag = open_bag(&cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    bag.resolve_fetch()
+        .with_policy(policy)
+        .with_threads(cmd.threads)
+        .with_max_retries(cmd.retries)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .resolve()
+}
+
+fn exec_package(cmd: PackageCmd) -> Result<Vec<PathBuf>> {
+    BagPackager::new(cmd.bag_path, cmd.archive)
+        .with_part_size(cmd.part_size)
+        .with_password(archive_password(cmd.password, cmd.key_file.as_deref())?)
+        .package()
+}
+
+fn exec_unpack(cmd: UnpackCmd) -> Result<PathBuf> {
+    let bag_path = match archive_password(cmd.password, cmd.key_file.as_deref())? {
+        Some(password) => unpack_bag_with_password(cmd.archive, cmd.destination, &password)?,
+        None => unpack_bag(cmd.archive, cmd.destination)?,
+    };
+    info!("Unpacked bag into {}", bag_path.display());
+    Ok(bag_path)
+}
+
+fn exec_split(cmd: SplitCmd) -> Result<Vec<Bag>> {
+    let bag = open_bag(&cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    bag.split(cmd.destination, cmd.max_size)
+        .with_group_identifier(cmd.bag_group_identifier)
+        .with_bagging_date(cmd.bagging_date)
+        .with_tag_line_width(tag_line_width(cmd.tag_line_width))
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .split()
+}
+
+fn exec_merge(cmd: MergeCmd) -> Result<Bag> {
+    let bags = cmd
+        .bag_paths
+        .iter()
+        .map(open_bag)
+        .collect::<Result<Vec<_>>>()?;
+
+    BagMerger::new(bags, cmd.destination)
+        .with_bagging_date(cmd.bagging_date)
+        .with_tag_line_width(tag_line_width(cmd.tag_line_width))
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .merge()
+}
+
+fn exec_copy(
+    cmd: CopyCmd,
+    progress: Arc<dyn ProgressListener>,
+    weak_algorithms: WeakAlgorithmPolicy,
+    rules: &[(String, RuleLevel)],
+) -> Result<ValidationResult> {
+    let mut options = ValidationOptions::new()
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .warnings_as_errors(cmd.warnings_as_errors)
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress);
+
+    for (code, level) in rules {
+        options = options.with_rule(code, *level);
+    }
+
+    copy_bag(&cmd.bag_path, &cmd.destination, &options)
+}
+
+fn exec_upload(cmd: UploadCmd) -> Result<String> {
+    let credentials = s3_credentials()?;
+    let (bucket, key) = cmd.destination;
+    let state_file = cmd.state_file.unwrap_or_else(|| {
+        let mut path = cmd.archive.clone().into_os_string();
+        path.push(".upload-state");
+        path.into()
+    });
+
+    let summary = S3Uploader::new(&bucket, &key, credentials)
+        .with_region(cmd.region)
+        .with_endpoint(cmd.endpoint)
+        .with_part_size(cmd.part_size)
+        .with_max_retries(cmd.retries)
+        .with_state_file(Some(state_file))
+        .upload(&cmd.archive)?;
+
+    let mut message = format!(
+        "Uploaded {} to s3://{bucket}/{key} in {} parts",
+        cmd.archive.display(),
+        summary.parts()
+    );
+    if summary.resumed_parts() > 0 {
+        message.push_str(&format!(
+            ", {} of which were uploaded previously",
+            summary.resumed_parts()
+        ));
+    }
+    Ok(message)
+}
+
+fn exec_upgrade(cmd: UpgradeCmd) -> Result<Bag> {
+    let bag = open_bag(cmd.bag_path)?;
+    info!("Opened bag: {:?}", bag);
+
+    if bag.declaration().is_utf8() {
+        info!("Bag is already UTF-8 encoded");
+        return Ok(bag);
+    }
+
+    // The bag's contents do not change, so the original bagging metadata is kept
+    let bagging_date = bag.bag_info().bagging_date().map(|tag| tag.value().into());
+    let software_agent = bag
+        .bag_info()
+        .software_agent()
+        .map(|tag| tag.value().into());
+
+    bag.update()
+        .recalculate_payload_manifests(false)
+        .with_bagging_date(bagging_date)
+        .with_software_agent(software_agent)
         .finalize()
 }
 
+fn exec_index_content(cmd: IndexContentCmd) -> Result<()> {
+    let mut inventory = Inventory::open(&cmd.database)?;
+    let encoding = path_encoding(cmd.strict_encoding);
+
+    for bag_path in &cmd.bag_paths {
+        let count = inventory.index_bag(bag_path, encoding)?;
+        info!("Indexed {} files in {}", count, bag_path.display());
+    }
+
+    Ok(())
+}
+
+fn exec_find(cmd: FindCmd) -> Result<Vec<InventoryFile>> {
+    if !cmd.database.is_file() {
+        return Err(IoRead {
+            source: io::Error::from(io::ErrorKind::NotFound),
+            path: cmd.database,
+        });
+    }
+
+    let inventory = Inventory::open(&cmd.database)?;
+    let algorithm = cmd.digest_algorithm.map(BagItDigestAlgorithm::from);
+    inventory.find_digest(&cmd.digest, algorithm)
+}
+
+fn exec_stats(cmd: StatsCmd) -> Result<String> {
+    let bag = open_bag(&cmd.bag_path)?;
+
+    // Keyed on the top-level payload directory, eg. data/dir/, or data/ for files that are not
+    // in a directory
+    let mut groups: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut total_files = 0;
+    let mut total_bytes = 0;
+
+    for file in bag.payload_files_with_encoding(path_encoding(cmd.strict_encoding))? {
+        let path = file
+            .path()
+            .strip_prefix("data/")
+            .unwrap_or_else(|| file.path());
+        let group = match path.split_once('/') {
+            Some((dir, _)) => format!("data/{dir}/"),
+            None => "data/".to_string(),
+        };
+        let size = file.size().unwrap_or(0);
+
+        let entry = groups.entry(group).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+        total_files += 1;
+        total_bytes += size;
+    }
+
+    let algorithms = bag
+        .algorithms()
+        .iter()
+        .map(|algorithm| algorithm.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut rows = vec![(
+        "DIRECTORY".to_string(),
+        "FILES".to_string(),
+        "BYTES".to_string(),
+        "PERCENT".to_string(),
+    )];
+    for (group, (files, bytes)) in &groups {
+        let percent = if total_bytes == 0 {
+            0.0
+        } else {
+            *bytes as f64 / total_bytes as f64 * 100.0
+        };
+        rows.push((
+            group.clone(),
+            files.to_string(),
+            bytes.to_string(),
+            format!("{percent:.1}%"),
+        ));
+    }
+
+    let width = |column: fn(&(String, String, String, String)) -> &String| {
+        rows.iter().map(|row| column(row).len()).max().unwrap_or(0)
+    };
+    let widths = (
+        width(|row| &row.0),
+        width(|row| &row.1),
+        width(|row| &row.2),
+        width(|row| &row.3),
+    );
+
+    let mut stats = format!(
+        "Payload files: {total_files}\nPayload bytes: {total_bytes}\nAlgorithms: {algorithms}\n"
+    );
+    for (group, files, bytes, percent) in &rows {
+        stats.push_str(&format!(
+            "\n{group:<0$}  {files:>1$}  {bytes:>2$}  {percent:>3$}",
+            widths.0, widths.1, widths.2, widths.3
+        ));
+    }
+
+    Ok(stats)
+}
+
+fn exec_export_checksums(cmd: ExportChecksumsCmd) -> Result<()> {
+    let bag = open_bag(&cmd.bag_path)?;
+    let format = cmd.format.into();
+    let encoding = path_encoding(cmd.strict_encoding);
+
+    if cmd.output == Path::new("-") {
+        export_checksums(
+            &bag,
+            format,
+            encoding,
+            io::BufWriter::new(io::stdout().lock()),
+        )?;
+    } else {
+        let file = fs::File::create(&cmd.output).context(IoCreateSnafu { path: &cmd.output })?;
+        export_checksums(&bag, format, encoding, io::BufWriter::new(file))?;
+    }
+    Ok(())
+}
+
+fn exec_ls(cmd: LsCmd) -> Result<()> {
+    let bag = open_bag(&cmd.bag_path)?;
+    list_files(
+        &bag,
+        &cmd.patterns,
+        cmd.tags,
+        cmd.format.into(),
+        path_encoding(cmd.strict_encoding),
+        io::BufWriter::new(io::stdout().lock()),
+    )?;
+    Ok(())
+}
+
+fn exec_dedupe_report(cmd: DedupeReportCmd) -> Result<String> {
+    let bag = open_bag(&cmd.bag_path)?;
+    let algorithm = bag
+        .algorithms()
+        .iter()
+        .rev()
+        .find(|algorithm| !algorithm.is_weak())
+        .or_else(|| bag.algorithms().first())
+        .copied()
+        .ok_or_else(|| General {
+            message: "The bag does not have any payload manifests".to_string(),
+        })?;
+
+    let mut by_digest: BTreeMap<String, (u64, Vec<String>)> = BTreeMap::new();
+    for file in bag.payload_files_with_encoding(path_encoding(cmd.strict_encoding))? {
+        if let Some(digest) = file.digest(algorithm) {
+            let entry = by_digest.entry(digest.to_ascii_lowercase()).or_default();
+            entry.0 = entry.0.max(file.size().unwrap_or(0));
+            entry.1.push(file.path().to_string());
+        }
+    }
+
+    let mut duplicates = by_digest
+        .into_iter()
+        .filter(|(_, (size, paths))| *size > 0 && paths.len() > 1)
+        .collect::<Vec<_>>();
+    // Largest savings first
+    duplicates.sort_by(
+        |(a_digest, (a_size, a_paths)), (b_digest, (b_size, b_paths))| {
+            (b_size * (b_paths.len() as u64 - 1))
+                .cmp(&(a_size * (a_paths.len() as u64 - 1)))
+                .then_with(|| a_digest.cmp(b_digest))
+        },
+    );
+
+    if let Some(csv_path) = &cmd.csv {
+        let mut csv = String::from("digest,size,copies,path\n");
+        for (digest, (size, paths)) in &duplicates {
+            for path in paths {
+                csv.push_str(&format!(
+                    "{digest},{size},{},{}\n",
+                    paths.len(),
+                    csv_field(path)
+                ));
+            }
+        }
+        fs::write(csv_path, csv).context(IoWriteSnafu { path: csv_path })?;
+    }
+
+    let redundant = duplicates
+        .iter()
+        .map(|(_, (_, paths))| paths.len() - 1)
+        .sum::<usize>();
+    let savings = duplicates
+        .iter()
+        .map(|(_, (size, paths))| size * (paths.len() as u64 - 1))
+        .sum::<u64>();
+
+    let mut report = format!(
+        "Duplicate groups: {}\nRedundant copies: {redundant}\nPotential savings: {savings} bytes",
+        duplicates.len()
+    );
+    for (digest, (size, paths)) in &duplicates {
+        report.push_str(&format!(
+            "\n\n{algorithm} {digest} ({size} bytes, {} copies)",
+            paths.len()
+        ));
+        for path in paths {
+            report.push_str(&format!("\n  {path}"));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Quotes a CSV field if it contains a comma, quote, or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn exec_validate(
+    cmd: ValidateCmd,
+    progress: Arc<dyn ProgressListener>,
+    weak_algorithms: WeakAlgorithmPolicy,
+    rules: &[(String, RuleLevel)],
+    default_threads: usize,
+) -> Result<ValidationResult> {
+    let mut options = ValidationOptions::new()
+        .verify_fixity(!cmd.skip_fixity)
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_algorithms(&map_algorithms(&cmd.digest_algorithm))
+        .with_sample_rate(cmd.sample.unwrap_or(cmd.sample_rate))
+        .with_threads(cmd.threads.unwrap_or(default_threads))
+        .fail_fast(cmd.fail_fast)
+        .with_max_duration(cmd.max_duration)
+        .with_prefetch(cmd.prefetch)
+        .with_prefetch_memory(cmd.prefetch_memory)
+        .warnings_as_errors(cmd.warnings_as_errors)
+        .warn_suspicious_files(cmd.warn_suspicious_files)
+        .check_portability(cmd.check_portability)
+        .with_max_path_length(cmd.max_path_length)
+        .check_tag_structure(cmd.check_tag_structure)
+        .digest_cache(cmd.digest_cache)
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress);
+
+    if let Some(count) = cmd.sample_count {
+        options = options.with_sample_count(count);
+    }
+    if let Some(seed) = cmd.sample_seed {
+        options = options.with_sample_seed(seed);
+    }
+
+    // Rules on the command line are added last, so that they replace config rules for the same code
+    for (code, level) in rules.iter().chain(&cmd.rule) {
+        options = options.with_rule(code, *level);
+    }
+
+    let s3_bag = cmd
+        .bag_path
+        .as_deref()
+        .and_then(Path::to_str)
+        .filter(|path| path.starts_with("s3://"))
+        .map(|path| parse_s3_url(path.trim_end_matches('/')))
+        .transpose()
+        .map_err(|message| General { message })?;
+
+    if let Some((bucket, prefix)) = s3_bag {
+        if cmd.oxum_only {
+            return Err(General {
+                message: "--oxum-only is not supported for bags in S3".to_string(),
+            });
+        }
+        let store = S3Store::new(bucket, s3_credentials()?)
+            .with_region(cmd.region)
+            .with_endpoint(cmd.endpoint);
+        return validate_store_bag(&store, &prefix, &options);
+    }
+
+    match (cmd.stdin_format, cmd.bag_path) {
+        (Some(StdinFormat::Tar), _) => validate_tar_stream(io::stdin().lock(), &options),
+        (None, Some(bag_path)) if bag_path.is_file() => {
+            if cmd.oxum_only {
+                return Err(General {
+                    message: "--oxum-only is not supported for serialized bags".to_string(),
+                });
+            }
+            let bag = match archive_password(cmd.password, cmd.key_file.as_deref())? {
+                Some(password) => open_serialized_bag_with_password(bag_path, &password)?,
+                None => open_serialized_bag(bag_path)?,
+            };
+            validate_serialized_bag(&bag, &options)
+        }
+        (None, Some(bag_path)) if cmd.oxum_only => open_bag(bag_path)?.quick_check(),
+        (None, Some(bag_path)) => validate_bag(bag_path, &options),
+        (None, None) => unreachable!("clap requires a bag path when not reading from stdin"),
+    }
+}
+
+fn exec_compare_replicas(
+    cmd: CompareReplicasCmd,
+    progress: Arc<dyn ProgressListener>,
+    weak_algorithms: WeakAlgorithmPolicy,
+    rules: &[(String, RuleLevel)],
+    default_threads: usize,
+) -> Result<ReplicaReport> {
+    let mut options = ValidationOptions::new()
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_sample_rate(cmd.sample_rate)
+        .with_threads(cmd.threads.unwrap_or(default_threads))
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress);
+
+    for (code, level) in rules {
+        options = options.with_rule(code, *level);
+    }
+
+    compare_replicas(&cmd.bag_paths, &options)
+}
+
+fn exec_audit(
+    cmd: AuditCmd,
+    progress: Arc<dyn ProgressListener>,
+    weak_algorithms: WeakAlgorithmPolicy,
+    rules: &[(String, RuleLevel)],
+    default_threads: usize,
+) -> Result<AuditReport> {
+    let mut options = ValidationOptions::new()
+        .with_path_encoding(path_encoding(cmd.strict_encoding))
+        .with_threads(cmd.threads.unwrap_or(default_threads))
+        .with_weak_algorithm_policy(weak_algorithms)
+        .with_progress_listener(progress);
+
+    for (code, level) in rules {
+        options = options.with_rule(code, *level);
+    }
+
+    let mut auditor = Auditor::new()
+        .with_max_duration(cmd.max_duration)
+        .with_max_bytes(cmd.max_bytes)
+        .with_validation_options(options);
+    if let Some(state) = cmd.state {
+        auditor = auditor.with_state_file(state);
+    }
+
+    auditor.audit(&cmd.bag_path)
+}
+
+fn exec_diff(cmd: DiffCmd) -> Result<BagDiff> {
+    diff_bags(
+        &cmd.left,
+        &cmd.right,
+        path_encoding(cmd.strict_encoding),
+        cmd.content,
+    )
+}
+
+/// Returns the values of the tag for `get`, and None for commands that edit tags
+fn exec_tag(cmd: TagCmd) -> Result<Option<Vec<String>>> {
+    match cmd.command {
+        TagCommand::Get(cmd) => exec_tag_get(cmd).map(Some),
+        TagCommand::Set(cmd) => exec_tag_set(cmd, false).map(|_| None),
+        TagCommand::Append(cmd) => exec_tag_set(cmd, true).map(|_| None),
+        TagCommand::Remove(cmd) => exec_tag_remove(cmd).map(|_| None),
+    }
+}
+
+fn exec_tag_get(cmd: TagGetCmd) -> Result<Vec<String>> {
+    let bag = open_bag(&cmd.bag_path)?;
+    Ok(bag
+        .bag_info()
+        .get_tags(&cmd.label)
+        .map(|tag| tag.value().to_string())
+        .collect())
+}
+
+fn exec_tag_set(cmd: TagSetCmd, append: bool) -> Result<()> {
+    edit_bag_info(&cmd.bag_path, &cmd.label, cmd.strict_encoding, |bag_info| {
+        if !append {
+            bag_info.remove_tags(&cmd.label);
+        }
+        bag_info.add_tag(&cmd.label, &cmd.value)
+    })
+}
+
+fn exec_tag_remove(cmd: TagGetCmd) -> Result<()> {
+    edit_bag_info(&cmd.bag_path, &cmd.label, cmd.strict_encoding, |bag_info| {
+        bag_info.remove_tags(&cmd.label);
+        Ok(())
+    })
+}
+
+/// Applies `edit` to the bag's bag-info.txt, and writes it back without touching the payload
+fn edit_bag_info<F>(bag_path: &Path, label: &str, strict_encoding: bool, edit: F) -> Result<()>
+where
+    F: FnOnce(&mut BagInfo) -> Result<()>,
+{
+    // Payload-Oxum must always describe the payload, so it's only changed by rebagging
+    if label.eq_ignore_ascii_case("Payload-Oxum") {
+        return Err(General {
+            message: "Payload-Oxum is calculated from the payload and cannot be edited".to_string(),
+        });
+    }
+
+    let mut bag = open_bag(bag_path)?;
+    let mut bag_info = bag.bag_info().clone();
+    edit(&mut bag_info)?;
+    bag.write_bag_info_with_encoding(bag_info, path_encoding(strict_encoding))
+}
+
+fn exec_config_init(cmd: &ConfigInitCmd, path: Option<&Path>) -> Result<PathBuf> {
+    let path = path.ok_or_else(|| InvalidConfig {
+        details: "Could not find the user's config directory. Specify a path with --config"
+            .to_string(),
+    })?;
+
+    if path.exists() && !cmd.force {
+        return Err(General {
+            message: format!(
+                "{} already exists. Use --force to overwrite it",
+                path.display()
+            ),
+        });
+    }
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).context(IoCreateSnafu { path: parent })?;
+    }
+    fs::write(path, STARTER_CONFIG).context(IoWriteSnafu { path })?;
+
+    Ok(path.into())
+}
+
+const STARTER_CONFIG: &str = r#"# bagr config file
+#
+# Settings are written in TOML, and settings that take more than one value are arrays.
+# Environment variables and command line flags take precedence over these settings.
+
+# Digest algorithms that new bags are created with
+digest-algorithm = ["sha512"]
+
+# Leave hidden files out of new bags. They're deleted when a bag is created in place
+exclude-hidden-files = false
+
+# Glob patterns of files to leave out of new bags, in addition to the patterns passed to --exclude
+# exclude = [".DS_Store", "Thumbs.db"]
+
+# Tags that are added to the bag-info.txt of new bags, unless they're specified on the command
+# line. Each value in the array is added as a separate tag
+# source-organization = ["Example University Library"]
+# contact-email = ["archives@example.org"]
+
+# Maximum number of threads to use when calculating digests. 0 uses one thread per CPU
+threads = 0
+
+# How to treat the weak digest algorithms md5 and sha1: allow, warn, or forbid
+weak-algorithms = "warn"
+
+# Shell command that screens every payload file as it's hashed
+# scan-cmd = "clamscan --no-summary -"
+
+# Log a warning for files that fail the scan, instead of rejecting them
+scan-warn-only = false
+
+# Periodically write progress to stderr
+# heartbeat = "5m"
+
+# Report validation issues with an issue code at a different level: ignore, warn, or error
+# validation-rules = ["MissingTagManifests=error", "SuspiciousFile=ignore"]
+
+# URL schemes that 'bagr fetch' may download files from: https, http, or file
+fetch-schemes = ["https"]
+
+# Glob patterns of the hosts that 'bagr fetch' may download files from. Every host is allowed by
+# default
+# fetch-hosts = ["*.example.org", "data.example.com"]
+"#;
+
+/// The config file in the user's config directory
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    config_dir.map(|dir| dir.join("bagr").join("config.toml"))
+}
+
+impl Config {
+    /// Resolves every setting from `args`, the environment, and the config file at `path`. It's
+    /// an error if the config file was explicitly specified and does not exist.
+    fn load(args: &BagrArgs, path: Option<PathBuf>) -> Result<Self> {
+        let (path, missing_path) = match path {
+            Some(path) if path.is_file() => (Some(path), None),
+            Some(path) if args.config.is_some() => {
+                return Err(InvalidConfig {
+                    details: format!("{} does not exist", path.display()),
+                })
+            }
+            Some(path) => (None, Some(path)),
+            None => (None, None),
+        };
+
+        let mut file = match &path {
+            Some(path) => read_config_file(path)?,
+            None => BTreeMap::new(),
+        };
+
+        let config = Self {
+            digest_algorithms: Setting::resolve(
+                CONFIG_DIGEST_ALGORITHM,
+                &mut file,
+                None,
+                vec![DigestAlgorithm::Sha512],
+                parse_config_algorithms,
+                |algorithms| {
+                    algorithms
+                        .iter()
+                        .map(arg_enum_name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                },
+            )?,
+            exclude_hidden_files: Setting::resolve(
+                CONFIG_EXCLUDE_HIDDEN_FILES,
+                &mut file,
+                None,
+                false,
+                parse_config_bool,
+                bool::to_string,
+            )?,
+            exclude: Setting::resolve(
+                CONFIG_EXCLUDE,
+                &mut file,
+                None,
+                Vec::new(),
+                parse_config_list,
+                |patterns| display_list(patterns, ", "),
+            )?,
+            source_organization: Setting::resolve(
+                CONFIG_SOURCE_ORGANIZATION,
+                &mut file,
+                None,
+                Vec::new(),
+                parse_config_lines,
+                |orgs| display_list(orgs, "; "),
+            )?,
+            contact_email: Setting::resolve(
+                CONFIG_CONTACT_EMAIL,
+                &mut file,
+                None,
+                Vec::new(),
+                parse_config_list,
+                |emails| display_list(emails, ", "),
+            )?,
+            threads: Setting::resolve(
+                CONFIG_THREADS,
+                &mut file,
+                None,
+                0,
+                |value| value.parse::<usize>().map_err(|e| e.to_string()),
+                |threads| match threads {
+                    0 => "one per CPU".to_string(),
+                    threads => threads.to_string(),
+                },
+            )?,
+            weak_algorithms: Setting::resolve(
+                CONFIG_WEAK_ALGORITHMS,
+                &mut file,
+                args.weak_algorithms,
+                WeakAlgorithms::Warn,
+                |value| WeakAlgorithms::from_str(value, true),
+                arg_enum_name,
+            )?,
+            scan_cmd: Setting::resolve(
+                CONFIG_SCAN_CMD,
+                &mut file,
+                args.scan_cmd.clone().map(Some),
+                None,
+                |value| Ok(Some(value.to_string())),
+                |command| command.clone().unwrap_or_else(|| "none".to_string()),
+            )?,
+            scan_warn_only: Setting::resolve(
+                CONFIG_SCAN_WARN_ONLY,
+                &mut file,
+                args.scan_warn_only.then_some(true),
+                false,
+                parse_config_bool,
+                bool::to_string,
+            )?,
+            heartbeat: Setting::resolve(
+                CONFIG_HEARTBEAT,
+                &mut file,
+                args.heartbeat.map(Some),
+                None,
+                |value| parse_duration(value).map(Some),
+                |interval| {
+                    interval
+                        .map(format_duration)
+                        .unwrap_or_else(|| "none".to_string())
+                },
+            )?,
+            validation_rules: Setting::resolve(
+                CONFIG_VALIDATION_RULES,
+                &mut file,
+                None,
+                Vec::new(),
+                parse_config_rules,
+                |rules| {
+                    if rules.is_empty() {
+                        return "none".to_string();
+                    }
+                    rules
+                        .iter()
+                        .map(|(code, level)| format!("{code}={level}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                },
+            )?,
+            fetch_schemes: Setting::resolve(
+                CONFIG_FETCH_SCHEMES,
+                &mut file,
+                None,
+                vec!["https".to_string()],
+                parse_config_list,
+                |schemes| schemes.join(", "),
+            )?,
+            fetch_hosts: Setting::resolve(
+                CONFIG_FETCH_HOSTS,
+                &mut file,
+                None,
+                Vec::new(),
+                parse_config_list,
+                |hosts| {
+                    if hosts.is_empty() {
+                        "any".to_string()
+                    } else {
+                        hosts.join(", ")
+                    }
+                },
+            )?,
+            path,
+            missing_path,
+        };
+
+        // Every known label was removed while resolving the settings
+        if let Some(label) = file.keys().next() {
+            return Err(InvalidConfig {
+                details: format!(
+                    "Unknown setting '{label}' in {}",
+                    config.path.as_ref().unwrap().display()
+                ),
+            });
+        }
+
+        Ok(config)
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.path, &self.missing_path) {
+            (Some(path), _) => writeln!(f, "Config file: {}", path.display())?,
+            (None, Some(path)) => writeln!(f, "Config file: {} (does not exist)", path.display())?,
+            (None, None) => writeln!(f, "Config file: none")?,
+        }
+        writeln!(f)?;
+        writeln!(f, "{}", self.digest_algorithms)?;
+        writeln!(f, "{}", self.exclude_hidden_files)?;
+        writeln!(f, "{}", self.exclude)?;
+        writeln!(f, "{}", self.source_organization)?;
+        writeln!(f, "{}", self.contact_email)?;
+        writeln!(f, "{}", self.threads)?;
+        writeln!(f, "{}", self.weak_algorithms)?;
+        writeln!(f, "{}", self.scan_cmd)?;
+        writeln!(f, "{}", self.scan_warn_only)?;
+        writeln!(f, "{}", self.heartbeat)?;
+        writeln!(f, "{}", self.validation_rules)?;
+        writeln!(f, "{}", self.fetch_schemes)?;
+        write!(f, "{}", self.fetch_hosts)
+    }
+}
+
+impl<T> Setting<T> {
+    /// The value passed on the command line is used if there is one. Otherwise, the value is
+    /// parsed from the setting's environment variable, or the config file, and falls back to
+    /// `default`. The setting is removed from `file`.
+    fn resolve(
+        label: &'static str,
+        file: &mut BTreeMap<String, String>,
+        cli: Option<T>,
+        default: T,
+        parse: impl Fn(&str) -> std::result::Result<T, String>,
+        display: impl Fn(&T) -> String,
+    ) -> Result<Self> {
+        let env_var = format!("BAGR_{}", label.to_uppercase().replace('-', "_"));
+        let file_value = file.remove(&label.to_lowercase());
+
+        let (value, source) = if let Some(value) = cli {
+            (value, SettingSource::CommandLine)
+        } else if let Ok(env_value) = std::env::var(&env_var) {
+            let value = parse(&env_value).map_err(|e| InvalidConfig {
+                details: format!("Invalid {env_var} '{env_value}': {e}"),
+            })?;
+            (value, SettingSource::Environment(env_var))
+        } else if let Some(file_value) = file_value {
+            let value = parse(&file_value).map_err(|e| InvalidConfig {
+                details: format!(
+                    "Invalid {label} '{}' in config file: {e}",
+                    file_value.replace('\n', ", ")
+                ),
+            })?;
+            (value, SettingSource::ConfigFile)
+        } else {
+            (default, SettingSource::Default)
+        };
+
+        Ok(Self {
+            label,
+            display: display(&value),
+            value,
+            source,
+        })
+    }
+}
+
+impl<T> Display for Setting<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({})", self.label, self.display, self.source)
+    }
+}
+
+impl Display for SettingSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::ConfigFile => write!(f, "config file"),
+            SettingSource::Environment(name) => write!(f, "environment variable {name}"),
+            SettingSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Reads a list of payload files, one per line, where each line is a source path optionally
+/// followed by a tab and its payload relative destination. Relative source paths are resolved
+/// against `src_dir`. The list is read from stdin when `path` is '-'.
+fn read_file_list(path: &Path, src_dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let contents = if path == Path::new("-") {
+        io::read_to_string(io::stdin().lock()).context(IoReadSnafu { path })?
+    } else {
+        fs::read_to_string(path).context(IoReadSnafu { path })?
+    };
+
+    let mut files = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (src, dest) = match line.split_once('\t') {
+            Some((src, dest)) => (Path::new(src), PathBuf::from(dest)),
+            None if Path::new(line).is_absolute() => {
+                let name = Path::new(line).file_name().ok_or_else(|| General {
+                    message: format!(
+                        "Line {} in {} must specify a destination for {line}",
+                        i + 1,
+                        path.display()
+                    ),
+                })?;
+                (Path::new(line), PathBuf::from(name))
+            }
+            None => (Path::new(line), PathBuf::from(line)),
+        };
+
+        files.push((src_dir.join(src), dest));
+    }
+
+    Ok(files)
+}
+
+/// Reads the settings in a TOML config file, keyed by their lowercase labels. Array values are
+/// joined with newlines, and other values are converted to strings, so that they're parsed the same
+/// way as environment variables.
+fn read_config_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let contents = fs::read_to_string(path).context(IoReadSnafu { path })?;
+    let table = contents.parse::<toml::Table>().map_err(|e| {
+        let line = e
+            .span()
+            .map_or(1, |span| contents[..span.start].matches('\n').count() + 1);
+        let message = e.message().lines().map(str::trim).collect::<Vec<_>>();
+        InvalidConfig {
+            details: format!(
+                "Line {line} in {} is not valid TOML: {}",
+                path.display(),
+                message.join(", ")
+            ),
+        }
+    })?;
+
+    let mut settings = BTreeMap::new();
+
+    for (label, value) in table {
+        let value = match value {
+            toml::Value::Array(values) => values
+                .into_iter()
+                .map(|value| config_value(&label, value, path))
+                .collect::<Result<Vec<_>>>()?
+                .join("\n"),
+            value => config_value(&label, value, path)?,
+        };
+        settings.insert(label.to_lowercase(), value);
+    }
+
+    Ok(settings)
+}
+
+/// Converts a scalar TOML value to the string form of a setting
+fn config_value(label: &str, value: toml::Value, path: &Path) -> Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(InvalidConfig {
+            details: format!(
+                "Setting '{label}' in {} must be a string, number, boolean, or an array of them",
+                path.display()
+            ),
+        }),
+    }
+}
+
+fn parse_config_algorithms(value: &str) -> std::result::Result<Vec<DigestAlgorithm>, String> {
+    let algorithms = value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|algorithm| !algorithm.is_empty())
+        .map(|algorithm| DigestAlgorithm::from_str(algorithm, true))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if algorithms.is_empty() {
+        Err("At least one algorithm must be specified".to_string())
+    } else {
+        Ok(algorithms)
+    }
+}
+
+fn parse_config_rules(value: &str) -> std::result::Result<Vec<(String, RuleLevel)>, String> {
+    value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(parse_rule)
+        .collect()
+}
+
+fn parse_config_list(value: &str) -> std::result::Result<Vec<String>, String> {
+    Ok(value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Parses a setting that's repeated for each value, because its values may contain commas
+fn parse_config_lines(value: &str) -> std::result::Result<Vec<String>, String> {
+    Ok(value
+        .lines()
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn display_list(values: &[String], separator: &str) -> String {
+    if values.is_empty() {
+        "none".to_string()
+    } else {
+        values.join(separator)
+    }
+}
+
+fn parse_config_bool(value: &str) -> std::result::Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err("Expected true or false".to_string()),
+    }
+}
+
+fn arg_enum_name<T: ArgEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [
+        (seconds / 3600, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let formatted = parts
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect::<String>();
+    if formatted.is_empty() {
+        "0s".to_string()
+    } else {
+        formatted
+    }
+}
+
+fn path_encoding(strict: bool) -> PathEncoding {
+    if strict {
+        PathEncoding::Strict
+    } else {
+        PathEncoding::Standard
+    }
+}
+
+/// Returns the absolute form of a bag's base directory, or the path as is if it can't be resolved
+/// Reads S3 credentials from the standard AWS environment variables
+/// Returns the password to encrypt or decrypt an archive with, which is either `password` or the
+/// contents of `key_file`, minus a trailing line ending
+fn archive_password(password: Option<String>, key_file: Option<&Path>) -> Result<Option<String>> {
+    let password = match key_file {
+        Some(key_file) => {
+            let contents = fs::read_to_string(key_file).context(IoReadSnafu { path: key_file })?;
+            let password = contents.strip_suffix('\n').unwrap_or(&contents);
+            Some(password.strip_suffix('\r').unwrap_or(password).to_string())
+        }
+        None => password,
+    };
+
+    if password.as_deref() == Some("") {
+        return Err(General {
+            message: "The archive password must not be empty".to_string(),
+        });
+    }
+    Ok(password)
+}
+
+fn s3_credentials() -> Result<S3Credentials> {
+    S3Credentials::from_env().ok_or_else(|| General {
+        message: "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set".to_string(),
+    })
+}
+
+fn absolute_base_dir(base_dir: &Path) -> PathBuf {
+    fs::canonicalize(base_dir).unwrap_or_else(|_| base_dir.into())
+}
+
+/// Joins a `/` separated path, relative to a bag's base directory, to the base directory
+fn absolute_path(base_dir: &Path, path: &str) -> PathBuf {
+    path.split('/')
+        .fold(base_dir.into(), |dir, part| dir.join(part))
+}
+
+fn tag_line_width(width: usize) -> Option<usize> {
+    if width == 0 {
+        None
+    } else {
+        Some(width)
+    }
+}
+
+/// Prints every issue, followed by the absolute path of the file it's about when `base_dir` is
+/// specified
+fn print_validation_result(result: &ValidationResult, oxum_only: bool, base_dir: Option<&Path>) {
+    for issue in result.issues() {
+        println!("{}", issue);
+        if let (Some(base_dir), Some(path)) = (base_dir, issue.path()) {
+            println!("  Path: {}", absolute_path(base_dir, path).display());
+        }
+    }
+
+    if result.is_partial() {
+        println!("Files not verified before the time limit:");
+        for path in result.unverified_files() {
+            match base_dir {
+                Some(base_dir) => {
+                    println!("  {} ({})", path, absolute_path(base_dir, path).display())
+                }
+                None => println!("  {}", path),
+            }
+        }
+    }
+
+    if let Some(sample) = result.sample() {
+        println!(
+            "Verified the digests of {} of {} payload files sampled with seed {}",
+            sample.sampled(),
+            sample.total(),
+            sample.seed()
+        );
+    }
+
+    if !result.is_valid() {
+        println!("Bag is invalid");
+    } else if result.is_partial() {
+        println!(
+            "Bag validation is partial: {} files were not verified",
+            result.unverified_files().len()
+        );
+    } else if oxum_only {
+        println!("Payload matches Payload-Oxum");
+    } else {
+        println!("Bag is valid");
+    }
+}
+
+fn print_audit_report(report: &AuditReport) {
+    for issue in report.validation().issues() {
+        println!("{}", issue);
+    }
+
+    println!(
+        "Verified {} of {} payload files ({} bytes)",
+        report.verified_files().len(),
+        report.total_files(),
+        report.verified_bytes()
+    );
+    if report.never_verified() > 0 {
+        println!("Files never verified: {}", report.never_verified());
+    }
+    if let Some(oldest) = report.oldest_verification() {
+        println!(
+            "Oldest verification: {}",
+            oldest.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+    }
+
+    if report.is_valid() {
+        println!("No problems found");
+    } else {
+        println!("Bag is invalid");
+    }
+}
+
+fn print_replica_report(report: &ReplicaReport, absolute_paths: bool) {
+    for replica in report.replicas() {
+        let health = if replica.is_healthy() {
+            "healthy"
+        } else {
+            "unhealthy"
+        };
+        println!("{}: {}", replica.path().display(), health);
+
+        let base_dir = absolute_paths.then(|| absolute_base_dir(replica.path()));
+        for issue in replica.validation().issues() {
+            println!("  {}", issue.to_string().replace('\n', "\n  "));
+            if let (Some(base_dir), Some(path)) = (&base_dir, issue.path()) {
+                println!("    Path: {}", absolute_path(base_dir, path).display());
+            }
+        }
+        for discrepancy in replica.discrepancies() {
+            println!("  [MISMATCH] {}", discrepancy);
+        }
+    }
+
+    let healthy = report
+        .replicas()
+        .iter()
+        .filter(|replica| replica.is_healthy())
+        .count();
+    println!(
+        "{} of {} replicas are healthy",
+        healthy,
+        report.replicas().len()
+    );
+}
+
+fn print_bag_diff(diff: &BagDiff) {
+    for path in diff.added() {
+        println!("Added {}", path);
+    }
+    for path in diff.removed() {
+        println!("Removed {}", path);
+    }
+    for path in diff.changed() {
+        println!("Changed {}", path);
+    }
+    for tag in diff.tags() {
+        let (old, new) = (tag.old_values().join(", "), tag.new_values().join(", "));
+        if tag.old_values().is_empty() {
+            println!("Added tag {}: {}", tag.label(), new);
+        } else if tag.new_values().is_empty() {
+            println!("Removed tag {}: {}", tag.label(), old);
+        } else {
+            println!("Changed tag {}: {} -> {}", tag.label(), old, new);
+        }
+    }
+
+    if diff.is_empty() {
+        println!("Bags are identical");
+    }
+}
+
+/// Parses contacts formatted as NAME;PHONE;EMAIL, where any of the fields may be empty
+fn parse_contact(value: &str) -> std::result::Result<Contact, String> {
+    let fields = value.split(';').map(str::trim).collect::<Vec<&str>>();
+    if fields.len() > 3 {
+        return Err(format!(
+            "Invalid contact '{value}'. Expected NAME;PHONE;EMAIL"
+        ));
+    }
+
+    let field = |i: usize| fields.get(i).copied().filter(|field| !field.is_empty());
+    let mut contact = Contact::new();
+    if let Some(name) = field(0) {
+        contact = contact.with_name(name);
+    }
+    if let Some(phone) = field(1) {
+        contact = contact.with_phone(phone);
+    }
+    if let Some(email) = field(2) {
+        contact = contact.with_email(email);
+    }
+
+    if contact == Contact::new() {
+        return Err(format!(
+            "Invalid contact '{value}'. A name, phone, or email is required"
+        ));
+    }
+    Ok(contact)
+}
+
+/// Parses CODE=LEVEL validation rules. Codes are matched case insensitively, and are normalized
+/// to the code's name.
+fn parse_rule(value: &str) -> std::result::Result<(String, RuleLevel), String> {
+    let (code, level) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid rule '{value}'. Expected CODE=LEVEL"))?;
+
+    let name = IssueCode::names()
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(code.trim()))
+        .ok_or_else(|| {
+            format!(
+                "Unknown issue code '{}'. Expected one of: {}",
+                code.trim(),
+                IssueCode::names().join(", ")
+            )
+        })?;
+    let level = level.trim().parse::<RuleLevel>().map_err(|_| {
+        format!(
+            "Invalid rule level '{}'. Expected ignore, warn, or error",
+            level.trim()
+        )
+    })?;
+
+    Ok((name.to_string(), level))
+}
+
+/// Parses Bagging-Date values and normalizes them to YYYY-MM-DD
+fn parse_bagging_date(value: &str) -> std::result::Result<String, String> {
+    parse_date(value).map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+/// Parses dates that may be ISO 8601 dates or date-times, 'today', or relative to the current
+/// date, like 'now-1d'
+fn parse_date(value: &str) -> std::result::Result<NaiveDate, String> {
+    let invalid =
+        || format!("Invalid date '{value}'. Expected a value like 2022-02-16, today, or now-1d");
+    let value = value.trim();
+    let today = Local::now().date_naive();
+
+    let date = if value.eq_ignore_ascii_case("today") || value.eq_ignore_ascii_case("now") {
+        today
+    } else if let Some(offset) = value.strip_prefix("now") {
+        let days = offset
+            .strip_suffix('d')
+            .and_then(|days| days.parse::<i64>().ok())
+            .filter(|_| offset.starts_with('-') || offset.starts_with('+'))
+            .ok_or_else(invalid)?;
+        today
+            .checked_add_signed(DateDuration::days(days))
+            .ok_or_else(invalid)?
+    } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date
+    } else if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        date_time.date_naive()
+    } else if let Ok(date_time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        date_time.date()
+    } else {
+        return Err(invalid());
+    };
+
+    Ok(date)
+}
+
+/// Parses Bag-Count values like '1 of 3' and '1 of ?'
+/// Parses a percentage, like 5% or 0.5%, into a fraction between 0 and 1
+fn parse_percent(value: &str) -> std::result::Result<f64, String> {
+    let invalid = || format!("Invalid percentage '{value}'. Expected a value like '5%'");
+
+    let percent = value
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| invalid())?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(invalid());
+    }
+
+    Ok(percent / 100.0)
+}
+
+fn parse_bag_count(value: &str) -> std::result::Result<(u64, Option<u64>), String> {
+    let invalid =
+        || format!("Invalid bag count '{value}'. Expected a value like '1 of 3' or '1 of ?'");
+
+    let (number, total) = value.split_once(" of ").ok_or_else(invalid)?;
+    let number = number.trim().parse::<u64>().map_err(|_| invalid())?;
+    let total = match total.trim() {
+        "?" => None,
+        total => Some(total.parse::<u64>().map_err(|_| invalid())?),
+    };
+
+    Ok((number, total))
+}
+
+/// Parses the rules that are used to rename payload files: nfc, sanitize, lowercase, and
+/// replace:PATTERN=REPLACEMENT
+fn parse_name_transform(value: &str) -> std::result::Result<Arc<dyn NameTransform>, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "nfc" => return Ok(Arc::new(NormalizeUnicode)),
+        "sanitize" => return Ok(Arc::new(SanitizeChars::default())),
+        "lowercase" => return Ok(Arc::new(Lowercase)),
+        _ => {}
+    }
+
+    let (pattern, replacement) = value
+        .strip_prefix("replace:")
+        .and_then(|rule| rule.split_once('='))
+        .ok_or_else(|| {
+            format!(
+                "Unknown rule '{}'. Expected nfc, sanitize, lowercase, or replace:PATTERN=REPLACEMENT",
+                value
+            )
+        })?;
+
+    ReplacePattern::new(pattern, replacement)
+        .map(|transform| Arc::new(transform) as Arc<dyn NameTransform>)
+        .map_err(|e| e.to_string())
+}
+
+/// Parses custom tag files and directories in the form SRC=DEST. When DEST is not specified, the
+/// file or directory is copied to the bag's base directory.
+fn parse_extra_tag_file(value: &str) -> std::result::Result<(PathBuf, PathBuf), String> {
+    match value.split_once('=') {
+        Some((src, dest)) if !src.is_empty() && !dest.is_empty() => Ok((src.into(), dest.into())),
+        Some(_) => Err(format!("Invalid tag file '{value}'. Expected SRC=DEST")),
+        None => {
+            let src = PathBuf::from(value);
+            let name = src
+                .file_name()
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("Invalid tag file '{value}'. Expected SRC=DEST"))?;
+            Ok((src, name))
+        }
+    }
+}
+
+/// Parses sizes like 4.7GB, 5TB, and 512MiB. A number without a unit is bytes.
+fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    const UNITS: [(&str, f64); 9] = [
+        ("b", 1.0),
+        ("kb", 1e3),
+        ("mb", 1e6),
+        ("gb", 1e9),
+        ("tb", 1e12),
+        ("kib", 1024.0),
+        ("mib", 1024.0 * 1024.0),
+        ("gib", 1024.0 * 1024.0 * 1024.0),
+        ("tib", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+    ];
+
+    let invalid = || format!("Invalid size '{value}'. Expected a value like 4.7GB or 512MiB");
+
+    let trimmed = value.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number = number.parse::<f64>().map_err(|_| invalid())?;
+    let unit = unit.trim().to_ascii_lowercase();
+
+    let multiplier = if unit.is_empty() {
+        1.0
+    } else {
+        UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, multiplier)| *multiplier)
+            .ok_or_else(invalid)?
+    };
+
+    let size = (number * multiplier).floor();
+    if size < 1.0 {
+        return Err(format!(
+            "Invalid size '{value}'. It must be at least 1 byte"
+        ));
+    }
+    Ok(size as u64)
+}
+
+/// Parses S3 URLs like s3://bucket/key into the bucket and the key
+fn parse_s3_url(value: &str) -> std::result::Result<(String, String), String> {
+    value
+        .strip_prefix("s3://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .map(|(bucket, key)| (bucket.to_string(), key.to_string()))
+        .ok_or_else(|| format!("Invalid S3 URL '{value}'. Expected a value like s3://bucket/key"))
+}
+
+/// Parses durations like 2h, 30m, 45s, and 1h30m. A number without a unit is seconds.
+fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{value}'. Expected a value like 2h, 30m, or 45s");
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut seconds = 0;
+    let mut number = String::new();
+
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let amount = number.parse::<u64>().map_err(|_| invalid())?;
+        seconds += amount * unit;
+        number.clear();
+    }
+
+    if !number.is_empty() || value.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs(seconds))
+}
+
 fn map_algorithms(algorithms: &[DigestAlgorithm]) -> Vec<BagItDigestAlgorithm> {
     algorithms
         .iter()
@@ -5,15 +5,121 @@ use std::path::{Path, PathBuf};
 fn bag_cli_tests() {
     // have to setup the manifest-encoding test here because git for windows hates newlines in paths
     setup_encoding_test();
+    setup_strict_encoding_test();
 
     trycmd::TestCases::new().case("tests/cmd/bag/*.toml");
 }
 
+#[test]
+fn package_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/package/*.toml");
+}
+
+#[test]
+fn inventory_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/inventory/*.toml");
+}
+
 #[test]
 fn rebag_cli_tests() {
     trycmd::TestCases::new().case("tests/cmd/rebag/*.toml");
 }
 
+#[test]
+fn add_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/add/*.toml");
+}
+
+#[test]
+fn rm_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/rm/*.toml");
+}
+
+#[test]
+fn compare_replicas_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/compare-replicas/*.toml");
+}
+
+#[test]
+fn diff_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/diff/*.toml");
+}
+
+#[test]
+fn dedupe_report_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/dedupe-report/*.toml");
+}
+
+#[test]
+fn export_checksums_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/export-checksums/*.toml");
+}
+
+#[test]
+fn ls_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/ls/*.toml");
+}
+
+#[test]
+fn stats_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/stats/*.toml");
+}
+
+#[test]
+fn validate_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/validate/*.toml");
+}
+
+#[test]
+fn upgrade_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/upgrade/*.toml");
+}
+
+#[test]
+fn repair_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/repair/*.toml");
+}
+
+#[test]
+fn fetch_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/fetch/*.toml");
+}
+
+#[test]
+fn split_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/split/*.toml");
+}
+
+#[test]
+fn merge_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/merge/*.toml");
+}
+
+#[test]
+fn unbag_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/unbag/*.toml");
+}
+
+#[test]
+fn copy_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/copy/*.toml");
+}
+
+#[test]
+fn audit_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/audit/*.toml");
+}
+
+#[test]
+fn tag_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/tag/*.toml");
+}
+
+#[test]
+fn config_cli_tests() {
+    trycmd::TestCases::new().case("tests/cmd/config/*.toml");
+}
+
 fn setup_encoding_test() {
     let in_base = base_path().join("bag").join("manifest-encoding.in");
     setup_encoding_files(in_base);
@@ -25,6 +131,23 @@ fn setup_encoding_test() {
     setup_encoding_files(out_base);
 }
 
+fn setup_strict_encoding_test() {
+    let in_base = base_path().join("bag").join("strict-encoding.in");
+    setup_strict_encoding_files(in_base);
+
+    let out_base = base_path()
+        .join("bag")
+        .join("strict-encoding.out")
+        .join("data");
+    setup_strict_encoding_files(out_base);
+}
+
+fn setup_strict_encoding_files(path: PathBuf) {
+    write_file(&path.join("test\ttab.txt"), "file with tab\n");
+    write_file(&path.join("test\nlf.txt"), "file with lf\n");
+    write_file(&path.join("test%20file.txt"), "file with %\n");
+}
+
 fn setup_encoding_files(path: PathBuf) {
     write_file(
         &path.join("dir\r\nwith%25everything%0D%0A").join("file.txt"),